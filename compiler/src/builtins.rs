@@ -7,6 +7,9 @@ use shared::StoredValue;
 use crate::compiler_types::*;
 use crate::context::*;
 use crate::globals::Globals;
+use crate::level_codec::{
+    ColorSettings, LevelSettings, LevelString, SettingKind, SettingValue, LEVEL_SETTINGS,
+};
 use crate::leveldata::*;
 use errors::{create_error, RuntimeError};
 use ahash::AHashMap;
@@ -76,6 +79,199 @@ fn div_zero_check(b: f64, op: &str, builtin: &str, info: &CompilerInfo) -> Resul
     }
 }
 
+// the settings of a level header as a dictionary, like `{speed: "fast", colors: [...]}`
+fn level_settings_value(
+    settings: LevelSettings,
+    globals: &mut Globals,
+    context: &Context,
+    info: &CompilerInfo,
+) -> Value {
+    let mut store = |value| store_const_value(value, globals, context.start_group, info.position);
+    let mut dict = AHashMap::default();
+    for (name, value) in settings.values {
+        let value = match value {
+            SettingValue::Bool(b) => Value::Bool(b),
+            SettingValue::Number(n) => Value::Number(n),
+            SettingValue::Text(t) => Value::Str(t),
+        };
+        dict.insert(LocalIntern::new(name.to_string()), store(value));
+    }
+    let mut colors = Vec::new();
+    for color in settings.colors {
+        let color_id = |id| {
+            Value::Color(Color {
+                id: Id::Specific(id),
+            })
+        };
+        let mut channel = AHashMap::default();
+        let [r, g, b] = color.rgb;
+        for (key, value) in [
+            ("channel", color_id(color.channel)),
+            ("r", Value::Number(r as f64)),
+            ("g", Value::Number(g as f64)),
+            ("b", Value::Number(b as f64)),
+            ("opacity", Value::Number(color.opacity)),
+            ("blending", Value::Bool(color.blending)),
+            (
+                "player_color",
+                color
+                    .player_color
+                    .map_or(Value::Null, |p| Value::Number(p as f64)),
+            ),
+            ("copy", color.copy.map_or(Value::Null, color_id)),
+        ] {
+            channel.insert(LocalIntern::new(key.to_string()), store(value));
+        }
+        colors.push(store(Value::Dict(channel)));
+    }
+    dict.insert(
+        LocalIntern::new(String::from("colors")),
+        store(Value::Array(colors)),
+    );
+    Value::Dict(dict)
+}
+
+// reads the settings given to `$.set_level_header`
+fn level_settings_from_dict(
+    dict: &AHashMap<LocalIntern<String>, StoredValue>,
+    globals: &Globals,
+) -> Result<LevelSettings, String> {
+    let mut settings = LevelSettings::default();
+    for (key, value) in dict {
+        let value = &globals.stored_values[*value];
+        if key.as_str() == "colors" {
+            let channels = match value {
+                Value::Array(a) => a,
+                _ => {
+                    return Err(format!(
+                        "Expected an array of color channels for `colors`, found {}",
+                        value.get_type_str(globals)
+                    ))
+                }
+            };
+            for channel in channels {
+                match &globals.stored_values[*channel] {
+                    Value::Dict(d) => settings.colors.push(color_settings_from_dict(d, globals)?),
+                    other => {
+                        return Err(format!(
+                            "Expected a dictionary for a color channel, found {}",
+                            other.get_type_str(globals)
+                        ))
+                    }
+                }
+            }
+            continue;
+        }
+
+        let (name, _, kind) = match LEVEL_SETTINGS
+            .iter()
+            .find(|(name, _, _)| *name == key.as_str())
+        {
+            Some(setting) => setting,
+            None => {
+                return Err(format!(
+                    "Unknown level setting `{}` (the settings are {}, colors)",
+                    key,
+                    LEVEL_SETTINGS
+                        .iter()
+                        .map(|(name, _, _)| *name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            }
+        };
+        let setting = match (kind, value) {
+            (SettingKind::Bool, Value::Bool(b)) => SettingValue::Bool(*b),
+            (SettingKind::Number, Value::Number(n)) => SettingValue::Number(*n),
+            (SettingKind::Text, Value::Str(s)) => SettingValue::Text(s.clone()),
+            (SettingKind::Choice(names), Value::Str(s)) if names.contains(&s.as_str()) => {
+                SettingValue::Text(s.clone())
+            }
+            _ => {
+                let expected = match kind {
+                    SettingKind::Bool => String::from("a boolean"),
+                    SettingKind::Number => String::from("a number"),
+                    SettingKind::Text => String::from("a string"),
+                    SettingKind::Choice(names) => format!(
+                        "one of {}",
+                        names
+                            .iter()
+                            .map(|n| format!("\"{}\"", n))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                };
+                let found = match value {
+                    Value::Str(s) => format!("\"{}\"", s),
+                    _ => value.get_type_str(globals),
+                };
+                return Err(format!(
+                    "Expected {} for `{}`, found {}",
+                    expected, name, found
+                ));
+            }
+        };
+        settings.values.insert(name, setting);
+    }
+    Ok(settings)
+}
+
+// a color channel is replaced as a whole, so the values that aren't given are the defaults
+fn color_settings_from_dict(
+    dict: &AHashMap<LocalIntern<String>, StoredValue>,
+    globals: &Globals,
+) -> Result<ColorSettings, String> {
+    let get = |key: &str| {
+        dict.get(&LocalIntern::new(key.to_string()))
+            .map(|v| &globals.stored_values[*v])
+    };
+    let specific = |value: &Value, key: &str| match value {
+        Value::Color(Color {
+            id: Id::Specific(id),
+        }) => Ok(*id),
+        _ => Err(format!(
+            "Expected a specific color ID (like 1000c) for `{}` of a color channel, found {}",
+            key,
+            value.get_type_str(globals)
+        )),
+    };
+
+    let mut color = match get("channel") {
+        Some(value) => ColorSettings::new(specific(value, "channel")?),
+        None => return Err(String::from("A color channel needs a `channel`")),
+    };
+    for (key, value) in dict {
+        let value = &globals.stored_values[*value];
+        let invalid = |expected: &str| {
+            format!(
+                "Expected {} for `{}` of a color channel, found {}",
+                expected,
+                key,
+                value.get_type_str(globals)
+            )
+        };
+        match (key.as_str(), value) {
+            ("channel", _) => (),
+            ("r" | "g" | "b", Value::Number(n)) if (0.0..=255.0).contains(n) => {
+                let i = ["r", "g", "b"].iter().position(|c| *c == key.as_str()).unwrap();
+                color.rgb[i] = *n as u8;
+            }
+            ("r" | "g" | "b", _) => return Err(invalid("a number from 0 to 255")),
+            ("opacity", Value::Number(n)) if (0.0..=1.0).contains(n) => color.opacity = *n,
+            ("opacity", _) => return Err(invalid("a number from 0 to 1")),
+            ("blending", Value::Bool(b)) => color.blending = *b,
+            ("blending", _) => return Err(invalid("a boolean")),
+            ("player_color", Value::Null) => color.player_color = None,
+            ("player_color", Value::Number(n)) if *n == 1.0 || *n == 2.0 => color.player_color = Some(*n as u8),
+            ("player_color", _) => return Err(invalid("1, 2 or null")),
+            ("copy", Value::Null) => color.copy = None,
+            ("copy", value) => color.copy = Some(specific(value, "copy")?),
+            _ => return Err(format!("Unknown color channel setting `{}` (the settings are channel, r, g, b, opacity, blending, player_color, copy)", key)),
+        }
+    }
+    Ok(color)
+}
+
 //use text_io;
 use errors::compiler_info::{CodeArea, CompilerInfo};
 
@@ -91,7 +287,7 @@ macro_rules! arg_length {
     };
 }
 
-pub fn context_trigger(context: &Context, uid_counter: &mut usize) -> GdObj {
+pub fn context_trigger(context: &Context, uid_counter: &mut usize, info: &CompilerInfo) -> GdObj {
     let mut params = AHashMap::default();
     params.insert(57, ObjParam::Group(context.start_group));
     (*uid_counter) += 1;
@@ -100,6 +296,7 @@ pub fn context_trigger(context: &Context, uid_counter: &mut usize) -> GdObj {
         func_id: context.func_id,
         mode: ObjectMode::Trigger,
         unique_id: *uid_counter,
+        origin: Some(info.clone()),
     }
 }
 
@@ -478,6 +675,12 @@ macro_rules! builtins {
             )*
         ];
 
+        pub const BUILTIN_DESCRIPTIONS: &[(&str, &str)] = &[
+            $(
+                (stringify!($name), $desc),
+            )*
+        ];
+
         #[derive(Debug, Clone)]
         pub struct BuiltinPermissions (AHashMap<Builtin, bool>);
 
@@ -534,6 +737,7 @@ macro_rules! builtins {
                     })
                 }
             }
+            $globals.outside_reads.count(func);
             for full_context in contexts.iter() {
                 let $full_context: *mut FullContext = full_context;
                 let $context = full_context.inner();
@@ -1011,7 +1215,7 @@ $.add(obj {
                     func_id: context.func_id,
                     mode: ObjectMode::Object,
                     unique_id: globals.uid_counter,
-
+                    origin: Some(info.clone()),
                 };
                 (*globals).objects.push(obj)
             }
@@ -1020,7 +1224,7 @@ $.add(obj {
                 let obj = GdObj {
                     params: obj_map,
                     mode: ObjectMode::Trigger,
-                    ..context_trigger(context, &mut globals.uid_counter)
+                    ..context_trigger(context, &mut globals.uid_counter, &info)
                 }
                 .context_parameters(context);
                 (*globals).trigger_order += 1.0;
@@ -1227,6 +1431,15 @@ $.edit_obj(object, X, 600)
         if !has_key {
             o.push((okey, oval));
         }
+        // the whole object is checked, since changing the object ID can change what the other keys are
+        if let Err(message) = crate::obj_schema::check_object(&o) {
+            return Err(RuntimeError::CustomError(create_error(
+                info,
+                &message,
+                &[],
+                None,
+            )));
+        }
 
         Value::Null
     }
@@ -1299,7 +1512,7 @@ $.extend_trigger_func(10g, () {
         Value::Group(context.start_group)
     }
 
-    [Random] #[safe = true, desc = "Generates random numbers, or picks a random element of an array", example = "
+    [Random] #[safe = true, desc = "Generates random numbers, or picks a random element of an array (the same ones every build when `--seed` is given)", example = "
 $.random() // a completely random number
 $.random([1, 2, 3, 6]) // returns either 1, 2, 3, or 6
 $.random(1..11) // returns a random integer between 1 and 10
@@ -1318,7 +1531,7 @@ $.random(1..11) // returns a random integer between 1 and 10
             }
 
             if arguments.is_empty() {
-                Value::Number(rand::thread_rng().gen())
+                Value::Number(globals.rng.gen())
             } else {
                 let val = match convert_type(&globals.stored_values[arguments[0]].clone(), type_id!(array), &info, globals, context) {
                     Ok(Value::Array(v)) => v,
@@ -1332,14 +1545,14 @@ $.random(1..11) // returns a random integer between 1 and 10
                 };
 
                 if arguments.len() == 1 {
-                    let rand_elem = val.choose(&mut rand::thread_rng());
+                    let rand_elem = val.choose(&mut globals.rng).copied();
 
-                    if rand_elem.is_some() {
+                    if let Some(elem) = rand_elem {
                         clone_and_get_value(
-                            *rand_elem.unwrap(),
+                            elem,
                             globals,
                             context.start_group,
-                            !globals.is_mutable(*rand_elem.unwrap())
+                            !globals.is_mutable(elem)
                         )
                     } else {
                         Value::Null
@@ -1361,14 +1574,14 @@ $.random(1..11) // returns a random integer between 1 and 10
                     let mut out_arr = Vec::<StoredValue>::new();
 
                     for _ in 0..times {
-                        let rand_elem = val.choose(&mut rand::thread_rng());
+                        let rand_elem = val.choose(&mut globals.rng).copied();
 
-                        if rand_elem.is_some() {
+                        if let Some(elem) = rand_elem {
                             out_arr.push(clone_value(
-                                *rand_elem.unwrap(),
+                                elem,
                                 globals,
                                 context.start_group,
-                                !globals.is_mutable(*rand_elem.unwrap()),
+                                !globals.is_mutable(elem),
                                 CodeArea::new()
                             ));
                         } else {
@@ -1402,6 +1615,89 @@ $.random(1..11) // returns a random integer between 1 and 10
         }
         Value::Str(globals.initial_string.clone())
     }
+
+    [ObjProps] #[safe = true, desc = "Returns the object keys SPWN knows about as a dictionary of `@object_key`s by name, with the values they take and their default value (this is `obj_props` in the standard library)", example = "x_key = $.obj_props().X"]
+    fn obj_props() {
+        if !arguments.is_empty() {
+            return Err(RuntimeError::BuiltinError {
+                builtin,
+                message: String::from("Expected 0 arguments"),
+                info,
+            });
+        }
+        let mut keys = Vec::new();
+        for schema in crate::obj_schema::OBJECT_PROPS {
+            let mut types = Vec::new();
+            for kind in crate::obj_schema::all_types(schema.key) {
+                if kind == crate::obj_schema::PropType::Groups {
+                    types.push(Pattern::Array(vec![Pattern::Type(type_id!(group))]));
+                }
+                for name in kind.type_names() {
+                    let pattern = Pattern::Type(globals.type_ids[*name].0);
+                    if !types.contains(&pattern) {
+                        types.push(pattern);
+                    }
+                }
+            }
+            let pattern = types
+                .into_iter()
+                .reduce(|a, b| Pattern::Either(Box::new(a), Box::new(b)))
+                .unwrap();
+            let default = match (schema.kind, schema.default) {
+                (_, None) => Value::Null,
+                (crate::obj_schema::PropType::Bool, Some(n)) => Value::Bool(n != 0.0),
+                (_, Some(n)) => Value::Number(n),
+            };
+
+            for name in schema.names {
+                keys.push((*name, schema.key, pattern.clone(), default.clone()));
+            }
+        }
+
+        let (type_name, id_name, pattern_name) = (globals.TYPE_MEMBER_NAME, globals.OBJ_KEY_ID, globals.OBJ_KEY_PATTERN);
+        let mut store = |value| store_const_value(value, globals, context.start_group, info.position);
+        let mut props = AHashMap::default();
+        for (name, id, pattern, default) in keys {
+            let mut key = AHashMap::default();
+            key.insert(type_name, store(Value::TypeIndicator(type_id!(object_key))));
+            key.insert(id_name, store(Value::Number(id as f64)));
+            key.insert(pattern_name, store(Value::Pattern(pattern)));
+            key.insert(LocalIntern::new(String::from("name")), store(Value::Str(name.to_string())));
+            key.insert(LocalIntern::new(String::from("default")), store(default));
+            props.insert(LocalIntern::new(name.to_string()), store(Value::Dict(key)));
+        }
+        Value::Dict(props)
+    }
+
+    [LevelHeader] #[safe = true, desc = "Returns the settings of the level being written to (like its speed, gamemode and color channels), with the changes made by `$.set_level_header`", example = "header = $.level_header()"]
+    fn level_header() {
+        if !arguments.is_empty() {
+            return Err(RuntimeError::BuiltinError {
+                builtin,
+                message: String::from("Expected 0 arguments"),
+                info,
+            });
+        }
+        let header = match LevelString::parse_header(&globals.initial_string) {
+            Ok(header) => header,
+            Err(e) => return Err(RuntimeError::BuiltinError { builtin, message: e.to_string(), info }),
+        };
+        let mut settings = match header.map(|h| h.settings()).transpose() {
+            Ok(settings) => settings.unwrap_or_default(),
+            Err(e) => return Err(RuntimeError::BuiltinError { builtin, message: e.to_string(), info }),
+        };
+        settings.merge(globals.level_settings.clone());
+        level_settings_value(settings, globals, context, &info)
+    }
+
+    [SetLevelHeader] #[safe = true, desc = "Changes settings of the level when it's written (the ones returned by `$.level_header`). A channel in `colors` replaces the channel with the same ID", example = "$.set_level_header({ speed: \"fast\", colors: [{ channel: 1000c, r: 0, g: 0, b: 0 }] })"]
+    fn set_level_header((settings): Dict) {
+        match level_settings_from_dict(&settings, globals) {
+            Ok(settings) => globals.level_settings.merge(settings),
+            Err(message) => return Err(RuntimeError::BuiltinError { builtin, message, info }),
+        }
+        Value::Null
+    }
     [ParseLevel] #[safe = true, desc = "Returns a array of the objects in the level being written to, or an empty array if there is no output level", example = "level = $.level_objects()"]
     [[RAW]] fn level_objects() {
         if !arguments.is_empty() {
@@ -1416,7 +1712,12 @@ $.random(1..11) // returns a random integer between 1 and 10
             None => {
                 let stored = store_const_value(
                     Value::Array(
-                        crate::parse_levelstring::parse_levelstring(&globals.initial_string)?.into_iter()
+                        crate::parse_levelstring::parse_levelstring(&globals.initial_string)
+                        .map_err(|e| RuntimeError::BuiltinError {
+                            builtin,
+                            message: e.to_string(),
+                            info: info.clone(),
+                        })?.into_iter()
                         .map(|v|
                             store_const_value(v, globals, context.start_group, CodeArea::new())
                         ).collect()
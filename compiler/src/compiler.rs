@@ -15,6 +15,7 @@ use parser::ast;
 
 use crate::globals::Globals;
 use crate::leveldata::*;
+use crate::output_cache;
 use crate::value::*;
 use crate::value_storage::*;
 use crate::STD_PATH;
@@ -34,6 +35,7 @@ use crate::compiler_types::*;
 use ariadne::Color as TColor;
 use ariadne::Fmt;
 
+#[allow(clippy::too_many_arguments)]
 pub fn compile_spwn(
     statements: Vec<ast::Statement>,
     source: SpwnSource,
@@ -41,12 +43,21 @@ pub fn compile_spwn(
     notes: ParseNotes,
     permissions: BuiltinPermissions,
     initial_level: String,
+    output_cache: Option<PathBuf>,
+    seed: Option<u64>,
     std_out: &mut impl Write,
 ) -> Result<Globals, RuntimeError> {
     //variables that get changed throughout the compiling
 
     let mut globals = Globals::new(source.clone(), permissions, initial_level, std_out);
     globals.includes = included_paths;
+    globals.output_cache = output_cache;
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(seed) = seed {
+        globals.seed_rng(seed);
+    }
+    #[cfg(target_arch = "wasm32")]
+    let _ = seed;
 
     let print_with_color = |a: &str, color| println!("{}", a.fg(color));
 
@@ -62,6 +73,8 @@ pub fn compile_spwn(
     //     )));
     // }
     let mut start_context = FullContext::new(&globals);
+    // the pointer set in `FullContext::new` is left dangling when the context is moved out
+    start_context.inner().root_context_ptr = &mut start_context;
     //store at pos 0
     // store_value(Value::Builtins, 1, &mut globals, &start_context);
     // store_value(Value::Null, 1, &mut globals, &start_context);
@@ -72,6 +85,12 @@ pub fn compile_spwn(
             pos: (0, 0),
         })
     };
+    if let Some(ranges) = id_ranges(&notes.tag, start_info.position.file)? {
+        globals.id_ranges.insert(start_info.position.file, ranges);
+    }
+    if let Some(layout) = trigger_layout(&notes.tag, start_info.position.file)? {
+        globals.layout = layout;
+    }
     use std::time::Instant;
 
     //println!("Importing standard library...");
@@ -81,35 +100,7 @@ pub fn compile_spwn(
     let start_time = Instant::now();
 
     if !notes.tag.tags.iter().any(|x| x.0 == "no_std") {
-        import_module(
-            &ImportType::Lib(STD_PATH.to_string()),
-            &mut start_context,
-            &mut globals,
-            start_info.clone(),
-            false,
-        )?;
-
-        if let FullContext::Split(_, _) = start_context {
-            return Err(RuntimeError::CustomError(create_error(
-                start_info,
-                "The standard library can not split the context",
-                &[],
-                None,
-            )));
-        }
-
-        if let Value::Dict(d) = &globals.stored_values[start_context.inner().return_value] {
-            for (a, b, c) in d.iter().map(|(k, v)| (*k, *v, -1)) {
-                start_context.inner().new_redefinable_variable(a, b, c)
-            }
-        } else {
-            return Err(RuntimeError::CustomError(create_error(
-                start_info,
-                "The standard library must return a dictionary",
-                &[],
-                None,
-            )));
-        }
+        import_std(&mut start_context, &mut globals, start_info.clone())?;
     }
 
     compile_scope(&statements, &mut start_context, &mut globals, start_info)?;
@@ -203,7 +194,7 @@ pub fn compile_scope(
     statements: &[ast::Statement],
     contexts: &mut FullContext,
     globals: &mut Globals,
-    mut info: CompilerInfo,
+    info: CompilerInfo,
 ) -> Result<(), RuntimeError> {
     if contexts.iter().next().is_none() {
         return Ok(());
@@ -211,6 +202,22 @@ pub fn compile_scope(
     contexts.enter_scope();
     contexts.reset_return_vals(globals);
 
+    compile_statements(statements, contexts, globals, info)?;
+
+    // TODO: get rid of lifetimes
+
+    contexts.exit_scope();
+
+    Ok(())
+}
+
+// compiles statements without entering a new scope, so the variables they define are kept after
+pub fn compile_statements(
+    statements: &[ast::Statement],
+    contexts: &mut FullContext,
+    globals: &mut Globals,
+    mut info: CompilerInfo,
+) -> Result<(), RuntimeError> {
     for statement in statements.iter() {
         //find out what kind of statement this is
         //let start_time = Instant::now();
@@ -522,7 +529,7 @@ pub fn compile_scope(
                         GdObj {
                             params,
 
-                            ..context_trigger(context, &mut globals.uid_counter)
+                            ..context_trigger(context, &mut globals.uid_counter, &info)
                         }
                         .context_parameters(context),
                         TriggerOrder(globals.trigger_order),
@@ -693,7 +700,7 @@ pub fn compile_scope(
                         }
                         Value::Dict(d) => {
                             // its a dict!
-                            for (k, v) in d {
+                            for (k, v) in sorted_entries(&d) {
                                 // going through the dict items
                                 full_context.disable_breaks(BreakType::ContinueLoop);
 
@@ -985,7 +992,7 @@ pub fn compile_scope(
         }
 
         //try to merge contexts
-        merge_all_contexts(contexts, globals, false);
+        merge_all_contexts(contexts, globals, false, &info);
 
         if contexts.iter().next().is_none() {
             break;
@@ -1006,10 +1013,6 @@ pub fn compile_scope(
         }
     }
 
-    // TODO: get rid of lifetimes
-
-    contexts.exit_scope();
-
     Ok(())
 }
 
@@ -1240,6 +1243,7 @@ pub fn do_assignment(
                                 //pick a start group
                                 let start_group =
                                     Group::next_free(&mut globals.closed_groups);
+                                globals.id_origins[0].insert(globals.closed_groups, info.position);
                                 //store value
                                 globals.stored_values[storage] =
                                     Value::TriggerFunc(TriggerFunction { start_group });
@@ -1585,6 +1589,7 @@ pub fn merge_all_contexts(
     contexts: &mut FullContext,
     globals: &mut Globals,
     check_return_vals: bool,
+    info: &CompilerInfo,
 ) {
     if let FullContext::Split(_, _) = contexts {
         let mut broken = Vec::new();
@@ -1599,7 +1604,7 @@ pub fn merge_all_contexts(
 
         if not_broken.len() > 1 {
             loop {
-                if !merge_contexts(&mut not_broken, globals, check_return_vals) {
+                if !merge_contexts(&mut not_broken, globals, check_return_vals, info) {
                     break;
                 }
             }
@@ -1700,6 +1705,178 @@ pub fn get_import_path(
     })
 }
 
+// imports the standard library and defines its exports as variables in the context
+pub fn import_std(
+    start_context: &mut FullContext,
+    globals: &mut Globals,
+    info: CompilerInfo,
+) -> Result<(), RuntimeError> {
+    import_module(
+        &ImportType::Lib(STD_PATH.to_string()),
+        start_context,
+        globals,
+        info.clone(),
+        false,
+    )?;
+
+    if let FullContext::Split(_, _) = start_context {
+        return Err(RuntimeError::CustomError(create_error(
+            info,
+            "The standard library can not split the context",
+            &[],
+            None,
+        )));
+    }
+
+    if let Value::Dict(d) = &globals.stored_values[start_context.inner().return_value] {
+        for (a, b, c) in d.iter().map(|(k, v)| (*k, *v, -1)) {
+            start_context.inner().new_redefinable_variable(a, b, c)
+        }
+    } else {
+        return Err(RuntimeError::CustomError(create_error(
+            info,
+            "The standard library must return a dictionary",
+            &[],
+            None,
+        )));
+    }
+    Ok(())
+}
+
+// the ranges set with `#[id_range(group = 500..600, item = 10..=19)]` at the top of a file,
+// which the arbitrary IDs made in the file are given IDs from
+fn id_ranges(
+    tag: &ast::Attribute,
+    file: LocalIntern<SpwnSource>,
+) -> Result<Option<IdRanges>, RuntimeError> {
+    let args = match tag.get("id_range") {
+        Some(args) => args,
+        None => return Ok(None),
+    };
+    let mut ranges = IdRanges::default();
+    for arg in args {
+        let info = CompilerInfo::from_area(CodeArea { file, pos: arg.pos });
+        let error = |message: &str| {
+            RuntimeError::CustomError(create_error(info.clone(), message, &[], None))
+        };
+
+        let class = match arg.symbol {
+            Some(name) => id_class(&name).map_err(|e| error(&e))?,
+            None => {
+                return Err(error(
+                    "Expected an ID class and a range, like `group = 500..600`",
+                ))
+            }
+        };
+        // only number literals, since the ranges are read before the file runs
+        let number = |v: &ast::Variable| match &v.value.body {
+            ast::ValueBody::Number(n)
+                if v.operator.is_none()
+                    && v.path.is_empty()
+                    && n.fract() == 0.0
+                    && *n >= 1.0
+                    && *n <= SpecificId::MAX as f64 =>
+            {
+                Some(*n as SpecificId)
+            }
+            _ => None,
+        };
+        let range = match (&arg.value.values[..], &arg.value.operators[..]) {
+            ([start, end], [op @ (ast::Operator::Range | ast::Operator::InclRange)]) => {
+                match (number(start), number(end)) {
+                    (Some(start), Some(end)) if *op == ast::Operator::InclRange => {
+                        Some(start..=end)
+                    }
+                    (Some(start), Some(end)) if end > 1 => Some(start..=end - 1),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        match range {
+            Some(range) if !range.is_empty() => ranges.0[class] = Some(range),
+            _ => {
+                return Err(error(
+                    "Expected a range of IDs like `500..600` or `500..=599`, with IDs from 1 up",
+                ))
+            }
+        }
+    }
+    Ok(Some(ranges))
+}
+
+// the layout set with `#[layout(tree)]` or `#[layout(x = 3000, y = 300, w = 20)]`
+// (a grid in that region) at the top of the main file
+fn trigger_layout(
+    tag: &ast::Attribute,
+    file: LocalIntern<SpwnSource>,
+) -> Result<Option<TriggerLayout>, RuntimeError> {
+    let args = match tag.get("layout") {
+        Some(args) => args,
+        None => return Ok(None),
+    };
+    let mut name = None;
+    let (mut x, mut y, mut width) = (None, None, None);
+    for arg in &args {
+        let info = CompilerInfo::from_area(CodeArea { file, pos: arg.pos });
+        let error = |message: &str| {
+            RuntimeError::CustomError(create_error(info.clone(), message, &[], None))
+        };
+        // only literals, since the layout is read before the file runs
+        let value = match (&arg.value.values[..], &arg.value.operators[..]) {
+            ([v], []) if v.operator.is_none() && v.path.is_empty() => &v.value.body,
+            _ => &ast::ValueBody::Null,
+        };
+
+        match (arg.symbol, value) {
+            (None, ast::ValueBody::Symbol(layout))
+                if ["column", "grouped", "tree", "grid"].contains(&layout.as_str()) =>
+            {
+                name = Some(layout.as_str())
+            }
+            (None, _) => {
+                return Err(error(
+                    "Expected a layout (the layouts are column, grouped, tree and grid)",
+                ))
+            }
+            (Some(key), ast::ValueBody::Number(n)) if *n >= 0.0 => match key.as_str() {
+                "x" => x = Some(*n),
+                "y" => y = Some(*n),
+                "w" if n.fract() == 0.0 && *n >= 1.0 && *n <= u32::MAX as f64 => {
+                    width = Some(*n as u32)
+                }
+                "w" => return Err(error("Expected a width of one or more whole blocks")),
+                _ => return Err(error("Expected `x`, `y` or `w`")),
+            },
+            (Some(_), _) => return Err(error("Expected a positive number")),
+        }
+    }
+
+    let info = CompilerInfo::from_area(CodeArea {
+        file,
+        pos: args.first().map_or((0, 0), |a| a.pos),
+    });
+    let error =
+        |message: &str| RuntimeError::CustomError(create_error(info.clone(), message, &[], None));
+    let region = x.is_some() || y.is_some() || width.is_some();
+    let grid = || match (x, y, width) {
+        (Some(x), Some(y), Some(width)) => Ok(TriggerLayout::Grid { x, y, width }),
+        _ => Err(error(
+            "The grid layout needs a region, like `#[layout(x = 3000, y = 300, w = 20)]`",
+        )),
+    };
+    Ok(Some(match name {
+        // a region without a layout is a grid
+        Some("grid") => grid()?,
+        None if region => grid()?,
+        None => return Err(error("Expected a layout, like `#[layout(tree)]`")),
+        Some(_) if region => return Err(error("Only the grid layout takes a region")),
+        Some("grouped") => TriggerLayout::Grouped,
+        Some("tree") => TriggerLayout::Tree,
+        _ => TriggerLayout::Column,
+    }))
+}
+
 pub fn import_module(
     path: &ImportType,
     contexts: &mut FullContext,
@@ -1709,6 +1886,13 @@ pub fn import_module(
 ) -> Result<(), RuntimeError> {
     if !forced {
         if let Some(ret) = globals.prev_imports.get(path).cloned() {
+            if let Some(files) = globals.import_dependencies.get(path) {
+                globals.import_files.extend(files.iter().cloned());
+            }
+            // so a cached library importing it depends on the same things
+            if let Some(reads) = globals.import_outside_reads.get(path).copied() {
+                globals.outside_reads.add(reads);
+            }
             merge_impl(&mut globals.implementations, &ret.1);
             for c in contexts.iter() {
                 c.inner().return_value = ret.0;
@@ -1804,13 +1988,80 @@ pub fn import_module(
         }
     };
 
+    let source_hash = output_cache::hash_source(&unparsed);
     let (parsed, notes) =
         match parser::parser::parse_spwn(unparsed, module_path.clone(), BUILTIN_NAMES) {
             Ok(p) => p,
             Err(err) => return Err(RuntimeError::PackageSyntaxError { err, info }),
         };
 
+    // files that don't pin their own IDs share the range of the file that imported them,
+    // unless they are a library of their own
+    let module_file = LocalIntern::new(module_path.clone());
+    match id_ranges(&notes.tag, module_file)? {
+        Some(ranges) => {
+            globals.id_ranges.insert(module_file, ranges);
+        }
+        None => {
+            if let ImportType::Script(_) = path {
+                if let Some(ranges) = globals.id_ranges.get(&info.position.file).cloned() {
+                    globals.id_ranges.insert(module_file, ranges);
+                }
+            }
+        }
+    }
+
+    let save_value = notes.tag.tags.iter().any(|x| x.0 == "cache_output");
+    // only whole libraries are kept on disk, not the files they import
+    let disk_cache = save_value
+        && !forced
+        && matches!(path, ImportType::Lib(_))
+        && globals.output_cache.is_some();
+
+    let deps_start = globals.import_files.len();
+    let reads_start = globals.outside_reads;
+    if disk_cache {
+        let closed_before = [
+            globals.closed_groups,
+            globals.closed_colors,
+            globals.closed_blocks,
+            globals.closed_items,
+        ];
+        if let Some(ret) = output_cache::load(globals, &module_path) {
+            // the IDs made by the cached library belong to it, for its `#[id_range]`
+            let closed_after = [
+                globals.closed_groups,
+                globals.closed_colors,
+                globals.closed_blocks,
+                globals.closed_items,
+            ];
+            for class in 0..4 {
+                for id in closed_before[class] + 1..=closed_after[class] {
+                    globals.id_origins[class].entry(id).or_insert(CodeArea {
+                        file: module_file,
+                        pos: (0, 0),
+                    });
+                }
+            }
+            globals
+                .import_dependencies
+                .insert(path.clone(), globals.import_files[deps_start..].to_vec());
+            let reads = globals.outside_reads.since(reads_start);
+            globals.import_outside_reads.insert(path.clone(), reads);
+            merge_impl(&mut globals.implementations, &ret.1);
+            for c in contexts.iter() {
+                c.inner().return_value = ret.0;
+            }
+            globals.prev_imports.insert(path.clone(), ret);
+            return Ok(());
+        }
+    }
+    globals
+        .import_files
+        .push((module_path.clone(), source_hash));
+
     let mut start_context = FullContext::new(globals);
+    start_context.inner().root_context_ptr = &mut start_context;
 
     globals.push_new_preserved();
     for c in contexts.with_breaks() {
@@ -1838,41 +2089,15 @@ pub fn import_module(
         stored_impl = Some(stored);
     }
 
-    if !notes.tag.tags.iter().any(|x| x.0 == "no_std") {
-        import_module(
-            &ImportType::Lib(STD_PATH.to_string()),
-            &mut start_context,
-            globals,
-            info.clone(),
-            false,
-        )?;
+    let before = output_cache::Snapshot::take(globals);
 
-        if let FullContext::Split(_, _) = start_context {
-            return Err(RuntimeError::CustomError(create_error(
-                info,
-                "The standard library can not split the context",
-                &[],
-                None,
-            )));
-        }
-
-        if let Value::Dict(d) = &globals.stored_values[start_context.inner().return_value] {
-            for (a, b, c) in d.iter().map(|(k, v)| (*k, *v, -1)) {
-                start_context.inner().new_redefinable_variable(a, b, c)
-            }
-        } else {
-            return Err(RuntimeError::CustomError(create_error(
-                info,
-                "The standard library must return a dictionary",
-                &[],
-                None,
-            )));
-        }
+    if !notes.tag.tags.iter().any(|x| x.0 == "no_std") {
+        import_std(&mut start_context, globals, info.clone())?;
     }
 
     let stored_path = globals.path;
 
-    (*globals).path = LocalIntern::new(module_path);
+    (*globals).path = LocalIntern::new(module_path.clone());
 
     let mut new_info = info.clone();
 
@@ -1895,7 +2120,6 @@ pub fn import_module(
 
     globals.pop_preserved();
 
-    let save_value = notes.tag.tags.iter().any(|x| x.0 == "cache_output");
     let mut out_values = 0;
     let mut output_saved = None;
     let mut impl_saved = None;
@@ -1968,13 +2192,26 @@ pub fn import_module(
     }
 
     if save_value {
-        globals.prev_imports.insert(
-            path.clone(),
-            (
-                output_saved.unwrap_or(globals.NULL_STORAGE),
-                impl_saved.unwrap_or_default(),
-            ),
-        );
+        let output = output_saved.unwrap_or(globals.NULL_STORAGE);
+        let implementations = impl_saved.unwrap_or_default();
+        let files = globals.import_files[deps_start..].to_vec();
+        if disk_cache {
+            // libraries that can't be cached are just evaluated again next time
+            let _ = output_cache::save(
+                globals,
+                &module_path,
+                &before,
+                &files,
+                output,
+                &implementations,
+            );
+        }
+        globals.import_dependencies.insert(path.clone(), files);
+        let reads = globals.outside_reads.since(reads_start);
+        globals.import_outside_reads.insert(path.clone(), reads);
+        globals
+            .prev_imports
+            .insert(path.clone(), (output, implementations));
     }
 
     Ok(())
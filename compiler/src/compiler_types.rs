@@ -840,7 +840,9 @@ impl ToTriggerFunc for ast::CompoundStatement {
             let start_group = if let Some(g) = start_group {
                 g
             } else {
-                Group::next_free(&mut globals.closed_groups)
+                let group = Group::next_free(&mut globals.closed_groups);
+                globals.id_origins[0].insert(globals.closed_groups, info.position);
+                group
             };
 
             full_context.inner().next_fn_id(globals);
@@ -4,7 +4,7 @@ use crate::globals::Globals;
 use crate::leveldata::*;
 use crate::value::{strict_value_equality, Value};
 use crate::value_storage::{clone_value, store_val_m};
-use errors::compiler_info::CodeArea;
+use errors::compiler_info::{CodeArea, CompilerInfo};
 
 //use std::boxed::Box;
 use ahash::AHashMap;
@@ -386,6 +386,7 @@ pub fn merge_contexts(
     contexts: &mut Vec<Context>,
     globals: &mut Globals,
     check_return_vals: bool,
+    info: &CompilerInfo,
 ) -> bool {
     let mut mergable_ind = Vec::<usize>::new();
     let mut ref_c = 0;
@@ -458,7 +459,7 @@ pub fn merge_contexts(
             GdObj {
                 params,
 
-                ..context_trigger(context, &mut globals.uid_counter)
+                ..context_trigger(context, &mut globals.uid_counter, info)
             }
             .context_parameters(context),
             TriggerOrder(globals.trigger_order),
@@ -12,9 +12,11 @@ use crate::context::VariableData;
 use errors::compiler_info::CodeArea;
 
 use crate::context::FullContext;
-use crate::leveldata::GdObj;
+use crate::level_codec::LevelSettings;
+use crate::leveldata::{GdObj, IdKeys, IdRanges, PinnedIds, TriggerLayout};
 
 use crate::compiler_types::*;
+use crate::output_cache::{Dependency, OutsideReads};
 use crate::value::*;
 
 use ahash::AHashMap;
@@ -33,6 +35,16 @@ pub struct Globals<'a> {
     pub closed_colors: u16,
     pub closed_blocks: u16,
     pub closed_items: u16,
+    // where in the source each arbitrary group, color, block and item ID was made (for the ID report)
+    pub id_origins: [AHashMap<ArbitraryId, CodeArea>; 4],
+    // the arbitrary IDs made with a name, like `?g("door")`
+    pub id_names: [AHashMap<LocalIntern<String>, ArbitraryId>; 4],
+    // the files that pinned their arbitrary IDs to a range with `#[id_range]`
+    pub id_ranges: AHashMap<LocalIntern<SpwnSource>, IdRanges>,
+    // where the triggers go in the editor, set with `#[layout]` in the main file
+    pub layout: TriggerLayout,
+    // the level settings changed with `$.set_level_header`, which are written to the header
+    pub level_settings: LevelSettings,
 
     pub path: LocalIntern<SpwnSource>,
 
@@ -50,6 +62,14 @@ pub struct Globals<'a> {
     pub initial_objects: Option<StoredValue>,
 
     pub prev_imports: AHashMap<ImportType, (StoredValue, Implementations)>,
+    // where outputs of `#[cache_output]` libraries are kept between builds (see `output_cache`)
+    pub output_cache: Option<PathBuf>,
+    // every file read by imports so far, and the ones each cached import depended on
+    pub import_files: Vec<Dependency>,
+    pub import_dependencies: AHashMap<ImportType, Vec<Dependency>>,
+    // calls to builtins that depend on more than the scripts, and the ones each cached import made
+    pub outside_reads: OutsideReads,
+    pub import_outside_reads: AHashMap<ImportType, OutsideReads>,
 
     pub trigger_order: f64,
 
@@ -70,6 +90,9 @@ pub struct Globals<'a> {
     pub OBJ_KEY_PATTERN: LocalIntern<String>,
     // the path to a potential executable built-in path
     pub built_in_path: Option<PathBuf>,
+    // used by `$.random`, seeded with `--seed` so builds that use it can be repeated
+    #[cfg(not(target_arch = "wasm32"))]
+    pub rng: rand::rngs::StdRng,
     pub std_out: &'a mut dyn Write,
 
     pub BUILTIN_STORAGE: StoredValue,
@@ -235,6 +258,11 @@ Arrays are used to store lists of values at compile time.
             closed_colors: 0,
             closed_blocks: 0,
             closed_items: 0,
+            id_origins: Default::default(),
+            id_names: Default::default(),
+            id_ranges: AHashMap::default(),
+            layout: TriggerLayout::default(),
+            level_settings: LevelSettings::default(),
             path: LocalIntern::new(path),
 
             lowest_y: AHashMap::default(),
@@ -242,6 +270,11 @@ Arrays are used to store lists of values at compile time.
             type_ids: AHashMap::default(),
 
             prev_imports: AHashMap::default(),
+            output_cache: None,
+            import_files: Vec::new(),
+            import_dependencies: AHashMap::default(),
+            outside_reads: OutsideReads::default(),
+            import_outside_reads: AHashMap::default(),
             type_id_count: 0,
             trigger_order: 0.0,
             uid_counter: 0,
@@ -273,6 +306,8 @@ Arrays are used to store lists of values at compile time.
             OBJ_KEY_ID: LocalIntern::new(String::from("id")),
             OBJ_KEY_PATTERN: LocalIntern::new(String::from("pattern")),
             built_in_path: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            rng: rand::SeedableRng::from_entropy(),
             std_out,
             type_descriptions,
             initial_objects: None,
@@ -350,6 +385,57 @@ Arrays are used to store lists of values at compile time.
     //         .map
     //         .retain(|a, _| -> bool { !removed.contains(a) });
     // }
+    /// Makes `$.random` give the same numbers every time (for `--seed`)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = rand::SeedableRng::seed_from_u64(seed);
+    }
+
+    /// The ranges the arbitrary IDs made in files with an `#[id_range]` have to end up in
+    pub fn pinned_ids(&self) -> PinnedIds {
+        let mut pinned = PinnedIds::default();
+        for ranges in self.id_ranges.values() {
+            for (class, range) in ranges.0.iter().enumerate() {
+                if let Some(range) = range {
+                    if !pinned.reserved[class].contains(range) {
+                        pinned.reserved[class].push(range.clone());
+                    }
+                }
+            }
+        }
+        for (class, origins) in self.id_origins.iter().enumerate() {
+            for (id, area) in origins {
+                let range = match self.id_ranges.get(&area.file) {
+                    Some(IdRanges(ranges)) => ranges[class].clone(),
+                    None => None,
+                };
+                if let Some(range) = range {
+                    let module = match &*area.file {
+                        SpwnSource::File(path) | SpwnSource::BuiltIn(path) => {
+                            path.display().to_string()
+                        }
+                        SpwnSource::String(_) => String::from("the script"),
+                    };
+                    pinned.ids[class].insert(*id, (module, range));
+                }
+            }
+        }
+        pinned
+    }
+
+    /// The names of the named arbitrary IDs, as keys for an `IdMap`
+    pub fn id_name_keys(&self) -> IdKeys {
+        let mut keys = IdKeys::default();
+        for (class, names) in self.id_names.iter().enumerate() {
+            keys[class] = names
+                .iter()
+                .map(|(name, id)| (*id, name.to_string()))
+                .collect();
+            keys[class].sort_unstable();
+        }
+        keys
+    }
+
     pub fn push_new_preserved(&mut self) {
         self.stored_values.preserved_stack.push(Vec::new());
     }
@@ -0,0 +1,618 @@
+//! Reading and writing level strings
+//!
+//! A level string is the level's settings (the header) followed by its objects, each ending with
+//! a `;`, like `kS38,<colors>,kA13,0,...;1,1,2,15,3,15;1,914,2,45,3,15,31,SGk=;`. Objects are
+//! comma separated keys and values, where the keys are numbers. The header is the same, with keys
+//! like `kA13`, and the color channels in its `kS38` value are separated by `|`, with their keys and
+//! values separated by `_`.
+//!
+//! Everything is kept as the text it was read as and in the same order, so properties SPWN doesn't
+//! know about are written back unchanged. Values are only parsed when they're asked for, and a value
+//! that doesn't parse is an error with its byte offset in the level string instead of a panic.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// What is wrong with a level string
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LevelErrorKind {
+    /// a key of an object or color channel that isn't a number
+    InvalidKey(String),
+    /// a key at the end of an object without a value after it
+    MissingValue(String),
+    /// a value that isn't what its key needs, like text in a group list
+    InvalidValue {
+        key: String,
+        value: String,
+        expected: &'static str,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LevelError {
+    pub kind: LevelErrorKind,
+    /// the byte in the level string the error is at
+    pub offset: usize,
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            LevelErrorKind::InvalidKey(key) => {
+                write!(f, "Expected a number as key, found `{}`", key)?
+            }
+            LevelErrorKind::MissingValue(key) => write!(f, "Key {} has no value", key)?,
+            LevelErrorKind::InvalidValue {
+                key,
+                value,
+                expected,
+            } => write!(
+                f,
+                "Expected {} for key {}, found `{}`",
+                expected, key, value
+            )?,
+        }
+        write!(f, " (at byte {} of the level string)", self.offset)
+    }
+}
+
+impl std::error::Error for LevelError {}
+
+/// A key and its value, as they were in the level string
+#[derive(Debug, Clone, Eq)]
+pub struct Prop<K> {
+    pub key: K,
+    pub value: String,
+    /// where the value starts in the level string (0 if it wasn't read from one)
+    pub offset: usize,
+}
+
+// where a property was read from doesn't matter when comparing it
+impl<K: PartialEq> PartialEq for Prop<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key && self.value == other.value
+    }
+}
+
+impl<K: fmt::Display> Prop<K> {
+    pub fn new(key: K, value: impl ToString) -> Self {
+        Prop {
+            key,
+            value: value.to_string(),
+            offset: 0,
+        }
+    }
+
+    fn error(&self, expected: &'static str) -> LevelError {
+        LevelError {
+            kind: LevelErrorKind::InvalidValue {
+                key: self.key.to_string(),
+                value: self.value.clone(),
+                expected,
+            },
+            offset: self.offset,
+        }
+    }
+
+    pub fn number(&self) -> Result<f64, LevelError> {
+        match self.value.trim().parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(n),
+            _ => Err(self.error("a number")),
+        }
+    }
+
+    pub fn bool(&self) -> bool {
+        self.value.trim() == "1"
+    }
+
+    /// A group, color, block or item ID (GD sometimes writes them like `5.0`)
+    pub fn id(&self) -> Result<u16, LevelError> {
+        parse_id(&self.value).ok_or_else(|| self.error("an ID"))
+    }
+
+    /// A list of IDs separated by `.`, like the groups of an object
+    pub fn ids(&self) -> Result<Vec<u16>, LevelError> {
+        self.value
+            .split('.')
+            .filter(|id| !id.trim().is_empty())
+            .map(|id| id.trim().parse::<u16>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| self.error("a list of IDs separated by `.`"))
+    }
+}
+
+fn parse_id(value: &str) -> Option<u16> {
+    let value = value.trim();
+    match value.parse::<u16>() {
+        Ok(id) => Some(id),
+        Err(_) => match value.parse::<f64>() {
+            Ok(n) if n.fract() == 0.0 && (0.0..=u16::MAX as f64).contains(&n) => Some(n as u16),
+            _ => None,
+        },
+    }
+}
+
+// splits `text` (which starts at `start` in the level string) into keys and values,
+// where a separator at the end is left out
+fn pairs(text: &str, separator: char, start: usize) -> Result<Vec<Prop<&str>>, LevelError> {
+    let mut tokens = Vec::new();
+    let mut offset = start;
+    for token in text.split(separator) {
+        tokens.push((token, offset));
+        offset += token.len() + separator.len_utf8();
+    }
+    if tokens.len() % 2 == 1 && tokens.last().map(|(t, _)| t.is_empty()) == Some(true) {
+        tokens.pop();
+    }
+    tokens
+        .chunks(2)
+        .map(|pair| match pair {
+            [(key, _), (value, offset)] => Ok(Prop {
+                key: *key,
+                value: value.to_string(),
+                offset: *offset,
+            }),
+            [(key, offset)] => Err(LevelError {
+                kind: LevelErrorKind::MissingValue(key.to_string()),
+                offset: *offset,
+            }),
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+// like `pairs`, for the keys that are numbers
+fn numbered_pairs(text: &str, separator: char, start: usize) -> Result<Vec<Prop<u16>>, LevelError> {
+    pairs(text, separator, start)?
+        .into_iter()
+        .map(|prop| match prop.key.trim().parse::<u16>() {
+            Ok(key) => Ok(Prop {
+                key,
+                value: prop.value,
+                offset: prop.offset,
+            }),
+            Err(_) => Err(LevelError {
+                kind: LevelErrorKind::InvalidKey(prop.key.to_string()),
+                // the key comes right before the value
+                offset: prop.offset - prop.key.len() - separator.len_utf8(),
+            }),
+        })
+        .collect()
+}
+
+fn write_props<K: fmt::Display>(
+    f: &mut fmt::Formatter<'_>,
+    props: &[Prop<K>],
+    separator: char,
+) -> fmt::Result {
+    for (i, prop) in props.iter().enumerate() {
+        if i > 0 {
+            write!(f, "{}", separator)?;
+        }
+        write!(f, "{}{}{}", prop.key, separator, prop.value)?;
+    }
+    Ok(())
+}
+
+/// An object, with its properties in the order they were in
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LevelObject {
+    pub props: Vec<Prop<u16>>,
+}
+
+impl LevelObject {
+    /// Reads an object (without the `;` after it) that starts at `start` in the level string
+    pub fn parse(text: &str, start: usize) -> Result<Self, LevelError> {
+        Ok(LevelObject {
+            props: numbered_pairs(text, ',', start)?,
+        })
+    }
+
+    pub fn get(&self, key: u16) -> Option<&Prop<u16>> {
+        self.props.iter().find(|p| p.key == key)
+    }
+
+    /// Changes the value of a key, or adds it at the end
+    pub fn set(&mut self, key: u16, value: impl ToString) {
+        match self.props.iter_mut().find(|p| p.key == key) {
+            Some(prop) => prop.value = value.to_string(),
+            None => self.props.push(Prop::new(key, value)),
+        }
+    }
+
+    /// The object ID (key 1), if it has one
+    pub fn object_id(&self) -> Result<Option<u16>, LevelError> {
+        self.get(1).map(Prop::id).transpose()
+    }
+
+    /// The groups of the object (key 57)
+    pub fn groups(&self) -> Result<Vec<u16>, LevelError> {
+        Ok(self.get(57).map(Prop::ids).transpose()?.unwrap_or_default())
+    }
+
+    /// The keys and values sorted by key, so objects that only differ in the order of their
+    /// properties can be compared
+    pub fn sorted(&self) -> Vec<(u16, &str)> {
+        let mut props = self
+            .props
+            .iter()
+            .map(|p| (p.key, p.value.as_str()))
+            .collect::<Vec<_>>();
+        props.sort_unstable();
+        props
+    }
+}
+
+impl fmt::Display for LevelObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_props(f, &self.props, ',')
+    }
+}
+
+/// A color channel of the level, from the `kS38` key of the header
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ColorChannel {
+    pub props: Vec<Prop<u16>>,
+}
+
+impl ColorChannel {
+    pub fn get(&self, key: u16) -> Option<&Prop<u16>> {
+        self.props.iter().find(|p| p.key == key)
+    }
+
+    pub fn set(&mut self, key: u16, value: impl ToString) {
+        match self.props.iter_mut().find(|p| p.key == key) {
+            Some(prop) => prop.value = value.to_string(),
+            None => self.props.push(Prop::new(key, value)),
+        }
+    }
+}
+
+impl fmt::Display for ColorChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_props(f, &self.props, '_')
+    }
+}
+
+/// The settings of the level, which come before the objects
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LevelHeader {
+    pub props: Vec<Prop<String>>,
+}
+
+// the header key the color channels are kept in
+const COLORS_KEY: &str = "kS38";
+
+impl LevelHeader {
+    pub fn parse(text: &str, start: usize) -> Result<Self, LevelError> {
+        Ok(LevelHeader {
+            props: pairs(text, ',', start)?
+                .into_iter()
+                .map(|p| Prop {
+                    key: p.key.to_string(),
+                    value: p.value,
+                    offset: p.offset,
+                })
+                .collect(),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Prop<String>> {
+        self.props.iter().find(|p| p.key == key)
+    }
+
+    pub fn set(&mut self, key: &str, value: impl ToString) {
+        match self.props.iter_mut().find(|p| p.key == key) {
+            Some(prop) => prop.value = value.to_string(),
+            None => self.props.push(Prop::new(key.to_string(), value)),
+        }
+    }
+
+    pub fn colors(&self) -> Result<Vec<ColorChannel>, LevelError> {
+        let prop = match self.get(COLORS_KEY) {
+            Some(prop) => prop,
+            None => return Ok(Vec::new()),
+        };
+        let mut offset = prop.offset;
+        let mut channels = Vec::new();
+        for channel in prop.value.split('|') {
+            if !channel.is_empty() {
+                channels.push(ColorChannel {
+                    props: numbered_pairs(channel, '_', offset)?,
+                });
+            }
+            offset += channel.len() + 1;
+        }
+        Ok(channels)
+    }
+
+    pub fn set_colors(&mut self, channels: &[ColorChannel]) {
+        let value = channels
+            .iter()
+            .map(|c| format!("{}|", c))
+            .collect::<String>();
+        self.set(COLORS_KEY, value);
+    }
+}
+
+impl fmt::Display for LevelHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_props(f, &self.props, ',')
+    }
+}
+
+/// A whole level string. Levels SPWN wrote without a level to add to don't have a header
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LevelString {
+    pub header: Option<LevelHeader>,
+    pub objects: Vec<LevelObject>,
+}
+
+// the header is the only part with keys that aren't numbers
+fn is_header(part: &str) -> bool {
+    let first_key = part.split(',').next().unwrap_or_default().trim();
+    !first_key.bytes().all(|b| b.is_ascii_digit())
+}
+
+impl LevelString {
+    /// Only reads the header, without the objects
+    pub fn parse_header(text: &str) -> Result<Option<LevelHeader>, LevelError> {
+        let part = text.split(';').next().unwrap_or_default();
+        if part.trim().is_empty() || !is_header(part) {
+            return Ok(None);
+        }
+        LevelHeader::parse(part, 0).map(Some)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, LevelError> {
+        let mut level = LevelString::default();
+        let mut offset = 0;
+        for (i, part) in text.split(';').enumerate() {
+            let start = offset;
+            offset += part.len() + 1;
+            // the `;` at the end, and objects SPWN removed before
+            if part.trim().is_empty() {
+                continue;
+            }
+            if i == 0 && is_header(part) {
+                level.header = Some(LevelHeader::parse(part, start)?);
+            } else {
+                level.objects.push(LevelObject::parse(part, start)?);
+            }
+        }
+        Ok(level)
+    }
+}
+
+impl fmt::Display for LevelString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(header) = &self.header {
+            write!(f, "{};", header)?;
+        }
+        for obj in &self.objects {
+            write!(f, "{};", obj)?;
+        }
+        Ok(())
+    }
+}
+
+/// The kind of value a level setting has
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingKind {
+    Bool,
+    Number,
+    Text,
+    /// one of the names, kept in the header as its index
+    Choice(&'static [&'static str]),
+}
+
+/// The level settings SPWN can read and change: the name scripts use, the key in the header and
+/// the kind of value
+pub const LEVEL_SETTINGS: [(&str, &str, SettingKind); 14] = [
+    (
+        "gamemode",
+        "kA2",
+        SettingKind::Choice(&["cube", "ship", "ball", "ufo", "wave", "robot", "spider"]),
+    ),
+    ("mini", "kA3", SettingKind::Bool),
+    (
+        "speed",
+        "kA4",
+        SettingKind::Choice(&["normal", "slow", "fast", "faster", "fastest"]),
+    ),
+    ("dual", "kA8", SettingKind::Bool),
+    ("two_player", "kA10", SettingKind::Bool),
+    ("flip_gravity", "kA11", SettingKind::Bool),
+    ("song_offset", "kA13", SettingKind::Number),
+    ("guidelines", "kA14", SettingKind::Text),
+    ("fade_in", "kA15", SettingKind::Bool),
+    ("fade_out", "kA16", SettingKind::Bool),
+    ("background", "kA6", SettingKind::Number),
+    ("ground", "kA7", SettingKind::Number),
+    ("ground_line", "kA17", SettingKind::Number),
+    ("font", "kA18", SettingKind::Number),
+];
+
+/// The value of a level setting (choices are their name)
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingValue {
+    Bool(bool),
+    Number(f64),
+    Text(String),
+}
+
+/// The settings of a color channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorSettings {
+    pub channel: u16,
+    pub rgb: [u8; 3],
+    pub opacity: f64,
+    pub blending: bool,
+    /// 1 or 2 to use the color of that player instead of `rgb`
+    pub player_color: Option<u8>,
+    /// the channel this one copies
+    pub copy: Option<u16>,
+}
+
+impl ColorSettings {
+    /// A white channel, like the ones GD makes
+    pub fn new(channel: u16) -> Self {
+        ColorSettings {
+            channel,
+            rgb: [255; 3],
+            opacity: 1.0,
+            blending: false,
+            player_color: None,
+            copy: None,
+        }
+    }
+
+    pub fn from_channel(channel: &ColorChannel) -> Result<Option<Self>, LevelError> {
+        let id = match channel.get(6) {
+            Some(prop) => prop.id()?,
+            None => return Ok(None),
+        };
+        let mut settings = ColorSettings::new(id);
+        for (i, key) in [1, 2, 3].into_iter().enumerate() {
+            if let Some(prop) = channel.get(key) {
+                settings.rgb[i] = prop.number()?.clamp(0.0, 255.0) as u8;
+            }
+        }
+        if let Some(prop) = channel.get(7) {
+            settings.opacity = prop.number()?;
+        }
+        settings.blending = channel.get(5).is_some_and(|p| p.bool());
+        if let Some(prop) = channel.get(4) {
+            let player = prop.number()?;
+            if player == 1.0 || player == 2.0 {
+                settings.player_color = Some(player as u8);
+            }
+        }
+        if let Some(prop) = channel.get(9) {
+            settings.copy = Some(prop.id()?).filter(|c| *c != 0);
+        }
+        Ok(Some(settings))
+    }
+
+    // changes the channel to these settings, keeping the keys SPWN doesn't know about
+    fn write(&self, channel: &mut ColorChannel) {
+        for (key, value) in [1, 2, 3].into_iter().zip(self.rgb) {
+            channel.set(key, value);
+        }
+        channel.set(4, self.player_color.map_or(-1, |p| p as i8));
+        channel.set(5, self.blending as u8);
+        channel.set(6, self.channel);
+        channel.set(7, self.opacity);
+        match self.copy {
+            Some(copy) => channel.set(9, copy),
+            None => channel.props.retain(|p| p.key != 9),
+        }
+    }
+}
+
+/// The level settings and color channels of a header. A setting that isn't in `values` isn't in
+/// the header (or isn't changed when the settings are applied)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LevelSettings {
+    /// by their name in `LEVEL_SETTINGS`
+    pub values: BTreeMap<&'static str, SettingValue>,
+    pub colors: Vec<ColorSettings>,
+}
+
+impl LevelSettings {
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty() && self.colors.is_empty()
+    }
+
+    /// Adds the settings of `other`, which replace the ones with the same name or channel
+    pub fn merge(&mut self, other: LevelSettings) {
+        self.values.extend(other.values);
+        for color in other.colors {
+            match self.colors.iter_mut().find(|c| c.channel == color.channel) {
+                Some(old) => *old = color,
+                None => self.colors.push(color),
+            }
+        }
+    }
+}
+
+impl LevelHeader {
+    pub fn settings(&self) -> Result<LevelSettings, LevelError> {
+        let mut settings = LevelSettings::default();
+        for (name, key, kind) in LEVEL_SETTINGS {
+            let prop = match self.get(key) {
+                Some(prop) => prop,
+                None => continue,
+            };
+            let value = match kind {
+                SettingKind::Bool => SettingValue::Bool(prop.bool()),
+                SettingKind::Number => SettingValue::Number(prop.number()?),
+                SettingKind::Text => SettingValue::Text(prop.value.clone()),
+                SettingKind::Choice(names) => {
+                    match prop.id().ok().and_then(|i| names.get(i as usize)) {
+                        Some(name) => SettingValue::Text(name.to_string()),
+                        None => return Err(prop.error("one of the options of the setting")),
+                    }
+                }
+            };
+            settings.values.insert(name, value);
+        }
+        for channel in self.colors()? {
+            settings
+                .colors
+                .extend(ColorSettings::from_channel(&channel)?);
+        }
+        Ok(settings)
+    }
+
+    /// Writes the settings into the header, leaving out values that don't fit their setting
+    pub fn apply_settings(&mut self, settings: &LevelSettings) -> Result<(), LevelError> {
+        for (name, key, kind) in LEVEL_SETTINGS {
+            let value = match (kind, settings.values.get(name)) {
+                (_, None) => continue,
+                (SettingKind::Bool, Some(SettingValue::Bool(b))) => (*b as u8).to_string(),
+                (SettingKind::Number, Some(SettingValue::Number(n))) => n.to_string(),
+                (SettingKind::Text, Some(SettingValue::Text(t))) => t.clone(),
+                (SettingKind::Choice(names), Some(SettingValue::Text(t))) => {
+                    match names.iter().position(|n| n == t) {
+                        Some(i) => i.to_string(),
+                        None => continue,
+                    }
+                }
+                _ => continue,
+            };
+            self.set(key, value);
+        }
+
+        if !settings.colors.is_empty() {
+            let mut channels = self.colors()?;
+            for color in &settings.colors {
+                let mut found = None;
+                for (i, channel) in channels.iter().enumerate() {
+                    if ColorSettings::from_channel(channel)?
+                        .is_some_and(|c| c.channel == color.channel)
+                    {
+                        found = Some(i);
+                        break;
+                    }
+                }
+                match found {
+                    Some(i) => color.write(&mut channels[i]),
+                    None => {
+                        let mut channel = ColorChannel::default();
+                        color.write(&mut channel);
+                        // the rest of the keys GD gives a new channel
+                        for (key, value) in
+                            [(8, 1), (11, 255), (12, 255), (13, 255), (15, 1), (18, 0)]
+                        {
+                            channel.set(key, value);
+                        }
+                        channels.push(channel);
+                    }
+                }
+            }
+            self.set_colors(&channels);
+        }
+        Ok(())
+    }
+}
@@ -3,9 +3,17 @@
 use crate::builtins::*;
 use crate::compiler_types::FunctionId;
 use crate::context::Context;
+use crate::level_codec::{LevelError, LevelObject, LevelString, Prop};
+use crate::obj_schema;
 use ahash::{AHashMap, AHashSet};
+use errors::compiler_info::{CodeArea, CompilerInfo};
+use internment::LocalIntern;
 use parser::ast::ObjectMode;
+use shared::SpwnSource;
+use std::collections::BTreeMap;
+use std::fmt::Write;
 use std::hash::Hash;
+use std::ops::RangeInclusive;
 
 pub struct TriggerOrder(f32);
 
@@ -118,6 +126,8 @@ pub struct GdObj {
     pub params: AHashMap<u16, ObjParam>,
     pub mode: ObjectMode,
     pub unique_id: usize,
+    // where in the source the object was added, for source maps
+    pub origin: Option<CompilerInfo>,
 }
 
 impl GdObj {
@@ -128,76 +138,32 @@ impl GdObj {
     }
 }
 
-pub fn get_used_ids(ls: &str) -> [AHashSet<u16>; 4] {
-    let mut out = [
-        AHashSet::<u16>::default(),
-        AHashSet::<u16>::default(),
-        AHashSet::<u16>::default(),
-        AHashSet::<u16>::default(),
-    ];
-    let objects = ls.split(';');
-    for obj in objects {
-        let props: Vec<&str> = obj.split(',').collect();
-        let mut map = AHashMap::default();
-
-        for i in (0..props.len() - 1).step_by(2) {
-            map.insert(props[i], props[i + 1]);
-        }
-
-        for (key, value) in &map {
-            match *key {
-                "57" => {
-                    //GROUPS
-                    let groups = value.split('.');
-                    for g in groups {
-                        let group = g.parse().unwrap();
-
-                        out[0].insert(group);
-                    }
-                }
-                "51" => {
-                    match (map.get("1"), map.get("52")) {
-                        (Some(&"1006"), Some(&"1")) => out[0].insert(value.parse().unwrap()),
-                        (Some(&"1006"), _) => out[1].insert(value.parse().unwrap()),
-                        _ => out[0].insert(value.parse().unwrap()),
-                    };
-                }
-                "71" => {
-                    out[0].insert(value.parse().unwrap());
-                }
-                //colors
-                "21" => {
-                    out[1].insert(value.parse().unwrap());
-                }
-                "22" => {
-                    out[1].insert(value.parse().unwrap());
-                }
-                "23" => {
-                    out[1].insert(value.parse().unwrap());
-                }
-
-                "80" => {
-                    match map.get("1") {
-                        //if collision trigger or block, add block id
-                        Some(&"1815") | Some(&"1816") => out[2].insert(value.parse().unwrap()),
-                        //counter display => do nothing
-                        Some(&"1615") => false,
-                        // else add item id
-                        _ => out[3].insert(value.parse().unwrap()),
-                    };
-                }
-
-                "95" => {
-                    out[2].insert(value.parse().unwrap());
+pub fn get_used_ids(level: &LevelString) -> Result<[AHashSet<u16>; 4], LevelError> {
+    let mut out: [AHashSet<u16>; 4] = Default::default();
+    for obj in &level.objects {
+        let object_id = obj.object_id()?;
+        let flag = |key| obj.get(key).is_some_and(Prop::bool);
+        for prop in &obj.props {
+            let kind = match obj_schema::prop_type(object_id, prop.key, flag) {
+                Some(kind) => kind,
+                None => continue,
+            };
+            let class = match kind.id_class() {
+                Some(class) => class,
+                None => continue,
+            };
+            if object_id.is_some_and(|id| obj_schema::ID_DISPLAYS.contains(&(id, prop.key))) {
+                continue;
+            }
+            match kind {
+                obj_schema::PropType::Groups => out[class].extend(prop.ids()?),
+                _ => {
+                    out[class].insert(prop.id()?);
                 }
-                //some of these depends on what object it is
-                //pulse target depends on group mode/color mode
-                //figure this out, future me
-                _ => (),
             }
         }
     }
-    out
+    Ok(out)
 }
 
 const START_HEIGHT: u16 = 10;
@@ -210,37 +176,321 @@ pub const SPWN_SIGNATURE_GROUP: Group = Group {
 };
 //use crate::ast::ObjectMode;
 
-pub fn remove_spwn_objects(file_content: &mut String) {
+pub fn remove_spwn_objects(level: &mut LevelString) {
+    //remove previous spwn objects
+    level.objects.retain(|obj| !is_spwn_object(obj));
+}
+
+fn is_spwn_object(obj: &LevelObject) -> bool {
     let spwn_group = match SPWN_SIGNATURE_GROUP.id {
-        Id::Specific(n) => n.to_string(),
+        Id::Specific(n) => n,
         _ => unreachable!(),
     };
-    (*file_content) = file_content
-        //remove previous spwn objects
-        .split(';')
-        .map(|obj| {
-            let key_val: Vec<&str> = obj.split(',').collect();
-            let mut ret = obj;
-            for i in (0..key_val.len()).step_by(2) {
-                if key_val[i] == "57" {
-                    let mut groups = key_val[i + 1].split('.');
-                    if groups.any(|x| x == spwn_group) {
-                        ret = "";
-                    }
+    // objects with groups that can't be read aren't from SPWN
+    obj.groups()
+        .is_ok_and(|groups| groups.contains(&spwn_group))
+}
+
+/// Compares the objects a build made with the ones the last build left in the level, and returns
+/// the level without the old objects that aren't made anymore, the indexes of the new objects that
+/// aren't in the level yet, and how many objects were removed
+pub fn update_spwn_objects(
+    level: &LevelString,
+    new_objects: &LevelString,
+) -> (LevelString, Vec<usize>, usize) {
+    let mut new = AHashMap::<Vec<(u16, &str)>, usize>::default();
+    for obj in &new_objects.objects {
+        *new.entry(obj.sorted()).or_default() += 1;
+    }
+
+    let mut kept = LevelString {
+        header: level.header.clone(),
+        objects: Vec::new(),
+    };
+    let mut removed = 0;
+    for obj in &level.objects {
+        if is_spwn_object(obj) {
+            match new.get_mut(&obj.sorted()) {
+                Some(count) if *count > 0 => *count -= 1,
+                _ => {
+                    removed += 1;
+                    continue;
                 }
             }
-            ret
-        })
-        .collect::<Vec<&str>>()
-        .join(";");
+        }
+        kept.objects.push(obj.clone());
+    }
+
+    let mut added = Vec::new();
+    for (i, obj) in new_objects.objects.iter().enumerate() {
+        match new.get_mut(&obj.sorted()) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                added.push(i);
+            }
+            _ => (),
+        }
+    }
+    (kept, added, removed)
+}
+
+// the text in the object the ID map is kept in starts with this
+const ID_MAP_HEADER: &str = "SPWN ID map";
+// the map is kept in a text object
+const ID_MAP_OBJECT: u16 = 914;
+const ID_MAP_CLASSES: [&str; 4] = ["group", "color", "block", "item"];
+
+/// A key for each arbitrary ID of every class, which stays the same between builds
+pub type IdKeys = [Vec<(ArbitraryId, String)>; 4];
+
+/// The specific IDs a build gave the arbitrary IDs, by a key that stays the same between builds
+/// (like the line that made the ID, or its name). The next build can then give the arbitrary IDs
+/// with the same keys the same IDs again
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdMap(pub [BTreeMap<String, SpecificId>; 4]);
+
+impl IdMap {
+    pub fn new(keys: &IdKeys, report: &IdReport) -> Self {
+        let mut map = IdMap::default();
+        for (class, keys) in keys.iter().enumerate() {
+            for (arbitrary, key) in keys {
+                let assigned = &report.assigned[class];
+                // IDs the optimizer removed were never given out
+                if let Ok(i) = assigned.binary_search_by_key(arbitrary, |(a, _)| *a) {
+                    map.0[class].insert(key.clone(), assigned[i].1);
+                }
+            }
+        }
+        map
+    }
+
+    /// Reads a map written by `to_text`, where lines starting with `#` are comments
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut map = IdMap::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (class, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let (id, key) = rest
+                .trim_start()
+                .split_once(char::is_whitespace)
+                .unwrap_or_default();
+            match (id_class(class), id.parse(), key.trim()) {
+                (Ok(class), Ok(id), key) if !key.is_empty() => {
+                    map.0[class].insert(key.to_string(), id);
+                }
+                _ => {
+                    return Err(format!(
+                        "Invalid line {}: `{}` (expected a class, an ID and a name, like `group 5 door`)",
+                        i + 1,
+                        line
+                    ))
+                }
+            }
+        }
+        Ok(map)
+    }
+
+    /// One line for every ID: the class, the specific ID and the key, lined up in columns
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (class, map) in self.0.iter().enumerate() {
+            for (key, id) in map {
+                writeln!(text, "{:<5} {:>5}  {}", ID_MAP_CLASSES[class], id, key).unwrap();
+            }
+        }
+        text
+    }
+
+    /// The map kept in a level by the last incremental build, or an empty one
+    pub fn from_level(level: &LevelString) -> Self {
+        for obj in level.objects.iter().filter(|obj| is_spwn_object(obj)) {
+            if obj.object_id() != Ok(Some(914)) {
+                continue;
+            }
+            let text = obj
+                .get(31)
+                .and_then(|text| base64::decode_config(&text.value, base64::URL_SAFE).ok())
+                .and_then(|text| String::from_utf8(text).ok());
+            if let Some(text) = text.as_deref().and_then(|t| t.strip_prefix(ID_MAP_HEADER)) {
+                return IdMap::from_text(text).unwrap_or_default();
+            }
+        }
+        IdMap::default()
+    }
+
+    /// The text object the map is kept in, far to the left of the level, marked as made by SPWN
+    pub fn to_object(&self) -> LevelObject {
+        let text = format!("{}\n{}", ID_MAP_HEADER, self.to_text());
+        let spwn_group = match SPWN_SIGNATURE_GROUP.id {
+            Id::Specific(n) => n,
+            _ => unreachable!(),
+        };
+        LevelObject {
+            props: vec![
+                Prop::new(1, ID_MAP_OBJECT),
+                Prop::new(2, -300),
+                Prop::new(3, 15),
+                Prop::new(31, base64::encode_config(text, base64::URL_SAFE)),
+                Prop::new(57, spwn_group),
+            ],
+        }
+    }
+
+    /// The object from `to_object` as it's shown in source maps, which is not made by any code
+    pub fn source_object() -> GdObj {
+        GdObj {
+            func_id: 0,
+            params: [(1, ObjParam::Number(ID_MAP_OBJECT as f64))]
+                .into_iter()
+                .collect(),
+            mode: ObjectMode::Object,
+            unique_id: 0,
+            origin: None,
+        }
+    }
+
+    /// The IDs from this map for the arbitrary IDs of a new build
+    pub fn previous(&self, keys: &IdKeys) -> [AHashMap<ArbitraryId, SpecificId>; 4] {
+        let mut previous: [AHashMap<ArbitraryId, SpecificId>; 4] = Default::default();
+        for class in 0..4 {
+            for (arbitrary, key) in &keys[class] {
+                if let Some(specific) = self.0[class].get(key) {
+                    previous[class].insert(*arbitrary, *specific);
+                }
+            }
+        }
+        previous
+    }
 }
 
 //returns the string to be appended to the old string
+pub const ID_CLASS_NAMES: [&str; 4] = ["group", "color", "block ID", "item ID"];
+
+/// The index of an ID class in `ID_CLASS_NAMES`, from a name like `group` or `g`
+pub fn id_class(name: &str) -> Result<usize, String> {
+    Ok(match name {
+        "g" | "group" | "groups" => 0,
+        "c" | "color" | "colors" => 1,
+        "b" | "block" | "blocks" => 2,
+        "i" | "item" | "items" => 3,
+        other => {
+            return Err(format!(
+                "Invalid ID class: {} (expected group, color, block or item)",
+                other
+            ))
+        }
+    })
+}
+
+/// The highest ID of each class (groups, colors, block IDs, item IDs) a level can use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdLimits(pub [SpecificId; 4]);
+
+impl Default for IdLimits {
+    fn default() -> Self {
+        IdLimits([999, 1099, 999, 999])
+    }
+}
+
+// the special color channels (background, ground, line, player colors and so on), which
+// arbitrary colors never become
+const SPECIAL_COLORS: std::ops::RangeInclusive<SpecificId> = 1000..=1012;
+
+impl IdLimits {
+    /// Sets one of the limits from text like `group=9999`
+    pub fn set(&mut self, text: &str) -> Result<(), String> {
+        let (class, limit) = text
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid ID limit: {} (expected `class=number`)", text))?;
+        let class = id_class(class.trim())?;
+        self.0[class] = limit
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid ID limit: {}", limit))?;
+        Ok(())
+    }
+
+    // the IDs arbitrary IDs of a class can become
+    fn free_range(&self, class: usize) -> impl Iterator<Item = SpecificId> {
+        (1..=self.0[class]).filter(move |id| class != 1 || !SPECIAL_COLORS.contains(id))
+    }
+}
+
+/// The IDs the arbitrary IDs of a module have to become, for every class
+/// (set with `#[id_range(group = 500..600)]` at the top of the module)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdRanges(pub [Option<RangeInclusive<SpecificId>>; 4]);
+
+/// The arbitrary IDs made by modules with an `#[id_range]`
+#[derive(Debug, Clone, Default)]
+pub struct PinnedIds {
+    /// the module that made every pinned arbitrary ID, and the range it has to be in
+    pub ids: [AHashMap<ArbitraryId, (String, RangeInclusive<SpecificId>)>; 4],
+    /// every range of every module, which the other arbitrary IDs are kept out of
+    pub reserved: [Vec<RangeInclusive<SpecificId>>; 4],
+}
+
+impl PinnedIds {
+    fn range(
+        &self,
+        class: usize,
+        id: ArbitraryId,
+    ) -> Option<&(String, RangeInclusive<SpecificId>)> {
+        self.ids[class].get(&id)
+    }
+
+    fn is_reserved(&self, class: usize, id: SpecificId) -> bool {
+        self.reserved[class].iter().any(|r| r.contains(&id))
+    }
+}
+
+/// How many IDs of each class a level uses, and what the arbitrary IDs became
+#[derive(Debug, Clone, Default)]
+pub struct IdReport {
+    pub limits: IdLimits,
+    pub used: [usize; 4],
+    pub free: [usize; 4],
+    /// the specific ID every arbitrary ID was given, sorted by arbitrary ID
+    pub assigned: [Vec<(ArbitraryId, SpecificId)>; 4],
+}
+
+/// A level needs more IDs of a class than the limit allows
+#[derive(Debug, Clone)]
+pub struct IdLimitError {
+    pub class: usize,
+    pub message: String,
+}
+
+impl fmt::Display for IdLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for IdLimitError {}
+
+/// How `append_objects` gives out IDs for the arbitrary IDs
+#[derive(Debug, Clone, Default)]
+pub struct IdOptions {
+    pub limits: IdLimits,
+    pub pinned: PinnedIds,
+    /// the IDs the arbitrary IDs were given by the last build, which they keep if they still can
+    pub previous: [AHashMap<ArbitraryId, SpecificId>; 4],
+}
+
+/// Gives the arbitrary IDs of the objects specific IDs that the objects already in the level
+/// (`level_ids`, from `get_used_ids`) don't use, and turns the objects into level string objects
 pub fn append_objects(
     mut objects: Vec<GdObj>,
-    old_ls: &str,
-) -> Result<(String, [usize; 4]), String> {
-    let mut closed_ids = get_used_ids(old_ls);
+    level_ids: &[AHashSet<SpecificId>; 4],
+    options: &IdOptions,
+) -> Result<(LevelString, IdReport), IdLimitError> {
+    let IdOptions { limits, pinned, .. } = options;
+    let mut closed_ids: [AHashSet<SpecificId>; 4] = Default::default();
+    let mut arbitrary_ids: [AHashSet<ArbitraryId>; 4] = Default::default();
 
     //collect all specific ids mentioned into closed_[id] lists
     for obj in &objects {
@@ -275,26 +525,127 @@ pub fn append_objects(
             for id in id {
                 match id {
                     Id::Specific(i) => {
+                        if i > limits.0[class_index] {
+                            return Err(IdLimitError {
+                                class: class_index,
+                                message: format!(
+                                    "This level uses {} {}, which is above the {} limit ({})",
+                                    ID_CLASS_NAMES[class_index],
+                                    i,
+                                    ID_CLASS_NAMES[class_index],
+                                    limits.0[class_index]
+                                ),
+                            });
+                        }
                         closed_ids[class_index].insert(i);
                     }
-                    _ => continue,
+                    Id::Arbitrary(i) => {
+                        arbitrary_ids[class_index].insert(i);
+                    }
                 }
             }
         }
     }
 
-    //find new ids for all the arbitrary ones
-    let mut id_maps: [AHashMap<ArbitraryId, SpecificId>; 4] = [
-        AHashMap::default(),
-        AHashMap::default(),
-        AHashMap::default(),
-        AHashMap::default(),
-    ];
+    // the IDs an arbitrary ID can become: the ones in its module's range if it has one,
+    // and otherwise the ones that aren't in any module's range
+    let free_ids = |class: usize, id: ArbitraryId| {
+        let range = pinned.range(class, id).map(|(_, r)| r.clone());
+        limits
+            .free_range(class)
+            .filter(move |specific| match &range {
+                Some(r) => r.contains(specific),
+                None => !pinned.is_reserved(class, *specific),
+            })
+    };
 
-    const ID_MAX: u16 = 999;
+    // the IDs from the last build are given out first, so they stay the same. the objects
+    // in the level that use them were made for them, since they were free at the time
+    let mut id_maps: [AHashMap<ArbitraryId, SpecificId>; 4] = Default::default();
+    for class in 0..4 {
+        let mut previous = options.previous[class]
+            .iter()
+            .filter(|(arbitrary, _)| arbitrary_ids[class].contains(arbitrary))
+            .collect::<Vec<_>>();
+        previous.sort_unstable();
+        for (arbitrary, specific) in previous {
+            if !closed_ids[class].contains(specific)
+                && free_ids(class, *arbitrary).any(|id| id == *specific)
+            {
+                closed_ids[class].insert(*specific);
+                id_maps[class].insert(*arbitrary, *specific);
+            }
+        }
+        closed_ids[class].extend(level_ids[class].iter().copied());
+    }
+
+    // fail before any IDs are given out if they won't all fit
+    for (class, arbitrary) in arbitrary_ids.iter().enumerate() {
+        let arbitrary = arbitrary
+            .iter()
+            .filter(|id| !id_maps[class].contains_key(id))
+            .collect::<Vec<_>>();
+        let mut modules = AHashMap::<&str, (&RangeInclusive<SpecificId>, usize)>::default();
+        for id in &arbitrary {
+            if let Some((module, range)) = pinned.range(class, **id) {
+                modules.entry(module.as_str()).or_insert((range, 0)).1 += 1;
+            }
+        }
+        let mut modules = modules.into_iter().collect::<Vec<_>>();
+        modules.sort_by_key(|(module, _)| *module);
+        for (module, (range, needed)) in modules {
+            let free = limits
+                .free_range(class)
+                .filter(|id| range.contains(id) && !closed_ids[class].contains(id))
+                .count();
+            if needed > free {
+                return Err(IdLimitError {
+                    class,
+                    message: format!(
+                        "The {} range {}..={} of {} is full! It needs {} more {}s, but only {} of them are free",
+                        ID_CLASS_NAMES[class],
+                        range.start(),
+                        range.end(),
+                        module,
+                        needed,
+                        ID_CLASS_NAMES[class],
+                        free,
+                    ),
+                });
+            }
+        }
+
+        let arbitrary = arbitrary
+            .iter()
+            .filter(|id| pinned.range(class, ***id).is_none())
+            .collect::<Vec<_>>();
+        let free = limits
+            .free_range(class)
+            .filter(|id| !closed_ids[class].contains(id) && !pinned.is_reserved(class, *id))
+            .count();
+        if arbitrary.len() > free {
+            return Err(IdLimitError {
+                class,
+                message: format!(
+                    "This level exceeds the {} limit! It needs {} more {}s, but only {} of the IDs up to {} are free",
+                    ID_CLASS_NAMES[class],
+                    arbitrary.len(),
+                    ID_CLASS_NAMES[class],
+                    free,
+                    limits.0[class],
+                ),
+            });
+        }
+    }
+
+    //find new ids for all the arbitrary ones
 
     for obj in &mut objects {
-        for prop in obj.params.values_mut() {
+        // the first arbitrary ID that's found gets the lowest free ID, so the
+        // properties are gone through in order to give the same IDs every time
+        let mut params = obj.params.iter_mut().collect::<Vec<_>>();
+        params.sort_by_key(|(prop, _)| **prop);
+        for (_, prop) in params {
             let class_index;
             let ids: Vec<&mut Id>;
             match prop {
@@ -326,23 +677,13 @@ pub fn append_objects(
                         *id = Id::Specific(match id_maps[class_index].get(i) {
                             Some(a) => *a,
                             None => {
-                                let mut out = None;
-                                for i in 1..10000 {
-                                    if !closed_ids[class_index].contains(&i) {
-                                        out = Some(i);
-                                        closed_ids[class_index].insert(i);
-                                        break;
-                                    }
-                                }
-                                if let Some(id) = out {
-                                    id_maps[class_index].insert(*i, id);
-                                    id
-                                } else {
-                                    return Err(format!(
-                                        "This level exceeds the {} limit!",
-                                        ["group", "color", "block ID", "item ID"][class_index]
-                                    ));
-                                }
+                                // there is always one left, this was checked above
+                                let new_id = free_ids(class_index, *i)
+                                    .find(|id| !closed_ids[class_index].contains(id))
+                                    .unwrap();
+                                closed_ids[class_index].insert(new_id);
+                                id_maps[class_index].insert(*i, new_id);
+                                new_id
                             }
                         })
                     }
@@ -351,109 +692,92 @@ pub fn append_objects(
             }
         }
     }
+
+    let mut report = IdReport {
+        limits: *limits,
+        ..Default::default()
+    };
     for (i, list) in closed_ids.iter_mut().enumerate() {
         list.remove(&0);
-        if list.len() > ID_MAX as usize {
-            return Err(format!(
-                "This level exceeds the {} limit! ({}/{})",
-                ["group", "color", "block ID", "item ID"][i],
-                list.len(),
-                ID_MAX
-            ));
-        }
+        report.used[i] = list.len();
+        report.free[i] = limits.free_range(i).filter(|id| !list.contains(id)).count();
+        report.assigned[i] = id_maps[i].iter().map(|(a, s)| (*a, *s)).collect();
+        report.assigned[i].sort_unstable();
     }
 
     //println!("group_map: {:?}", id_maps[0]);
 
-    fn serialize_obj(mut trigger: GdObj) -> String {
-        let mut obj_string = String::new();
-        match trigger.mode {
-            ObjectMode::Object => {
-                match trigger.params.get_mut(&57) {
-                    Some(ObjParam::GroupList(l)) => (*l).push(SPWN_SIGNATURE_GROUP),
-                    Some(ObjParam::Group(g)) => {
-                        let group = *g;
-                        trigger
-                            .params
-                            .insert(57, ObjParam::GroupList(vec![group, SPWN_SIGNATURE_GROUP]));
-                    }
-                    _ => {
-                        trigger
-                            .params
-                            .insert(57, ObjParam::Group(SPWN_SIGNATURE_GROUP));
-                    }
-                };
-
-                let mut param_list = trigger.params.iter().collect::<Vec<(&u16, &ObjParam)>>();
-
-                param_list.sort_by(|a, b| (*a.0).cmp(b.0));
-
-                for param in param_list {
-                    obj_string += &format!("{},{},", param.0, param.1);
-                }
-
-                obj_string + ";"
+    fn serialize_obj(mut trigger: GdObj) -> LevelObject {
+        match trigger.params.get_mut(&57) {
+            Some(ObjParam::GroupList(l)) => (*l).push(SPWN_SIGNATURE_GROUP),
+            Some(ObjParam::Group(g)) => {
+                let group = *g;
+                trigger
+                    .params
+                    .insert(57, ObjParam::GroupList(vec![group, SPWN_SIGNATURE_GROUP]));
             }
-            ObjectMode::Trigger => {
-                match trigger.params.get_mut(&57) {
-                    Some(ObjParam::GroupList(l)) => {
-                        (*l).push(SPWN_SIGNATURE_GROUP);
-                        //list
-                    }
-                    Some(ObjParam::Group(g)) => {
-                        let group = *g;
-                        trigger
-                            .params
-                            .insert(57, ObjParam::GroupList(vec![group, SPWN_SIGNATURE_GROUP]));
-                    }
-                    _ => {
-                        trigger
-                            .params
-                            .insert(57, ObjParam::Group(SPWN_SIGNATURE_GROUP));
-                        //Vec::new()
-                    }
-                };
+            _ => {
+                trigger
+                    .params
+                    .insert(57, ObjParam::Group(SPWN_SIGNATURE_GROUP));
+            }
+        };
 
-                /*let spawned = match trigger.params.get(&62) {
-                    Some(ObjParam::Bool(b)) => *b,
-                    _ => groups.iter().any(|x| x.id != ID::Specific(0)),
-                };
+        /*let spawned = match trigger.params.get(&62) {
+            Some(ObjParam::Bool(b)) => *b,
+            _ => groups.iter().any(|x| x.id != ID::Specific(0)),
+        };
 
-                if spawned {
-                    obj_string += "87,1,";
-                }*/
+        if spawned {
+            obj_string += "87,1,";
+        }*/
 
-                let mut param_list = trigger.params.iter().collect::<Vec<(&u16, &ObjParam)>>();
+        let mut param_list = trigger.params.iter().collect::<Vec<(&u16, &ObjParam)>>();
 
-                param_list.sort_by(|a, b| (*a.0).cmp(b.0));
+        param_list.sort_by(|a, b| (*a.0).cmp(b.0));
 
-                for param in param_list {
-                    obj_string += &format!("{},{},", param.0, param.1);
-                }
-                obj_string + "108,1;" //linked group
-            }
+        let mut obj = LevelObject {
+            props: param_list
+                .into_iter()
+                .map(|(key, value)| Prop::new(*key, value))
+                .collect(),
+        };
+        if trigger.mode == ObjectMode::Trigger {
+            obj.props.push(Prop::new(108, 1)); //linked group
         }
+        obj
     }
 
-    let mut full_obj_string = String::new();
+    let new_objects = LevelString {
+        header: None,
+        objects: objects.into_iter().map(serialize_obj).collect(),
+    };
+    Ok((new_objects, report))
+}
 
-    for obj in objects {
-        full_obj_string += &serialize_obj(obj)
-    }
-    Ok((
-        full_obj_string,
-        [
-            closed_ids[0].len(),
-            closed_ids[1].len(),
-            closed_ids[2].len(),
-            closed_ids[3].len(),
-        ],
-    ))
+/// Where `apply_fn_ids` puts the triggers in the editor
+/// (set with `#[layout(...)]` at the top of the main file)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TriggerLayout {
+    /// every trigger in one column at the start of the level
+    #[default]
+    Column,
+    /// a block of triggers for every function ID, with a text label above it
+    Grouped,
+    /// like `Grouped`, but the blocks of the function IDs made inside another
+    /// function ID are put to the right of its block, one row lower
+    Tree,
+    /// all the triggers in a grid `width` blocks wide, with its bottom left corner at `x`, `y`
+    Grid { x: f64, y: f64, width: u32 },
 }
 
-pub fn apply_fn_ids(func_ids: &[FunctionId]) -> Vec<GdObj> {
+pub fn apply_fn_ids(func_ids: &[FunctionId], layout: &TriggerLayout) -> Vec<GdObj> {
     //println!("{:?}", trigger);
 
+    if *layout != TriggerLayout::Column {
+        return arrange_triggers(func_ids, layout);
+    }
+
     let mut objectlist = Vec::new();
 
     for func_id in func_ids.iter() {
@@ -485,18 +809,7 @@ pub fn apply_fn_ids(func_ids: &[FunctionId]) -> Vec<GdObj> {
                 let y_pos = (i as u16) % possible_height + START_HEIGHT;
                 let x_pos = 0;
 
-                let spawned = match obj.params.get(&62) {
-                    Some(ObjParam::Bool(b)) => *b,
-                    _ => match obj.params.get(&57) {
-                        None => false,
-                        // Some(ObjParam::GroupList(l)) => {
-                        //     l.iter().any(|x| x.id != ID::Specific(0))
-                        // }
-                        Some(ObjParam::Group(g)) => g.id != Id::Specific(0),
-                        Some(ObjParam::GroupList(g)) => g[0].id != Id::Specific(0),
-                        _ => unreachable!(),
-                    },
-                };
+                let spawned = is_spawned(obj);
 
                 let mut new_obj = obj.clone();
 
@@ -542,3 +855,267 @@ pub fn apply_fn_ids(func_ids: &[FunctionId]) -> Vec<GdObj> {
 
     full_obj_list
 }
+
+fn is_spawned(obj: &GdObj) -> bool {
+    match obj.params.get(&62) {
+        Some(ObjParam::Bool(b)) => *b,
+        _ => match obj.params.get(&57) {
+            None => false,
+            // Some(ObjParam::GroupList(l)) => {
+            //     l.iter().any(|x| x.id != ID::Specific(0))
+            // }
+            Some(ObjParam::Group(g)) => g.id != Id::Specific(0),
+            Some(ObjParam::GroupList(g)) => g[0].id != Id::Specific(0),
+            _ => unreachable!(),
+        },
+    }
+}
+
+// the triggers of a group run from left to right when it's spawned, so the column layout
+// puts every spawned trigger a little to the right of the one before it. the other layouts
+// keep that for the triggers in the same column, and move the triggers that would end up
+// left of a trigger that runs before them in the same group
+fn arrange_triggers(func_ids: &[FunctionId], layout: &TriggerLayout) -> Vec<GdObj> {
+    let mut objectlist = Vec::new();
+    for (id, func_id) in func_ids.iter().enumerate() {
+        objectlist.extend(func_id.obj_list.iter().map(|(obj, order)| (id, obj, order)));
+    }
+    objectlist.sort_by(|x, y| x.2.partial_cmp(y.2).unwrap());
+
+    let possible_height = (MAX_HEIGHT - START_HEIGHT) as usize;
+
+    // triggers that aren't spawned run when the player gets to them,
+    // so they stay at the start of the level
+    let mut start_triggers = Vec::new();
+    let mut spawned = Vec::new();
+    // the spawned triggers of every function ID, in order
+    let mut blocks = vec![Vec::new(); func_ids.len()];
+
+    let mut full_obj_list = Vec::<GdObj>::new();
+    for (id, obj, _) in objectlist {
+        let mut new_obj = obj.clone();
+        if obj.mode == ObjectMode::Trigger {
+            if is_spawned(obj) {
+                new_obj.params.insert(62, ObjParam::Bool(true));
+                new_obj.params.insert(87, ObjParam::Bool(true));
+                spawned.push(full_obj_list.len());
+                blocks[id].push(full_obj_list.len());
+            } else {
+                start_triggers.push(full_obj_list.len());
+            }
+        }
+        full_obj_list.push(new_obj);
+    }
+
+    place_block(
+        &mut full_obj_list,
+        &start_triggers,
+        (0, 0),
+        possible_height,
+        layout,
+    );
+    for i in &start_triggers {
+        full_obj_list[*i].params.insert(2, ObjParam::Number(0.0));
+    }
+
+    let mut labels = Vec::new();
+    let mut sources = AHashMap::default();
+    let mut label_block = |objects: &mut Vec<GdObj>, id: usize, col: usize, top: usize| {
+        let columns = place_block(
+            objects,
+            &blocks[id],
+            (col, top),
+            possible_height - top,
+            layout,
+        );
+        // the closest call in the file the build started from, rather than in a library
+        let area = objects[blocks[id][0]].origin.as_ref().map(|info| {
+            let main_file = info.call_stack.first().unwrap_or(&info.position).file;
+            std::iter::once(&info.position)
+                .chain(info.call_stack.iter().rev())
+                .find(|area| area.file == main_file)
+                .unwrap_or(&info.position)
+        });
+        let text = area
+            .and_then(|area| source_line(area, &mut sources))
+            .unwrap_or_else(|| format!("function {}", id));
+        labels.push(label(
+            &text,
+            (col * 30 + 15) as f64,
+            ((81 - START_HEIGHT as usize - top) * 30 + 15) as f64,
+        ));
+        columns
+    };
+
+    match layout {
+        TriggerLayout::Column => unreachable!(),
+        TriggerLayout::Grouped => {
+            // the first column is for the triggers that aren't spawned
+            let mut col = 1;
+            for (id, block) in blocks.iter().enumerate() {
+                if !block.is_empty() {
+                    col += label_block(&mut full_obj_list, id, col, 0) + 1;
+                }
+            }
+        }
+        TriggerLayout::Tree => {
+            let mut children = vec![Vec::new(); func_ids.len()];
+            let mut roots = Vec::new();
+            for (id, func_id) in func_ids.iter().enumerate() {
+                match func_id.parent {
+                    Some(parent) => children[parent].push(id),
+                    None => roots.push(id),
+                }
+            }
+            // returns how many columns the function ID and the ones inside it took
+            fn place_tree(
+                id: usize,
+                col: usize,
+                depth: usize,
+                children: &[Vec<usize>],
+                is_empty: &dyn Fn(usize) -> bool,
+                place: &mut dyn FnMut(usize, usize, usize) -> usize,
+            ) -> usize {
+                let mut width = 0;
+                if !is_empty(id) {
+                    width += place(id, col, depth) + 1;
+                }
+                for child in &children[id] {
+                    width += place_tree(*child, col + width, depth + 1, children, is_empty, place);
+                }
+                width
+            }
+            let empty = blocks.iter().map(|b| b.is_empty()).collect::<Vec<_>>();
+            let mut col = 1;
+            for root in roots {
+                col += place_tree(
+                    root,
+                    col,
+                    0,
+                    &children,
+                    &|id| empty[id],
+                    &mut |id, col, depth| {
+                        label_block(&mut full_obj_list, id, col, depth.min(possible_height / 2))
+                    },
+                );
+            }
+        }
+        TriggerLayout::Grid { width, .. } => {
+            let rows = spawned.len().div_ceil(*width as usize).max(1);
+            place_block(&mut full_obj_list, &spawned, (0, 0), rows, layout);
+        }
+    }
+
+    // moves the triggers that are left of a trigger in the same group that runs before them
+    let mut last_x = AHashMap::<Id, f64>::default();
+    for i in spawned {
+        let obj = &mut full_obj_list[i];
+        let groups = match obj.params.get(&57) {
+            Some(ObjParam::Group(g)) => vec![g.id],
+            Some(ObjParam::GroupList(list)) => list.iter().map(|g| g.id).collect(),
+            _ => Vec::new(),
+        };
+        let mut x = match obj.params.get(&2) {
+            Some(ObjParam::Number(x)) => *x,
+            _ => 0.0,
+        };
+        if let Some(last) = groups
+            .iter()
+            .filter_map(|g| last_x.get(g))
+            .copied()
+            .reduce(f64::max)
+        {
+            if x <= last {
+                x = last + DELTA_X as f64;
+                obj.params.insert(2, ObjParam::Number(x));
+            }
+        }
+        for group in groups {
+            last_x.insert(group, x);
+        }
+    }
+
+    full_obj_list.extend(labels);
+    full_obj_list
+}
+
+// puts triggers in columns of `rows`, starting at the column and row in `start`, and returns
+// how many columns they took. in the grid layout the rows are counted up from the bottom of the
+// grid, and in the others down from the top of the column layout
+fn place_block(
+    objects: &mut [GdObj],
+    block: &[usize],
+    start: (usize, usize),
+    rows: usize,
+    layout: &TriggerLayout,
+) -> usize {
+    // small enough that the last trigger of a column is still left of the next column
+    let step = (30.0 / rows as f64).min(DELTA_X as f64);
+    for (k, i) in block.iter().enumerate() {
+        let (col, row) = (start.0 + k / rows, start.1 + k % rows);
+        let x = (col * 30 + 15) as f64 + (k % rows) as f64 * step;
+        let (x, y) = match layout {
+            TriggerLayout::Grid {
+                x: grid_x,
+                y: grid_y,
+                ..
+            } => (grid_x + x, grid_y + (row * 30 + 15) as f64),
+            _ => (x, ((80 - START_HEIGHT as usize - row) * 30 + 15) as f64),
+        };
+        objects[*i].params.insert(2, ObjParam::Number(x));
+        objects[*i].params.insert(3, ObjParam::Number(y));
+    }
+    block.len().div_ceil(rows)
+}
+
+// "file.spwn:12" for the code at `area`, if its source can be read
+fn source_line(
+    area: &CodeArea,
+    sources: &mut AHashMap<LocalIntern<SpwnSource>, Option<String>>,
+) -> Option<String> {
+    let source = sources
+        .entry(area.file)
+        .or_insert_with(|| match area.file.as_ref() {
+            SpwnSource::File(path) => std::fs::read_to_string(path).ok(),
+            SpwnSource::BuiltIn(path) => {
+                get_lib_file(path).and_then(|f| f.contents_utf8().map(String::from))
+            }
+            SpwnSource::String(s) => Some(s.as_ref().clone()),
+        });
+    let source = source.as_ref()?;
+    let line = source.as_bytes()[..area.pos.0.min(source.len())]
+        .iter()
+        .filter(|b| **b == b'\n')
+        .count()
+        + 1;
+    Some(match area.file.as_ref() {
+        SpwnSource::File(path) | SpwnSource::BuiltIn(path) => format!(
+            "{}:{}",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            line
+        ),
+        SpwnSource::String(_) => format!("line {}", line),
+    })
+}
+
+// a small text object
+fn label(text: &str, x: f64, y: f64) -> GdObj {
+    GdObj {
+        func_id: 0,
+        params: [
+            (1, ObjParam::Number(914.0)),
+            (2, ObjParam::Number(x)),
+            (3, ObjParam::Number(y)),
+            (
+                31,
+                ObjParam::Text(base64::encode_config(text, base64::URL_SAFE)),
+            ),
+            (32, ObjParam::Number(0.5)),
+        ]
+        .into_iter()
+        .collect(),
+        mode: ObjectMode::Object,
+        unique_id: 0,
+        origin: None,
+    }
+}
@@ -7,7 +7,10 @@ pub mod compiler;
 pub mod compiler_types;
 pub mod context;
 pub mod globals;
+pub mod level_codec;
 pub mod leveldata;
+pub mod obj_schema;
+pub mod output_cache;
 pub mod parse_levelstring;
 pub mod value;
 pub mod value_storage;
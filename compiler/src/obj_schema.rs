@@ -0,0 +1,389 @@
+//! What the properties of objects are
+//!
+//! Every object property (the keys of `obj { ... }`) has a type, which says what kind of value it
+//! takes and, for IDs, which class of ID it is. A few properties mean something else on some
+//! objects, like the item ID of a collision block being a block ID, so those objects have their own
+//! entries in `OBJECT_OVERRIDES`.
+//!
+//! The table is used to read level strings, to find the IDs a level already uses, to check the
+//! values of object literals and `$.edit_obj`, and for `obj_props` in the standard library.
+
+use crate::builtins::{self, Id};
+use crate::level_codec::{LevelError, LevelObject, Prop};
+use crate::leveldata::ObjParam;
+
+/// The kind of value an object property takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropType {
+    Number,
+    Bool,
+    Text,
+    /// a number of seconds, or `@epsilon` for the shortest delay
+    Delay,
+    Group,
+    /// the groups of an object, which can also be one group
+    Groups,
+    Color,
+    Block,
+    Item,
+}
+
+impl PropType {
+    /// The class of ID the property is (0 to 3 for groups, colors, blocks and items), if it is one
+    pub fn id_class(self) -> Option<usize> {
+        match self {
+            PropType::Group | PropType::Groups => Some(0),
+            PropType::Color => Some(1),
+            PropType::Block => Some(2),
+            PropType::Item => Some(3),
+            _ => None,
+        }
+    }
+
+    /// The SPWN types a value of the property can have, for the patterns in `obj_props`
+    pub fn type_names(self) -> &'static [&'static str] {
+        match self {
+            PropType::Number => &["number"],
+            PropType::Bool => &["bool"],
+            PropType::Text => &["string"],
+            PropType::Delay => &["number", "epsilon"],
+            // a specific ID can also be given as a number
+            PropType::Group => &["group", "trigger_function", "number"],
+            PropType::Groups => &["group"],
+            PropType::Color => &["color", "number"],
+            PropType::Block => &["block", "number"],
+            PropType::Item => &["item", "number"],
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            PropType::Number => "a number",
+            PropType::Bool => "a boolean",
+            PropType::Text => "a string",
+            PropType::Delay => "a number or @epsilon",
+            PropType::Group => "a group",
+            PropType::Groups => "a group or an array of groups",
+            PropType::Color => "a color",
+            PropType::Block => "a block ID",
+            PropType::Item => "an item ID",
+        }
+    }
+
+    pub fn accepts(self, param: &ObjParam) -> bool {
+        let whole = |n: f64| n >= 0.0 && n.fract() == 0.0 && n <= u16::MAX as f64;
+        match (self, param) {
+            (PropType::Number, ObjParam::Number(_))
+            | (PropType::Bool, ObjParam::Bool(_))
+            | (PropType::Text, ObjParam::Text(_))
+            | (PropType::Delay, ObjParam::Number(_) | ObjParam::Epsilon)
+            | (PropType::Group, ObjParam::Group(_))
+            | (PropType::Groups, ObjParam::Group(_) | ObjParam::GroupList(_))
+            | (PropType::Color, ObjParam::Color(_))
+            | (PropType::Block, ObjParam::Block(_))
+            | (PropType::Item, ObjParam::Item(_)) => true,
+            // GD keeps booleans as 0 and 1
+            (PropType::Bool, ObjParam::Number(n)) => *n == 0.0 || *n == 1.0,
+            // a list of one group is written the same as the group
+            (PropType::Group, ObjParam::GroupList(list)) => list.len() == 1,
+            (
+                PropType::Group | PropType::Color | PropType::Block | PropType::Item,
+                ObjParam::Number(n),
+            ) => whole(*n),
+            _ => false,
+        }
+    }
+
+    /// Reads the value of the property from a level string
+    pub fn decode(self, prop: &Prop<u16>) -> Result<ObjParam, LevelError> {
+        Ok(match self {
+            // numbers that can't be read are kept as they were
+            PropType::Number | PropType::Delay => match prop.number() {
+                Ok(n) => ObjParam::Number(n),
+                Err(_) => ObjParam::Text(prop.value.clone()),
+            },
+            PropType::Bool => ObjParam::Bool(prop.bool()),
+            PropType::Text => ObjParam::Text(prop.value.clone()),
+            PropType::Group => ObjParam::Group(builtins::Group {
+                id: Id::Specific(prop.id()?),
+            }),
+            PropType::Groups => ObjParam::GroupList(
+                prop.ids()?
+                    .into_iter()
+                    .map(|g| builtins::Group {
+                        id: Id::Specific(g),
+                    })
+                    .collect(),
+            ),
+            PropType::Color => ObjParam::Color(builtins::Color {
+                id: Id::Specific(prop.id()?),
+            }),
+            PropType::Block => ObjParam::Block(builtins::Block {
+                id: Id::Specific(prop.id()?),
+            }),
+            PropType::Item => ObjParam::Item(builtins::Item {
+                id: Id::Specific(prop.id()?),
+            }),
+        })
+    }
+}
+
+/// A property, with the names `obj_props` has for it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PropSchema {
+    pub key: u16,
+    pub names: &'static [&'static str],
+    pub kind: PropType,
+    /// what GD uses when the property isn't there (booleans are 0 or 1)
+    pub default: Option<f64>,
+}
+
+const fn prop(
+    key: u16,
+    names: &'static [&'static str],
+    kind: PropType,
+    default: Option<f64>,
+) -> PropSchema {
+    PropSchema {
+        key,
+        names,
+        kind,
+        default,
+    }
+}
+
+use PropType::*;
+
+// booleans are off by default
+const OFF: Option<f64> = Some(0.0);
+
+/// Every property SPWN knows about, with what it is on most objects
+pub const OBJECT_PROPS: [PropSchema; 92] = [
+    prop(1, &["OBJ_ID"], Number, None),
+    prop(2, &["X"], Number, Some(0.0)),
+    prop(3, &["Y"], Number, Some(0.0)),
+    prop(4, &["HORIZONTAL_FLIP"], Bool, OFF),
+    prop(5, &["VERTICAL_FLIP"], Bool, OFF),
+    prop(6, &["ROTATION"], Number, Some(0.0)),
+    prop(7, &["TRIGGER_RED"], Number, Some(255.0)),
+    prop(8, &["TRIGGER_GREEN"], Number, Some(255.0)),
+    prop(9, &["TRIGGER_BLUE"], Number, Some(255.0)),
+    prop(10, &["DURATION"], Number, Some(0.5)),
+    prop(11, &["TOUCH_TRIGGERED"], Bool, OFF),
+    prop(13, &["PORTAL_CHECKED"], Bool, OFF),
+    prop(15, &["PLAYER_COLOR_1"], Bool, OFF),
+    prop(16, &["PLAYER_COLOR_2"], Bool, OFF),
+    prop(17, &["BLENDING"], Bool, OFF),
+    prop(20, &["EDITOR_LAYER_1"], Number, Some(0.0)),
+    prop(21, &["COLOR"], Color, None),
+    prop(22, &["COLOR_2"], Color, None),
+    prop(23, &["TARGET_COLOR"], Color, None),
+    prop(24, &["Z_LAYER"], Number, None),
+    prop(25, &["Z_ORDER"], Number, None),
+    prop(28, &["MOVE_X"], Number, Some(0.0)),
+    prop(29, &["MOVE_Y"], Number, Some(0.0)),
+    prop(30, &["EASING"], Number, Some(0.0)),
+    prop(31, &["TEXT"], Text, None),
+    prop(32, &["SCALING"], Number, Some(1.0)),
+    prop(34, &["GROUP_PARENT"], Bool, OFF),
+    prop(35, &["OPACITY"], Number, Some(1.0)),
+    prop(36, &["ACTIVE_TRIGGER"], Bool, OFF),
+    prop(41, &["HVS_ENABLED"], Bool, OFF),
+    prop(42, &["COLOR_2_HVS_ENABLED"], Bool, OFF),
+    prop(43, &["HVS"], Text, None),
+    prop(44, &["COLOR_2_HVS"], Text, None),
+    prop(45, &["FADE_IN"], Number, Some(0.0)),
+    prop(46, &["HOLD"], Number, Some(0.0)),
+    prop(47, &["FADE_OUT"], Number, Some(0.0)),
+    prop(48, &["PULSE_HSV"], Bool, OFF),
+    prop(49, &["COPIED_COLOR_HVS"], Text, None),
+    prop(50, &["COPIED_COLOR_ID"], Color, None),
+    prop(51, &["TARGET"], Group, None),
+    prop(52, &["TARGET_TYPE"], Number, Some(0.0)),
+    prop(54, &["YELLOW_TELEPORTATION_PORTAL_DISTANCE"], Number, None),
+    prop(56, &["ACTIVATE_GROUP"], Bool, OFF),
+    prop(57, &["GROUPS"], Groups, None),
+    prop(58, &["LOCK_TO_PLAYER_X"], Bool, OFF),
+    prop(59, &["LOCK_TO_PLAYER_Y"], Bool, OFF),
+    prop(60, &["COPY_OPACITY"], Bool, OFF),
+    prop(61, &["EDITOR_LAYER_2"], Number, Some(0.0)),
+    prop(62, &["SPAWN_TRIGGERED"], Bool, OFF),
+    prop(63, &["SPAWN_DURATION"], Delay, Some(0.0)),
+    prop(64, &["DONT_FADE"], Bool, OFF),
+    prop(65, &["MAIN_ONLY"], Bool, OFF),
+    prop(66, &["DETAIL_ONLY"], Bool, OFF),
+    prop(67, &["DONT_ENTER"], Bool, OFF),
+    prop(68, &["ROTATE_DEGREES"], Number, Some(0.0)),
+    prop(69, &["TIMES_360"], Number, Some(0.0)),
+    prop(70, &["LOCK_OBJECT_ROTATION"], Bool, OFF),
+    prop(71, &["FOLLOW", "CENTER", "TARGET_POS"], Group, None),
+    prop(72, &["X_MOD"], Number, Some(1.0)),
+    prop(73, &["Y_MOD"], Number, Some(1.0)),
+    prop(75, &["STRENGTH"], Number, None),
+    prop(76, &["ANIMATION_ID"], Number, Some(0.0)),
+    prop(77, &["COUNT"], Number, Some(0.0)),
+    prop(78, &["SUBTRACT_COUNT"], Number, Some(0.0)),
+    prop(79, &["PICKUP_MODE"], Number, Some(0.0)),
+    prop(80, &["ITEM", "BLOCK_A"], Item, None),
+    prop(81, &["HOLD_MODE"], Bool, OFF),
+    prop(82, &["TOGGLE_MODE"], Number, Some(0.0)),
+    prop(84, &["INTERVAL"], Number, None),
+    prop(85, &["EASING_RATE"], Number, Some(2.0)),
+    prop(86, &["EXCLUSIVE"], Bool, OFF),
+    prop(87, &["MULTI_TRIGGER"], Bool, OFF),
+    prop(88, &["COMPARISON"], Number, Some(0.0)),
+    prop(89, &["DUAL_MODE"], Bool, OFF),
+    prop(90, &["SPEED"], Number, None),
+    prop(91, &["DELAY"], Number, Some(0.0)),
+    prop(92, &["Y_OFFSET"], Number, Some(0.0)),
+    prop(93, &["ACTIVATE_ON_EXIT"], Bool, OFF),
+    prop(94, &["DYNAMIC_BLOCK"], Bool, OFF),
+    prop(95, &["BLOCK_B"], Block, None),
+    prop(96, &["GLOW_DISABLED"], Bool, OFF),
+    prop(97, &["ROTATION_SPEED"], Number, Some(0.0)),
+    prop(98, &["DISABLE_ROTATION"], Bool, OFF),
+    prop(100, &["USE_TARGET"], Bool, OFF),
+    prop(101, &["TARGET_POS_AXES"], Number, Some(0.0)),
+    prop(102, &["EDITOR_DISABLE"], Bool, OFF),
+    prop(103, &["HIGH_DETAIL"], Bool, OFF),
+    prop(104, &["COUNT_MULTI_ACTIVATE"], Bool, OFF),
+    prop(105, &["MAX_SPEED"], Number, None),
+    prop(106, &["RANDOMIZE_START"], Bool, OFF),
+    prop(107, &["ANIMATION_SPEED"], Number, None),
+    prop(108, &["LINKED_GROUP"], Number, Some(0.0)),
+];
+
+/// A property that is something else on one object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PropOverride {
+    pub object: u16,
+    pub key: u16,
+    /// only when this boolean property of the object is on (the first override that applies is used)
+    pub when: Option<u16>,
+    pub kind: PropType,
+}
+
+const fn over(object: u16, key: u16, when: Option<u16>, kind: PropType) -> PropOverride {
+    PropOverride {
+        object,
+        key,
+        when,
+        kind,
+    }
+}
+
+pub const OBJECT_OVERRIDES: [PropOverride; 5] = [
+    // the pulse trigger targets a group when its target type is on, and a color otherwise
+    over(1006, 51, Some(52), Group),
+    over(1006, 51, None, Color),
+    over(899, 51, None, Color),
+    // the collision trigger and collision block
+    over(1815, 80, None, Block),
+    over(1816, 80, None, Block),
+];
+
+/// Objects that only show an ID, so the IDs in them don't count as used by the level
+pub const ID_DISPLAYS: [(u16, u16); 1] = [
+    // the item counter
+    (1615, 80),
+];
+
+pub fn schema(key: u16) -> Option<&'static PropSchema> {
+    OBJECT_PROPS.iter().find(|p| p.key == key)
+}
+
+/// The type of a property on an object, where `flag` says if a boolean property of the object is on
+pub fn prop_type(object: Option<u16>, key: u16, flag: impl Fn(u16) -> bool) -> Option<PropType> {
+    OBJECT_OVERRIDES
+        .iter()
+        .find(|o| Some(o.object) == object && o.key == key && o.when.is_none_or(&flag))
+        .map(|o| o.kind)
+        .or_else(|| schema(key).map(|p| p.kind))
+}
+
+// every type a property can have on an object, or on any object if it isn't known yet
+fn possible_types(object: Option<u16>, key: u16) -> Vec<PropType> {
+    let mut types = OBJECT_OVERRIDES
+        .iter()
+        .filter(|o| o.key == key && object.is_none_or(|id| id == o.object))
+        .map(|o| o.kind)
+        .collect::<Vec<_>>();
+    if types.is_empty() || object.is_none() {
+        types.extend(schema(key).map(|p| p.kind));
+    }
+    types.dedup();
+    types
+}
+
+/// The types the value of a property can have on any object, for the pattern of its object key
+pub fn all_types(key: u16) -> Vec<PropType> {
+    let mut types = possible_types(None, key);
+    types.sort_by_key(|t| *t as u8);
+    types.dedup();
+    types
+}
+
+/// Checks that every value of an object has the type its property takes on that object
+pub fn check_object(props: &[(u16, ObjParam)]) -> Result<(), String> {
+    let object = props.iter().find_map(|(key, param)| match (key, param) {
+        (1, ObjParam::Number(n)) => Some(*n as u16),
+        _ => None,
+    });
+    for (key, param) in props {
+        let types = possible_types(object, *key);
+        if types.is_empty() || types.iter().any(|t| t.accepts(param)) {
+            continue;
+        }
+        let name = match schema(*key) {
+            Some(schema) => format!("{} ({})", key, schema.names.join(" / ")),
+            None => key.to_string(),
+        };
+        let on = match object {
+            Some(id) => format!(" on object {}", id),
+            None => String::new(),
+        };
+        return Err(format!(
+            "Object key {}{} takes {}, found {}",
+            name,
+            on,
+            types
+                .iter()
+                .map(|t| t.description())
+                .collect::<Vec<_>>()
+                .join(" or "),
+            param_description(param)
+        ));
+    }
+    Ok(())
+}
+
+fn param_description(param: &ObjParam) -> &'static str {
+    match param {
+        ObjParam::Group(_) => "a group",
+        ObjParam::Color(_) => "a color",
+        ObjParam::Block(_) => "a block ID",
+        ObjParam::Item(_) => "an item ID",
+        ObjParam::Number(_) => "a number",
+        ObjParam::Bool(_) => "a boolean",
+        ObjParam::Text(_) => "a string",
+        ObjParam::GroupList(_) => "an array of groups",
+        ObjParam::Epsilon => "@epsilon",
+    }
+}
+
+/// Reads the properties of an object from a level string, as the types they have on that object.
+/// Properties SPWN doesn't know about are numbers, or text if they aren't numbers
+pub fn decode_object(obj: &LevelObject) -> Result<Vec<(u16, ObjParam)>, LevelError> {
+    let object = obj.object_id()?;
+    let flag = |key| obj.get(key).is_some_and(|p| p.bool());
+    obj.props
+        .iter()
+        .map(|prop| {
+            let param = match prop_type(object, prop.key, flag) {
+                Some(kind) => kind.decode(prop)?,
+                None => PropType::Number.decode(prop)?,
+            };
+            Ok((prop.key, param))
+        })
+        .collect()
+}
@@ -0,0 +1,1232 @@
+//! Keeping the outputs of `#[cache_output]` libraries on disk between builds
+//!
+//! Within one build, a library tagged with `#[cache_output]` is only evaluated once (see
+//! `Globals::prev_imports`). This module stores what such an import produced - the returned value,
+//! everything reachable from it and the library's implementations - so later builds can skip
+//! evaluating the library entirely.
+//!
+//! An entry is only used when it was written by the same compiler and every file read while
+//! evaluating the library (including nested imports) still has the same contents.
+//! Libraries with effects other than their output (adding objects or triggers, or changing the
+//! level header) are never stored, since those effects can't be replayed from the cache. Neither
+//! are libraries that call builtins whose results don't only depend on the scripts, like
+//! `$.random` or `$.readfile`, or that print something. Libraries reading the level being written
+//! to are, but only used again for the same level. Arbitrary IDs are fine: the ones a library
+//! took are given new numbers when it's loaded. Named ones (like `?g("door")`) are not, since
+//! the names would be lost. The builtins allowed with `--allow` and `--deny` are part of the key
+//! of an entry.
+//!
+//! Macro bodies aren't stored: they are parsed again from the file that defines them, which is
+//! known to be unchanged.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use ahash::{AHashMap, AHashSet};
+use internment::LocalIntern;
+use parser::ast;
+use serde::{Deserialize, Serialize};
+use shared::{FileRange, ImportType, SpwnSource, StoredValue};
+
+use crate::builtins::{
+    get_lib_file, Block, Builtin, BuiltinPermissions, Color, Group, Id, Item, BUILTIN_LIST,
+    BUILTIN_NAMES,
+};
+use crate::compiler_types::{Implementations, TypeId};
+use crate::globals::Globals;
+use crate::level_codec::LevelSettings;
+use crate::leveldata::ObjParam;
+use crate::value::{Macro, MacroArgDef, Pattern, TriggerFunction, Value};
+use crate::value_storage::store_val_m;
+use crate::STD_PATH;
+use errors::compiler_info::CodeArea;
+
+/// A file read during an import, with the hash of its contents
+pub type Dependency = (SpwnSource, u64);
+
+/// The default location of the cache, in the user's cache directory
+pub fn default_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Caches"))
+    } else {
+        std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+    };
+    base.map(|dir| dir.join("spwn").join("libraries"))
+}
+
+// FNV-1a, which (unlike the std hasher) is guaranteed to stay the same between builds
+pub fn hash_source(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// the version doesn't change between development builds, so the age of the executable is included
+fn compiler_id() -> String {
+    let built = std::env::current_exe()
+        .and_then(fs::metadata)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format!("{} {}", env!("CARGO_PKG_VERSION"), built)
+}
+
+fn entry_path(dir: &Path, library: &SpwnSource, permissions: &BuiltinPermissions) -> PathBuf {
+    // only the builtins that aren't at their default setting, so the key stays short
+    let changed = BUILTIN_LIST
+        .iter()
+        .filter(|b| permissions.is_allowed(**b) != permissions.is_safe(**b))
+        .map(|b| String::from(*b))
+        .collect::<Vec<_>>()
+        .join(",");
+    let key = hash_source(&format!("{}\n{:?}\n{}", compiler_id(), library, changed));
+    dir.join(format!("{:016x}.json", key))
+}
+
+/// How many times builtins whose results depend on more than the scripts were called
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OutsideReads {
+    // builtins like `$.random`, `$.readfile` or `$.print`, which keep a library from being cached
+    pub other: usize,
+    // `$.level_string` and `$.level_objects`, which only depend on the level being written to
+    pub level: usize,
+}
+
+impl OutsideReads {
+    pub fn count(&mut self, builtin: Builtin) {
+        if reads_level(builtin) {
+            self.level += 1;
+        } else if reads_outside(builtin) {
+            self.other += 1;
+        }
+    }
+
+    pub fn add(&mut self, reads: OutsideReads) {
+        self.other += reads.other;
+        self.level += reads.level;
+    }
+
+    pub fn since(self, before: OutsideReads) -> OutsideReads {
+        OutsideReads {
+            other: self.other - before.other,
+            level: self.level - before.level,
+        }
+    }
+}
+
+fn reads_level(builtin: Builtin) -> bool {
+    matches!(builtin, Builtin::ReadLevel | Builtin::ParseLevel)
+}
+
+// whether a builtin's result depends on something other than the scripts (like the time or files),
+// or it shows something to the user
+fn reads_outside(builtin: Builtin) -> bool {
+    use Builtin::*;
+    matches!(
+        builtin,
+        Print
+            | Time
+            | GetInput
+            | HTTPRequest
+            | Random
+            // also depends on the changes made by `$.set_level_header` before it
+            | LevelHeader
+            | CWD
+            | ReadFile
+            | WriteFile
+            | DeleteFile
+            | FileExists
+            | FileKind
+            | MetaData
+            | ReadDir
+            | MkDir
+            | RmDir
+            | RmDirAll
+    )
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+enum Source {
+    File(PathBuf),
+    BuiltIn(PathBuf),
+}
+
+impl Source {
+    fn new(source: &SpwnSource) -> Result<Self, String> {
+        match source {
+            SpwnSource::File(p) => Ok(Source::File(p.clone())),
+            SpwnSource::BuiltIn(p) => Ok(Source::BuiltIn(p.clone())),
+            SpwnSource::String(_) => Err("code that isn't in a file can't be cached".to_string()),
+        }
+    }
+
+    fn to_spwn_source(&self) -> SpwnSource {
+        match self {
+            Source::File(p) => SpwnSource::File(p.clone()),
+            Source::BuiltIn(p) => SpwnSource::BuiltIn(p.clone()),
+        }
+    }
+
+    fn read(&self) -> Option<String> {
+        match self {
+            Source::File(p) => fs::read_to_string(p).ok(),
+            Source::BuiltIn(p) => get_lib_file(p)
+                .and_then(|f| f.contents_utf8())
+                .map(String::from),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    compiler: String,
+    files: Vec<(Source, u64)>,
+    // the hash of the level being written to, if the library read it
+    level: Option<u64>,
+    // how many arbitrary groups, colors, blocks and items the library took
+    arbitrary_ids: [u16; 4],
+    types: Vec<TypeDef>,
+    values: Vec<Stored>,
+    output: Ref,
+    // type, member, value, in scope
+    implementations: Vec<(String, String, Ref, bool)>,
+}
+
+// a type defined by the library
+#[derive(Serialize, Deserialize)]
+struct TypeDef {
+    name: String,
+    file: Source,
+    pos: FileRange,
+    desc: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    value: Val,
+    fn_context: IdNum,
+    mutable: bool,
+    file: Source,
+    pos: FileRange,
+}
+
+// a stored value, either in the entry itself or somewhere that exists before the library is loaded
+#[derive(Serialize, Deserialize, Clone)]
+enum Ref {
+    Local(usize),
+    Std(String),
+    StdImpl(String, String),
+    Null,
+    Builtins,
+}
+
+// arbitrary IDs are counted from the first one the library took
+#[derive(Serialize, Deserialize)]
+enum IdNum {
+    Specific(u16),
+    Arbitrary(u16),
+}
+
+const GROUP: usize = 0;
+const COLOR: usize = 1;
+const BLOCK: usize = 2;
+const ITEM: usize = 3;
+
+#[derive(Serialize, Deserialize)]
+enum Val {
+    Group(IdNum),
+    Color(IdNum),
+    Block(IdNum),
+    Item(IdNum),
+    Number(f64),
+    Bool(bool),
+    TriggerFunc(IdNum),
+    Dict(Vec<(String, Ref)>),
+    Macro(MacroDef),
+    Str(String),
+    Array(Vec<Ref>),
+    Obj(Vec<(u16, Param)>, bool),
+    Builtins,
+    BuiltinFunction(String),
+    TypeIndicator(String),
+    Range(i32, i32, usize),
+    Pattern(Pat),
+    Null,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MacroDef {
+    def_file: Source,
+    arg_pos: FileRange,
+    added_tags: Vec<Tag>,
+    // name, default, pattern
+    args: Vec<(String, Option<Ref>, Option<Ref>)>,
+    def_variables: Vec<(String, Ref)>,
+    ret_pattern: Option<Ref>,
+}
+
+// an attribute like `#[desc("...")]`, only literals are supported as arguments
+#[derive(Serialize, Deserialize)]
+struct Tag {
+    name: String,
+    // name, value, argument position, value position
+    args: Vec<(Option<String>, Literal, FileRange, FileRange)>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Literal {
+    Str(String, Option<Flag>),
+    Number(f64),
+    Bool(bool),
+}
+
+#[derive(Serialize, Deserialize)]
+enum Flag {
+    Base64,
+    Raw,
+    Unindent,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Param {
+    Group(IdNum),
+    Color(IdNum),
+    Block(IdNum),
+    Item(IdNum),
+    Number(f64),
+    Bool(bool),
+    Text(String),
+    GroupList(Vec<IdNum>),
+    Epsilon,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Pat {
+    Type(String),
+    Array(Vec<Pat>),
+    Either(Box<Pat>, Box<Pat>),
+    Both(Box<Pat>, Box<Pat>),
+    Not(Box<Pat>),
+    Any,
+    Eq(Ref),
+    NotEq(Ref),
+    MoreThan(Ref),
+    LessThan(Ref),
+    MoreOrEq(Ref),
+    LessOrEq(Ref),
+    In(Ref),
+    Macro { args: Vec<Pat>, ret: Box<Pat> },
+}
+
+/// The parts of the compiler state that a library import could change besides its output
+pub struct Snapshot {
+    closed_ids: [u16; 4],
+    objects: usize,
+    triggers: usize,
+    functions: usize,
+    type_id_count: u16,
+    level_settings: LevelSettings,
+    outside_reads: OutsideReads,
+    named_ids: usize,
+}
+
+impl Snapshot {
+    pub fn take(globals: &Globals) -> Self {
+        Snapshot {
+            closed_ids: [
+                globals.closed_groups,
+                globals.closed_colors,
+                globals.closed_blocks,
+                globals.closed_items,
+            ],
+            objects: globals.objects.len(),
+            triggers: globals.uid_counter,
+            functions: globals.func_ids.len(),
+            type_id_count: globals.type_id_count,
+            level_settings: globals.level_settings.clone(),
+            outside_reads: globals.outside_reads,
+            named_ids: globals.id_names.iter().map(|names| names.len()).sum(),
+        }
+    }
+
+    // new types and arbitrary IDs don't count, they are stored with the entry (but their names
+    // aren't)
+    fn has_effects_since(&self, before: &Snapshot) -> bool {
+        self.objects != before.objects
+            || self.triggers != before.triggers
+            || self.functions != before.functions
+            || self.level_settings != before.level_settings
+            || self.outside_reads.other != before.outside_reads.other
+            || self.named_ids != before.named_ids
+    }
+}
+
+fn tag((name, args): &(String, Vec<ast::Argument>)) -> Result<Tag, String> {
+    let mut out = Vec::new();
+    for arg in args {
+        let (body, pos) = match arg.value.values.as_slice() {
+            [var] if var.operator.is_none() && var.path.is_empty() && var.tag.tags.is_empty() => {
+                (&var.value.body, var.pos)
+            }
+            _ => return Err("only attributes with literal arguments can be cached".to_string()),
+        };
+        let value = match body {
+            ast::ValueBody::Str(s) => Literal::Str(
+                s.inner.clone(),
+                s.flags.as_ref().map(|f| match f {
+                    ast::StringFlags::Base64 => Flag::Base64,
+                    ast::StringFlags::Raw => Flag::Raw,
+                    ast::StringFlags::Unindent => Flag::Unindent,
+                }),
+            ),
+            ast::ValueBody::Number(n) => Literal::Number(finite(*n)?),
+            ast::ValueBody::Bool(b) => Literal::Bool(*b),
+            _ => return Err("only attributes with literal arguments can be cached".to_string()),
+        };
+        out.push((arg.symbol.map(|s| s.to_string()), value, arg.pos, pos));
+    }
+    Ok(Tag {
+        name: name.clone(),
+        args: out,
+    })
+}
+
+fn attribute(tag: &Tag) -> (String, Vec<ast::Argument>) {
+    let args = tag
+        .args
+        .iter()
+        .map(|(symbol, value, arg_pos, pos)| {
+            let body = match value {
+                Literal::Str(text, flag) => ast::ValueBody::Str(ast::StrInner {
+                    inner: text.clone(),
+                    flags: flag.as_ref().map(|f| match f {
+                        Flag::Base64 => ast::StringFlags::Base64,
+                        Flag::Raw => ast::StringFlags::Raw,
+                        Flag::Unindent => ast::StringFlags::Unindent,
+                    }),
+                }),
+                Literal::Number(n) => ast::ValueBody::Number(*n),
+                Literal::Bool(b) => ast::ValueBody::Bool(*b),
+            };
+            ast::Argument {
+                symbol: symbol.as_ref().map(|s| LocalIntern::new(s.clone())),
+                value: body.to_variable(*pos).to_expression(),
+                pos: *arg_pos,
+            }
+        })
+        .collect();
+    (tag.name.clone(), args)
+}
+
+fn finite(n: f64) -> Result<f64, String> {
+    if n.is_finite() {
+        Ok(n)
+    } else {
+        Err("infinite numbers can't be cached".to_string())
+    }
+}
+
+// the values the standard library exports, which other libraries refer to by name
+fn std_exports<'a>(
+    globals: &'a Globals,
+) -> Option<(
+    &'a AHashMap<LocalIntern<String>, StoredValue>,
+    &'a Implementations,
+)> {
+    let (output, implementations) = globals
+        .prev_imports
+        .get(&ImportType::Lib(STD_PATH.to_string()))?;
+    match &globals.stored_values[*output] {
+        Value::Dict(d) => Some((d, implementations)),
+        _ => None,
+    }
+}
+
+// every macro defined in a file, by the position of its arguments
+type MacroDefs = AHashMap<FileRange, ast::Macro>;
+
+fn macros_in(source: &Source, text: String) -> Option<MacroDefs> {
+    let (statements, _) =
+        parser::parser::parse_spwn(text, source.to_spwn_source(), BUILTIN_NAMES).ok()?;
+    let mut macros = AHashMap::default();
+    collect_statements(&statements, &mut macros);
+    Some(macros)
+}
+
+fn collect_statements(statements: &[ast::Statement], out: &mut MacroDefs) {
+    use ast::StatementBody::*;
+    for statement in statements {
+        match &statement.body {
+            Call(c) => collect_variable(&c.function, out),
+            Expr(e) | Extract(e) | Return(Some(e)) => collect_expression(e, out),
+            Definition(def) => {
+                collect_variable(&def.symbol, out);
+                if let Some(value) = &def.value {
+                    collect_expression(value, out);
+                }
+            }
+            Impl(imp) => {
+                collect_variable(&imp.symbol, out);
+                collect_dict(&imp.members, out);
+            }
+            If(i) => {
+                collect_expression(&i.condition, out);
+                collect_statements(&i.if_body, out);
+                if let Some(body) = &i.else_body {
+                    collect_statements(body, out);
+                }
+            }
+            For(f) => {
+                collect_expression(&f.symbol, out);
+                collect_expression(&f.array, out);
+                collect_statements(&f.body, out);
+            }
+            While(w) => {
+                collect_expression(&w.condition, out);
+                collect_statements(&w.body, out);
+            }
+            Error(e) => collect_expression(&e.message, out),
+            TypeDef { .. } | Return(None) | Break | Continue => (),
+        }
+    }
+}
+
+fn collect_expression(expr: &ast::Expression, out: &mut MacroDefs) {
+    for value in &expr.values {
+        collect_variable(value, out);
+    }
+}
+
+fn collect_dict(defs: &[ast::DictDef], out: &mut MacroDefs) {
+    for def in defs {
+        match def {
+            ast::DictDef::Def((_, e)) | ast::DictDef::Extract(e) => collect_expression(e, out),
+        }
+    }
+}
+
+fn collect_variable(var: &ast::Variable, out: &mut MacroDefs) {
+    use ast::ValueBody as V;
+    match &var.value.body {
+        V::CmpStmt(c) => collect_statements(&c.statements, out),
+        V::Dictionary(defs) => collect_dict(defs, out),
+        V::Expression(e) => collect_expression(e, out),
+        V::Match(value, cases) => {
+            collect_expression(value, out);
+            for case in cases {
+                if let ast::CaseType::Pattern(p) = &case.typ {
+                    collect_expression(p, out);
+                }
+                collect_expression(&case.body, out);
+            }
+        }
+        V::Array(elements) => {
+            for element in elements {
+                collect_expression(&element.value, out);
+            }
+        }
+        V::ListComp(comp) => {
+            collect_expression(&comp.iterator, out);
+            if let Some(condition) = &comp.condition {
+                collect_expression(condition, out);
+            }
+            collect_expression(&comp.body, out);
+        }
+        V::Obj(obj) => {
+            for (key, value) in &obj.props {
+                collect_expression(key, out);
+                collect_expression(value, out);
+            }
+        }
+        V::Macro(m) => {
+            for (_, default, _, pattern, _, _) in &m.args {
+                for e in default.iter().chain(pattern) {
+                    collect_expression(e, out);
+                }
+            }
+            if let Some(ret) = &m.ret_type {
+                collect_expression(ret, out);
+            }
+            collect_statements(&m.body.statements, out);
+            out.insert(m.arg_pos, m.clone());
+        }
+        V::MacroPattern(p) => {
+            for arg in &p.args {
+                collect_expression(arg, out);
+            }
+            collect_expression(&p.ret, out);
+        }
+        V::Ternary(t) => {
+            collect_expression(&t.condition, out);
+            collect_expression(&t.if_expr, out);
+            collect_expression(&t.else_expr, out);
+        }
+        V::Id(_)
+        | V::Number(_)
+        | V::Symbol(_)
+        | V::Bool(_)
+        | V::Str(_)
+        | V::Import(..)
+        | V::Resolved(_)
+        | V::TypeIndicator(_)
+        | V::SelfVal
+        | V::Null => (),
+    }
+
+    for path in &var.path {
+        match path {
+            ast::Path::Index(e) => collect_expression(e, out),
+            ast::Path::NSlice(slices) => {
+                for slice in slices {
+                    for e in slice.left.iter().chain(&slice.right).chain(&slice.step) {
+                        collect_expression(e, out);
+                    }
+                }
+            }
+            ast::Path::Call(args) => {
+                for arg in args {
+                    collect_expression(&arg.value, out);
+                }
+            }
+            ast::Path::Constructor(defs) => collect_dict(defs, out),
+            ast::Path::Member(_)
+            | ast::Path::Associated(_)
+            | ast::Path::Increment
+            | ast::Path::Decrement => (),
+        }
+    }
+}
+
+struct Saver<'a, 'g> {
+    globals: &'a Globals<'g>,
+    first_ids: [u16; 4],
+    files: AHashSet<Source>,
+    known: AHashMap<StoredValue, Ref>,
+    type_names: AHashMap<TypeId, String>,
+    indices: AHashMap<StoredValue, usize>,
+    queue: Vec<StoredValue>,
+    macros: AHashMap<Source, Option<MacroDefs>>,
+}
+
+impl<'a, 'g> Saver<'a, 'g> {
+    fn reference(&mut self, v: StoredValue) -> Ref {
+        if v == self.globals.NULL_STORAGE {
+            return Ref::Null;
+        }
+        if v == self.globals.BUILTIN_STORAGE {
+            return Ref::Builtins;
+        }
+        if let Some(r) = self.known.get(&v) {
+            return r.clone();
+        }
+        let next = self.queue.len();
+        let index = *self.indices.entry(v).or_insert(next);
+        if index == next {
+            self.queue.push(v);
+        }
+        Ref::Local(index)
+    }
+
+    fn id(&self, id: Id, class: usize) -> Result<IdNum, String> {
+        match id {
+            Id::Specific(n) => Ok(IdNum::Specific(n)),
+            Id::Arbitrary(n) if n > self.first_ids[class] => {
+                Ok(IdNum::Arbitrary(n - self.first_ids[class]))
+            }
+            Id::Arbitrary(_) => Err("an arbitrary ID is from outside the library".to_string()),
+        }
+    }
+
+    fn type_name(&self, id: TypeId) -> Result<String, String> {
+        self.type_names
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| format!("unknown type id {}", id))
+    }
+
+    fn source(&self, source: &SpwnSource) -> Result<Source, String> {
+        Source::new(source)
+    }
+
+    fn value(&mut self, value: &Value) -> Result<Val, String> {
+        Ok(match value {
+            Value::Group(g) => Val::Group(self.id(g.id, GROUP)?),
+            Value::Color(c) => Val::Color(self.id(c.id, COLOR)?),
+            Value::Block(b) => Val::Block(self.id(b.id, BLOCK)?),
+            Value::Item(i) => Val::Item(self.id(i.id, ITEM)?),
+            Value::Number(n) => Val::Number(finite(*n)?),
+            Value::Bool(b) => Val::Bool(*b),
+            Value::TriggerFunc(f) => Val::TriggerFunc(self.id(f.start_group.id, GROUP)?),
+            Value::Dict(d) => Val::Dict(
+                d.iter()
+                    .map(|(k, v)| (k.to_string(), self.reference(*v)))
+                    .collect(),
+            ),
+            Value::Macro(m) => Val::Macro(self.macro_def(m)?),
+            Value::Str(s) => Val::Str(s.clone()),
+            Value::Array(a) => Val::Array(a.iter().map(|v| self.reference(*v)).collect()),
+            Value::Obj(params, mode) => Val::Obj(
+                params
+                    .iter()
+                    .map(|(k, p)| Ok((*k, self.param(p)?)))
+                    .collect::<Result<_, String>>()?,
+                *mode == ast::ObjectMode::Trigger,
+            ),
+            Value::Builtins => Val::Builtins,
+            Value::BuiltinFunction(b) => Val::BuiltinFunction(String::from(*b)),
+            Value::TypeIndicator(t) => Val::TypeIndicator(self.type_name(*t)?),
+            Value::Range(start, end, step) => Val::Range(*start, *end, *step),
+            Value::Pattern(p) => Val::Pattern(self.pattern(p)?),
+            Value::Null => Val::Null,
+        })
+    }
+
+    fn macro_def(&mut self, m: &Macro) -> Result<MacroDef, String> {
+        let def_file = self.source(&m.def_file)?;
+        if !self.files.contains(&def_file) {
+            return Err("a macro is defined outside of the library's files".to_string());
+        }
+
+        // the body is parsed again when loading, so it has to be exactly what's in the file
+        let macros = self
+            .macros
+            .entry(def_file.clone())
+            .or_insert_with(|| def_file.read().and_then(|text| macros_in(&def_file, text)));
+        // attributes written where the macro is stored (like `a: #[desc("...")] make_macro()`)
+        // are added to the macro's own
+        let added_tags = match macros.as_ref().and_then(|macros| macros.get(&m.arg_pos)) {
+            Some(ast_macro)
+                if ast_macro.body.statements == m.body
+                    && ast_macro.args.len() == m.args.len()
+                    && m.tag.tags.starts_with(&ast_macro.properties.tags) =>
+            {
+                m.tag.tags[ast_macro.properties.tags.len()..]
+                    .iter()
+                    .map(tag)
+                    .collect::<Result<_, String>>()?
+            }
+            _ => return Err("a macro doesn't match its definition".to_string()),
+        };
+
+        Ok(MacroDef {
+            def_file,
+            arg_pos: m.arg_pos,
+            added_tags,
+            args: m
+                .args
+                .iter()
+                .map(|arg| {
+                    (
+                        arg.name.to_string(),
+                        arg.default.map(|v| self.reference(v)),
+                        arg.pattern.map(|v| self.reference(v)),
+                    )
+                })
+                .collect(),
+            def_variables: m
+                .def_variables
+                .iter()
+                .map(|(k, v)| (k.to_string(), self.reference(*v)))
+                .collect(),
+            ret_pattern: m.ret_pattern.map(|v| self.reference(v)),
+        })
+    }
+
+    fn param(&self, p: &ObjParam) -> Result<Param, String> {
+        Ok(match p {
+            ObjParam::Group(g) => Param::Group(self.id(g.id, GROUP)?),
+            ObjParam::Color(c) => Param::Color(self.id(c.id, COLOR)?),
+            ObjParam::Block(b) => Param::Block(self.id(b.id, BLOCK)?),
+            ObjParam::Item(i) => Param::Item(self.id(i.id, ITEM)?),
+            ObjParam::Number(n) => Param::Number(finite(*n)?),
+            ObjParam::Bool(b) => Param::Bool(*b),
+            ObjParam::Text(t) => Param::Text(t.clone()),
+            ObjParam::GroupList(list) => Param::GroupList(
+                list.iter()
+                    .map(|g| self.id(g.id, GROUP))
+                    .collect::<Result<_, String>>()?,
+            ),
+            ObjParam::Epsilon => Param::Epsilon,
+        })
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) -> Result<Pat, String> {
+        Ok(match pattern {
+            Pattern::Type(t) => Pat::Type(self.type_name(*t)?),
+            Pattern::Array(a) => Pat::Array(
+                a.iter()
+                    .map(|p| self.pattern(p))
+                    .collect::<Result<_, String>>()?,
+            ),
+            Pattern::Either(a, b) => {
+                Pat::Either(Box::new(self.pattern(a)?), Box::new(self.pattern(b)?))
+            }
+            Pattern::Both(a, b) => {
+                Pat::Both(Box::new(self.pattern(a)?), Box::new(self.pattern(b)?))
+            }
+            Pattern::Not(p) => Pat::Not(Box::new(self.pattern(p)?)),
+            Pattern::Any => Pat::Any,
+            Pattern::Eq(v) => Pat::Eq(self.reference(*v)),
+            Pattern::NotEq(v) => Pat::NotEq(self.reference(*v)),
+            Pattern::MoreThan(v) => Pat::MoreThan(self.reference(*v)),
+            Pattern::LessThan(v) => Pat::LessThan(self.reference(*v)),
+            Pattern::MoreOrEq(v) => Pat::MoreOrEq(self.reference(*v)),
+            Pattern::LessOrEq(v) => Pat::LessOrEq(self.reference(*v)),
+            Pattern::In(v) => Pat::In(self.reference(*v)),
+            Pattern::Macro { args, ret } => Pat::Macro {
+                args: args
+                    .iter()
+                    .map(|p| self.pattern(p))
+                    .collect::<Result<_, String>>()?,
+                ret: Box::new(self.pattern(ret)?),
+            },
+        })
+    }
+}
+
+/// Stores the output of a library import, if it can be replayed without evaluating the library
+pub fn save(
+    globals: &Globals,
+    library: &SpwnSource,
+    before: &Snapshot,
+    files: &[Dependency],
+    output: StoredValue,
+    implementations: &Implementations,
+) -> Result<(), String> {
+    let dir = match &globals.output_cache {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    if Snapshot::take(globals).has_effects_since(before) {
+        return Err("the library has side effects".to_string());
+    }
+
+    let type_names: AHashMap<TypeId, String> = globals
+        .type_ids
+        .iter()
+        .map(|(name, (id, _))| (*id, name.clone()))
+        .collect();
+
+    let mut known = AHashMap::default();
+    if let Some((exports, std_impls)) = std_exports(globals) {
+        for (name, v) in exports {
+            known.insert(*v, Ref::Std(name.to_string()));
+        }
+        for (typ, members) in std_impls {
+            for (name, (v, _)) in members {
+                known.insert(*v, Ref::StdImpl(type_names[typ].clone(), name.to_string()));
+            }
+        }
+    }
+
+    let mut entry_files = Vec::new();
+    for (source, hash) in files {
+        entry_files.push((Source::new(source)?, *hash));
+    }
+
+    let after = Snapshot::take(globals);
+    let mut saver = Saver {
+        globals,
+        first_ids: before.closed_ids,
+        files: entry_files.iter().map(|(s, _)| s.clone()).collect(),
+        known,
+        type_names,
+        indices: AHashMap::default(),
+        queue: Vec::new(),
+        macros: AHashMap::default(),
+    };
+
+    let output = saver.reference(output);
+    let mut cached_impls = Vec::new();
+    for (typ, members) in implementations {
+        for (name, (v, in_scope)) in members {
+            cached_impls.push((
+                saver.type_name(*typ)?,
+                name.to_string(),
+                saver.reference(*v),
+                *in_scope,
+            ));
+        }
+    }
+
+    let mut values = Vec::new();
+    let mut next = 0;
+    while next < saver.queue.len() {
+        let data = &globals.stored_values.map[saver.queue[next]];
+        values.push(Stored {
+            value: saver.value(&data.val)?,
+            fn_context: saver.id(data.fn_context.id, GROUP)?,
+            mutable: data.mutable,
+            file: saver.source(&data.def_area.file)?,
+            pos: data.def_area.pos,
+        });
+        next += 1;
+    }
+
+    let mut types = Vec::new();
+    for (name, (id, area)) in &globals.type_ids {
+        if *id > before.type_id_count {
+            types.push(TypeDef {
+                name: name.clone(),
+                file: Source::new(&area.file)?,
+                pos: area.pos,
+                desc: globals.type_descriptions.get(id).cloned(),
+            });
+        }
+    }
+
+    let entry = Entry {
+        compiler: compiler_id(),
+        files: entry_files,
+        level: (after.outside_reads.level != before.outside_reads.level)
+            .then(|| hash_source(&globals.initial_string)),
+        arbitrary_ids: [0, 1, 2, 3].map(|class| after.closed_ids[class] - before.closed_ids[class]),
+        types,
+        values,
+        output,
+        implementations: cached_impls,
+    };
+
+    let json = serde_json::to_string(&entry).map_err(|e| e.to_string())?;
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    // written next to the entry first, so a build that's stopped halfway never leaves a broken entry
+    let path = entry_path(dir, library, &globals.permissions);
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, json).map_err(|e| e.to_string())?;
+    fs::rename(&temp, &path).map_err(|e| e.to_string())
+}
+
+struct Loader<'a> {
+    first_ids: [u16; 4],
+    local: Vec<StoredValue>,
+    std: Option<(AHashMap<LocalIntern<String>, StoredValue>, Implementations)>,
+    type_ids: &'a AHashMap<String, TypeId>,
+    macros: AHashMap<Source, MacroDefs>,
+}
+
+impl<'a> Loader<'a> {
+    fn id(&self, id: &IdNum, class: usize) -> Id {
+        match id {
+            IdNum::Specific(n) => Id::Specific(*n),
+            IdNum::Arbitrary(n) => Id::Arbitrary(self.first_ids[class] + n),
+        }
+    }
+
+    fn reference(&self, r: &Ref, globals: &Globals) -> Option<StoredValue> {
+        Some(match r {
+            Ref::Local(i) => *self.local.get(*i)?,
+            Ref::Std(name) => *self.std.as_ref()?.0.get(&LocalIntern::new(name.clone()))?,
+            Ref::StdImpl(typ, name) => {
+                self.std
+                    .as_ref()?
+                    .1
+                    .get(self.type_ids.get(typ)?)?
+                    .get(&LocalIntern::new(name.clone()))?
+                    .0
+            }
+            Ref::Null => globals.NULL_STORAGE,
+            Ref::Builtins => globals.BUILTIN_STORAGE,
+        })
+    }
+
+    fn value(&self, value: &Val, globals: &Globals) -> Option<Value> {
+        Some(match value {
+            Val::Group(n) => Value::Group(Group {
+                id: self.id(n, GROUP),
+            }),
+            Val::Color(n) => Value::Color(Color {
+                id: self.id(n, COLOR),
+            }),
+            Val::Block(n) => Value::Block(Block {
+                id: self.id(n, BLOCK),
+            }),
+            Val::Item(n) => Value::Item(Item {
+                id: self.id(n, ITEM),
+            }),
+            Val::Number(n) => Value::Number(*n),
+            Val::Bool(b) => Value::Bool(*b),
+            Val::TriggerFunc(n) => Value::TriggerFunc(TriggerFunction {
+                start_group: Group {
+                    id: self.id(n, GROUP),
+                },
+            }),
+            Val::Dict(d) => Value::Dict(
+                d.iter()
+                    .map(|(k, v)| Some((LocalIntern::new(k.clone()), self.reference(v, globals)?)))
+                    .collect::<Option<_>>()?,
+            ),
+            Val::Macro(m) => Value::Macro(Box::new(self.macro_value(m, globals)?)),
+            Val::Str(s) => Value::Str(s.clone()),
+            Val::Array(a) => Value::Array(
+                a.iter()
+                    .map(|v| self.reference(v, globals))
+                    .collect::<Option<_>>()?,
+            ),
+            Val::Obj(params, trigger) => Value::Obj(
+                params.iter().map(|(k, p)| (*k, self.param(p))).collect(),
+                if *trigger {
+                    ast::ObjectMode::Trigger
+                } else {
+                    ast::ObjectMode::Object
+                },
+            ),
+            Val::Builtins => Value::Builtins,
+            Val::BuiltinFunction(name) => Value::BuiltinFunction(name.parse::<Builtin>().ok()?),
+            Val::TypeIndicator(t) => Value::TypeIndicator(*self.type_ids.get(t)?),
+            Val::Range(start, end, step) => Value::Range(*start, *end, *step),
+            Val::Pattern(p) => Value::Pattern(self.pattern(p, globals)?),
+            Val::Null => Value::Null,
+        })
+    }
+
+    fn macro_value(&self, m: &MacroDef, globals: &Globals) -> Option<Macro> {
+        let ast_macro = self.macros.get(&m.def_file)?.get(&m.arg_pos)?;
+        if ast_macro.args.len() != m.args.len() {
+            return None;
+        }
+        let mut args = Vec::new();
+        for ((name, default, pattern), (ast_name, _, attribute, _, position, arg_type)) in
+            m.args.iter().zip(&ast_macro.args)
+        {
+            if name != ast_name.as_ref() {
+                return None;
+            }
+            args.push(MacroArgDef {
+                name: *ast_name,
+                default: match default {
+                    Some(v) => Some(self.reference(v, globals)?),
+                    None => None,
+                },
+                attribute: attribute.clone(),
+                pattern: match pattern {
+                    Some(v) => Some(self.reference(v, globals)?),
+                    None => None,
+                },
+                position: *position,
+                arg_type: *arg_type,
+            });
+        }
+        Some(Macro {
+            args,
+            def_variables: m
+                .def_variables
+                .iter()
+                .map(|(k, v)| Some((LocalIntern::new(k.clone()), self.reference(v, globals)?)))
+                .collect::<Option<_>>()?,
+            def_file: LocalIntern::new(m.def_file.to_spwn_source()),
+            body: ast_macro.body.statements.clone(),
+            tag: ast::Attribute {
+                tags: ast_macro
+                    .properties
+                    .tags
+                    .iter()
+                    .cloned()
+                    .chain(m.added_tags.iter().map(attribute))
+                    .collect(),
+            },
+            arg_pos: m.arg_pos,
+            ret_pattern: match &m.ret_pattern {
+                Some(v) => Some(self.reference(v, globals)?),
+                None => None,
+            },
+        })
+    }
+
+    fn param(&self, p: &Param) -> ObjParam {
+        match p {
+            Param::Group(n) => ObjParam::Group(Group {
+                id: self.id(n, GROUP),
+            }),
+            Param::Color(n) => ObjParam::Color(Color {
+                id: self.id(n, COLOR),
+            }),
+            Param::Block(n) => ObjParam::Block(Block {
+                id: self.id(n, BLOCK),
+            }),
+            Param::Item(n) => ObjParam::Item(Item {
+                id: self.id(n, ITEM),
+            }),
+            Param::Number(n) => ObjParam::Number(*n),
+            Param::Bool(b) => ObjParam::Bool(*b),
+            Param::Text(t) => ObjParam::Text(t.clone()),
+            Param::GroupList(list) => ObjParam::GroupList(
+                list.iter()
+                    .map(|n| Group {
+                        id: self.id(n, GROUP),
+                    })
+                    .collect(),
+            ),
+            Param::Epsilon => ObjParam::Epsilon,
+        }
+    }
+
+    fn pattern(&self, pattern: &Pat, globals: &Globals) -> Option<Pattern> {
+        Some(match pattern {
+            Pat::Type(t) => Pattern::Type(*self.type_ids.get(t)?),
+            Pat::Array(a) => Pattern::Array(
+                a.iter()
+                    .map(|p| self.pattern(p, globals))
+                    .collect::<Option<_>>()?,
+            ),
+            Pat::Either(a, b) => Pattern::Either(
+                Box::new(self.pattern(a, globals)?),
+                Box::new(self.pattern(b, globals)?),
+            ),
+            Pat::Both(a, b) => Pattern::Both(
+                Box::new(self.pattern(a, globals)?),
+                Box::new(self.pattern(b, globals)?),
+            ),
+            Pat::Not(p) => Pattern::Not(Box::new(self.pattern(p, globals)?)),
+            Pat::Any => Pattern::Any,
+            Pat::Eq(v) => Pattern::Eq(self.reference(v, globals)?),
+            Pat::NotEq(v) => Pattern::NotEq(self.reference(v, globals)?),
+            Pat::MoreThan(v) => Pattern::MoreThan(self.reference(v, globals)?),
+            Pat::LessThan(v) => Pattern::LessThan(self.reference(v, globals)?),
+            Pat::MoreOrEq(v) => Pattern::MoreOrEq(self.reference(v, globals)?),
+            Pat::LessOrEq(v) => Pattern::LessOrEq(self.reference(v, globals)?),
+            Pat::In(v) => Pattern::In(self.reference(v, globals)?),
+            Pat::Macro { args, ret } => Pattern::Macro {
+                args: args
+                    .iter()
+                    .map(|p| self.pattern(p, globals))
+                    .collect::<Option<_>>()?,
+                ret: Box::new(self.pattern(ret, globals)?),
+            },
+        })
+    }
+}
+
+/// Loads the output of a library import stored by an earlier build, if it's still up to date.
+/// The files the library depends on are added to `globals.import_files`.
+pub fn load(globals: &mut Globals, library: &SpwnSource) -> Option<(StoredValue, Implementations)> {
+    let dir = globals.output_cache.as_ref()?;
+    let json = fs::read_to_string(entry_path(dir, library, &globals.permissions)).ok()?;
+    let entry: Entry = serde_json::from_str(&json).ok()?;
+    if entry.compiler != compiler_id() {
+        return None;
+    }
+
+    let mut texts = AHashMap::default();
+    for (file, hash) in &entry.files {
+        let text = file.read()?;
+        if hash_source(&text) != *hash {
+            return None;
+        }
+        texts.insert(file.clone(), text);
+    }
+    if entry
+        .level
+        .is_some_and(|hash| hash_source(&globals.initial_string) != hash)
+    {
+        return None;
+    }
+
+    // types defined by the library get the next free ids, unless an earlier import defined them
+    let mut type_ids: AHashMap<String, TypeId> = globals
+        .type_ids
+        .iter()
+        .map(|(name, (id, _))| (name.clone(), *id))
+        .collect();
+    let mut new_types = Vec::new();
+    let mut type_id_count = globals.type_id_count;
+    for typ in &entry.types {
+        let area = CodeArea {
+            file: LocalIntern::new(typ.file.to_spwn_source()),
+            pos: typ.pos,
+        };
+        match globals.type_ids.get(&typ.name) {
+            Some((_, defined)) if *defined == area => (),
+            Some(_) => return None,
+            None => {
+                type_id_count += 1;
+                type_ids.insert(typ.name.clone(), type_id_count);
+                new_types.push((typ, area, type_id_count));
+            }
+        }
+    }
+
+    let mut macros = AHashMap::default();
+    for stored in &entry.values {
+        if let Val::Macro(m) = &stored.value {
+            if !macros.contains_key(&m.def_file) {
+                let text = texts.get(&m.def_file)?.clone();
+                macros.insert(m.def_file.clone(), macros_in(&m.def_file, text)?);
+            }
+        }
+    }
+
+    let first_ids = Snapshot::take(globals).closed_ids;
+    let mut loader = Loader {
+        first_ids,
+        local: Vec::new(),
+        std: std_exports(globals).map(|(exports, imps)| (exports.clone(), imps.clone())),
+        type_ids: &type_ids,
+        macros,
+    };
+    for stored in &entry.values {
+        let fn_context = Group {
+            id: loader.id(&stored.fn_context, GROUP),
+        };
+        let area = CodeArea {
+            file: LocalIntern::new(stored.file.to_spwn_source()),
+            pos: stored.pos,
+        };
+        loader.local.push(store_val_m(
+            Value::Null,
+            globals,
+            fn_context,
+            !stored.mutable,
+            area,
+        ));
+    }
+
+    // values that fail to load are left unreferenced, and will be garbage collected
+    let mut values = Vec::new();
+    for stored in &entry.values {
+        values.push(loader.value(&stored.value, globals)?);
+    }
+    let output = loader.reference(&entry.output, globals)?;
+    let mut implementations = Implementations::default();
+    for (typ, name, v, in_scope) in &entry.implementations {
+        implementations
+            .entry(*type_ids.get(typ)?)
+            .or_default()
+            .insert(
+                LocalIntern::new(name.clone()),
+                (loader.reference(v, globals)?, *in_scope),
+            );
+    }
+    for (key, value) in loader.local.iter().zip(values) {
+        globals.stored_values[*key] = value;
+    }
+
+    for (typ, area, id) in new_types {
+        globals.type_ids.insert(typ.name.clone(), (id, area));
+        if let Some(desc) = &typ.desc {
+            globals.type_descriptions.insert(id, desc.clone());
+        }
+    }
+    globals.type_id_count = type_id_count;
+    globals.closed_groups += entry.arbitrary_ids[GROUP];
+    globals.closed_colors += entry.arbitrary_ids[COLOR];
+    globals.closed_blocks += entry.arbitrary_ids[BLOCK];
+    globals.closed_items += entry.arbitrary_ids[ITEM];
+    globals.import_files.extend(
+        entry
+            .files
+            .iter()
+            .map(|(file, hash)| (file.to_spwn_source(), *hash)),
+    );
+    if entry.level.is_some() {
+        globals.outside_reads.level += 1;
+    }
+
+    Some((output, implementations))
+}
@@ -859,7 +859,7 @@ impl Value {
                     out += "::";
                 }
                 out += "{";
-                let mut d_iter = d.iter();
+                let mut d_iter = sorted_entries(&d).into_iter();
                 for (count, (key, val)) in (&mut d_iter).enumerate() {
                     if count > MAX_DICT_EL_DISPLAY {
                         let left = d_iter.count();
@@ -869,7 +869,7 @@ impl Value {
                         break;
                     }
 
-                    let stored_val = display_inner(&globals.stored_values[*val].clone(), globals)?;
+                    let stored_val = display_inner(&globals.stored_values[val].clone(), globals)?;
                     out += &format!("{}: {}, ", key, stored_val);
                 }
                 if !d.is_empty() {
@@ -1314,6 +1314,16 @@ pub fn convert_type(
     })
 }
 
+/// The entries of a dictionary sorted by their keys, so going through a dictionary
+/// gives the same order (and the same IDs) every time
+pub fn sorted_entries(
+    dict: &AHashMap<LocalIntern<String>, StoredValue>,
+) -> Vec<(LocalIntern<String>, StoredValue)> {
+    let mut entries = dict.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>();
+    entries.sort_by(|(a, _), (b, _)| a.as_ref().cmp(b.as_ref()));
+    entries
+}
+
 //copied from https://stackoverflow.com/questions/59401720/how-do-i-find-the-key-for-a-value-in-a-hashmap
 pub fn find_key_for_value(
     map: &AHashMap<String, (u16, CodeArea)>,
@@ -1549,8 +1559,27 @@ impl VariableFuncs for ast::Variable {
                     }
                 }
                 ast::ValueBody::Id(id) => {
-                    full_context.inner().return_value = store_const_value(
-                        match id.class_name {
+                    let class = match id.class_name {
+                        IdClass::Group => 0,
+                        IdClass::Color => 1,
+                        IdClass::Block => 2,
+                        IdClass::Item => 3,
+                    };
+                    // a name that was used before is the same ID again
+                    let named = id
+                        .name
+                        .and_then(|name| globals.id_names[class].get(&name).copied());
+                    let value = match named {
+                        Some(named) => {
+                            let arbitrary = Id::Arbitrary(named);
+                            match id.class_name {
+                                IdClass::Group => Value::Group(Group { id: arbitrary }),
+                                IdClass::Color => Value::Color(Color { id: arbitrary }),
+                                IdClass::Block => Value::Block(Block { id: arbitrary }),
+                                IdClass::Item => Value::Item(Item { id: arbitrary }),
+                            }
+                        }
+                        None => match id.class_name {
                             IdClass::Group => {
                                 if id.unspecified {
                                     Value::Group(Group::next_free(&mut globals.closed_groups))
@@ -1580,10 +1609,26 @@ impl VariableFuncs for ast::Variable {
                                 }
                             }
                         },
+                    };
+                    let value = store_const_value(
+                        value,
                         globals,
                         full_context.inner().start_group,
                         info.position,
-                    )
+                    );
+                    if id.unspecified && named.is_none() {
+                        let counter = match id.class_name {
+                            IdClass::Group => globals.closed_groups,
+                            IdClass::Color => globals.closed_colors,
+                            IdClass::Block => globals.closed_blocks,
+                            IdClass::Item => globals.closed_items,
+                        };
+                        globals.id_origins[class].insert(counter, info.position);
+                        if let Some(name) = id.name {
+                            globals.id_names[class].insert(name, counter);
+                        }
+                    }
+                    full_context.inner().return_value = value;
                 }
                 ast::ValueBody::Number(num) => {
                     full_context.inner().return_value = store_const_value(
@@ -1874,7 +1919,7 @@ impl VariableFuncs for ast::Variable {
                             Value::Dict(d) => {
                                 // its a dict!
 
-                                for (k, v) in d {
+                                for (k, v) in sorted_entries(&d) {
                                     for c in context.iter() {
                                         let fn_context = c.inner().start_group;
                                         let key = store_val_m(
@@ -2860,6 +2905,16 @@ impl VariableFuncs for ast::Variable {
                             ))
                         }
 
+                        // values that GD would read as something else, like a color as an item ID
+                        if let Err(message) = crate::obj_schema::check_object(&obj) {
+                            return Err(RuntimeError::CustomError(create_error(
+                                info,
+                                &message,
+                                &[],
+                                None,
+                            )));
+                        }
+
                         context.inner().return_value = store_const_value(
                             Value::Obj(obj, o.mode),
                             globals,
@@ -3598,7 +3653,7 @@ impl VariableFuncs for ast::Variable {
             }
         }
 
-        merge_all_contexts(contexts, globals, true);
+        merge_all_contexts(contexts, globals, true, &info);
 
         Ok(())
     }
@@ -116,6 +116,25 @@ impl RainbowColorGenerator {
         )
     }
 }
+// removes the terminal color codes that are added to labels with `.fg()`
+pub fn strip_ansi(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // skip until the end of the escape sequence
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
 pub fn create_report(rep: ErrorReport) -> ariadne::Report<CodeArea> {
     use ariadne::{Config, Label, Report, ReportKind};
 
@@ -221,7 +240,7 @@ pub fn create_error(
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ErrorReport {
     pub info: CompilerInfo,
     pub message: String,
@@ -428,7 +447,7 @@ impl From<RuntimeError> for ErrorReport {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum SyntaxError {
     ExpectedErr {
         expected: String,
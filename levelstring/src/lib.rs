@@ -156,14 +156,10 @@ use std::io::Read;
 fn xor(data: Vec<u8>, key: u8) -> Vec<u8> {
     data.into_iter().map(|b| b ^ key).collect()
 }
-fn base_64_decrypt(encoded: Vec<u8>) -> Vec<u8> {
+fn base_64_decrypt(encoded: Vec<u8>) -> Result<Vec<u8>, String> {
     let l = encoded.len();
-    base64::decode(
-        String::from_utf8([encoded, b"=".repeat(l % 4)].concat())
-            .unwrap()
-            .as_str(),
-    )
-    .unwrap()
+    base64::decode([encoded, b"=".repeat((4 - l % 4) % 4)].concat())
+        .map_err(|e| format!("Level data is not valid base64: {}", e))
 }
 
 use quick_xml::events::{BytesText, Event};
@@ -185,7 +181,7 @@ fn decrypt_savefile(mut sf: Vec<u8>) -> Result<Vec<u8>, String> {
         type AesEcb = Ecb<Aes256, Pkcs7>;
 
         // re-create cipher mode instance
-        let cipher = AesEcb::new_from_slices(IOS_KEY, &[]).unwrap();
+        let cipher = AesEcb::new_from_slices(IOS_KEY, &[]).map_err(|e| format!("{}", e))?;
 
         Ok(match cipher.decrypt(&mut sf) {
             Ok(v) => v,
@@ -202,9 +198,10 @@ fn decrypt_savefile(mut sf: Vec<u8>) -> Result<Vec<u8>, String> {
             Ok(b) => b,
             Err(e) => return Err(format!("{}", e)),
         };
-        let mut decoder = gzip::Decoder::new(&b64[..]).unwrap();
         let mut data = Vec::new();
-        decoder.read_to_end(&mut data).unwrap();
+        gzip::Decoder::new(&b64[..])
+            .and_then(|mut decoder| decoder.read_to_end(&mut data))
+            .map_err(|e| format!("Save file could not be decompressed: {}", e))?;
         Ok(data)
     }
 }
@@ -228,7 +225,9 @@ pub fn get_level_string(ls: Vec<u8>, level_name: Option<&String>) -> Result<Stri
         match reader.read_event(&mut buf) {
             // unescape and decode the text event using the reader encoding
             Ok(Event::Text(e)) => {
-                let text = e.unescape_and_decode(&reader).unwrap();
+                let text = e
+                    .unescape_and_decode(&reader)
+                    .map_err(|e| xml_error(&reader, e))?;
 
                 if text == "k2" {
                     k2_detected = true;
@@ -257,7 +256,7 @@ pub fn get_level_string(ls: Vec<u8>, level_name: Option<&String>) -> Result<Stri
             }
 
             Ok(Event::Eof) => break, // exits the loop when reaching end of file
-            Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
+            Err(e) => return Err(xml_error(&reader, e)),
             _ => (), // There are several other `Event`s we do not consider here
         }
 
@@ -293,28 +292,101 @@ pub fn get_level_string(ls: Vec<u8>, level_name: Option<&String>) -> Result<Stri
         }
     }*/
     //decrypting level string
+    decode_level_string(&level_string)
+}
+
+fn xml_error<B: std::io::BufRead>(reader: &Reader<B>, e: quick_xml::Error) -> String {
+    format!(
+        "Error reading the XML at byte {}: {}",
+        reader.buffer_position(),
+        e
+    )
+}
+
+use quick_xml::Writer;
+use std::fs;
+use std::io::Cursor;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// the start object of a freshly created level, used when there is no existing level to build on
+pub const DEFAULT_LEVEL_HEADER: &str = "kS38,1_40_2_125_3_255_11_255_12_255_13_255_4_-1_6_1000_7_1_15_1_18_0_8_1|1_0_2_102_3_255_11_255_12_255_13_255_4_-1_6_1001_7_1_15_1_18_0_8_1|1_0_2_102_3_255_11_255_12_255_13_255_4_-1_6_1009_7_1_15_1_18_0_8_1|1_255_2_255_3_255_11_255_12_255_13_255_4_-1_6_1002_5_1_7_1_15_1_18_0_8_1|1_255_2_255_3_255_11_255_12_255_13_255_4_-1_6_1005_5_1_7_1_15_1_18_0_8_1|1_255_2_255_3_255_11_255_12_255_13_255_4_-1_6_1006_5_1_7_1_15_1_18_0_8_1|,kA13,0,kA15,0,kA16,0,kA14,,kA6,0,kA7,0,kA17,0,kA18,0,kS39,0,kA2,0,kA3,0,kA8,0,kA4,0,kA9,0,kA10,0,kA11,0;";
+
+// gzips and base64 encodes a level string the same way GD stores it (the `k4` key)
+pub fn encode_level_string(ls: &str) -> String {
+    let mut ls_encoder = gzip::Encoder::new(Vec::new()).unwrap();
+    ls_encoder.write_all(ls.as_bytes()).unwrap();
+    let b64_encrypted = base64::encode(ls_encoder.finish().into_result().unwrap());
+    let fin = b64_encrypted.replace('+', "-").replace('/', "_");
+    "H4sIAAAAAAAAC".to_string() + &fin[13..]
+}
+
+// inverse of `encode_level_string`
+pub fn decode_level_string(encoded: &str) -> Result<String, String> {
     let ls_b64 = base_64_decrypt(
-        level_string
+        encoded
             .replace('-', "+")
             .replace('_', "/")
             .replace('\0', "")
             .as_bytes()
             .to_vec(),
-    );
-
-    //println!("{}", String::from_utf8(ls_b64.clone()).unwrap());
+    )?;
 
-    let mut ls_decoder = gzip::Decoder::new(&ls_b64[..]).unwrap();
     let mut ls_buf = Vec::new();
-    ls_decoder.read_to_end(&mut ls_buf).unwrap();
+    gzip::Decoder::new(&ls_b64[..])
+        .and_then(|mut decoder| decoder.read_to_end(&mut ls_buf))
+        .map_err(|e| format!("Level data could not be decompressed: {}", e))?;
 
-    Ok(String::from_utf8(ls_buf).unwrap())
+    String::from_utf8(ls_buf).map_err(|_| "Level data is not valid UTF-8".to_string())
 }
 
-use quick_xml::Writer;
-use std::fs;
-use std::io::Cursor;
-use std::path::PathBuf;
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// a standalone level file (.gmd), in the format used by GDShare to import and export levels
+pub fn export_gmd(ls: &str, level_name: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\"?><plist version=\"1.0\" gjver=\"2.0\"><dict><k>kCEK</k><i>4</i><k>k2</k><s>{}</s><k>k4</k><s>{}</s><k>k13</k><t /><k>k21</k><i>2</i><k>k50</k><i>35</i></dict></plist>",
+        escape_xml(level_name),
+        encode_level_string(ls),
+    )
+}
+
+// the level string in a standalone level file, the inverse of `export_gmd`
+pub fn import_gmd(gmd: &str) -> Result<String, String> {
+    let mut reader = Reader::from_str(gmd);
+    reader.trim_text(true);
+
+    let mut buf = Vec::new();
+    // only the texts of `<k>` elements are keys, since the level name could be "k4" too
+    let mut in_key = false;
+    let mut k4_detected = false;
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(e)) => in_key = e.name() == b"k",
+            Ok(Event::End(_)) => in_key = false,
+            Ok(Event::Text(e)) => {
+                let text = e
+                    .unescape_and_decode(&reader)
+                    .map_err(|e| xml_error(&reader, e))?;
+                if in_key {
+                    k4_detected = text == "k4";
+                } else if k4_detected {
+                    return decode_level_string(&text);
+                }
+            }
+            Ok(Event::Eof) => return Err("The level file has no level string in it".to_string()),
+            Err(e) => return Err(xml_error(&reader, e)),
+            _ => (),
+        }
+        buf.clear();
+    }
+}
 
 pub fn encrypt_level_string(
     ls: String,
@@ -322,9 +394,8 @@ pub fn encrypt_level_string(
     path: PathBuf,
     level_name: Option<String>,
 ) -> Result<(), String> {
-    let mut file = fs::File::open(path.clone()).unwrap();
-    let mut file_content = Vec::new();
-    file.read_to_end(&mut file_content).unwrap();
+    let file_content =
+        fs::read(&path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
 
     //decrypting the savefile
     let content = decrypt_savefile(file_content)?;
@@ -350,27 +421,24 @@ pub fn encrypt_level_string(
         match reader.read_event(&mut buf) {
             // unescape and decode the text event using the reader encoding
             Ok(Event::Text(e)) => {
-                let text = e.unescape_and_decode(&reader).unwrap();
+                let text = e
+                    .unescape_and_decode(&reader)
+                    .map_err(|e| xml_error(&reader, e))?;
                 if k4_detected && level_detected {
-                    let encrypted_ls: String = {
-                        let mut ls_encoder = gzip::Encoder::new(Vec::new()).unwrap();
-                        ls_encoder.write_all(full_ls.as_bytes()).unwrap();
-                        let b64_encrypted =
-                            base64::encode(&ls_encoder.finish().into_result().unwrap());
-                        let fin = b64_encrypted.replace('+', "-").replace('/', "_");
-                        "H4sIAAAAAAAAC".to_string() + &fin[13..]
-                    };
-
-                    assert!(writer
+                    let encrypted_ls = encode_level_string(&full_ls);
+
+                    writer
                         .write_event(Event::Text(BytesText::from_plain_str(&encrypted_ls)))
-                        .is_ok());
+                        .map_err(|e| format!("{}", e))?;
                     done = true;
                     k4_detected = false;
                 } else {
                     if k4_detected {
                         k4_detected = false;
                     }
-                    assert!(writer.write_event(Event::Text(e)).is_ok());
+                    writer
+                        .write_event(Event::Text(e))
+                        .map_err(|e| format!("{}", e))?;
 
                     if k2_detected {
                         if let Some(level_name) = &level_name {
@@ -396,16 +464,22 @@ pub fn encrypt_level_string(
                 }
             }
             Ok(Event::Eof) => break, // exits the loop when reaching end of file
-            Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
-            Ok(e) => assert!(writer.write_event(e).is_ok()),
+            Err(e) => return Err(xml_error(&reader, e)),
+            Ok(e) => writer.write_event(e).map_err(|e| format!("{}", e))?,
         }
 
         // if we don't keep a borrow elsewhere, we can clear the buffer to keep memory usage low
         buf.clear();
     }
+    if !done {
+        // leave the save alone instead of writing it back unchanged
+        return Err(match level_name {
+            Some(level_name) => format!("Level named \"{}\" was not found!", level_name),
+            None => "No level found! Please create a level for SPWN to operate on!".to_string(),
+        });
+    }
     let bytes = writer.into_inner().into_inner();
     //encrypt level save
-    use std::io::Write;
 
     if cfg!(target_os = "macos") {
         use aes::Aes256;
@@ -422,14 +496,17 @@ pub fn encrypt_level_string(
         type AesEcb = Ecb<Aes256, Pkcs7>;
 
         // re-create cipher mode instance
-        let cipher = AesEcb::new_from_slices(IOS_KEY, &[]).unwrap();
+        let cipher = AesEcb::new_from_slices(IOS_KEY, &[]).map_err(|e| format!("{}", e))?;
 
         let fin = cipher.encrypt_vec(&bytes);
-        assert!(fs::write(path, fin).is_ok());
+        write_save_file(&path, &fin)?;
     } else {
-        let mut encoder = zlib::Encoder::new(Vec::new()).unwrap();
-        encoder.write_all(&bytes).unwrap();
-        let compressed = encoder.finish().into_result().unwrap();
+        let compressed = zlib::Encoder::new(Vec::new())
+            .and_then(|mut encoder| {
+                encoder.write_all(&bytes)?;
+                encoder.finish().into_result()
+            })
+            .map_err(|e| format!("Save file could not be compressed: {}", e))?;
         use crc32fast::Hasher;
 
         let mut hasher = Hasher::new();
@@ -450,7 +527,147 @@ pub fn encrypt_level_string(
             .to_vec();
 
         let fin = xor(encoded, 11);
-        assert!(fs::write(path, fin).is_ok());
+        write_save_file(&path, &fin)?;
+    }
+    Ok(())
+}
+
+// how many of the newest backups of a save file are kept, the ones after this are deleted (except
+// the oldest one, which has the save from before SPWN first wrote to it)
+pub const BACKUPS_KEPT: usize = 10;
+
+// backups are kept in a folder next to the save file
+pub fn backup_dir(save: &Path) -> PathBuf {
+    save.with_file_name("spwn-backups")
+}
+
+fn save_name(save: &Path) -> String {
+    save.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+// the time a backup was made, and a number that tells apart backups made in the same second
+fn backup_stamp(save_name: &str, backup: &Path) -> Option<(String, u32)> {
+    let name = backup.file_name()?.to_str()?;
+    let stamp = name
+        .strip_prefix(save_name)?
+        .strip_prefix('.')?
+        .strip_suffix(".bak")?;
+    match stamp.split_once('_') {
+        Some((date, rest)) => match rest.split_once('_') {
+            Some((time, n)) => Some((format!("{}_{}", date, time), n.parse().ok()?)),
+            None => Some((stamp.to_string(), 1)),
+        },
+        None => None,
+    }
+}
+
+// UTC, as `YYYY-MM-DD_HH-MM-SS`
+fn timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+    // days since 1970-01-01 to a civil date (Howard Hinnant's `civil_from_days`)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+// the backups of a save file, newest first
+pub fn list_backups(save: &Path) -> Result<Vec<PathBuf>, String> {
+    let dir = backup_dir(save);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let name = save_name(save);
+    let entries =
+        fs::read_dir(&dir).map_err(|e| format!("Could not read {}: {}", dir.display(), e))?;
+    let mut backups = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter_map(|path| backup_stamp(&name, &path).map(|stamp| (stamp, path)))
+        .collect::<Vec<_>>();
+    backups.sort_by(|a, b| b.0.cmp(&a.0));
+    Ok(backups.into_iter().map(|(_, path)| path).collect())
+}
+
+// copies the save file into the backup folder, and deletes the backups past `BACKUPS_KEPT` other
+// than the oldest one
+pub fn backup_save_file(save: &Path) -> Result<Option<PathBuf>, String> {
+    if !save.exists() {
+        return Ok(None);
+    }
+    let dir = backup_dir(save);
+    fs::create_dir_all(&dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+
+    let name = save_name(save);
+    let stamp = timestamp(SystemTime::now());
+    // numbered past the newest backup from this second, since deleted ones free their names
+    let made = list_backups(save)?
+        .iter()
+        .filter_map(|backup| backup_stamp(&name, backup))
+        .filter(|(s, _)| *s == stamp)
+        .map(|(_, n)| n)
+        .max();
+    let backup = match made {
+        None => dir.join(format!("{}.{}.bak", name, stamp)),
+        Some(n) => dir.join(format!("{}.{}_{}.bak", name, stamp, n + 1)),
+    };
+    fs::copy(save, &backup).map_err(|e| {
+        format!(
+            "Could not back up {} to {}: {}",
+            save.display(),
+            backup.display(),
+            e
+        )
+    })?;
+
+    let backups = list_backups(save)?;
+    let oldest = backups.len().saturating_sub(1);
+    for old in backups.iter().take(oldest).skip(BACKUPS_KEPT) {
+        fs::remove_file(old)
+            .map_err(|e| format!("Could not delete old backup {}: {}", old.display(), e))?;
+    }
+    Ok(Some(backup))
+}
+
+// backs up the save file, then replaces it without ever leaving it half written:
+// the data goes to a temporary file next to it first, which is then renamed over it
+pub fn write_save_file(save: &Path, data: &[u8]) -> Result<(), String> {
+    backup_save_file(save)?;
+
+    let temp = save.with_file_name(format!("{}.spwn-tmp", save_name(save)));
+    let written = fs::File::create(&temp).and_then(|mut file| {
+        file.write_all(data)?;
+        file.sync_all()
+    });
+    if let Err(e) = written.and_then(|_| fs::rename(&temp, save)) {
+        fs::remove_file(&temp).ok();
+        return Err(format!("Could not write {}: {}", save.display(), e));
     }
     Ok(())
 }
+
+// puts a backup back in place of the save file, which is backed up first
+pub fn restore_backup(save: &Path, backup: &Path) -> Result<(), String> {
+    let data =
+        fs::read(backup).map_err(|e| format!("Could not read {}: {}", backup.display(), e))?;
+    write_save_file(save, &data)
+}
@@ -1,21 +1,24 @@
 use compiler::{
-    builtins::{Group, Id},
-    leveldata::ObjParam,
+    builtins::{Color, Group, Id, Item},
+    leveldata::{GdObj, ObjParam},
 };
 
 use crate::{
-    obj_props, optimize::is_start_group, ReservedIds, TriggerNetwork, TriggerRole, Triggerlist,
+    changed_prop, obj_props, optimize::is_start_group, passes::DroppedTrigger, ReservedIds,
+    TriggerNetwork, TriggerRole, Triggerlist,
 };
 
 // performes a DFS from each start group, and removes all triggers that:
 // - dont lead to an output trigger
 // - are not reachable from a start group
+// - change a group, color or item ID that nothing else uses (these are added to `dropped`)
 
 pub fn dead_code_optimization(
     network: &mut TriggerNetwork,
     objects: &mut Triggerlist,
     //closed_group: &mut u16,
     reserved: &ReservedIds,
+    dropped: &mut Vec<DroppedTrigger>,
 ) {
     for (group, gang) in network.map.clone() {
         if is_start_group(group, reserved) {
@@ -28,6 +31,7 @@ pub fn dead_code_optimization(
                     //closed_group,
                     reserved,
                     &mut visited,
+                    dropped,
                 ) == DeadCodeResult::Keep
                 {
                     (*network.map.get_mut(&group).unwrap()).triggers[i].deleted = false;
@@ -51,6 +55,7 @@ fn check_for_dead_code<'a>(
     //closed_group: &mut u16,
     reserved: &ReservedIds,
     visited_stack: &mut Vec<(Group, usize)>,
+    dropped: &mut Vec<DroppedTrigger>,
 ) -> DeadCodeResult {
     use DeadCodeResult::*;
     //returns whether to keep or delete the trigger
@@ -60,23 +65,16 @@ fn check_for_dead_code<'a>(
     }
 
     if trigger.role == TriggerRole::Output {
-        if let Some(ObjParam::Group(Group {
-            id: i @ Id::Arbitrary(_),
-        })) = objects[trigger.obj].0.params.get(&obj_props::TARGET)
-        {
-            // if let Some(ObjParam::Number(id)) = objects[trigger.obj].0.params.get(&1) {
-            //     if matches!(*id as u16, obj_ids::TOGGLE | 1616) // toggle or stop trigger
-            //         && !reserved.object_groups.contains(i)
-            //         && !reserved.trigger_groups.contains(i)
-            //     {
-            //         dbg!(i);
-            //         return false;
-            //     }
-            // }
-
-            if !reserved.object_groups.contains(i) && !reserved.trigger_groups.contains(i) {
-                return Delete;
+        let obj = &objects[trigger.obj].0;
+        if let Some(class) = unused_target(obj, reserved) {
+            if !dropped.iter().any(|d| d.ptr == trigger.obj) {
+                dropped.push(DroppedTrigger {
+                    ptr: trigger.obj,
+                    object: obj.clone(),
+                    class,
+                });
             }
+            return Delete;
         }
         (*network.map.get_mut(&start.0).unwrap()).triggers[start.1].deleted = false;
         return Keep;
@@ -132,6 +130,7 @@ fn check_for_dead_code<'a>(
             //closed_group,
             reserved,
             visited_stack,
+            dropped,
         ) == Keep
         {
             (*network.map.get_mut(&trigger_ptr.0).unwrap()).triggers[trigger_ptr.1].deleted = false;
@@ -143,3 +142,25 @@ fn check_for_dead_code<'a>(
 
     out
 }
+
+// the kind of ID an output trigger changes, if it's an arbitrary ID
+// that no object has and no other trigger uses
+fn unused_target(obj: &GdObj, reserved: &ReservedIds) -> Option<&'static str> {
+    match obj.params.get(&changed_prop(obj)?)? {
+        ObjParam::Group(Group {
+            id: id @ Id::Arbitrary(_),
+        }) => (!reserved.object_groups.contains(id)
+            && !reserved.trigger_groups.contains(id)
+            && !reserved.read_groups.contains(id))
+        .then_some("group"),
+        ObjParam::Color(Color {
+            id: id @ Id::Arbitrary(_),
+        }) => (!reserved.object_colors.contains(id) && !reserved.read_colors.contains(id))
+            .then_some("color"),
+        ObjParam::Item(Item {
+            id: id @ Id::Arbitrary(_),
+        }) => (!reserved.object_items.contains(id) && !reserved.read_items.contains(id))
+            .then_some("item ID"),
+        _ => None,
+    }
+}
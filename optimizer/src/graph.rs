@@ -0,0 +1,222 @@
+// the triggers of a level as a graph of groups and the triggers in them,
+// for looking at what the optimizer did
+
+use std::fmt::Write;
+
+use compiler::builtins::{Group, Id};
+use compiler::compiler_types::FunctionId;
+use compiler::leveldata::{GdObj, ObjParam};
+
+use crate::optimize::build_network;
+use crate::{obj_ids, obj_props, trigger_name, TriggerRole, NO_GROUP};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Group,
+    Trigger(TriggerRole),
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphNode {
+    pub id: String,
+    pub label: String,
+    pub kind: NodeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    // from a group to a trigger in it
+    Contains,
+    // from a trigger to the group it spawns or toggles on
+    Activates,
+    // from a trigger to the group it toggles off or stops
+    Deactivates,
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphEdge {
+    pub from: String,
+    pub to: String,
+    pub kind: EdgeKind,
+    // the spawn delay in seconds
+    pub delay: Option<f64>,
+    pub order: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TriggerGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+impl TriggerGraph {
+    pub fn from_triggers(func_ids: &[FunctionId]) -> Self {
+        let network = build_network(func_ids);
+        let mut graph = TriggerGraph::default();
+
+        // sorted, so the graphs of two builds can be compared
+        let mut groups = network.map.iter().collect::<Vec<_>>();
+        groups.sort_by_key(|(group, _)| group_sort_key(**group));
+
+        let mut group_nodes = groups.iter().map(|(group, _)| **group).collect::<Vec<_>>();
+
+        for (group, gang) in groups {
+            let mut triggers = gang.triggers.clone();
+            triggers.sort_by(|a, b| {
+                let order = |t: &crate::Trigger| func_ids[t.obj.0].obj_list[t.obj.1].1 .0;
+                order(a)
+                    .partial_cmp(&order(b))
+                    .unwrap()
+                    .then(a.obj.cmp(&b.obj))
+            });
+
+            for trigger in triggers {
+                let (obj, order) = &func_ids[trigger.obj.0].obj_list[trigger.obj.1];
+                let id = format!("t{}_{}", trigger.obj.0, trigger.obj.1);
+                graph.nodes.push(GraphNode {
+                    id: id.clone(),
+                    label: trigger_name(obj),
+                    kind: NodeKind::Trigger(trigger.role),
+                });
+                graph.edges.push(GraphEdge {
+                    from: group_id(*group),
+                    to: id.clone(),
+                    kind: EdgeKind::Contains,
+                    delay: None,
+                    order: order.0,
+                });
+
+                if let Some((target, kind)) = activated_group(obj) {
+                    if !group_nodes.contains(&target) {
+                        group_nodes.push(target);
+                    }
+                    let delay = match obj.params.get(&63) {
+                        Some(ObjParam::Number(d)) => *d,
+                        _ => 0.0,
+                    };
+                    graph.edges.push(GraphEdge {
+                        from: id,
+                        to: group_id(target),
+                        kind,
+                        delay: Some(delay).filter(|_| object_id(obj) == Some(obj_ids::SPAWN)),
+                        order: order.0,
+                    });
+                }
+            }
+        }
+
+        group_nodes.sort_by_key(|g| group_sort_key(*g));
+        let group_nodes = group_nodes.into_iter().map(|group| GraphNode {
+            id: group_id(group),
+            label: group_label(group),
+            kind: NodeKind::Group,
+        });
+        graph.nodes.splice(0..0, group_nodes);
+        graph
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph triggers {\n    rankdir=LR;\n");
+        for node in &self.nodes {
+            let attributes = match node.kind {
+                NodeKind::Group => String::from("shape=ellipse"),
+                NodeKind::Trigger(role) => format!(
+                    "shape=box, style=filled, fillcolor=\"{}\"",
+                    match role {
+                        TriggerRole::Spawn => "lightblue",
+                        TriggerRole::Output => "orange",
+                        TriggerRole::Func => "palegreen",
+                    }
+                ),
+            };
+            writeln!(
+                out,
+                "    {} [label=\"{}\", {}];",
+                node.id,
+                node.label.replace('"', "\\\""),
+                attributes
+            )
+            .unwrap();
+        }
+        for edge in &self.edges {
+            let mut label = match edge.delay {
+                Some(delay) => format!("{}s, ", delay),
+                None => String::new(),
+            };
+            write!(label, "order {}", edge.order).unwrap();
+            let style = match edge.kind {
+                EdgeKind::Contains => ", color=gray, fontcolor=gray",
+                EdgeKind::Activates => "",
+                EdgeKind::Deactivates => ", color=red, fontcolor=red, style=dashed",
+            };
+            writeln!(
+                out,
+                "    {} -> {} [label=\"{}\"{}];",
+                edge.from, edge.to, label, style
+            )
+            .unwrap();
+        }
+        out += "}\n";
+        out
+    }
+}
+
+fn group_sort_key(group: Group) -> (bool, u16) {
+    match group.id {
+        Id::Specific(id) => (false, id),
+        Id::Arbitrary(id) => (true, id),
+    }
+}
+
+fn group_id(group: Group) -> String {
+    match group.id {
+        Id::Specific(id) => format!("g{}", id),
+        Id::Arbitrary(id) => format!("a{}", id),
+    }
+}
+
+fn group_label(group: Group) -> String {
+    if group == NO_GROUP {
+        return String::from("no group");
+    }
+    match group.id {
+        Id::Specific(id) => format!("{}g", id),
+        Id::Arbitrary(id) => format!("?g{}", id),
+    }
+}
+
+fn object_id(obj: &GdObj) -> Option<u16> {
+    match obj.params.get(&1) {
+        Some(ObjParam::Number(id)) => Some(*id as u16),
+        _ => None,
+    }
+}
+
+// the group a spawn, toggle, count (or similar) trigger controls
+fn activated_group(obj: &GdObj) -> Option<(Group, EdgeKind)> {
+    let target = match obj.params.get(&obj_props::TARGET) {
+        Some(ObjParam::Group(g)) => *g,
+        _ => return None,
+    };
+    let activates = !matches!(
+        obj.params.get(&obj_props::ACTIVATE_GROUP),
+        Some(ObjParam::Bool(false)) | None
+    );
+    let kind = match object_id(obj)? {
+        obj_ids::SPAWN | obj_ids::TOUCH => EdgeKind::Activates,
+        obj_ids::STOP => EdgeKind::Deactivates,
+        obj_ids::COUNT
+        | obj_ids::INSTANT_COUNT
+        | obj_ids::COLLISION
+        | obj_ids::ON_DEATH
+        | obj_ids::TOGGLE => {
+            if activates {
+                EdgeKind::Activates
+            } else {
+                EdgeKind::Deactivates
+            }
+        }
+        _ => return None,
+    };
+    Some((target, kind))
+}
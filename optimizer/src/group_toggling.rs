@@ -427,6 +427,7 @@ pub fn create_toggle_trigger(
         func_id: obj.0,
         mode: ObjectMode::Trigger,
         unique_id: objects[obj].0.unique_id,
+        origin: objects[obj].0.origin.clone(),
     };
 
     (*objects.list)[obj.0]
@@ -1,17 +1,24 @@
 use compiler::{
     builtins::{Group, Id},
     compiler_types::{FunctionId, TriggerOrder},
+    globals::Globals,
     leveldata::{self, GdObj, ObjParam},
 };
-use ahash::{AHashMap, AHashSet};
+use ahash::AHashSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 mod dead_code;
+pub mod graph;
 mod group_toggling;
 pub mod optimize;
+pub mod passes;
+pub mod simulate;
 mod spawn_optimisation;
 mod trigger_dedup;
 
-pub type Swaps = AHashMap<Group, (Group, TriggerOrder)>;
+// the optimizer only uses sorted maps for things it goes through,
+// so the same triggers are always optimized the same way
+pub type Swaps = BTreeMap<Group, (Group, TriggerOrder)>;
 
 mod obj_ids {
     #![allow(dead_code)]
@@ -45,6 +52,8 @@ pub mod obj_props {
     pub const TARGET: u16 = 51;
     pub const GROUPS: u16 = 57;
     pub const ACTIVATE_GROUP: u16 = 56;
+    pub const TARGET_COLOR: u16 = 23;
+    pub const ITEM: u16 = 80;
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
@@ -71,6 +80,12 @@ pub struct ReservedIds {
     pub object_blocks: AHashSet<Id>,
 
     pub object_items: AHashSet<Id>,
+
+    // ids that triggers use in any prop other than obj_props::GROUPS
+    // and the one they change (see `changed_prop`)
+    pub read_groups: AHashSet<Id>,
+    pub read_colors: AHashSet<Id>,
+    pub read_items: AHashSet<Id>,
 }
 
 impl ReservedIds {
@@ -83,6 +98,10 @@ impl ReservedIds {
             object_blocks: Default::default(),
 
             object_items: Default::default(),
+
+            read_groups: Default::default(),
+            read_colors: Default::default(),
+            read_items: Default::default(),
         };
         for obj in objects {
             for param in obj.params.values() {
@@ -112,23 +131,63 @@ impl ReservedIds {
 
         for fn_id in func_ids {
             for (trigger, _) in &fn_id.obj_list {
-                for (prop, param) in trigger.params.iter() {
-                    if *prop == 57 {
-                        match &param {
-                            leveldata::ObjParam::Group(g) => {
-                                reserved.trigger_groups.insert(g.id);
-                            }
-                            leveldata::ObjParam::GroupList(g) => {
-                                reserved.trigger_groups.extend(g.iter().map(|g| g.id));
-                            }
-
-                            _ => (),
-                        }
+                reserved.add_trigger(trigger);
+            }
+        }
+        reserved
+    }
+
+    /// Like `from_objects`, but also keeps the named arbitrary IDs (like `?g("door")`), which are
+    /// there for objects placed by hand that the optimizer can't see
+    pub fn from_globals(globals: &Globals) -> Self {
+        let mut reserved = Self::from_objects(&globals.objects, &globals.func_ids);
+        for (class, names) in globals.id_names.iter().enumerate() {
+            let ids = names.values().map(|id| Id::Arbitrary(*id));
+            match class {
+                0 => reserved.object_groups.extend(ids),
+                1 => reserved.object_colors.extend(ids),
+                2 => reserved.object_blocks.extend(ids),
+                _ => reserved.object_items.extend(ids),
+            }
+        }
+        reserved
+    }
+
+    pub(crate) fn add_trigger(&mut self, trigger: &GdObj) {
+        let changed = match get_role(trigger) {
+            TriggerRole::Output => changed_prop(trigger),
+            _ => None,
+        };
+        for (prop, param) in trigger.params.iter() {
+            if *prop == obj_props::GROUPS {
+                match &param {
+                    leveldata::ObjParam::Group(g) => {
+                        self.trigger_groups.insert(g.id);
                     }
+                    leveldata::ObjParam::GroupList(g) => {
+                        self.trigger_groups.extend(g.iter().map(|g| g.id));
+                    }
+
+                    _ => (),
+                }
+            } else if Some(*prop) != changed {
+                match &param {
+                    leveldata::ObjParam::Group(g) => {
+                        self.read_groups.insert(g.id);
+                    }
+                    leveldata::ObjParam::GroupList(g) => {
+                        self.read_groups.extend(g.iter().map(|g| g.id));
+                    }
+                    leveldata::ObjParam::Color(g) => {
+                        self.read_colors.insert(g.id);
+                    }
+                    leveldata::ObjParam::Item(g) => {
+                        self.read_items.insert(g.id);
+                    }
+                    _ => (),
                 }
             }
         }
-        reserved
     }
 }
 
@@ -137,8 +196,8 @@ pub struct ObjPtr(usize, usize);
 //                                     triggers      connections in
 #[derive(Default)]
 pub struct TriggerNetwork {
-    map: AHashMap<Group, TriggerGang>,
-    connectors: AHashMap<Group, AHashSet<ObjPtr>>,
+    pub map: BTreeMap<Group, TriggerGang>,
+    pub connectors: BTreeMap<Group, BTreeSet<ObjPtr>>,
 }
 
 #[derive(Debug, Clone)]
@@ -258,6 +317,54 @@ pub fn get_role(obj: &GdObj) -> TriggerRole {
     }
 }
 
+// the prop with the group, color or item an output trigger changes,
+// like the group a move trigger moves
+pub fn changed_prop(obj: &GdObj) -> Option<u16> {
+    match obj.params.get(&1) {
+        Some(ObjParam::Number(obj_id)) => Some(match *obj_id as u16 {
+            obj_ids::COLOR => obj_props::TARGET_COLOR,
+            obj_ids::PICKUP => obj_props::ITEM,
+            _ => obj_props::TARGET,
+        }),
+        _ => None,
+    }
+}
+
+pub fn trigger_name(obj: &GdObj) -> String {
+    let id = match obj.params.get(&1) {
+        Some(ObjParam::Number(id)) => *id as u16,
+        _ => return String::from("object"),
+    };
+    match id {
+        obj_ids::MOVE => "move",
+        obj_ids::ROTATE => "rotate",
+        obj_ids::ANIMATE => "animate",
+        obj_ids::PULSE => "pulse",
+        obj_ids::COUNT => "count",
+        obj_ids::ALPHA => "alpha",
+        obj_ids::TOGGLE => "toggle",
+        obj_ids::FOLLOW => "follow",
+        obj_ids::SPAWN => "spawn",
+        obj_ids::STOP => "stop",
+        obj_ids::TOUCH => "touch",
+        obj_ids::INSTANT_COUNT => "instant count",
+        obj_ids::ON_DEATH => "on death",
+        obj_ids::FOLLOW_PLAYER_Y => "follow player y",
+        obj_ids::COLLISION => "collision",
+        obj_ids::PICKUP => "pickup",
+        obj_ids::BG_EFFECT_ON => "bg effect on",
+        obj_ids::BG_EFFECT_OFF => "bg effect off",
+        obj_ids::SHAKE => "shake",
+        obj_ids::COLOR => "color",
+        obj_ids::ENABLE_TRAIL => "enable trail",
+        obj_ids::DISABLE_TRAIL => "disable trail",
+        obj_ids::HIDE => "hide",
+        obj_ids::SHOW => "show",
+        _ => return format!("object {}", id),
+    }
+    .to_string()
+}
+
 pub const NO_GROUP: Group = Group {
     id: Id::Specific(0),
 };
@@ -2,10 +2,10 @@ use compiler::builtins::{Group, Id};
 use compiler::compiler_types::{FunctionId, TriggerOrder};
 use parser::ast::ObjectMode;
 
+use crate::passes::{role_counts, PassContext, Pipeline};
 use crate::{
-    dead_code, get_role, group_toggling, obj_ids, obj_props, spawn_optimisation, trigger_dedup,
-    ObjPtr, ReservedIds, Swaps, Trigger, TriggerGang, TriggerNetwork, TriggerRole, Triggerlist,
-    NO_GROUP,
+    get_role, obj_ids, obj_props, ObjPtr, ReservedIds, Swaps, Trigger, TriggerGang, TriggerNetwork,
+    TriggerRole, Triggerlist, NO_GROUP,
 };
 
 //mod icalgebra;
@@ -14,50 +14,28 @@ use compiler::leveldata::{GdObj, ObjParam};
 use ahash::AHashMap;
 
 pub fn optimize(
+    obj_in: Vec<FunctionId>,
+    closed_group: u16,
+    reserved: ReservedIds,
+) -> Vec<FunctionId> {
+    optimize_with(obj_in, closed_group, reserved, &mut Pipeline::default())
+}
+
+// runs the passes of the pipeline instead of the default ones
+pub fn optimize_with(
     mut obj_in: Vec<FunctionId>,
-    mut closed_group: u16,
-    mut reserved: ReservedIds,
+    closed_group: u16,
+    reserved: ReservedIds,
+    pipeline: &mut Pipeline,
 ) -> Vec<FunctionId> {
-    let mut network = TriggerNetwork::default();
+    let mut network = build_network(&obj_in);
 
     let toggle_groups = get_toggle_groups(&obj_in);
 
-    // sort all triggers by their group
-    for (f, fnid) in obj_in.iter().enumerate() {
-        for (o, (obj, _)) in fnid.obj_list.iter().enumerate() {
-            //if let Some(ObjParam::Number(id)) = obj.params.get(&1) {
-            // let mut hd = false;
-            // if let Some(ObjParam::Bool(hd_val)) = obj.params.get(&103) {
-            //     hd = *hd_val;
-            // }
-            let trigger = Trigger {
-                obj: ObjPtr(f, o),
-                role: get_role(obj),
-                deleted: false,
-            };
-            if let Some(ObjParam::Group(group)) = obj.params.get(&obj_props::GROUPS) {
-                match network.map.get_mut(&group) {
-                    Some(l) => (*l).triggers.push(trigger),
-                    None => {
-                        network.map.insert(*group, TriggerGang::new(vec![trigger]));
-                    }
-                }
-            } else {
-                match network.map.get_mut(&NO_GROUP) {
-                    Some(l) => (*l).triggers.push(trigger),
-                    None => {
-                        network
-                            .map
-                            .insert(NO_GROUP, TriggerGang::new(vec![trigger]));
-                    }
-                }
-            }
-            //}
-        }
-    }
-
     let mut objects = Triggerlist { list: &mut obj_in };
 
+    pipeline.report.before = role_counts(&network);
+
     //optimize
     //optimize_network(&mut network);
 
@@ -67,37 +45,15 @@ pub fn optimize(
     // this somewhere else if i want to add an option to not have optimization
     //network = fix_read_write_order(&mut objects, &network, &mut closed_group);
 
-    // round 1
-    //dbg!(&objects.list);
-
-    for _ in 0..10 {
-        clean_network(&mut network, &objects, true);
-
-        dead_code::dead_code_optimization(&mut network, &mut objects, &reserved);
-
-        clean_network(&mut network, &objects, false);
-        //dbg!(&objects.list);
-
-        spawn_optimisation::spawn_optimisation(
-            &mut network,
-            &mut objects,
-            &reserved,
-            &toggle_groups,
-        );
-
-        clean_network(&mut network, &objects, false);
-        //dbg!(&objects.list);
-
-        update_reserved(&mut network, &mut objects, &mut reserved);
-    }
-
-    clean_network(&mut network, &objects, false);
-
-    trigger_dedup::dedup_triggers(&mut network, &mut objects, &reserved);
-
-    clean_network(&mut network, &objects, false);
-
-    group_toggling::group_toggling(&mut network, &mut objects, &reserved, &mut closed_group);
+    let mut ctx = PassContext {
+        reserved,
+        toggle_groups,
+        closed_group,
+        merged: 0,
+        dropped: Vec::new(),
+    };
+    pipeline.run(&mut network, &mut objects, &mut ctx);
+    let mut closed_group = ctx.closed_group;
     //dbg!(&network);
 
     let zero_group = Group {
@@ -124,6 +80,7 @@ pub fn optimize(
                 new_start_group,
                 zero_group,
                 0.0,
+                TriggerOrder(0.0),
                 &mut objects,
                 &mut network,
                 TriggerRole::Spawn,
@@ -132,9 +89,49 @@ pub fn optimize(
         }
     }
 
+    pipeline.report.after = role_counts(&network);
+    pipeline.report.dropped = ctx.dropped;
     rebuild(&network, &obj_in)
 }
 
+// sorts all triggers by their group
+pub(crate) fn build_network(obj_in: &[FunctionId]) -> TriggerNetwork {
+    let mut network = TriggerNetwork::default();
+    for (f, fnid) in obj_in.iter().enumerate() {
+        for (o, (obj, _)) in fnid.obj_list.iter().enumerate() {
+            //if let Some(ObjParam::Number(id)) = obj.params.get(&1) {
+            // let mut hd = false;
+            // if let Some(ObjParam::Bool(hd_val)) = obj.params.get(&103) {
+            //     hd = *hd_val;
+            // }
+            let trigger = Trigger {
+                obj: ObjPtr(f, o),
+                role: get_role(obj),
+                deleted: false,
+            };
+            if let Some(ObjParam::Group(group)) = obj.params.get(&obj_props::GROUPS) {
+                match network.map.get_mut(&group) {
+                    Some(l) => (*l).triggers.push(trigger),
+                    None => {
+                        network.map.insert(*group, TriggerGang::new(vec![trigger]));
+                    }
+                }
+            } else {
+                match network.map.get_mut(&NO_GROUP) {
+                    Some(l) => (*l).triggers.push(trigger),
+                    None => {
+                        network
+                            .map
+                            .insert(NO_GROUP, TriggerGang::new(vec![trigger]));
+                    }
+                }
+            }
+            //}
+        }
+    }
+    network
+}
+
 pub fn is_start_group(g: Group, reserved: &ReservedIds) -> bool {
     matches!(g.id, Id::Specific(_)) || reserved.object_groups.contains(&g.id)
 }
@@ -194,30 +191,20 @@ fn get_toggle_groups(objects: &[FunctionId]) -> ToggleGroups {
     toggle_groups
 }
 
-fn update_reserved(
+pub(crate) fn update_reserved(
     network: &mut TriggerNetwork,
     objects: &mut Triggerlist,
 
     reserved: &mut ReservedIds,
 ) {
     reserved.trigger_groups.clear();
+    reserved.read_groups.clear();
+    reserved.read_colors.clear();
+    reserved.read_items.clear();
 
     for gang in network.map.values() {
         for trigger in gang.triggers.iter() {
-            for (prop, param) in objects[trigger.obj].0.params.iter() {
-                if *prop == obj_props::GROUPS {
-                    match &param {
-                        ObjParam::Group(g) => {
-                            reserved.trigger_groups.insert(g.id);
-                        }
-                        ObjParam::GroupList(g) => {
-                            reserved.trigger_groups.extend(g.iter().map(|g| g.id));
-                        }
-
-                        _ => (),
-                    }
-                }
-            }
+            reserved.add_trigger(&objects[trigger.obj].0);
         }
     }
 }
@@ -296,6 +283,9 @@ pub fn clean_network(network: &mut TriggerNetwork, objects: &Triggerlist, delete
     *network = new_network;
 }
 
+// how much room the triggers of a merged group get if there's no trigger after them
+const ORDER_WINDOW: f64 = 0.1;
+
 // fn instant_count_optimization(
 //     network: &mut TriggerNetwork,
 //     objects: &mut Triggerlist,
@@ -307,51 +297,79 @@ pub fn clean_network(network: &mut TriggerNetwork, objects: &Triggerlist, delete
 //     replace_groups(swaps, network, objects);
 // }
 
-pub fn replace_groups(table: Swaps, objects: &mut Triggerlist) {
-    let mut map: AHashMap<Group, (Vec<ObjPtr>, Group, TriggerOrder)> = table
-        .into_iter()
-        .map(|(a, (b, c))| (a, (vec![], b, c)))
-        .collect();
+// merges every group in the table into the group it maps to. the triggers of a merged
+// group go right after the given order (the order of the spawn trigger that was removed),
+// before the next trigger in the group they are merged into, and keep their order.
+// a group that is merged into a group that's merged itself goes there first,
+// so the triggers end up in the right place
+pub fn replace_groups(mut table: Swaps, objects: &mut Triggerlist) {
+    // the objects that use each group in the table, and the triggers in them
+    let mut users = AHashMap::<Group, Vec<ObjPtr>>::default();
+    let mut members = AHashMap::<Group, Vec<ObjPtr>>::default();
+    for (i, fn_id) in objects.list.iter().enumerate() {
+        for (j, (object, _)) in fn_id.obj_list.iter().enumerate() {
+            for (prop, param) in object.params.iter() {
+                let groups = match param {
+                    ObjParam::Group(g) => std::slice::from_ref(g),
+                    ObjParam::GroupList(list) => list.as_slice(),
+                    _ => continue,
+                };
+                for g in groups {
+                    if table.contains_key(g) {
+                        users.entry(*g).or_default().push(ObjPtr(i, j));
+                    }
+                    if *prop == obj_props::GROUPS
+                        && (table.contains_key(g) || table.values().any(|(to, _)| to == g))
+                    {
+                        members.entry(*g).or_default().push(ObjPtr(i, j));
+                    }
+                }
+            }
+        }
+    }
 
-    for (i, fn_id) in objects.list.iter_mut().enumerate() {
-        for (j, (object, _)) in &mut fn_id.obj_list.iter_mut().enumerate() {
-            for (prop, param) in &mut object.params.iter_mut() {
+    while !table.is_empty() {
+        // a group that nothing is merged into anymore (any group if they make a cycle)
+        let from = table
+            .keys()
+            .copied()
+            .find(|g| !table.values().any(|(to, _)| to == g))
+            .unwrap_or_else(|| *table.keys().next().unwrap());
+        let (to, order) = table.remove(&from).unwrap();
+
+        let from_users = users.remove(&from).unwrap_or_default();
+        for ptr in &from_users {
+            for param in objects[*ptr].0.params.values_mut() {
                 match param {
-                    ObjParam::Group(g) => {
-                        if let Some(to) = map.get_mut(&g) {
-                            *g = to.1;
-                            if *prop == obj_props::GROUPS {
-                                to.0.push(ObjPtr(i, j));
-                            }
-                        }
-                    }
+                    ObjParam::Group(g) if *g == from => *g = to,
                     ObjParam::GroupList(list) => {
-                        for g in list {
-                            if let Some(to) = map.get_mut(&g) {
-                                *g = to.1;
-                                if *prop == obj_props::GROUPS {
-                                    to.0.push(ObjPtr(i, j));
-                                }
-                            }
+                        for g in list.iter_mut().filter(|g| **g == from) {
+                            *g = to;
                         }
                     }
                     _ => (),
                 }
             }
         }
-    }
-    //dbg!(&map);
+        if table.contains_key(&to) {
+            users.entry(to).or_default().extend(from_users);
+        }
+
+        let mut triggers = members.remove(&from).unwrap_or_default();
+        let to_members = members.entry(to).or_default();
+        let next = to_members
+            .iter()
+            .map(|t| objects[*t].1 .0)
+            .filter(|o| *o > order.0)
+            .fold(order.0 + ORDER_WINDOW, f64::min);
 
-    for (_, (mut triggers, _, order)) in map.into_iter() {
         // sort objects by trigger order
         triggers.sort_by(|a, b| objects[*a].1 .0.partial_cmp(&objects[*b].1 .0).unwrap());
-        const ORDER_WINDOW: f64 = 0.1;
-        let delta = ORDER_WINDOW / triggers.len() as f64;
+        let delta = (next - order.0) / (triggers.len() + 1) as f64;
         for (i, trigger) in triggers.iter().enumerate() {
-            let new_order = TriggerOrder(order.0 + i as f64 * delta + delta);
-
-            objects[*trigger].1 = new_order;
+            objects[*trigger].1 = TriggerOrder(order.0 + i as f64 * delta + delta);
         }
+        to_members.extend(triggers);
     }
 }
 
@@ -389,6 +407,7 @@ pub fn create_spawn_trigger(
     target_group: Group,
     group: Group,
     delay: f64,
+    order: TriggerOrder,
     objects: &mut Triggerlist,
     network: &mut TriggerNetwork,
     role: TriggerRole,
@@ -401,13 +420,12 @@ pub fn create_spawn_trigger(
 
     new_obj_map.insert(obj_props::GROUPS, ObjParam::Group(group));
 
-    let order = objects[trigger.obj].1;
-
     let new_obj = GdObj {
         params: new_obj_map,
         func_id: trigger.obj.0,
         mode: ObjectMode::Trigger,
         unique_id: objects[trigger.obj].0.unique_id,
+        origin: objects[trigger.obj].0.origin.clone(),
     };
 
     (*objects.list)[trigger.obj.0]
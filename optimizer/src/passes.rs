@@ -0,0 +1,394 @@
+// the optimizer is a list of passes over the trigger network,
+// which is run by a `Pipeline`
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+
+use ahash::{AHashMap, AHashSet};
+use compiler::leveldata::GdObj;
+
+use crate::optimize::{clean_network, update_reserved, ToggleGroups};
+use crate::{
+    dead_code, group_toggling, spawn_optimisation, trigger_dedup, ObjPtr, ReservedIds,
+    TriggerNetwork, TriggerRole, Triggerlist, NO_GROUP,
+};
+
+// the most times the repeated passes are run, in case they never settle
+const MAX_ROUNDS: usize = 100;
+
+// what a pass knows about the level other than the triggers
+pub struct PassContext {
+    pub reserved: ReservedIds,
+    pub toggle_groups: ToggleGroups,
+    // the last arbitrary group that was used
+    pub closed_group: u16,
+    // passes add the groups they merged into other groups here, for the report
+    pub merged: usize,
+    pub dropped: Vec<DroppedTrigger>,
+}
+
+/// An output trigger that was removed because nothing uses what it changes
+#[derive(Debug, Clone)]
+pub struct DroppedTrigger {
+    pub ptr: ObjPtr,
+    pub object: GdObj,
+    // "group", "color" or "item ID"
+    pub class: &'static str,
+}
+
+/// What a pass did to the triggers, added up over every time it ran
+#[derive(Debug, Clone, Default)]
+pub struct PassStats {
+    pub name: &'static str,
+    pub runs: usize,
+    pub removed: usize,
+    pub merged: usize,
+    pub created: usize,
+    // negative if the pass used new groups
+    pub groups_freed: isize,
+}
+
+/// What the optimizer did, filled in every time a pipeline runs
+#[derive(Debug, Clone, Default)]
+pub struct OptReport {
+    pub passes: Vec<PassStats>,
+    // the triggers of each role before and after optimizing
+    pub before: BTreeMap<TriggerRole, usize>,
+    pub after: BTreeMap<TriggerRole, usize>,
+    pub dropped: Vec<DroppedTrigger>,
+}
+
+pub trait Pass {
+    // the name used for the pass in `--opt-passes`
+    fn name(&self) -> &'static str;
+
+    // repeated passes are run again (together with the repeated passes next to them)
+    // until none of them change the network anymore, the others are run once
+    fn repeated(&self) -> bool {
+        false
+    }
+
+    // passes that have to come before this one in the pipeline
+    fn requires(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    // the network is cleaned after every pass, so deleted triggers are gone
+    // and the connections are up to date when the next pass starts
+    fn run(
+        &mut self,
+        network: &mut TriggerNetwork,
+        objects: &mut Triggerlist,
+        ctx: &mut PassContext,
+    );
+}
+
+// removes triggers that don't lead to an output trigger or can't be reached
+pub struct DeadCode;
+
+impl Pass for DeadCode {
+    fn name(&self) -> &'static str {
+        "dead_code"
+    }
+    fn repeated(&self) -> bool {
+        true
+    }
+    fn run(
+        &mut self,
+        network: &mut TriggerNetwork,
+        objects: &mut Triggerlist,
+        ctx: &mut PassContext,
+    ) {
+        // every trigger starts out deleted, and the ones that are needed are brought back
+        clean_network(network, objects, true);
+        dead_code::dead_code_optimization(network, objects, &ctx.reserved, &mut ctx.dropped);
+    }
+}
+
+// combines and removes spawn triggers
+pub struct SpawnOptimisation;
+
+impl Pass for SpawnOptimisation {
+    fn name(&self) -> &'static str {
+        "spawn"
+    }
+    fn repeated(&self) -> bool {
+        true
+    }
+    // every spawn trigger has to lead to an output trigger
+    fn requires(&self) -> &'static [&'static str] {
+        &["dead_code"]
+    }
+    fn run(
+        &mut self,
+        network: &mut TriggerNetwork,
+        objects: &mut Triggerlist,
+        ctx: &mut PassContext,
+    ) {
+        ctx.merged += spawn_optimisation::spawn_optimisation(
+            network,
+            objects,
+            &ctx.reserved,
+            &ctx.toggle_groups,
+        );
+    }
+}
+
+// merges groups that contain triggers with the same behavior
+pub struct TriggerDedup;
+
+impl Pass for TriggerDedup {
+    fn name(&self) -> &'static str {
+        "dedup"
+    }
+    fn run(
+        &mut self,
+        network: &mut TriggerNetwork,
+        objects: &mut Triggerlist,
+        ctx: &mut PassContext,
+    ) {
+        ctx.merged +=
+            trigger_dedup::dedup_triggers(network, objects, &ctx.reserved, &ctx.toggle_groups);
+    }
+}
+
+// replaces spawn triggers that run in the same frame with toggle triggers
+pub struct GroupToggling;
+
+impl Pass for GroupToggling {
+    fn name(&self) -> &'static str {
+        "group_toggling"
+    }
+    fn run(
+        &mut self,
+        network: &mut TriggerNetwork,
+        objects: &mut Triggerlist,
+        ctx: &mut PassContext,
+    ) {
+        group_toggling::group_toggling(network, objects, &ctx.reserved, &mut ctx.closed_group);
+    }
+}
+
+/// The names of the built in passes, in the order they are run by default
+pub const PASS_NAMES: [&str; 4] = ["dead_code", "spawn", "dedup", "group_toggling"];
+
+fn builtin_pass(name: &str) -> Option<Box<dyn Pass>> {
+    Some(match name {
+        "dead_code" => Box::new(DeadCode),
+        "spawn" => Box::new(SpawnOptimisation),
+        "dedup" => Box::new(TriggerDedup),
+        "group_toggling" => Box::new(GroupToggling),
+        _ => return None,
+    })
+}
+
+/// The passes the optimizer runs, in order
+pub struct Pipeline {
+    passes: Vec<Box<dyn Pass>>,
+    pub report: OptReport,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline {
+            passes: PASS_NAMES.iter().filter_map(|n| builtin_pass(n)).collect(),
+            report: OptReport::default(),
+        }
+    }
+}
+
+impl Pipeline {
+    pub fn empty() -> Self {
+        Pipeline {
+            passes: Vec::new(),
+            report: OptReport::default(),
+        }
+    }
+
+    /// Built in passes from a comma separated list of names, like `dead_code,dedup`
+    pub fn from_names(names: &str) -> Result<Self, String> {
+        let mut pipeline = Self::empty();
+        for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            match builtin_pass(name) {
+                Some(pass) => pipeline.push(pass)?,
+                None => {
+                    return Err(format!(
+                        "Unknown optimizer pass: `{}` (the passes are {})",
+                        name,
+                        PASS_NAMES.join(", ")
+                    ))
+                }
+            }
+        }
+        Ok(pipeline)
+    }
+
+    // fails if a pass that the new pass requires isn't in the pipeline yet
+    pub fn push(&mut self, pass: Box<dyn Pass>) -> Result<(), String> {
+        for required in pass.requires() {
+            if !self.passes.iter().any(|p| p.name() == *required) {
+                return Err(format!(
+                    "The `{}` optimizer pass needs the `{}` pass to come before it",
+                    pass.name(),
+                    required
+                ));
+            }
+        }
+        self.passes.push(pass);
+        Ok(())
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|p| p.name()).collect()
+    }
+
+    pub fn run(
+        &mut self,
+        network: &mut TriggerNetwork,
+        objects: &mut Triggerlist,
+        ctx: &mut PassContext,
+    ) {
+        self.report.passes = self
+            .passes
+            .iter()
+            .map(|p| PassStats {
+                name: p.name(),
+                ..Default::default()
+            })
+            .collect();
+
+        let mut i = 0;
+        while i < self.passes.len() {
+            if !self.passes[i].repeated() {
+                clean_network(network, objects, false);
+                self.run_pass(i, network, objects, ctx);
+                i += 1;
+                continue;
+            }
+
+            // repeated passes next to each other are run as one round
+            let end = self.passes[i..]
+                .iter()
+                .position(|p| !p.repeated())
+                .map_or(self.passes.len(), |p| i + p);
+
+            clean_network(network, objects, false);
+            let mut state = network_state(network, objects);
+            for _ in 0..MAX_ROUNDS {
+                for pass in i..end {
+                    self.run_pass(pass, network, objects, ctx);
+                    clean_network(network, objects, false);
+                }
+                update_reserved(network, objects, &mut ctx.reserved);
+
+                let new_state = network_state(network, objects);
+                if new_state == state {
+                    break;
+                }
+                state = new_state;
+            }
+            i = end;
+        }
+    }
+
+    fn run_pass(
+        &mut self,
+        i: usize,
+        network: &mut TriggerNetwork,
+        objects: &mut Triggerlist,
+        ctx: &mut PassContext,
+    ) {
+        let before = live_triggers(network, objects);
+        let groups_before = live_groups(network);
+        ctx.merged = 0;
+
+        self.passes[i].run(network, objects, ctx);
+
+        // the network isn't always cleaned yet, but deleted triggers are marked
+        let after = live_triggers(network, objects);
+        let stats = &mut self.report.passes[i];
+        stats.runs += 1;
+        stats.merged += ctx.merged;
+        stats.groups_freed += groups_before as isize - live_groups(network) as isize;
+
+        // triggers that were deleted and made again the same way (like the spawn
+        // triggers that are rebuilt every time) count as neither removed nor created
+        let mut removed = AHashMap::<u64, usize>::default();
+        for (ptr, hash) in &before {
+            if !after.contains_key(ptr) {
+                *removed.entry(*hash).or_default() += 1;
+            }
+        }
+        for (ptr, hash) in &after {
+            if before.contains_key(ptr) {
+                continue;
+            }
+            match removed.get_mut(hash) {
+                Some(count) if *count > 0 => *count -= 1,
+                _ => stats.created += 1,
+            }
+        }
+        stats.removed += removed.values().sum::<usize>();
+    }
+}
+
+// how many triggers there are of every role
+pub(crate) fn role_counts(network: &TriggerNetwork) -> BTreeMap<TriggerRole, usize> {
+    let mut counts = BTreeMap::new();
+    for gang in network.map.values() {
+        for trigger in gang.triggers.iter().filter(|t| !t.deleted) {
+            *counts.entry(trigger.role).or_default() += 1;
+        }
+    }
+    counts
+}
+
+fn live_triggers(network: &TriggerNetwork, objects: &Triggerlist) -> AHashMap<ObjPtr, u64> {
+    network
+        .map
+        .values()
+        .flat_map(|gang| gang.triggers.iter())
+        .filter(|t| !t.deleted)
+        .map(|t| (t.obj, params_hash(&objects[t.obj].0)))
+        .collect()
+}
+
+fn live_groups(network: &TriggerNetwork) -> usize {
+    network
+        .map
+        .iter()
+        .filter(|(group, gang)| **group != NO_GROUP && gang.triggers.iter().any(|t| !t.deleted))
+        .map(|(group, _)| *group)
+        .collect::<AHashSet<_>>()
+        .len()
+}
+
+fn params_hash(obj: &GdObj) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let mut params = obj.params.iter().collect::<Vec<_>>();
+    params.sort_by_key(|(prop, _)| **prop);
+    params.hash(&mut hasher);
+    hasher.finish()
+}
+
+// a hash of the triggers that are left and their properties, which doesn't depend
+// on the order of the groups in the network or where the objects are stored
+// (the spawn triggers are made again every time, as new objects)
+fn network_state(network: &TriggerNetwork, objects: &Triggerlist) -> u64 {
+    let mut state = 0u64;
+    for (group, gang) in network.map.iter() {
+        for trigger in &gang.triggers {
+            let (obj, order) = &objects[trigger.obj];
+            let mut hasher = DefaultHasher::new();
+            group.hash(&mut hasher);
+            trigger.role.hash(&mut hasher);
+            trigger.deleted.hash(&mut hasher);
+            order.0.to_bits().hash(&mut hasher);
+            params_hash(obj).hash(&mut hasher);
+
+            state = state.wrapping_add(hasher.finish());
+        }
+    }
+    state
+}
@@ -0,0 +1,409 @@
+// a small simulator for the triggers the compiler makes, which is used to check
+// that the optimizer doesn't change what a level does
+//
+// it knows about spawn triggers (and their delays), toggle, stop, pickup, count
+// and instant count triggers. every other output trigger is only written to the trace,
+// with the time it ran at. triggers that change an arbitrary ID that no object has
+// (like a toggle trigger for a function) are left out of the trace, since they only
+// matter for what they do to other triggers, and the optimizer is allowed to change them
+
+use std::collections::BTreeMap;
+
+use ahash::{AHashMap, AHashSet};
+use compiler::builtins::{Color, Group, Id, Item};
+use compiler::compiler_types::FunctionId;
+use compiler::leveldata::{GdObj, ObjParam};
+
+use crate::trigger_dedup::canonical_param;
+use crate::{
+    changed_prop, get_role, obj_ids, obj_props, trigger_name, ReservedIds, TriggerRole, NO_GROUP,
+};
+
+// the simulation is cut off after this many seconds, triggers or nested spawns,
+// so recursive functions still end
+const MAX_TIME: f64 = 60.0;
+const MAX_STEPS: usize = 100_000;
+const MAX_DEPTH: usize = 200;
+
+// properties that don't change what an output trigger does
+const IGNORED_PROPS: [u16; 4] = [1, obj_props::GROUPS, 62, 87];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    // seconds after the simulation started
+    pub time: f64,
+    // the trigger and its properties, like `move 28:10 51:?5`
+    pub trigger: String,
+}
+
+/// The output triggers that ran in a simulation, in the order they ran
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    pub events: Vec<TraceEvent>,
+    // the time the simulation was stopped at, if it ran for too long
+    pub cut_off: Option<f64>,
+}
+
+struct SimTrigger<'a> {
+    obj: &'a GdObj,
+    groups: Vec<Group>,
+    recorded: bool,
+}
+
+struct Simulation<'a> {
+    triggers: Vec<SimTrigger<'a>>,
+    // the triggers in every group, sorted by their order
+    groups: AHashMap<Group, Vec<usize>>,
+    // groups that were toggled off. a trigger doesn't run if any of its groups is off
+    disabled: AHashSet<Group>,
+    items: AHashMap<Id, f64>,
+    // count triggers that are waiting for their item to reach their count
+    counters: Vec<usize>,
+    // delayed spawns by their time (in microseconds) and when they were made,
+    // with the group they spawn and the spawn trigger
+    pending: BTreeMap<(i64, usize), (Group, usize)>,
+    made: usize,
+    now: i64,
+    steps: usize,
+    trace: Trace,
+}
+
+/// Runs the triggers, starting by spawning `start`. The triggers without a group
+/// (`NO_GROUP`) are the ones that run when the level starts
+pub fn simulate(objects: &[GdObj], func_ids: &[FunctionId], start: Group) -> Trace {
+    let reserved = ReservedIds::from_objects(objects, func_ids);
+
+    let mut triggers = Vec::new();
+    let mut groups = AHashMap::<Group, Vec<(usize, f64)>>::new();
+    for (obj, order) in func_ids.iter().flat_map(|f| f.obj_list.iter()) {
+        let trigger_groups = match obj.params.get(&obj_props::GROUPS) {
+            Some(ObjParam::Group(g)) => vec![*g],
+            Some(ObjParam::GroupList(list)) => list.clone(),
+            _ => vec![NO_GROUP],
+        };
+        for group in &trigger_groups {
+            groups
+                .entry(*group)
+                .or_default()
+                .push((triggers.len(), order.0));
+        }
+        triggers.push(SimTrigger {
+            obj,
+            groups: trigger_groups,
+            recorded: recorded(obj, &reserved),
+        });
+    }
+
+    let mut sim = Simulation {
+        triggers,
+        groups: groups
+            .into_iter()
+            .map(|(group, mut list)| {
+                list.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(a.0.cmp(&b.0)));
+                (group, list.into_iter().map(|(i, _)| i).collect())
+            })
+            .collect(),
+        disabled: AHashSet::default(),
+        items: AHashMap::default(),
+        counters: Vec::new(),
+        pending: BTreeMap::new(),
+        made: 0,
+        now: 0,
+        steps: 0,
+        trace: Trace::default(),
+    };
+
+    sim.spawn(start, 0);
+    while sim.trace.cut_off.is_none() {
+        let ((time, _), (group, _)) = match sim.pending.pop_first() {
+            Some(next) => next,
+            None => break,
+        };
+        if seconds(time) > MAX_TIME {
+            sim.now = time;
+            sim.stop();
+            break;
+        }
+        sim.now = time;
+        sim.spawn(group, 0);
+    }
+    sim.trace
+}
+
+/// The groups a simulation can start at: `NO_GROUP`, and every group with
+/// a specific ID, since those could be spawned by triggers in the level
+pub fn start_groups(func_ids: &[FunctionId]) -> Vec<Group> {
+    let mut groups = vec![NO_GROUP];
+    for (obj, _) in func_ids.iter().flat_map(|f| f.obj_list.iter()) {
+        if let Some(ObjParam::Group(
+            g @ Group {
+                id: Id::Specific(_),
+            },
+        )) = obj.params.get(&obj_props::GROUPS)
+        {
+            if !groups.contains(g) {
+                groups.push(*g);
+            }
+        }
+    }
+    groups
+}
+
+/// Compares two traces. The triggers that ran at the same time can be in any order
+pub fn compare(before: &Trace, after: &Trace) -> Result<(), String> {
+    let cut_off = [before.cut_off, after.cut_off]
+        .into_iter()
+        .flatten()
+        .fold(f64::INFINITY, f64::min);
+    let (before, after) = (instants(before, cut_off), instants(after, cut_off));
+
+    let times = before
+        .keys()
+        .chain(after.keys())
+        .copied()
+        .collect::<std::collections::BTreeSet<_>>();
+    for time in times {
+        let (a, b) = (before.get(&time), after.get(&time));
+        if a != b {
+            return Err(format!(
+                "At {}s, the triggers {:?} ran before optimizing, but {:?} ran after",
+                seconds(time),
+                a.cloned().unwrap_or_default(),
+                b.cloned().unwrap_or_default()
+            ));
+        }
+    }
+    Ok(())
+}
+
+// the triggers that ran at every time before the cut off
+fn instants(trace: &Trace, cut_off: f64) -> BTreeMap<i64, Vec<&str>> {
+    let mut instants = BTreeMap::<i64, Vec<&str>>::new();
+    for event in trace.events.iter().filter(|e| e.time < cut_off) {
+        instants
+            .entry(micros(event.time))
+            .or_default()
+            .push(&event.trigger);
+    }
+    for triggers in instants.values_mut() {
+        triggers.sort_unstable();
+    }
+    instants
+}
+
+/// Checks that two versions of the triggers (like before and after optimizing)
+/// do the same thing, starting from every start group of the first one
+pub fn check_equivalent(
+    objects: &[GdObj],
+    before: &[FunctionId],
+    after: &[FunctionId],
+) -> Result<(), String> {
+    for start in start_groups(before) {
+        compare(
+            &simulate(objects, before, start),
+            &simulate(objects, after, start),
+        )
+        .map_err(|e| format!("{} (starting from {:?})", e, start.id))?;
+    }
+    Ok(())
+}
+
+impl<'a> Simulation<'a> {
+    fn spawn(&mut self, group: Group, depth: usize) {
+        if depth > MAX_DEPTH {
+            self.stop();
+            return;
+        }
+        let triggers = match self.groups.get(&group) {
+            Some(triggers) => triggers.clone(),
+            None => return,
+        };
+        for i in triggers {
+            if self.trace.cut_off.is_some() {
+                return;
+            }
+            // toggling the group off in the middle stops the rest of it
+            if self.triggers[i]
+                .groups
+                .iter()
+                .any(|g| self.disabled.contains(g))
+            {
+                continue;
+            }
+            self.steps += 1;
+            if self.steps > MAX_STEPS {
+                self.stop();
+                return;
+            }
+            self.run(i, depth);
+        }
+    }
+
+    fn run(&mut self, i: usize, depth: usize) {
+        let obj = self.triggers[i].obj;
+        if self.triggers[i].recorded {
+            let mut params = obj
+                .params
+                .iter()
+                .filter(|(prop, _)| !IGNORED_PROPS.contains(prop))
+                .collect::<Vec<_>>();
+            params.sort_by_key(|(prop, _)| **prop);
+            let mut trigger = trigger_name(obj);
+            for (prop, param) in params {
+                trigger += &format!(" {}:{}", prop, canonical_param(param));
+            }
+            self.trace.events.push(TraceEvent {
+                time: seconds(self.now),
+                trigger,
+            });
+        }
+
+        let target = match obj.params.get(&obj_props::TARGET) {
+            Some(ObjParam::Group(g)) => *g,
+            _ => NO_GROUP,
+        };
+        let activate = matches!(
+            obj.params.get(&obj_props::ACTIVATE_GROUP),
+            Some(ObjParam::Bool(true))
+        );
+        let item = match obj.params.get(&obj_props::ITEM) {
+            Some(ObjParam::Item(item)) => Some(item.id),
+            _ => None,
+        };
+        let count = number(obj, 77);
+
+        match object_id(obj) {
+            Some(obj_ids::SPAWN) => {
+                let delay = micros(number(obj, 63));
+                if delay == 0 {
+                    self.spawn(target, depth + 1);
+                } else {
+                    self.pending
+                        .insert((self.now + delay, self.made), (target, i));
+                    self.made += 1;
+                }
+            }
+            Some(obj_ids::TOGGLE) => self.toggle(target, activate),
+            Some(obj_ids::STOP) => {
+                let triggers = &self.triggers;
+                self.pending
+                    .retain(|_, (_, spawn)| !triggers[*spawn].groups.contains(&target));
+            }
+            Some(obj_ids::PICKUP) => {
+                if let Some(item) = item {
+                    let value = self.items.entry(item).or_default();
+                    *value += count;
+                    let value = *value;
+                    self.check_counters(item, value, depth);
+                }
+            }
+            Some(obj_ids::INSTANT_COUNT) => {
+                let value = item
+                    .and_then(|i| self.items.get(&i))
+                    .copied()
+                    .unwrap_or(0.0);
+                let passes = match number(obj, 88) as u8 {
+                    1 => value > count,
+                    2 => value < count,
+                    _ => value == count,
+                };
+                if passes {
+                    self.activate(target, activate, depth);
+                }
+            }
+            Some(obj_ids::COUNT) if !self.counters.contains(&i) => self.counters.push(i),
+            _ => (),
+        }
+    }
+
+    // what the toggle trigger does
+    fn toggle(&mut self, group: Group, on: bool) {
+        if on {
+            self.disabled.remove(&group);
+        } else {
+            self.disabled.insert(group);
+        }
+    }
+
+    // what count and instant count triggers do: toggle the group on and spawn it,
+    // or toggle it off
+    fn activate(&mut self, group: Group, on: bool, depth: usize) {
+        self.toggle(group, on);
+        if on {
+            self.spawn(group, depth + 1);
+        }
+    }
+
+    fn check_counters(&mut self, item: Id, value: f64, depth: usize) {
+        for i in self.counters.clone() {
+            let obj = self.triggers[i].obj;
+            if !matches!(obj.params.get(&obj_props::ITEM), Some(ObjParam::Item(it)) if it.id == item)
+                || number(obj, 77) != value
+            {
+                continue;
+            }
+            if !matches!(obj.params.get(&104), Some(ObjParam::Bool(true))) {
+                self.counters.retain(|c| *c != i);
+            }
+            let target = match obj.params.get(&obj_props::TARGET) {
+                Some(ObjParam::Group(g)) => *g,
+                _ => continue,
+            };
+            let activate = matches!(
+                obj.params.get(&obj_props::ACTIVATE_GROUP),
+                Some(ObjParam::Bool(true))
+            );
+            self.activate(target, activate, depth);
+        }
+    }
+
+    fn stop(&mut self) {
+        self.trace.cut_off = Some(seconds(self.now));
+    }
+}
+
+// whether a trigger shows up in the trace
+fn recorded(obj: &GdObj, reserved: &ReservedIds) -> bool {
+    if get_role(obj) != TriggerRole::Output {
+        return false;
+    }
+    match changed_prop(obj).and_then(|prop| obj.params.get(&prop)) {
+        // group 0 is the same as no group, so there's nothing to change
+        Some(ObjParam::Group(group)) if *group == NO_GROUP => false,
+        Some(ObjParam::Group(Group {
+            id: id @ Id::Arbitrary(_),
+        })) => reserved.object_groups.contains(id),
+        Some(ObjParam::Color(Color {
+            id: id @ Id::Arbitrary(_),
+        })) => reserved.object_colors.contains(id),
+        Some(ObjParam::Item(Item {
+            id: id @ Id::Arbitrary(_),
+        })) => reserved.object_items.contains(id),
+        _ => true,
+    }
+}
+
+fn object_id(obj: &GdObj) -> Option<u16> {
+    match obj.params.get(&1) {
+        Some(ObjParam::Number(id)) => Some(*id as u16),
+        _ => None,
+    }
+}
+
+fn number(obj: &GdObj, prop: u16) -> f64 {
+    match obj.params.get(&prop) {
+        Some(ObjParam::Number(n)) => *n,
+        Some(ObjParam::Bool(b)) => *b as u8 as f64,
+        // the smallest spawn delay
+        Some(ObjParam::Epsilon) => 0.05,
+        _ => 0.0,
+    }
+}
+
+fn micros(seconds: f64) -> i64 {
+    (seconds * 1_000_000.0).round() as i64
+}
+
+fn seconds(micros: i64) -> f64 {
+    micros as f64 / 1_000_000.0
+}
@@ -3,6 +3,7 @@ use crate::optimize::create_spawn_trigger;
 use crate::optimize::is_start_group;
 use crate::optimize::replace_groups;
 use crate::optimize::ToggleGroups;
+use crate::ObjPtr;
 use crate::ReservedIds;
 use crate::Swaps;
 use crate::Trigger;
@@ -11,10 +12,11 @@ use crate::TriggerRole;
 use crate::Triggerlist;
 use crate::NO_GROUP;
 use compiler::builtins::Group;
+use compiler::compiler_types::TriggerOrder;
 use compiler::leveldata::ObjParam;
-use ahash::{AHashMap, AHashSet};
+use std::collections::{BTreeMap, BTreeSet};
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
 struct SpawnDelay {
     pub(crate) delay: u32,
     pub(crate) epsiloned: bool,
@@ -26,6 +28,8 @@ struct Connection {
     end_group: Group,
     delay: SpawnDelay,
     trigger: Trigger,
+    // the spawn triggers from the start group to the end group
+    path: Vec<ObjPtr>,
 }
 
 #[derive(Debug)]
@@ -62,22 +66,27 @@ struct SpawnTrigger {
 // attempts to remove as many spawn triggers as possible by
 // - combining them into a single spawn trigger (with their combined delay)
 // - removing the spawn trigger if it is not needed (if it has 0 delay)
+// returns how many groups were merged into another group
 
 pub(crate) fn spawn_optimisation(
     network: &mut TriggerNetwork,
     objects: &mut Triggerlist,
     reserved: &ReservedIds,
     toggle_groups: &ToggleGroups,
-) {
-    let mut spawn_connections = AHashMap::<Group, Vec<SpawnTrigger>>::default();
-    let mut inputs = AHashSet::<Group>::default();
-    let mut outputs = AHashSet::<Group>::default();
+) -> usize {
+    let mut spawn_connections = BTreeMap::<Group, Vec<SpawnTrigger>>::new();
+    let mut inputs = BTreeSet::<Group>::default();
+    let mut outputs = BTreeSet::<Group>::default();
 
-    let mut cycle_points = AHashSet::<Group>::default();
+    let mut cycle_points = BTreeSet::<Group>::default();
     let mut all = Vec::new();
 
     for (group, gang) in network.map.iter_mut() {
-        let output_condition = gang.triggers.iter().any(|t| t.role != TriggerRole::Spawn);
+        // groups that can be toggled off or stopped can't be skipped either,
+        // since the toggle or stop trigger would miss the spawn triggers
+        let output_condition = gang.triggers.iter().any(|t| t.role != TriggerRole::Spawn)
+            || toggle_groups.toggles_off.contains_key(group)
+            || toggle_groups.stops.contains_key(group);
         if output_condition {
             outputs.insert(*group);
         }
@@ -135,6 +144,14 @@ pub(crate) fn spawn_optimisation(
         }
     }
 
+    // stopping a group only cancels the delays of its own spawn triggers, so the
+    // delays of the groups it spawns can't be moved into it
+    for group in toggle_groups.stops.keys() {
+        if let Some(connections) = spawn_connections.get(group) {
+            outputs.extend(connections.iter().map(|c| c.target));
+        }
+    }
+
     //bg!(&spawn_connections, &all);
 
     for start in inputs.clone() {
@@ -175,6 +192,7 @@ pub(crate) fn spawn_optimisation(
             &cycle_points,
             &spawn_connections,
             &mut visited,
+            &mut Vec::new(),
             &mut all,
         );
         //println!("</{:?}>", start);
@@ -182,16 +200,19 @@ pub(crate) fn spawn_optimisation(
 
     //dbg!(&all);
 
-    let mut deduped = AHashMap::default();
+    let mut deduped = BTreeMap::default();
 
+    // the same path can be found more than once, but two paths of spawn triggers
+    // that spawn the same group still have to spawn it twice
     for Connection {
         start_group,
         end_group,
         delay,
         trigger,
+        path,
     } in all
     {
-        deduped.insert((start_group, end_group, delay), trigger);
+        deduped.insert((start_group, end_group, delay, path), trigger);
     }
 
     let mut swaps = Swaps::default();
@@ -208,7 +229,50 @@ pub(crate) fn spawn_optimisation(
     //     end_counts.entry(end).and_modify(|c| *c += 1).or_insert(1);
     // }
 
-    for ((start, end, delay), trigger) in deduped {
+    // when the groups in between were skipped, several connections start with the same
+    // spawn trigger. they keep the order of the spawn triggers along their paths,
+    // and go between that spawn trigger and the next trigger in the start group
+    let path_orders = |path: &[ObjPtr]| path.iter().map(|p| objects[*p].1 .0).collect::<Vec<_>>();
+    let mut connections = deduped.into_iter().collect::<Vec<_>>();
+    connections.sort_by(|(a, _), (b, _)| {
+        path_orders(&a.3)
+            .partial_cmp(&path_orders(&b.3))
+            .unwrap()
+    });
+    // the order of the first connection, the space between them and how many were made
+    let mut slots = BTreeMap::<ObjPtr, (f64, f64, usize)>::default();
+    for ((start, ..), trigger) in &connections {
+        let first = objects[trigger.obj].1 .0;
+        let slot = slots.entry(trigger.obj).or_insert_with(|| {
+            let next = network.map[start]
+                .triggers
+                .iter()
+                .map(|t| objects[t.obj].1 .0)
+                .filter(|o| *o > first)
+                .fold(first + 1.0, f64::min);
+            (first, next - first, 0)
+        });
+        slot.2 += 1;
+    }
+    for slot in slots.values_mut() {
+        slot.1 /= slot.2 as f64;
+        slot.2 = 0;
+    }
+
+    // a group can only be merged into another group if there's one path to it
+    // (or from it), even if there's only one spawn trigger for it
+    let mut paths_to = BTreeMap::<Group, usize>::default();
+    let mut paths_from = BTreeMap::<Group, usize>::default();
+    for ((start, end, ..), _) in &connections {
+        *paths_from.entry(*start).or_default() += 1;
+        *paths_to.entry(*end).or_default() += 1;
+    }
+
+    for ((start, end, delay, _), trigger) in connections {
+        let slot = slots.get_mut(&trigger.obj).unwrap();
+        let order = TriggerOrder(slot.0 + slot.1 * slot.2 as f64);
+        slot.2 += 1;
+
         let d = if delay.delay < 50 && delay.epsiloned {
             50
         } else {
@@ -220,6 +284,7 @@ pub(crate) fn spawn_optimisation(
                 end,
                 start,
                 d as f64 / 1000.0,
+                order,
                 objects,
                 network,
                 TriggerRole::Spawn,
@@ -227,17 +292,13 @@ pub(crate) fn spawn_optimisation(
             )
         };
 
-        let mut insert_to_swaps = |a: Group, b: Group, objects: &mut Triggerlist| {
-            let order = objects[trigger.obj].1;
-            for v in swaps.values_mut() {
-                if v.0 == a {
-                    *v = (b, order);
-                }
-            }
+        let mut insert_to_swaps = |a: Group, b: Group| {
+            // groups that are merged into a merged group are moved along with it
+            // (see `replace_groups`)
             assert!(swaps.insert(a, (b, order)).is_none());
         };
 
-        let default = &AHashSet::default();
+        let default = &BTreeSet::default();
         let targeters = network.connectors.get(&start).unwrap_or(default);
 
         let start_can_toggle_off = if let Some(togglers) = toggle_groups.toggles_off.get(&start) {
@@ -254,39 +315,48 @@ pub(crate) fn spawn_optimisation(
         //     false
         // };
 
+        // if the end group can be toggled off, merging it into the start group
+        // would make the toggle trigger turn off the start group as well
+        // (and stopping either group would stop the spawn triggers of both)
         if start_can_toggle_off
-            || (toggle_groups.toggles_on.contains_key(&start)
-                && toggle_groups.toggles_off.contains_key(&end))
+            || toggle_groups.toggles_off.contains_key(&end)
             || toggle_groups.stops.contains_key(&end)
+            || toggle_groups.stops.contains_key(&start)
         {
             plain_trigger(network)
-        } else if d == 0 && !is_start_group(end, reserved) && network.map[&end].connections_in == 1
+        } else if d == 0
+            && !is_start_group(end, reserved)
+            && network.map[&end].connections_in == 1
+            && paths_to[&end] == 1
         {
             //dbg!(end, start);
-            insert_to_swaps(end, start, objects);
+            insert_to_swaps(end, start);
         } else if d == 0 && !is_start_group(start, reserved)
                 && network.map[&start].connections_in == 1 //??
+                && paths_from[&start] == 1
                 && (network.map[&start].triggers.is_empty()
                     || network.map[&start].triggers.iter().all(|t| t.deleted))
         {
-            insert_to_swaps(start, end, objects);
+            insert_to_swaps(start, end);
         } else {
             plain_trigger(network)
         }
     }
     //dbg!(&swaps);
 
+    let merged = swaps.len();
     replace_groups(swaps, objects);
+    merged
 }
 
 // set triggers that make cycles to inputs and outputs
 fn look_for_cycle(
     current: Group,
-    ictriggers: &AHashMap<Group, Vec<SpawnTrigger>>,
+    ictriggers: &BTreeMap<Group, Vec<SpawnTrigger>>,
     visited: &mut Vec<Group>,
-    inputs: &mut AHashSet<Group>,
-    outputs: &mut AHashSet<Group>,
-    cycle_points: &mut AHashSet<Group>,
+    inputs: &mut BTreeSet<Group>,
+    outputs: &mut BTreeSet<Group>,
+    cycle_points: &mut BTreeSet<Group>,
     all: &mut Vec<Connection>,
 ) {
     if let Some(connections) = ictriggers.get(&current) {
@@ -305,6 +375,7 @@ fn look_for_cycle(
                     end_group: *g,
                     delay: *delay,
                     trigger: *trigger,
+                    path: vec![trigger.obj],
                 });
                 cycle_points.insert(current);
 
@@ -326,10 +397,11 @@ fn traverse(
     origin: Group,
     total_delay: SpawnDelay, // delay from the origin to the current trigger
     trigger: Option<Trigger>,
-    outputs: &AHashSet<Group>,
-    cycle_points: &AHashSet<Group>,
-    spawn_connections: &AHashMap<Group, Vec<SpawnTrigger>>,
+    outputs: &BTreeSet<Group>,
+    cycle_points: &BTreeSet<Group>,
+    spawn_connections: &BTreeMap<Group, Vec<SpawnTrigger>>,
     visited: &mut Vec<Group>,
+    path: &mut Vec<ObjPtr>,
     all: &mut Vec<Connection>,
 ) {
     if visited.contains(&current) {
@@ -349,12 +421,14 @@ fn traverse(
                 epsiloned: total_delay.epsiloned || d.epsiloned,
             };
             visited.push(current);
+            path.push(t2.obj);
             if outputs.contains(g) {
                 all.push(Connection {
                     start_group: origin,
                     end_group: *g,
                     delay: new_delay,
                     trigger: trigger.unwrap_or(*t2),
+                    path: path.clone(),
                 });
 
                 // avoid infinite loop
@@ -371,22 +445,27 @@ fn traverse(
                         cycle_points,
                         spawn_connections,
                         visited,
+                        &mut Vec::new(),
                         all,
                     );
                 }
             } else {
+                // the connection keeps the order of the first spawn trigger,
+                // since that's the one in the start group
                 traverse(
                     *g,
                     origin,
                     new_delay,
-                    trigger,
+                    trigger.or(Some(*t2)),
                     outputs,
                     cycle_points,
                     spawn_connections,
                     visited,
+                    path,
                     all,
                 );
             }
+            path.pop();
             assert_eq!(visited.pop(), Some(current));
         }
     } else if let Some(t) = trigger {
@@ -395,6 +474,7 @@ fn traverse(
             end_group: current,
             delay: total_delay,
             trigger: t,
+            path: path.clone(),
         }) //?
     } else {
         //unreachable!();
@@ -2,6 +2,7 @@ use crate::optimize::clean_network;
 use crate::Swaps;
 
 use crate::optimize::replace_groups;
+use crate::optimize::ToggleGroups;
 
 use crate::obj_ids;
 
@@ -29,152 +30,118 @@ use compiler::builtins::Id;
 
 use compiler::leveldata::ObjParam;
 
-pub(crate) fn param_identifier(param: &ObjParam) -> String {
-    let str = match param {
+use ahash::AHashMap;
+
+// a parameter the way the game reads it, so parameters that are written differently
+// but end up the same in the level string are the same
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum CanonicalParam {
+    // in thousandths, since numbers are written with at most 3 decimals
+    Number(i64),
+    // groups, colors, blocks and items, sorted (a group is the same as a list of one group)
+    Ids(Vec<Id>),
+    Text(String),
+}
+
+pub(crate) fn canonical_param(param: &ObjParam) -> CanonicalParam {
+    match param {
         ObjParam::Group(Group { id })
         | ObjParam::Color(Color { id })
         | ObjParam::Block(Block { id })
-        | ObjParam::Item(Item { id }) => match id {
-            Id::Specific(id) => format!("{}", id),
-            Id::Arbitrary(id) => format!("?{}", id),
-        },
-        ObjParam::Number(n) => {
-            if (n.round() - n).abs() < 0.001 {
-                format!("{}", *n as i32)
-            } else {
-                format!("{:.1$}", n, 3)
-            }
-        }
-        ObjParam::Bool(b) => (if *b { "1" } else { "0" }).to_string(),
-        ObjParam::Text(t) => t.to_string(),
+        | ObjParam::Item(Item { id }) => CanonicalParam::Ids(vec![*id]),
         ObjParam::GroupList(list) => {
-            let mut out = String::new();
-
-            for g in list {
-                match g.id {
-                    Id::Specific(id) => out += &format!("{}.", id),
-                    Id::Arbitrary(id) => out += &format!("?{}.", id),
-                }
-            }
-            out.pop();
-            out
+            let mut ids = list.iter().map(|g| g.id).collect::<Vec<_>>();
+            ids.sort();
+            ids.dedup();
+            CanonicalParam::Ids(ids)
         }
-        ObjParam::Epsilon => "0.050".to_string(),
-    };
-    str
-    // use std::collections::hash_map::DefaultHasher;
-    // use std::hash::{Hash, Hasher};
-
-    // let mut hasher = DefaultHasher::new();
-
-    // str.hash(&mut hasher);
-    // hasher.finish()
-}
-use std::cmp::Ordering;
-use std::collections::BTreeSet;
-#[derive(Debug, PartialEq, Eq, Hash)]
-pub(crate) struct TriggerParam(u16, String);
-
-impl PartialOrd for TriggerParam {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        let first = self.0.cmp(&other.0);
-        Some(if first == Ordering::Equal {
-            self.1.cmp(&other.1)
+        // rounded the same way as in the level string
+        ObjParam::Number(n) => CanonicalParam::Number(if n.fract().abs() < 0.001 {
+            *n as i32 as i64 * 1000
         } else {
-            first
-        })
-    }
-}
-
-impl Ord for TriggerParam {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap()
+            (n * 1000.0).round() as i64
+        }),
+        ObjParam::Bool(b) => CanonicalParam::Number(if *b { 1000 } else { 0 }),
+        ObjParam::Epsilon => CanonicalParam::Number(50),
+        ObjParam::Text(t) => CanonicalParam::Text(t.clone()),
     }
 }
 
-#[derive(Debug)]
-pub(crate) struct TriggerBehavior(BTreeSet<TriggerParam>, i64);
-
-impl PartialEq for TriggerBehavior {
-    fn eq(&self, other: &Self) -> bool {
-        self.0 == other.0
-    }
-}
-
-impl Eq for TriggerBehavior {}
-
-impl Ord for TriggerBehavior {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap()
-    }
-}
-
-// TODO: make this sort by trigger order as well
-impl PartialOrd for TriggerBehavior {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        let order_cmp = self.1.partial_cmp(&other.1).unwrap();
-        if order_cmp != Ordering::Equal {
-            return Some(order_cmp);
-        }
-
-        let mut iter1 = self.0.iter();
-        let mut iter2 = other.0.iter();
-        loop {
-            if let Some(val1) = iter1.next() {
-                if let Some(val2) = iter2.next() {
-                    let cmp = val1.cmp(val2);
-                    if cmp != Ordering::Equal {
-                        return Some(cmp);
-                    }
-                } else {
-                    return Some(Ordering::Greater);
-                }
-            } else {
-                return Some(Ordering::Less);
+// like `move 28:10 51:?5` in the simulator's traces
+impl std::fmt::Display for CanonicalParam {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CanonicalParam::Number(n) if n % 1000 == 0 => write!(f, "{}", n / 1000),
+            CanonicalParam::Number(n) => write!(f, "{}", *n as f64 / 1000.0),
+            CanonicalParam::Ids(ids) => {
+                let ids = ids
+                    .iter()
+                    .map(|id| match id {
+                        Id::Specific(id) => id.to_string(),
+                        Id::Arbitrary(id) => format!("?{}", id),
+                    })
+                    .collect::<Vec<_>>();
+                write!(f, "{}", ids.join("."))
             }
+            CanonicalParam::Text(t) => write!(f, "{}", t),
         }
     }
 }
 
+// the properties of a trigger other than its groups, sorted by property
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct TriggerBehavior(Vec<(u16, CanonicalParam)>);
+
 pub(crate) fn get_trigger_behavior(t: Trigger, objects: &Triggerlist) -> TriggerBehavior {
-    let mut set = BTreeSet::new();
-    for (prop, param) in &objects[t.obj].0.params {
-        if *prop == obj_props::GROUPS {
-            // group
-            continue;
-        }
-        set.insert(TriggerParam(*prop, param_identifier(param)));
-    }
-    TriggerBehavior(set, (objects[t.obj].1 .0 * 100000.0) as i64)
+    let mut params = objects[t.obj]
+        .0
+        .params
+        .iter()
+        .filter(|(prop, _)| **prop != obj_props::GROUPS)
+        .map(|(prop, param)| (*prop, canonical_param(param)))
+        .collect::<Vec<_>>();
+    params.sort();
+    TriggerBehavior(params)
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub(crate) struct TriggerGangBehavior(BTreeSet<TriggerBehavior>);
+// the triggers of a group in the order they run (triggers with the same order
+// are sorted by their behavior), so groups in different functions can be the same
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub(crate) struct TriggerGangBehavior(Vec<TriggerBehavior>);
 
 pub(crate) fn get_triggergang_behavior(
     gang: &TriggerGang,
     objects: &Triggerlist,
 ) -> TriggerGangBehavior {
-    let mut set = BTreeSet::new();
-
-    for trigger in &gang.triggers {
-        set.insert(get_trigger_behavior(*trigger, objects));
-    }
-
-    TriggerGangBehavior(set)
+    let mut triggers = gang
+        .triggers
+        .iter()
+        .map(|t| (objects[t.obj].1 .0, get_trigger_behavior(*t, objects)))
+        .collect::<Vec<_>>();
+    triggers.sort_by(|(o1, b1), (o2, b2)| o1.partial_cmp(o2).unwrap().then_with(|| b1.cmp(b2)));
+
+    TriggerGangBehavior(triggers.into_iter().map(|(_, b)| b).collect())
 }
 
+// returns how many groups were merged into another group
 pub(crate) fn dedup_triggers(
     network: &mut TriggerNetwork,
     objects: &mut Triggerlist,
     reserved: &ReservedIds,
-) {
+    toggle_groups: &ToggleGroups,
+) -> usize {
+    let mut merged = 0;
     loop {
         let mut swaps = Swaps::default();
-        let mut representative_groups = Vec::<(TriggerGangBehavior, Group, TriggerOrder)>::new();
+        let mut representative_groups =
+            AHashMap::<TriggerGangBehavior, (Group, TriggerOrder)>::default();
 
         for (group, gang) in network.map.iter_mut() {
-            if is_start_group(*group, reserved) {
+            // a toggle or stop trigger for one of the groups would affect both
+            if is_start_group(*group, reserved)
+                || toggle_groups.toggles_off.contains_key(group)
+                || toggle_groups.stops.contains_key(group)
+            {
                 continue;
             }
             let contains_stackable_trigger = gang.triggers.iter().any(|t| {
@@ -191,27 +158,20 @@ pub(crate) fn dedup_triggers(
             }
             let behavior = get_triggergang_behavior(gang, objects);
 
-            let mut found = false;
-            for (b, repr, order) in representative_groups.iter() {
-                if b == &behavior {
-                    for trigger in &mut gang.triggers {
-                        (*trigger).deleted = true;
-                    }
-                    //dbg!(behavior, repr, group, &representative_groups);
-                    assert!(swaps.insert(*group, (*repr, *order)).is_none());
-
-                    found = true;
-                    break;
+            if let Some((repr, order)) = representative_groups.get(&behavior) {
+                for trigger in &mut gang.triggers {
+                    (*trigger).deleted = true;
                 }
-            }
-            if !found {
+                // the spawn and toggle triggers for this group now go to the representative
+                assert!(swaps.insert(*group, (*repr, *order)).is_none());
+            } else {
                 let mut order = TriggerOrder(0.0);
                 for o in gang.triggers.iter().map(|t| objects[t.obj].1 .0) {
                     if o > order.0 {
                         order = TriggerOrder(o);
                     }
                 }
-                representative_groups.push((behavior, *group, order));
+                representative_groups.insert(behavior, (*group, order));
             }
         }
 
@@ -220,7 +180,9 @@ pub(crate) fn dedup_triggers(
         if swaps.is_empty() {
             break;
         }
+        merged += swaps.len();
         replace_groups(swaps, objects);
         clean_network(network, objects, false);
     }
+    merged
 }
@@ -509,4 +509,6 @@ pub struct Id {
     pub number: u16,
     pub unspecified: bool,
     pub class_name: IdClass,
+    // the name of a named arbitrary ID, like `?g("door")`
+    pub name: Option<LocalIntern<String>>,
 }
@@ -1,240 +1,962 @@
 // tools for automatically formatting spwn files
+//
+// `format` prints a whole file again from its syntax tree. the tree doesn't keep everything that was
+// written, so the rest is taken from the source: comments and blank lines are put back between the
+// statements and elements they were found between, and literals are copied as they were written.
+
+use std::str::FromStr;
+
+use errors::SyntaxError;
+use logos::Logos;
+use shared::{FileRange, SpwnSource};
 
 use crate::ast::*;
+use crate::parser::{parse_spwn_all, Token};
 
 pub trait SpwnFmt {
-    fn fmt(&self, ind: Indent) -> String;
+    fn fmt(&self, ind: Indent) -> String {
+        let mut p = Printer::new(None, FmtConfig::default());
+        finish(self.fmt_with(&mut p, ind))
+    }
+
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String;
 }
 
+// how many levels deep a line is indented
 type Indent = u16;
 
-fn tabs(mut num: Indent) -> String {
-    let mut out = String::new();
-    while num > 4 {
-        out += "\t";
-        num -= 4;
+/// How `format` lays out code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FmtConfig {
+    pub indent: IndentStyle,
+}
+
+impl Default for FmtConfig {
+    fn default() -> Self {
+        FmtConfig {
+            indent: IndentStyle::Spaces(4),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndentStyle {
+    Tabs,
+    Spaces(u8),
+}
+
+impl FromStr for IndentStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "tab" | "tabs" => Ok(IndentStyle::Tabs),
+            _ => match s.parse() {
+                Ok(n) if n > 0 => Ok(IndentStyle::Spaces(n)),
+                _ => Err(format!(
+                    "Invalid indentation: {} (expected a number of spaces or `tab`)",
+                    s
+                )),
+            },
+        }
     }
+}
 
-    for _ in 0..num {
-        out += " ";
+/// Formats the code of a whole file. Fails if the code has syntax errors, or (as a safety net)
+/// if the formatted code wouldn't mean exactly the same thing or would lose a comment
+pub fn format(
+    code: &str,
+    file: SpwnSource,
+    builtins: &[&'static str],
+    config: &FmtConfig,
+) -> Result<String, Vec<SyntaxError>> {
+    let crlf = code.contains("\r\n");
+    let code = code.replace("\r\n", "\n");
+    let (statements, notes) = parse_spwn_all(code.clone(), file.clone(), builtins)?;
+
+    let mut p = Printer::new(Some(&code), *config);
+    let mut lines = Vec::new();
+    let mut last = None;
+
+    if !notes.tag.tags.is_empty() {
+        // the file's attribute doesn't belong to a statement, so its position has to be found again
+        let span = p.next_code(0).and_then(|(start, _)| {
+            let open = p.find(start + 1, b'[')?;
+            Some((start, p.find(open + 1, b']')? + 1))
+        });
+        p.push_item(&mut lines, &mut last, span, 0, "", |p| {
+            p.attribute(&notes.tag, 0)
+        });
     }
+    p.statements(&mut lines, &mut last, &statements, 0);
+    p.leading_comments(&mut lines, &mut last, code.len(), 0);
 
-    out
+    let mut out = finish(lines.join("\n"));
+    if !out.is_empty() {
+        out.push('\n');
+    }
+
+    check_unchanged(&code, &out, &statements, &notes.tag, file, builtins)?;
+
+    if crlf {
+        out = out.replace('\n', "\r\n");
+    }
+    Ok(out)
+}
+
+// makes sure the formatted code parses to the same syntax tree as the original, and has all of its comments
+fn check_unchanged(
+    code: &str,
+    formatted: &str,
+    statements: &[Statement],
+    tag: &Attribute,
+    file: SpwnSource,
+    builtins: &[&'static str],
+) -> Result<(), Vec<SyntaxError>> {
+    let error = |message: &str, pos: FileRange| {
+        vec![SyntaxError::SyntaxError {
+            message: format!(
+                "{} (this is a bug in the formatter, the file was left as it is)",
+                message
+            ),
+            pos,
+            file: file.clone(),
+        }]
+    };
+
+    let (new_statements, new_notes) =
+        match parse_spwn_all(formatted.to_string(), file.clone(), builtins) {
+            Ok(p) => p,
+            Err(_) => return Err(error("The formatted code has syntax errors", (0, 0))),
+        };
+
+    if let Some(s) = statements
+        .iter()
+        .zip(&new_statements)
+        .find(|(a, b)| without_positions(a) != without_positions(b))
+        .map(|(a, _)| a)
+    {
+        return Err(error("Formatting would change what this code means", s.pos));
+    }
+    if statements.len() != new_statements.len()
+        || without_positions(tag) != without_positions(&new_notes.tag)
+    {
+        return Err(error(
+            "Formatting would change what this code means",
+            (0, 0),
+        ));
+    }
+
+    let kept = find_comments(formatted);
+    if let Some(c) = find_comments(code)
+        .iter()
+        .enumerate()
+        .find(|(i, c)| kept.get(*i).map(|k| &k.text) != Some(&c.text))
+        .map(|(_, c)| c)
+    {
+        return Err(error("This comment couldn't be kept", c.pos));
+    }
+    Ok(())
+}
+
+// the debug output of a syntax tree, without the `(start, end)` positions that are all over it
+fn without_positions(tree: &impl std::fmt::Debug) -> String {
+    let text = format!("{:?}", tree);
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+
+    // the length of a `(123, 456)` at the start of `s`
+    let position = |s: &str| {
+        let digits = |s: &str| s.bytes().take_while(u8::is_ascii_digit).count();
+        let start = digits(&s[1..]);
+        let s2 = s[1 + start..].strip_prefix(", ")?;
+        let end = digits(s2);
+        (start > 0 && end > 0 && s2[end..].starts_with(')')).then(|| 1 + start + 2 + end + 1)
+    };
+
+    while let Some(i) = rest.find('(') {
+        out += &rest[..i];
+        rest = &rest[i..];
+        match position(rest) {
+            Some(len) => rest = &rest[len..],
+            None => {
+                out.push('(');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out + rest
+}
+
+#[derive(Debug, Clone)]
+struct Comment {
+    pos: FileRange,
+    text: String,
+}
+
+// where the comment or string literal that starts at `i` ends, and whether it's a comment
+fn skip_literal(text: &[u8], i: usize) -> Option<(usize, bool)> {
+    match text[i] {
+        b'/' if text.get(i + 1) == Some(&b'/') => Some((
+            text[i..]
+                .iter()
+                .position(|c| *c == b'\n')
+                .map_or(text.len(), |n| i + n),
+            true,
+        )),
+        b'/' if text.get(i + 1) == Some(&b'*') => Some((
+            text[i + 2..]
+                .windows(2)
+                .position(|w| w == b"*/")
+                .map_or(text.len(), |n| i + n + 4),
+            true,
+        )),
+        q @ (b'"' | b'\'') => {
+            let mut j = i + 1;
+            while j < text.len() && text[j] != q {
+                j += if text[j] == b'\\' { 2 } else { 1 };
+            }
+            Some(((j + 1).min(text.len()), false))
+        }
+        _ => None,
+    }
+}
+
+fn find_comments(text: &str) -> Vec<Comment> {
+    let bytes = text.as_bytes();
+    let mut comments = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match skip_literal(bytes, i) {
+            Some((end, is_comment)) => {
+                if is_comment {
+                    comments.push(Comment {
+                        pos: (i, end),
+                        text: text[i..end].trim_end().to_string(),
+                    });
+                }
+                i = end;
+            }
+            None => i += 1,
+        }
+    }
+    comments
+}
+
+// lines that continue a multiline string or comment start with this while printing,
+// so they aren't indented along with the code around them
+const VERBATIM: char = '\u{0}';
+
+fn verbatim(text: &str) -> String {
+    text.replace('\n', &format!("\n{}", VERBATIM))
 }
 
-pub fn _format(input: Vec<Statement>) -> String {
+fn finish(text: String) -> String {
+    text.replace(VERBATIM, "")
+}
+
+fn escape(s: &str) -> String {
     let mut out = String::new();
-    for s in input {
-        out += &s.fmt(0)
+    for c in s.chars() {
+        match c {
+            '\\' => out += "\\\\",
+            '"' => out += "\\\"",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            c => out.push(c),
+        }
     }
     out
 }
 
-pub fn _format2(input: &ValueBody) -> String {
-    input.fmt(0)
+// keys that aren't names have to be written as strings
+fn dict_key(name: &str) -> String {
+    let mut lexer = Token::lexer(name);
+    match (lexer.next(), lexer.next()) {
+        (Some(Token::Symbol | Token::Type), None) if lexer.span().end == name.len() => {
+            name.to_string()
+        }
+        _ => format!("\"{}\"", escape(name)),
+    }
+}
+
+// for strings that weren't read from a source
+fn string_literal(s: &StrInner) -> String {
+    match &s.flags {
+        Some(StringFlags::Raw) => format!("r\"{}\"", s.inner),
+        Some(StringFlags::Base64) => match base64::decode(&s.inner)
+            .ok()
+            .and_then(|b| String::from_utf8(b).ok())
+        {
+            Some(decoded) => format!("64\"{}\"", escape(&decoded)),
+            None => format!("\"{}\"", escape(&s.inner)),
+        },
+        _ => format!("\"{}\"", escape(&s.inner)),
+    }
 }
 
-fn element_list(elements: &[impl SpwnFmt], open: char, closing: char, ind: Indent) -> String {
-    if elements.is_empty() {
-        return format!("{}{}", open, closing);
+/// Keeps track of the source while printing, and of which of its comments have been printed
+pub struct Printer<'a> {
+    config: FmtConfig,
+    source: Option<&'a str>,
+    comments: Vec<Comment>,
+    // the first comment that hasn't been printed yet
+    next_comment: usize,
+}
+
+// something that's printed as an element of a list, like an argument or a dictionary entry
+trait ListItem: SpwnFmt {
+    // the part of the source it was parsed from, if it's known
+    fn span(&self) -> Option<FileRange>;
+}
+
+impl<'a> Printer<'a> {
+    fn new(source: Option<&'a str>, config: FmtConfig) -> Self {
+        Printer {
+            config,
+            source,
+            comments: source.map(find_comments).unwrap_or_default(),
+            next_comment: 0,
+        }
+    }
+
+    fn tabs(&self, ind: Indent) -> String {
+        match self.config.indent {
+            IndentStyle::Tabs => "\t".repeat(ind as usize),
+            IndentStyle::Spaces(n) => " ".repeat(ind as usize * n as usize),
+        }
+    }
+
+    // removes a level of indentation from every line of `text` after the first
+    fn dedent(&self, text: &str) -> String {
+        let unit = self.tabs(1);
+        let mut lines = text.split('\n');
+        let mut out = lines.next().unwrap_or_default().to_string();
+        for line in lines {
+            out.push('\n');
+            out += line.strip_prefix(unit.as_str()).unwrap_or(line);
+        }
+        out
+    }
+
+    // the first character of code at or after `from`
+    fn next_code(&self, from: usize) -> Option<(usize, u8)> {
+        let text = self.source?.as_bytes();
+        let mut i = from;
+        while i < text.len() {
+            match skip_literal(text, i) {
+                Some((end, true)) => i = end,
+                _ if text[i].is_ascii_whitespace() => i += 1,
+                _ => return Some((i, text[i])),
+            }
+        }
+        None
+    }
+
+    // the first `c` at or after `from` that isn't inside brackets, strings or comments
+    fn find(&self, from: usize, c: u8) -> Option<usize> {
+        let text = self.source?.as_bytes();
+        let mut depth = 0usize;
+        let mut i = from;
+        while i < text.len() {
+            if let Some((end, _)) = skip_literal(text, i) {
+                i = end;
+                continue;
+            }
+            if depth == 0 && text[i] == c {
+                return Some(i);
+            }
+            match text[i] {
+                b'(' | b'[' | b'{' => depth += 1,
+                b')' | b']' | b'}' if depth == 0 => return None,
+                b')' | b']' | b'}' => depth -= 1,
+                _ => (),
+            }
+            i += 1;
+        }
+        None
+    }
+
+    // the opening and closing brackets of the first `{ ... }` at or after `from`
+    fn block_at(&self, from: usize) -> Option<(usize, usize)> {
+        let open = self.find(from, b'{')?;
+        Some((open, self.find(open + 1, b'}')?))
     }
 
-    let mut elem_text = Vec::<String>::new();
-    let mut sum = 0;
+    // the text of the first token of one of these kinds in `pos`, as it was written
+    fn literal(&self, pos: Option<FileRange>, kinds: &[Token]) -> Option<String> {
+        let (start, end) = pos?;
+        let mut lexer = Token::lexer(self.source?.get(start..end)?);
+        while let Some(token) = lexer.next() {
+            if kinds.contains(&token) {
+                return Some(lexer.slice().to_string());
+            }
+        }
+        None
+    }
+
+    // whether there's an empty line after `from`, before `to` or anything else that comes first
+    fn blank_line(&self, from: usize, to: usize) -> bool {
+        match self.source.and_then(|s| s.get(from..to)) {
+            Some(gap) => {
+                let rest =
+                    gap.trim_start_matches(|c: char| c.is_whitespace() || c == ',' || c == ';');
+                gap[..gap.len() - rest.len()].matches('\n').count() > 1
+            }
+            None => false,
+        }
+    }
+
+    // the comments starting before `pos` that haven't been printed yet
+    fn comments_before(&mut self, pos: usize) -> Vec<Comment> {
+        let start = self.next_comment;
+        while self
+            .comments
+            .get(self.next_comment)
+            .is_some_and(|c| c.pos.0 < pos)
+        {
+            self.next_comment += 1;
+        }
+        self.comments[start..self.next_comment].to_vec()
+    }
 
-    let last = elements.len() - 1;
+    // the comments after something that ends at `end`, on the same line and with only separators in between
+    fn trailing_comments(&mut self, end: usize) -> Vec<Comment> {
+        let mut comments = Vec::new();
+        let mut from = end;
+        while let (Some(c), Some(text)) = (self.comments.get(self.next_comment), self.source) {
+            match text.get(from..c.pos.0) {
+                Some(gap) if gap.bytes().all(|b| matches!(b, b' ' | b'\t' | b',' | b';')) => {
+                    from = c.pos.1;
+                    comments.push(c.clone());
+                    self.next_comment += 1;
+                }
+                _ => break,
+            }
+        }
+        comments
+    }
+
+    // prints the comments before `pos` on their own lines, keeping empty lines between them.
+    // `last` is where the last thing printed ended, or `None` at the start of a block
+    fn leading_comments(
+        &mut self,
+        lines: &mut Vec<String>,
+        last: &mut Option<usize>,
+        pos: usize,
+        ind: Indent,
+    ) {
+        for c in self.comments_before(pos) {
+            if last.is_some_and(|l| self.blank_line(l, c.pos.0)) {
+                lines.push(String::new());
+            }
+            lines.push(self.tabs(ind) + &verbatim(&c.text));
+            *last = Some(c.pos.1);
+        }
+    }
+
+    // prints a statement or list element on a new line, with the comments around it,
+    // and returns its text without the comments
+    fn push_item(
+        &mut self,
+        lines: &mut Vec<String>,
+        last: &mut Option<usize>,
+        span: Option<FileRange>,
+        ind: Indent,
+        suffix: &str,
+        print: impl FnOnce(&mut Self) -> String,
+    ) -> String {
+        let (start, end) = match span {
+            Some(span) if self.source.is_some() => span,
+            _ => {
+                let text = print(self);
+                lines.push(self.tabs(ind) + &text + suffix);
+                *last = None;
+                return text;
+            }
+        };
+
+        self.leading_comments(lines, last, start, ind);
+        if last.is_some_and(|l| self.blank_line(l, start)) {
+            lines.push(String::new());
+        }
+
+        let text = print(self);
+        let mut line = self.tabs(ind) + &text + suffix;
+        // comments inside of it that couldn't be put anywhere else go after it
+        let inner = self.comments_before(end);
+        let trailing = self.trailing_comments(end);
+        if inner.is_empty() {
+            for c in &trailing {
+                line += " ";
+                line += &verbatim(&c.text);
+            }
+            lines.push(line);
+        } else {
+            lines.push(line);
+            for c in inner.iter().chain(&trailing) {
+                lines.push(self.tabs(ind) + &verbatim(&c.text));
+            }
+        }
+        *last = Some(
+            self.comments[..self.next_comment]
+                .last()
+                .map_or(end, |c| c.pos.1.max(end)),
+        );
+        text
+    }
 
-    for (_i, el) in elements.iter().enumerate() {
-        let text = el.fmt(0);
+    fn statements(
+        &mut self,
+        lines: &mut Vec<String>,
+        last: &mut Option<usize>,
+        statements: &[Statement],
+        ind: Indent,
+    ) {
+        for s in statements {
+            self.push_item(lines, last, Some(s.pos), ind, "", |p| s.fmt_with(p, ind));
+        }
+    }
 
-        sum += text.lines().next().unwrap().len();
+    // a `{ ... }` block, where `range` is the position of its brackets in the source
+    fn block(
+        &mut self,
+        statements: &[Statement],
+        range: Option<(usize, usize)>,
+        ind: Indent,
+    ) -> String {
+        let mut lines = Vec::new();
+        let mut last = None;
+        self.statements(&mut lines, &mut last, statements, ind + 1);
+        if let Some((_, close)) = range {
+            self.leading_comments(&mut lines, &mut last, close, ind + 1);
+        }
 
-        elem_text.push(text)
+        if lines.is_empty() {
+            "{}".to_string()
+        } else {
+            format!("{{\n{}\n{}}}", lines.join("\n"), self.tabs(ind))
+        }
     }
 
-    let vertical = if elements.len() == 1 {
-        sum > 150
-    } else {
-        elem_text.iter().enumerate().any(|(i, x)| {
-            if i != last {
-                x.len() > 50 || x.contains('\n')
+    // a list of elements between brackets, either on one line or with one element per line.
+    // `range` is the position of the brackets in the source, if it's known
+    fn list<T: ListItem>(
+        &mut self,
+        elements: &[T],
+        open: char,
+        closing: char,
+        range: Option<(usize, usize)>,
+        vertical: bool,
+        ind: Indent,
+    ) -> String {
+        let comments_start = self.next_comment;
+        let close = range.map(|(_, close)| close).or_else(|| {
+            let (start, _) = elements.last()?.span()?;
+            self.find(start, closing as u8)
+        });
+
+        let mut lines = Vec::new();
+        let mut last = None;
+        let mut elem_text = Vec::new();
+        for el in elements {
+            elem_text.push(
+                self.push_item(&mut lines, &mut last, el.span(), ind + 1, ",", |p| {
+                    el.fmt_with(p, ind + 1)
+                }),
+            );
+        }
+        if let Some(close) = close {
+            self.leading_comments(&mut lines, &mut last, close, ind + 1);
+        }
+
+        if elem_text.is_empty() && lines.is_empty() {
+            return format!("{}{}", open, closing);
+        }
+
+        // roughly how long the line would be with everything on it
+        let sum: usize = elem_text
+            .iter()
+            .map(|x| x.lines().next().unwrap_or_default().len() + 2)
+            .sum::<usize>()
+            + self.tabs(ind).len();
+        let last_index = elem_text.len().saturating_sub(1);
+
+        let vertical = vertical
+            || self.next_comment != comments_start
+            || if elem_text.len() == 1 {
+                sum > 150
             } else {
-                sum > 100
+                elem_text.iter().enumerate().any(|(i, x)| {
+                    if i != last_index {
+                        x.len() > 50 || x.contains('\n')
+                    } else {
+                        // a long attribute puts the rest of the element on the next line
+                        sum > 100 || (x.starts_with("#[") && x.contains('\n'))
+                    }
+                })
+            };
+
+        if vertical {
+            format!(
+                "{}\n{}\n{}{}",
+                open,
+                lines.join("\n"),
+                self.tabs(ind),
+                closing
+            )
+        } else {
+            let last_elem = elem_text.pop().unwrap();
+            let mut out = open.to_string();
+            for el in &elem_text {
+                out += el;
+                out += ", ";
             }
-        })
-    };
+            // it was printed to go on its own line, one level deeper than it ends up
+            out += &self.dedent(&last_elem);
+            out.push(closing);
+            out
+        }
+    }
 
-    if vertical {
-        let mut out = format!("{}\n", open);
+    // `#[...]`
+    fn attribute(&mut self, attr: &Attribute, ind: Indent) -> String {
+        String::from("#") + &self.list(&attr.tags, '[', ']', None, false, ind)
+    }
 
-        for el in &elem_text {
-            for line in el.lines() {
-                out += &format!("{}{}\n", tabs(ind + 4), line);
+    fn value(&mut self, body: &ValueBody, pos: Option<FileRange>, ind: Indent) -> String {
+        use ValueBody::*;
+        let start = pos.map(|(start, _)| start);
+        match body {
+            Id(x) => x.fmt_with(self, ind),
+            Number(x) => self
+                .literal(
+                    pos,
+                    &[
+                        Token::Number,
+                        Token::BinaryLiteral,
+                        Token::HexLiteral,
+                        Token::OctalLiteral,
+                    ],
+                )
+                .unwrap_or_else(|| x.to_string()),
+            CmpStmt(x) => {
+                let range = start.and_then(|s| self.block_at(s));
+                format!("!{}", self.block(&x.statements, range, ind))
             }
-            out.pop();
-            out += ",\n";
+            Dictionary(x) => {
+                let range = start.and_then(|s| self.block_at(s));
+                self.list(x, '{', '}', range, false, ind)
+            }
+            Array(x) => {
+                let range = start.and_then(|s| {
+                    let open = self.find(s, b'[')?;
+                    Some((open, self.find(open + 1, b']')?))
+                });
+                self.list(x, '[', ']', range, false, ind)
+            }
+            Symbol(x) => x.to_string(),
+            Bool(x) => x.to_string(),
+            Expression(x) => match self.decorator(x, pos, ind) {
+                Some(text) => text,
+                None => format!("({})", x.fmt_with(self, ind)),
+            },
+            Str(x) => match self.literal(pos, &[Token::StringLiteral]) {
+                Some(text) => verbatim(&text),
+                None => string_literal(x),
+            },
+            Import(x, forced) => {
+                let target = match x {
+                    shared::ImportType::Script(path) => {
+                        match self.literal(pos, &[Token::StringLiteral]) {
+                            Some(text) => text,
+                            None => format!("\"{}\"", escape(&path.to_string_lossy())),
+                        }
+                    }
+                    shared::ImportType::Lib(name) => name.clone(),
+                };
+                format!("import{} {}", if *forced { "!" } else { "" }, target)
+            }
+            Match(value, cases) => {
+                let range = self.block_at(value.get_pos().1);
+                format!(
+                    "match {} {}",
+                    value.fmt_with(self, ind),
+                    self.list(cases, '{', '}', range, true, ind)
+                )
+            }
+            ListComp(c) => format!(
+                "[{} for {} in {}{}]",
+                c.body.fmt_with(self, ind),
+                c.symbol,
+                c.iterator.fmt_with(self, ind),
+                match &c.condition {
+                    Some(cond) => format!(", if {}", cond.fmt_with(self, ind)),
+                    None => String::new(),
+                }
+            ),
+            Obj(x) => {
+                let range = start.and_then(|s| self.block_at(s));
+                format!(
+                    "{} {}",
+                    match x.mode {
+                        ObjectMode::Object => "obj",
+                        ObjectMode::Trigger => "trigger",
+                    },
+                    self.list(&x.props, '{', '}', range, false, ind)
+                )
+            }
+            Macro(x) => self.macro_def(x, ind),
+            Resolved(_) => "<val>".to_string(),
+            TypeIndicator(x) => format!("@{}", x),
+            MacroPattern(x) => {
+                // `a -> b` is the same as `(a) -> b`
+                let short = match &x.args[..] {
+                    [arg] if arg.values.len() == 1 => {
+                        !matches!(start.and_then(|s| self.next_code(s)), Some((_, b'(')))
+                    }
+                    _ => false,
+                };
+                let args = if short {
+                    x.args[0].fmt_with(self, ind)
+                } else {
+                    self.list(&x.args, '(', ')', None, false, ind)
+                };
+                format!("{} -> {}", args, x.ret.fmt_with(self, ind))
+            }
+            SelfVal => "self".to_string(),
+            Null => "null".to_string(),
+            Ternary(t) => format!(
+                "{} if {}{} else {}",
+                t.if_expr.fmt_with(self, ind),
+                if t.is_pattern { "is " } else { "" },
+                t.condition.fmt_with(self, ind),
+                t.else_expr.fmt_with(self, ind)
+            ),
+        }
+    }
+
+    // the parser turns decorators (`[[deco]] (args) { ... }` and `[[deco]] !{ ... }`) into calls of the decorator,
+    // with the macro or trigger function as the last argument
+    fn decorator(&mut self, e: &Expression, pos: Option<FileRange>, ind: Indent) -> Option<String> {
+        let (start, _) = pos?;
+        let (open, _) = self.next_code(start).filter(|(_, c)| *c == b'[')?;
+        self.next_code(open + 1).filter(|(_, c)| *c == b'[')?;
+
+        let deco = match &e.values[..] {
+            [deco] if e.operators.is_empty() => deco,
+            _ => return None,
+        };
+        let args = match deco.path.last() {
+            Some(Path::Call(args)) => args,
+            _ => return None,
+        };
+        let target = match args.last() {
+            Some(Argument {
+                symbol: None,
+                value,
+                ..
+            }) if value.operators.is_empty() && value.values.len() == 1 => &value.values[0],
+            _ => return None,
+        };
+        if !matches!(
+            target.value.body,
+            ValueBody::Macro(_) | ValueBody::CmpStmt(_)
+        ) {
+            return None;
         }
-        /*if elements.len() == 1 {
-            out.pop();
-            out.pop();
-            out += "\n";
-        }*/
 
-        out + &format!("{}{}", tabs(ind), closing)
-    } else {
-        let mut out = format!("{}", open);
-        let last_elem = elem_text.pop().unwrap();
-        let iter = elem_text.iter();
+        let mut deco = deco.clone();
+        deco.path.pop();
+        if args.len() > 1 {
+            deco.path.push(Path::Call(args[..args.len() - 1].to_vec()));
+        }
+        // the macro has the same tags as the variable they're both in, which are already printed
+        Some(format!(
+            "[[{}]] {}",
+            deco.fmt_with(self, ind),
+            self.value(&target.value.body, Some(target.pos), ind)
+        ))
+    }
+
+    fn macro_def(&mut self, m: &Macro, ind: Indent) -> String {
+        let arrow_body = |p: &mut Self| match &m.body.statements[..] {
+            [Statement {
+                body: StatementBody::Return(Some(expr)),
+                ..
+            }] => Some(expr.fmt_with(p, ind)),
+            _ => None,
+        };
 
-        for el in iter {
-            out += &format!("{}, ", el);
+        // `x => ...`
+        let shorthand = self
+            .source
+            .and_then(|s| s.get(m.arg_pos.0..))
+            .is_some_and(|s| s.starts_with("=>"));
+        if shorthand {
+            if let Some(body) = arrow_body(self) {
+                let arg = m.args.first().map_or("_".to_string(), |a| a.0.to_string());
+                return format!("{} => {}", arg, body);
+            }
         }
 
-        let mut last_elem_lines = last_elem.lines();
-        out += last_elem_lines.next().unwrap();
+        let range = self
+            .source
+            .map(|_| (m.arg_pos.0, m.arg_pos.1.saturating_sub(1)));
+        let mut out = self.list(&m.args, '(', ')', range, false, ind);
+        let mut after = m.arg_pos.1;
+        if let Some(ret) = &m.ret_type {
+            out += &format!(" -> {}", ret.fmt_with(self, ind));
+            after = ret.get_pos().1;
+        }
 
-        for line in last_elem_lines {
-            out += &format!("\n{}{}", tabs(ind), line);
+        if let Some((_, b'=')) = self.next_code(after) {
+            if let Some(body) = arrow_body(self) {
+                return format!("{} => {}", out, body);
+            }
         }
+        let range = self.block_at(after);
+        format!("{} {}", out, self.block(&m.body.statements, range, ind))
+    }
 
-        out.push(closing);
-        out
+    // a value in an expression. parentheses that the parser adds for operator precedence are left out
+    fn operand(&mut self, expr: &Expression, i: usize, ind: Indent) -> String {
+        match implicit_parens(&expr.values[i]) {
+            Some(inner) => inner.fmt_with(self, ind),
+            None => expr.values[i].fmt_with(self, ind),
+        }
+    }
+}
+
+// the expression in a variable the parser made to group operators by precedence
+// (`a + b * c` is parsed as `a + (b * c)`), which doesn't start at a `(` like the ones that were written
+fn implicit_parens(var: &Variable) -> Option<&Expression> {
+    match &var.value.body {
+        ValueBody::Expression(inner)
+            if !inner.operators.is_empty()
+                && var.operator.is_none()
+                && var.path.is_empty()
+                && var.tag.tags.is_empty()
+                && var.pos.0 == inner.get_pos().0 =>
+        {
+            Some(inner)
+        }
+        _ => None,
+    }
+}
+
+impl ListItem for DictDef {
+    fn span(&self) -> Option<FileRange> {
+        match self {
+            DictDef::Def((_, expr)) | DictDef::Extract(expr) => Some(expr.get_pos()),
+        }
+    }
+}
+
+impl ListItem for ArrayDef {
+    fn span(&self) -> Option<FileRange> {
+        Some(self.value.get_pos())
+    }
+}
+
+impl ListItem for Argument {
+    fn span(&self) -> Option<FileRange> {
+        Some(self.pos)
+    }
+}
+
+impl ListItem for ArgDef {
+    fn span(&self) -> Option<FileRange> {
+        Some(self.4)
+    }
+}
+
+impl ListItem for (Expression, Expression) {
+    fn span(&self) -> Option<FileRange> {
+        Some((self.0.get_pos().0, self.1.get_pos().1))
+    }
+}
+
+impl ListItem for Case {
+    fn span(&self) -> Option<FileRange> {
+        match &self.typ {
+            CaseType::Pattern(pat) => Some((pat.get_pos().0, self.body.get_pos().1)),
+            CaseType::Default => Some(self.body.get_pos()),
+        }
+    }
+}
+
+impl ListItem for Expression {
+    fn span(&self) -> Option<FileRange> {
+        Some(self.get_pos())
+    }
+}
+
+impl ListItem for (String, Vec<Argument>) {
+    fn span(&self) -> Option<FileRange> {
+        None
     }
 }
 
 impl SpwnFmt for DictDef {
-    fn fmt(&self, ind: Indent) -> String {
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
         match self {
-            DictDef::Def((name, expr)) => format!("{}{}: {}", tabs(ind), name, expr.fmt(ind)),
-            DictDef::Extract(expr) => format!("{}..{}", tabs(ind), expr.fmt(ind)),
-        }
-    }
-}
-
-// fn trim_start_tabs(string: &str) -> (&str, Indent) {
-//     //https://doc.rust-lang.org/src/core/str/mod.rs.html#4082-4090
-//     let mut ind = 0;
-//     for (i, c) in string.chars().enumerate() {
-//         match c {
-//             '\t' => ind += 4,
-//             ' ' => ind += 1,
-//             _ => return (unsafe { string.get_unchecked(i..string.len()) }, ind),
-//         }
-//     }
-//     ("", ind)
-// }
-
-// fn indent_comment(comment: &str, ind: Indent) -> String {
-//     let mut in_comment = false;
-//     let mut current_off = 0;
-//     let mut out = String::new();
-//     for line in comment.lines() {
-//         let (trimmed, ind_offset) = trim_start_tabs(line);
-//         if !in_comment {
-//             if trimmed.starts_with("//") {
-//                 out += &format!("{}{}\r\n", tabs(ind), trimmed);
-//             } else if trimmed.starts_with("/*") {
-//                 in_comment = true;
-//                 current_off = ind_offset;
-//                 out += &format!("{}{}\r\n", tabs(ind), trimmed);
-//             }
-//         } else {
-//             out += &format!("{}{}\r\n", tabs(ind_offset - current_off), trimmed);
-//         }
-
-//         if line.trim_end().ends_with("*/") {
-//             in_comment = false
-//         }
-//     }
-//     out
-// }
-
-/*#[cfg(test)]
-mod tests {
-    use super::*;
-    #[test]
-    fn test() {
-        println!(
-            "{}",
-            indent_comment(
-                &String::from(
-                    "
-//hello
-                /*
-                a = {
-                    b = 2
-                    c = 3
-                    a = {
-                        b = 2
-                        c = 3
-                        a = {
-                            b = 2
-                            c = 3
-                        }
-                    }
+            DictDef::Def((name, expr)) => {
+                let shorthand = match &expr.values[..] {
+                    [Variable {
+                        operator: None,
+                        value:
+                            ValueLiteral {
+                                body: ValueBody::Symbol(s),
+                            },
+                        path,
+                        tag,
+                        ..
+                    }] => s == name && path.is_empty() && tag.tags.is_empty(),
+                    _ => false,
+                };
+                if shorthand && name.as_str() != "type" {
+                    name.to_string()
+                } else {
+                    format!("{}: {}", dict_key(name), expr.fmt_with(p, ind))
                 }
-                */
-
-//bruh
-//bruh
-//bruh
-        "
-                ),
-                0
-            )
-        )
+            }
+            DictDef::Extract(expr) => format!("..{}", expr.fmt_with(p, ind)),
+        }
     }
-}*/
+}
 
 impl SpwnFmt for Statement {
-    fn fmt(&self, ind: Indent) -> String {
-        let mut out = String::new();
-        // if let Some(comment) = &self.comment.0 {
-        //     //out += "[stmt pre]";
-
-        //     out += &indent_comment(comment, ind);
-        //     //
-        //     if !comment.ends_with('\n') {
-        //         out += "\n";
-        //     }
-        //     out += &tabs(ind);
-        // }
-        out += &if self.arrow {
-            format!("-> {}\n", self.body.fmt(ind))
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
+        let body = self.body.fmt_with(p, ind);
+        if self.arrow {
+            format!("-> {}", body)
         } else {
-            format!("{}\n", self.body.fmt(ind))
-        };
-        // if let Some(comment) = &self.comment.1 {
-        //     if !comment.starts_with('\n') {
-        //         out += "\n";
-        //     }
-
-        //     //out += &tabs(ind);
-        //     //out += "[stmt post]";
-        //     out += &indent_comment(comment, ind);
-        // }
-        out
+            body
+        }
     }
 }
 
 impl SpwnFmt for StatementBody {
-    fn fmt(&self, ind: Indent) -> String {
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
         match self {
-            //StatementBody::Definition(def) => format!("{}", def.fmt(ind)),
-            StatementBody::Call(call) => call.fmt(ind),
-            StatementBody::Expr(x) => x.fmt(ind),
-            StatementBody::TypeDef { name, .. } => format!("type {}", name),
+            StatementBody::Call(call) => call.fmt_with(p, ind),
+            StatementBody::Expr(x) => x.fmt_with(p, ind),
+            StatementBody::TypeDef { name, attr } => {
+                format!("{}type @{}", attr.fmt_with(p, ind), name)
+            }
             StatementBody::Return(x) => match x {
-                Some(expr) => format!("return {}", expr.fmt(ind)),
+                Some(expr) => format!("return {}", expr.fmt_with(p, ind)),
                 None => "return".to_string(),
             },
-            StatementBody::Definition(x) => x.fmt(ind),
-            StatementBody::Impl(x) => x.fmt(ind),
-            StatementBody::If(x) => x.fmt(ind),
-            StatementBody::For(x) => x.fmt(ind),
-            StatementBody::While(_) => "While loop lol".to_string(),
-            StatementBody::Error(x) => x.fmt(ind),
-            StatementBody::Extract(x) => format!("extract {}", x.fmt(ind)),
+            StatementBody::Definition(x) => x.fmt_with(p, ind),
+            StatementBody::Impl(x) => x.fmt_with(p, ind),
+            StatementBody::If(x) => x.fmt_with(p, ind),
+            StatementBody::For(x) => x.fmt_with(p, ind),
+            StatementBody::While(x) => x.fmt_with(p, ind),
+            StatementBody::Error(x) => x.fmt_with(p, ind),
+            StatementBody::Extract(x) => format!("extract {}", x.fmt_with(p, ind)),
             StatementBody::Break => String::from("break"),
             StatementBody::Continue => String::from("continue"),
         }
@@ -243,83 +965,35 @@ impl SpwnFmt for StatementBody {
 
 //for object def
 impl SpwnFmt for (Expression, Expression) {
-    fn fmt(&self, ind: Indent) -> String {
-        format!("{}: {}", self.0.fmt(ind), self.1.fmt(ind))
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
+        format!("{}: {}", self.0.fmt_with(p, ind), self.1.fmt_with(p, ind))
     }
 }
 
 impl SpwnFmt for ArrayDef {
-    fn fmt(&self, ind: Indent) -> String {
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
         match &self.operator {
-            Some(ArrayPrefix::Collect) => format!("*{}", self.value.fmt(ind)),
-            Some(ArrayPrefix::Spread) => format!("..{}", self.value.fmt(ind)),
-            None => self.value.fmt(ind),
+            Some(ArrayPrefix::Collect) => format!("*{}", self.value.fmt_with(p, ind)),
+            Some(ArrayPrefix::Spread) => format!("..{}", self.value.fmt_with(p, ind)),
+            None => self.value.fmt_with(p, ind),
         }
     }
 }
 
 impl SpwnFmt for ValueBody {
-    fn fmt(&self, ind: Indent) -> String {
-        use ValueBody::*;
-        match self {
-            Id(x) => x.fmt(ind),
-            Number(x) => format!("{}", x),
-            CmpStmt(x) => format!("!{{\n{}\n{}}}", x.fmt(ind + 4), tabs(ind)),
-            Dictionary(x) => element_list(x, '{', '}', ind),
-            Array(x) => element_list(x, '[', ']', ind),
-            Symbol(x) => x.to_string(),
-            Bool(x) => format!("{}", x),
-            Expression(x) => format!("({})", x.fmt(ind)),
-            Str(x) => format!("\"{}\"", x.inner),
-            Import(x, f) => format!("import{} {:?}", if *f { "!" } else { "" }, x),
-            Obj(x) => {
-                (match x.mode {
-                    ObjectMode::Object => "obj".to_string(),
-                    ObjectMode::Trigger => "trigger".to_string(),
-                }) + &element_list(&x.props, '{', '}', ind)
-            }
-            Macro(x) => x.fmt(ind),
-            Resolved(_) => "<val>".to_string(),
-            TypeIndicator(x) => format!("@{}", x),
-            Null => "null".to_string(),
-            SelfVal => "self".to_string(),
-            Ternary(t) => {
-                if !t.is_pattern {
-                    format!(
-                        "{} if is {} else {}",
-                        t.if_expr.fmt(ind),
-                        t.condition.fmt(ind),
-                        t.else_expr.fmt(ind)
-                    )
-                } else {
-                    format!(
-                        "{} if {} else {}",
-                        t.if_expr.fmt(ind),
-                        t.condition.fmt(ind),
-                        t.else_expr.fmt(ind)
-                    )
-                }
-            }
-            ListComp(c) => format!(
-                "{} for {} in {}",
-                c.body.fmt(ind),
-                c.symbol,
-                c.iterator.fmt(ind)
-            ),
-            Match(_, _) => todo!(),
-            MacroPattern(_) => todo!(),
-        }
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
+        p.value(self, None, ind)
     }
 }
 
 impl SpwnFmt for ValueLiteral {
-    fn fmt(&self, ind: Indent) -> String {
-        self.body.fmt(ind)
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
+        self.body.fmt_with(p, ind)
     }
 }
 
 impl SpwnFmt for IdClass {
-    fn fmt(&self, _ind: Indent) -> String {
+    fn fmt_with(&self, _p: &mut Printer, _ind: Indent) -> String {
         match self {
             IdClass::Group => "g",
             IdClass::Color => "c",
@@ -330,15 +1004,43 @@ impl SpwnFmt for IdClass {
     }
 }
 
+impl SpwnFmt for Slice {
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
+        let mut part = |e: &Option<Expression>| match e {
+            Some(e) => e.fmt_with(p, ind),
+            None => String::new(),
+        };
+        // the middle part is the step when there are three
+        match &self.step {
+            Some(step) => {
+                let left = part(&self.left);
+                let step = part(&Some(step.clone()));
+                format!("{}:{}:{}", left, step, part(&self.right))
+            }
+            None => {
+                let left = part(&self.left);
+                format!("{}:{}", left, part(&self.right))
+            }
+        }
+    }
+}
+
 impl SpwnFmt for Path {
-    fn fmt(&self, ind: Indent) -> String {
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
         match self {
             Path::Member(def) => format!(".{}", def),
             Path::Associated(def) => format!("::{}", def),
-            Path::NSlice(_def) => "[its a slice ok]".to_string(),
-            Path::Constructor(dict) => format!("::{}", element_list(dict, '{', '}', ind)),
-            Path::Index(call) => format!("[{}]", call.fmt(ind)),
-            Path::Call(x) => element_list(x, '(', ')', ind),
+            Path::NSlice(slices) => format!(
+                "[{}]",
+                slices
+                    .iter()
+                    .map(|s| s.fmt_with(p, ind))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Path::Constructor(dict) => format!("::{}", p.list(dict, '{', '}', None, false, ind)),
+            Path::Index(call) => format!("[{}]", call.fmt_with(p, ind)),
+            Path::Call(x) => p.list(x, '(', ')', None, false, ind),
             Path::Increment => "++".to_string(),
             Path::Decrement => "--".to_string(),
         }
@@ -346,103 +1048,97 @@ impl SpwnFmt for Path {
 }
 
 impl SpwnFmt for Argument {
-    fn fmt(&self, ind: Indent) -> String {
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
         if let Some(symbol) = &self.symbol {
-            format!("{} = {}", symbol, self.value.fmt(ind))
+            format!("{} = {}", symbol, self.value.fmt_with(p, ind))
         } else {
-            self.value.fmt(ind)
+            self.value.fmt_with(p, ind)
         }
     }
 }
 
 impl SpwnFmt for Call {
-    fn fmt(&self, ind: Indent) -> String {
-        format!("{}!", self.function.fmt(ind))
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
+        format!("{}!", self.function.fmt_with(p, ind))
     }
 }
 
 impl SpwnFmt for For {
-    fn fmt(&self, ind: Indent) -> String {
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
+        let symbol = self.symbol.fmt_with(p, ind);
+        let array = self.array.fmt_with(p, ind);
+        let range = p.block_at(self.array.get_pos().1);
         format!(
-            "for {} in {} {{\n{}\n{}}}",
-            self.symbol.fmt(ind),
-            self.array.fmt(ind),
-            CompoundStatement {
-                statements: self.body.clone()
-            }
-            .fmt(ind + 4),
-            tabs(ind)
+            "for {} in {} {}",
+            symbol,
+            array,
+            p.block(&self.body, range, ind)
         )
     }
 }
 
-impl SpwnFmt for Variable {
-    fn fmt(&self, ind: Indent) -> String {
-        let mut out = String::new();
-
-        // if let Some(comment) = &self.comment.0 {
-        //     //out += "[var pre]";
-        //     out += &indent_comment(comment, ind);
+impl SpwnFmt for While {
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
+        let condition = self.condition.fmt_with(p, ind);
+        let range = p.block_at(self.condition.get_pos().1);
+        format!("while {} {}", condition, p.block(&self.body, range, ind))
+    }
+}
 
-        //     if comment.ends_with('\n') {
-        //         out += &tabs(ind);
-        //     }
-        // }
+impl SpwnFmt for Variable {
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
+        let mut out = self.tag.fmt_with(p, ind);
 
         if let Some(op) = &self.operator {
-            out += &op.fmt(ind);
+            out += &op.fmt_with(p, ind);
         }
 
-        out += &self.value.fmt(ind);
+        out += &p.value(&self.value.body, Some(self.pos), ind);
 
-        for p in &self.path {
-            out += &p.fmt(ind).to_string();
+        for path in &self.path {
+            out += &path.fmt_with(p, ind);
         }
 
-        // if let Some(comment) = &self.comment.1 {
-        //     //out += "[var post]";
-
-        //     out += &indent_comment(comment, ind);
-
-        //     /*if comment.ends_with("\n") {
-        //         out += &tabs(ind);
-        //     }*/
-        // }
-
         out
     }
 }
 
 impl SpwnFmt for Expression {
-    fn fmt(&self, ind: Indent) -> String {
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
         let mut out = String::new();
         for (i, op) in self.operators.iter().enumerate() {
-            if let Operator::Range|Operator::InclRange = op {
-                out += &format!("{}{}", self.values[i].fmt(ind), (*op).fmt(ind));
+            out += &p.operand(self, i, ind);
+            if let Operator::Range | Operator::InclRange = op {
+                out += &op.fmt_with(p, ind);
             } else {
-                out += &format!("{} {} ", self.values[i].fmt(ind), (*op).fmt(ind));
+                out += &format!(" {} ", op.fmt_with(p, ind));
             }
         }
 
-        out += &self.values.last().unwrap().fmt(ind);
+        out += &p.operand(self, self.values.len() - 1, ind);
 
         out
     }
 }
 
 impl SpwnFmt for Id {
-    fn fmt(&self, ind: Indent) -> String {
-        format!(
-            "{}{}{}",
-            self.number,
-            if self.unspecified { "?" } else { "" },
-            self.class_name.fmt(ind),
-        )
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
+        if let Some(name) = &self.name {
+            format!(
+                "?{}(\"{}\")",
+                self.class_name.fmt_with(p, ind),
+                escape(name)
+            )
+        } else if self.unspecified {
+            format!("?{}", self.class_name.fmt_with(p, ind))
+        } else {
+            format!("{}{}", self.number, self.class_name.fmt_with(p, ind))
+        }
     }
 }
 
 impl SpwnFmt for Operator {
-    fn fmt(&self, _ind: Indent) -> String {
+    fn fmt_with(&self, _p: &mut Printer, _ind: Indent) -> String {
         match self {
             Operator::Or => "||",
             Operator::And => "&&",
@@ -481,7 +1177,7 @@ impl SpwnFmt for Operator {
 }
 
 impl SpwnFmt for UnaryOperator {
-    fn fmt(&self, _ind: Indent) -> String {
+    fn fmt_with(&self, _p: &mut Printer, _ind: Indent) -> String {
         match self {
             UnaryOperator::Not => "!",
             UnaryOperator::Minus => "-",
@@ -493,20 +1189,25 @@ impl SpwnFmt for UnaryOperator {
             UnaryOperator::LessPattern => "<",
             UnaryOperator::MoreOrEqPattern => ">=",
             UnaryOperator::LessOrEqPattern => "<=",
-            UnaryOperator::InPattern => "in",
+            UnaryOperator::InPattern => "in ",
         }
         .to_string()
     }
 }
 
 impl SpwnFmt for Definition {
-    fn fmt(&self, ind: Indent) -> String {
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
         format!(
             "{}{}{}",
             if self.mutable { "let " } else { "" },
-            self.symbol.fmt(ind),
+            self.symbol.fmt_with(p, ind),
             if let Some(value) = &self.value {
-                format!(" = {}", value.fmt(ind))
+                // `a = b + c` is parsed as an assignment, which leaves `b + c` grouped on its own
+                let value = match &value.values[..] {
+                    [var] if value.operators.is_empty() => implicit_parens(var).unwrap_or(value),
+                    _ => value,
+                };
+                format!(" = {}", value.fmt_with(p, ind))
             } else {
                 String::new()
             }
@@ -515,61 +1216,60 @@ impl SpwnFmt for Definition {
 }
 
 impl SpwnFmt for Error {
-    fn fmt(&self, ind: Indent) -> String {
-        format!("error {}", self.message.fmt(ind))
-    }
-}
-
-fn trim_newline(s: &mut String) {
-    if s.ends_with('\n') {
-        s.pop();
-        if s.ends_with('\r') {
-            s.pop();
-        }
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
+        format!("throw {}", self.message.fmt_with(p, ind))
     }
 }
 
 impl SpwnFmt for CompoundStatement {
-    fn fmt(&self, ind: Indent) -> String {
-        let mut out = String::new();
-
-        for s in &self.statements {
-            out += &format!("{}{}", tabs(ind), s.fmt(ind));
-        }
-
-        trim_newline(&mut out);
-
-        out
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
+        let mut lines = Vec::new();
+        p.statements(&mut lines, &mut None, &self.statements, ind);
+        lines.join("\n")
     }
 }
 
 impl SpwnFmt for Implementation {
-    fn fmt(&self, ind: Indent) -> String {
-        format!("impl {} ", self.symbol.fmt(ind)) + &element_list(&self.members, '{', '}', ind)
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
+        let symbol = self.symbol.fmt_with(p, ind);
+        let range = p.block_at(self.symbol.pos.1);
+        format!(
+            "impl {} {}",
+            symbol,
+            p.list(&self.members, '{', '}', range, true, ind)
+        )
     }
 }
 
 impl SpwnFmt for If {
-    fn fmt(&self, ind: Indent) -> String {
-        let mut out = format!(
-            "if {} {{\n{}\n{}}}",
-            self.condition.fmt(ind),
-            CompoundStatement {
-                statements: self.if_body.clone()
-            }
-            .fmt(ind + 4),
-            tabs(ind)
-        );
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
+        let condition = self.condition.fmt_with(p, ind);
+        let range = p.block_at(self.condition.get_pos().1);
+        let mut out = format!("if {} {}", condition, p.block(&self.if_body, range, ind));
 
         if let Some(body) = &self.else_body {
-            out += &format!(
-                " else {{\n{}\n{}}}",
-                CompoundStatement {
-                    statements: body.clone()
-                }
-                .fmt(ind + 4),
-                tabs(ind)
+            // whatever comes after the `else`
+            let after = range
+                .and_then(|(_, close)| p.next_code(close + 1))
+                .and_then(|(i, _)| p.next_code(i + "else".len()));
+            let else_if = matches!(
+                (after, &body[..]),
+                (
+                    Some((_, b'i')) | None,
+                    [Statement {
+                        body: StatementBody::If(_),
+                        arrow: false,
+                        ..
+                    }],
+                )
             );
+
+            if else_if {
+                out += &format!(" else {}", body[0].fmt_with(p, ind));
+            } else {
+                let range = after.and_then(|(i, _)| p.block_at(i));
+                out += &format!(" else {}", p.block(body, range, ind));
+            }
         }
 
         out
@@ -577,49 +1277,63 @@ impl SpwnFmt for If {
 }
 
 impl SpwnFmt for ArgDef {
-    fn fmt(&self, ind: Indent) -> String {
-        let (name, value, tag, typ, _, _) = self;
-
-        let mut out = tag.fmt(ind);
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
+        let (name, value, tag, typ, _, arg_type) = self;
+
+        let mut out = tag.fmt_with(p, ind);
+        out += match arg_type {
+            ArgType::Ref => "&",
+            ArgType::Mut => "let ",
+            ArgType::Const => "",
+        };
         out += name;
         if let Some(expr) = typ {
-            out += &format!(": {}", expr.fmt(ind));
+            out += &format!(": {}", expr.fmt_with(p, ind));
         }
 
         if let Some(expr) = value {
-            out += &format!(" = {}", expr.fmt(ind));
+            out += &format!(" = {}", expr.fmt_with(p, ind));
         }
         out
     }
 }
 
 impl SpwnFmt for Macro {
-    fn fmt(&self, ind: Indent) -> String {
-        let mut out = String::new();
-
-        out += &self.properties.fmt(ind);
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
+        p.macro_def(self, ind)
+    }
+}
 
-        out += &element_list(&self.args, '(', ')', ind);
-        out += &format!(" {{\n{}\n{}}}", &self.body.fmt(ind + 4), tabs(ind));
-        out
+impl SpwnFmt for Case {
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
+        match &self.typ {
+            CaseType::Pattern(pat) => {
+                format!("{}: {}", pat.fmt_with(p, ind), self.body.fmt_with(p, ind))
+            }
+            CaseType::Default => format!("else: {}", self.body.fmt_with(p, ind)),
+        }
     }
 }
 
 impl SpwnFmt for (String, Vec<Argument>) {
-    fn fmt(&self, ind: Indent) -> String {
-        self.0.clone() + &element_list(&self.1, '(', ')', ind)
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
+        if self.1.is_empty() {
+            self.0.clone()
+        } else {
+            self.0.clone() + &p.list(&self.1, '(', ')', None, false, ind)
+        }
     }
 }
 
 impl SpwnFmt for Attribute {
-    fn fmt(&self, ind: Indent) -> String {
+    fn fmt_with(&self, p: &mut Printer, ind: Indent) -> String {
         if self.tags.is_empty() {
             return String::new();
         }
 
-        let text = String::from("#") + &element_list(&self.tags, '[', ']', ind);
+        let text = p.attribute(self, ind);
         if text.len() > 60 {
-            text + "\n" + &tabs(ind)
+            text + "\n" + &p.tabs(ind)
         } else {
             text + " "
         }
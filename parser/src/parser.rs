@@ -368,6 +368,8 @@ pub struct Tokens<'a> {
     line_breaks: Vec<u32>,
     //index 0 = element of iter / last element in stack
     index: TokenPos,
+    // errors from statements that were skipped over by `recover`
+    errors: Vec<SyntaxError>,
 }
 
 impl<'a> Tokens<'a> {
@@ -377,6 +379,105 @@ impl<'a> Tokens<'a> {
             stack: Vec::new(),
             line_breaks: vec![0],
             index: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    // the amount of tokens that have been consumed, used as a bookmark for `recover`
+    fn consumed(&self) -> usize {
+        self.stack.len() - self.index
+    }
+
+    // skips the rest of a statement that failed to parse, so the parser can carry on with the next one.
+    // `start` is where the statement began. this stops after the first statement separator that
+    // isn't inside brackets, or before the '}' that closes the surrounding block
+    fn recover(&mut self, start: usize, in_block: bool) {
+        use Token::*;
+
+        // the indentation of the line that `pos` is on
+        fn indent(source: &str, pos: usize) -> usize {
+            let line_start = source[..pos].rfind('\n').map_or(0, |i| i + 1);
+            source[line_start..]
+                .chars()
+                .take_while(|c| *c == ' ' || *c == '\t')
+                .count()
+        }
+
+        // returns true if the token ends the statement. `open` has the unclosed brackets, with the
+        // indentation of the lines they were opened on
+        fn track(
+            open: &mut Vec<(Token, usize)>,
+            token: Token,
+            span: (usize, usize),
+            source: &str,
+            in_block: bool,
+        ) -> bool {
+            match token {
+                OpenBracket | OpenSquareBracket | OpenCurlyBracket => {
+                    open.push((token, indent(source, span.0)))
+                }
+                // unclosed brackets inside a '{}' are most likely just typos
+                ClosingCurlyBracket => {
+                    match open.iter().rposition(|(t, _)| *t == OpenCurlyBracket) {
+                        Some(i) => open.truncate(i),
+                        None => {
+                            open.clear();
+                            return in_block;
+                        }
+                    }
+                }
+                ClosingBracket | ClosingSquareBracket => {
+                    let opening = if token == ClosingBracket {
+                        OpenBracket
+                    } else {
+                        OpenSquareBracket
+                    };
+                    if open.last().map(|(t, _)| *t) == Some(opening) {
+                        open.pop();
+                    }
+                }
+                StatementSeparator => {
+                    if open.is_empty() {
+                        return true;
+                    }
+                    // a '(' or '[' outside of any block that is never closed would skip the rest
+                    // of the file, so a line that isn't indented further than the statement and
+                    // doesn't close a bracket starts the next statement
+                    let next_line = source[span.1..].trim_start_matches([' ', '\t']);
+                    return !in_block
+                        && source[span.0..span.1].contains('\n')
+                        && open.iter().all(|(t, _)| *t != OpenCurlyBracket)
+                        && next_line.starts_with(|c| !matches!(c, ')' | ']'))
+                        && indent(source, span.1) <= open[0].1;
+                }
+                _ => (),
+            }
+            false
+        }
+
+        let source = self.iter.source();
+        let end = self.consumed();
+        let mut open = Vec::new();
+        let mut current = if end > start {
+            for (token, _, range) in &self.stack[start..end - 1] {
+                if let Some(t) = token {
+                    track(&mut open, *t, (range.start, range.end), source, false);
+                }
+            }
+            self.current()
+        } else {
+            self.next(true)
+        };
+
+        while let Some(token) = current {
+            if track(&mut open, token, self.position(), source, in_block) {
+                if token == ClosingCurlyBracket {
+                    // leave it for the block to find
+                    self.previous_no_ignore(true);
+                }
+                break;
+            }
+            current = self.next(true);
         }
     }
 
@@ -552,10 +653,19 @@ impl<'a> Tokens<'a> {
 const STATEMENT_SEPARATOR_DESC: &str = "Statement separator (line-break or ';')";
 
 pub fn parse_spwn(
-    mut unparsed: String,
+    unparsed: String,
     source: SpwnSource,
     builtin_list: &[&'static str],
 ) -> Result<(Vec<ast::Statement>, ParseNotes), SyntaxError> {
+    parse_spwn_all(unparsed, source, builtin_list).map_err(|mut errors| errors.swap_remove(0))
+}
+
+// like `parse_spwn`, but keeps going after a statement fails to parse, and returns every syntax error in the file
+pub fn parse_spwn_all(
+    mut unparsed: String,
+    source: SpwnSource,
+    builtin_list: &[&'static str],
+) -> Result<(Vec<ast::Statement>, ParseNotes), Vec<SyntaxError>> {
     unparsed = unparsed.replace("\r\n", "\n");
 
     let tokens_iter = Token::lexer(&unparsed);
@@ -577,23 +687,29 @@ pub fn parse_spwn(
 
     tokens.line_breaks = line_breaks;
 
-    let start_tag = check_for_tag(&mut tokens, &mut notes)?;
+    let start_tag = match check_for_tag(&mut tokens, &mut notes) {
+        Ok(t) => t,
+        Err(e) => return Err(vec![e]),
+    };
     notes.tag = start_tag;
     loop {
+        let start;
         //+ do something if we have tokens. if no more tokens, leave loop
         match tokens.next(false) {
             //oops we just advanced the tokens in an attempt to check if we have any
             Some(_) => {
                 tokens.previous_no_ignore(false); //bring tokens back to original
+                start = tokens.consumed();
 
                 //+ we are going to parse the tokens
-                let parsed = parse_statement(&mut tokens, &mut notes)?;
-                // if parsed.comment.0 == None && !statements.is_empty() {
-                //     parsed.comment.0 = statements.last().unwrap().comment.1.clone();
-                //     (*statements.last_mut().unwrap()).comment.1 = None;
-                // }
-
-                statements.push(parsed)
+                match parse_statement(&mut tokens, &mut notes) {
+                    Ok(parsed) => statements.push(parsed),
+                    Err(e) => {
+                        tokens.errors.push(e);
+                        tokens.recover(start, false);
+                        continue;
+                    }
+                }
             }
             None => break, //+ no more tokens, probably end of file
         }
@@ -602,18 +718,23 @@ pub fn parse_spwn(
         match tokens.next(true) {
             Some(Token::StatementSeparator) => {}
             Some(a) => {
-                return Err(SyntaxError::ExpectedErr {
+                tokens.errors.push(SyntaxError::ExpectedErr {
                     expected: STATEMENT_SEPARATOR_DESC.to_string(),
                     found: format!("{}: \"{}\"", a.typ(), tokens.slice()),
                     pos: tokens.position(),
-                    file: notes.file,
-                })
+                    file: notes.file.clone(),
+                });
+                tokens.recover(start, false);
             }
             None => break,
         }
     }
 
-    Ok((statements, notes))
+    if tokens.errors.is_empty() {
+        Ok((statements, notes))
+    } else {
+        Err(tokens.errors)
+    }
 }
 
 fn parse_cmp_stmt(
@@ -623,19 +744,22 @@ fn parse_cmp_stmt(
     let mut statements = Vec::<ast::Statement>::new();
     let opening_bracket = tokens.position();
     loop {
+        let start;
         match tokens.next(false) {
             Some(Token::ClosingCurlyBracket) => break,
             Some(_) => {
                 tokens.previous_no_ignore(false);
-
-                let parsed = parse_statement(tokens, notes)?;
-                // if parsed.comment.0 == None && !statements.is_empty() {
-                //     parsed.comment.0 = statements.last().unwrap().comment.1.clone();
-                //     (*statements.last_mut().unwrap()).comment.1 = None;
-                // }
-
-                statements.push(parsed) // add to big statement list
-                                        //println!("statement done");
+                start = tokens.consumed();
+
+                match parse_statement(tokens, notes) {
+                    Ok(parsed) => statements.push(parsed), // add to big statement list
+                    Err(e) => {
+                        // keep parsing the rest of the block, so later errors are found too
+                        tokens.errors.push(e);
+                        tokens.recover(start, true);
+                        continue;
+                    }
+                }
             }
             None => {
                 return Err(SyntaxError::SyntaxError {
@@ -649,6 +773,15 @@ fn parse_cmp_stmt(
         match tokens.next(true) {
             Some(Token::StatementSeparator) => {}
             Some(Token::ClosingCurlyBracket) => break,
+            Some(a) => {
+                tokens.errors.push(SyntaxError::ExpectedErr {
+                    expected: STATEMENT_SEPARATOR_DESC.to_string(),
+                    found: format!("{}: \"{}\"", a.typ(), tokens.slice()),
+                    pos: tokens.position(),
+                    file: notes.file.clone(),
+                });
+                tokens.recover(start, true);
+            }
             a => expected!(STATEMENT_SEPARATOR_DESC.to_string(), tokens, notes, a),
         }
     }
@@ -1311,7 +1444,7 @@ fn parse_expr(
             tern_values.reverse();
             tern_operators.reverse();
 
-            let (end_pos, _) = tokens.position();
+            let (_, end_pos) = tokens.position();
 
             // SPWN syntax structures can get pretty messy with variables, valuebodies,
             // valueliterals, expressions, etc.
@@ -2199,7 +2332,11 @@ fn try_parse_macro(
                                 match try_parse_macro_pattern(tokens, notes) {
                                     Ok(pat) => ast::ValueBody::MacroPattern(pat),
                                     // return macro error, since its more likely that they were trying to make a normal macro
-                                    Err(_) => return Err(e),
+                                    Err(_) => {
+                                        // (along with any errors found in its body)
+                                        *tokens = test_tokens;
+                                        return Err(e);
+                                    }
                                 }
                             }
                         }
@@ -2221,7 +2358,11 @@ fn try_parse_macro(
                             match try_parse_macro_pattern(tokens, notes) {
                                 Ok(pat) => ast::ValueBody::MacroPattern(pat),
                                 // return macro error, since its more likely that they were trying to make a normal macro
-                                Err(_) => return Err(e),
+                                Err(_) => {
+                                    // (along with any errors found in its body)
+                                    *tokens = test_tokens;
+                                    return Err(e);
+                                }
                             }
                         }
                     }
@@ -2248,7 +2389,11 @@ fn try_parse_macro(
                 Err(e) => match try_parse_macro_pattern(tokens, notes) {
                     Ok(pat) => Ok(ast::ValueBody::MacroPattern(pat)),
                     // return macro error, since its more likely that they were trying to make a normal macro
-                    Err(_) => Err(e),
+                    Err(_) => {
+                        // (along with any errors found in its body)
+                        *tokens = test_tokens;
+                        Err(e)
+                    }
                 },
             }
         }
@@ -2444,10 +2589,41 @@ fn parse_variable(
                 ),
             };
 
+            // `?g("door")` is an arbitrary ID with a name, which keeps its ID between builds
+            let name = if unspecified {
+                match tokens.next(true) {
+                    Some(Token::OpenBracket) => {
+                        let name = match tokens.next(false) {
+                            Some(Token::StringLiteral) => {
+                                str_content(tokens.slice(), tokens, notes)?.0
+                            }
+                            a => expected!(
+                                "a name for the ID (a string)".to_string(),
+                                tokens,
+                                notes,
+                                a
+                            ),
+                        };
+                        match tokens.next(false) {
+                            Some(Token::ClosingBracket) => (),
+                            a => expected!("')'".to_string(), tokens, notes, a),
+                        };
+                        Some(LocalIntern::new(name))
+                    }
+                    _ => {
+                        tokens.previous_no_ignore(true);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
             ast::ValueBody::Id(ast::Id {
                 class_name,
                 unspecified,
                 number,
+                name,
             })
         }
         Some(Token::True) => ast::ValueBody::Bool(true),
@@ -0,0 +1,137 @@
+//! Serializing errors as JSON for tools, instead of rendering them with ariadne
+//!
+//! Every error becomes one JSON object:
+//! ```json
+//! {
+//!     "message": "Use of undefined member",
+//!     "position": <area>,
+//!     "labels": [{ "area": <area>, "message": "'len' is undefined" }],
+//!     "call_stack": [<area>, ...],
+//!     "note": null
+//! }
+//! ```
+//! where an area is
+//! ```json
+//! {
+//!     "file": "path/to/file.spwn",
+//!     "source": "file",
+//!     "byte_start": 0, "byte_end": 5,
+//!     "start": { "line": 1, "column": 1 },
+//!     "end": { "line": 1, "column": 6 }
+//! }
+//! ```
+//! `source` is `"file"`, `"builtin"` (libraries inside the binary) or `"string"` (code given through
+//! `spwn eval`, where `file` is null). Lines and columns start at 1, and columns count characters.
+//! The line and column fields are left out if the source can't be read.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use serde_json::{json, Value as Json};
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+
+use crate::compiler_info::CodeArea;
+use crate::{read_source, LineIndex, SpwnCache};
+use errors::{create_report, strip_ansi, ErrorReport};
+use shared::SpwnSource;
+
+/// How errors are printed, chosen with `--message-format`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageFormat {
+    Human,
+    Json,
+}
+
+/// Prints an error to stderr, rendered with ariadne or as one line of JSON
+pub fn print_error(report: ErrorReport, cache: &mut SpwnCache, format: MessageFormat) {
+    match format {
+        MessageFormat::Human => create_report(report).eprint(cache).unwrap(),
+        MessageFormat::Json => eprintln!("{}", JsonReporter::default().report(&report)),
+    }
+}
+
+/// Like `print_error`, for errors that don't point at any code
+pub fn print_error_message(text: &str, format: MessageFormat) {
+    match format {
+        MessageFormat::Human => {
+            let mut stderr = StandardStream::stderr(ColorChoice::Always);
+            stderr
+                .set_color(ColorSpec::new().set_fg(Some(Color::Red)))
+                .unwrap();
+            writeln!(&mut stderr, "{}", text).unwrap();
+            stderr.set_color(&ColorSpec::new()).unwrap();
+        }
+        MessageFormat::Json => eprintln!("{}", JsonReporter::default().message(text)),
+    }
+}
+
+#[derive(Default)]
+pub struct JsonReporter {
+    indexes: HashMap<SpwnSource, Option<LineIndex>>,
+}
+
+impl JsonReporter {
+    pub fn report(&mut self, report: &ErrorReport) -> Json {
+        json!({
+            "message": strip_ansi(&report.message),
+            "position": self.area(&report.info.position),
+            "labels": report
+                .labels
+                .iter()
+                .map(|(area, message)| json!({
+                    "area": self.area(area),
+                    "message": strip_ansi(message),
+                }))
+                .collect::<Vec<_>>(),
+            "call_stack": report
+                .info
+                .call_stack
+                .iter()
+                .map(|area| self.area(area))
+                .collect::<Vec<_>>(),
+            "note": report.note.as_ref().map(|n| strip_ansi(n)),
+        })
+    }
+
+    // for errors that don't point at any code
+    pub fn message(&self, message: &str) -> Json {
+        json!({
+            "message": strip_ansi(message),
+            "position": null,
+            "labels": [],
+            "call_stack": [],
+            "note": null,
+        })
+    }
+
+    pub(crate) fn area(&mut self, area: &CodeArea) -> Json {
+        let source = area.file.as_ref();
+        let (file, kind) = match source {
+            SpwnSource::File(p) => (Some(p.to_string_lossy().to_string()), "file"),
+            SpwnSource::BuiltIn(p) => (Some(p.to_string_lossy().to_string()), "builtin"),
+            SpwnSource::String(_) => (None, "string"),
+        };
+
+        let mut out = json!({
+            "file": file,
+            "source": kind,
+            "byte_start": area.pos.0,
+            "byte_end": area.pos.1,
+        });
+
+        let index = self
+            .indexes
+            .entry(source.clone())
+            .or_insert_with(|| read_source(source).ok().map(|s| LineIndex::new(&s)));
+
+        if let Some(index) = index {
+            let position = |offset| {
+                let (line, column) = index.line_col(offset);
+                json!({ "line": line + 1, "column": column + 1 })
+            };
+            out["start"] = position(area.pos.0);
+            out["end"] = position(area.pos.1);
+        }
+        out
+    }
+}
@@ -6,7 +6,9 @@ pub use ::compiler::compiler;
 pub use ::compiler::compiler_types;
 pub use ::compiler::context;
 pub use ::compiler::globals;
+pub use ::compiler::level_codec;
 pub use ::compiler::leveldata;
+pub use ::compiler::obj_schema;
 pub use ::compiler::value;
 pub use ::compiler::value_storage;
 pub use ::docgen::documentation;
@@ -20,7 +22,7 @@ pub use ::compiler::STD_PATH;
 
 pub use ::parser::fmt;
 pub use ::parser::parser;
-pub use ::parser::parser::parse_spwn;
+pub use ::parser::parser::{parse_spwn, parse_spwn_all};
 
 pub use errors;
 pub use errors::compiler_info;
@@ -32,26 +34,40 @@ use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 
+pub mod json_errors;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod lsp;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod repl;
+pub mod source_map;
+
 #[derive(Default)]
 pub struct SpwnCache {
     files: HashMap<SpwnSource, Source>,
 }
 
+// reads the contents of a source file, including the ones built into the binary
+pub fn read_source(source: &SpwnSource) -> Result<String, String> {
+    Ok(match source {
+        SpwnSource::File(path) => fs::read_to_string(path).map_err(|e| e.to_string())?,
+        SpwnSource::BuiltIn(path) => match get_lib_file(path) {
+            Some(file) => match file.contents_utf8() {
+                Some(c) => c.to_string(),
+                None => return Err("Invalid built in file content".to_string()),
+            },
+            _ => return Err("Could not find built in file".to_string()),
+        },
+        SpwnSource::String(a) => a.as_ref().clone(),
+    })
+}
+
 impl ariadne::Cache<SpwnSource> for SpwnCache {
     fn fetch(&mut self, source: &SpwnSource) -> Result<&Source, Box<dyn std::fmt::Debug + '_>> {
         Ok(match self.files.entry(source.clone()) {
             Entry::Occupied(entry) => entry.into_mut(),
-            Entry::Vacant(entry) => entry.insert(Source::from(match source {
-                SpwnSource::File(path) => fs::read_to_string(path).map_err(|e| Box::new(e) as _)?,
-                SpwnSource::BuiltIn(path) => match get_lib_file(path) {
-                    Some(file) => match file.contents_utf8() {
-                        Some(c) => c.to_string(),
-                        None => return Err(Box::new("Invalid built in file content")),
-                    },
-                    _ => return Err(Box::new("Could not find built in file")),
-                },
-                SpwnSource::String(a) => a.as_ref().clone(),
-            })),
+            Entry::Vacant(entry) => entry.insert(Source::from(
+                read_source(source).map_err(|e| Box::new(e) as _)?,
+            )),
         })
     }
     fn display<'a>(&self, source: &'a SpwnSource) -> Option<Box<dyn std::fmt::Display + 'a>> {
@@ -62,20 +78,131 @@ impl ariadne::Cache<SpwnSource> for SpwnCache {
     }
 }
 
+/// A key for every arbitrary ID a build made, from the line of code that made it: the file, the
+/// text of the line, and how many IDs the line made before (or the name of a named ID).
+/// Incremental builds give the IDs with the same key the same specific IDs, so lines can be added
+/// and moved around them
+pub fn id_keys(globals: &globals::Globals) -> leveldata::IdKeys {
+    let mut line_indexes = HashMap::<SpwnSource, Option<LineIndex>>::new();
+    let mut keys = globals.id_name_keys();
+    for (class, origins) in globals.id_origins.iter().enumerate() {
+        let named = keys[class].iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        for (_, name) in &mut keys[class] {
+            *name = format!("name: {}", name);
+        }
+        let mut origins = origins
+            .iter()
+            .filter(|(id, _)| !named.contains(id))
+            .collect::<Vec<_>>();
+        origins.sort_unstable_by_key(|(id, _)| **id);
+        let mut made = HashMap::<String, usize>::new();
+        for (id, area) in origins {
+            let file = match &*area.file {
+                SpwnSource::File(path) | SpwnSource::BuiltIn(path) => path.display().to_string(),
+                SpwnSource::String(_) => String::from("<input>"),
+            };
+            let index = line_indexes
+                .entry((*area.file).clone())
+                .or_insert_with(|| read_source(&area.file).ok().map(|s| LineIndex::new(&s)));
+            let line = index.as_ref().and_then(|index| {
+                let (line, _) = index.line_col(area.pos.0);
+                index.text().lines().nth(line)
+            });
+            let place = format!("{}: {}", file, line.unwrap_or_default().trim());
+            let count = made.entry(place.clone()).or_default();
+            keys[class].push((*id, format!("{} #{}", place, count)));
+            *count += 1;
+        }
+    }
+    keys
+}
+
+/// Converts byte offsets (as found in `CodeArea`s) into zero-based line and column numbers
+pub struct LineIndex {
+    text: String,
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        // the parser works on the source with windows line endings removed
+        let text = text.replace("\r\n", "\n");
+        let mut line_starts = vec![0];
+        line_starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        LineIndex { text, line_starts }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn line_of(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        }
+    }
+
+    fn clamp(&self, offset: usize) -> usize {
+        let mut offset = offset.min(self.text.len());
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+
+    /// line and column, where the column is counted in characters
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = self.clamp(offset);
+        let line = self.line_of(offset);
+        let col = self.text[self.line_starts[line]..offset].chars().count();
+        (line, col)
+    }
+
+    /// line and column, where the column is counted in UTF-16 code units (used by the LSP)
+    pub fn line_col_utf16(&self, offset: usize) -> (usize, usize) {
+        let offset = self.clamp(offset);
+        let line = self.line_of(offset);
+        let col = self.text[self.line_starts[line]..offset]
+            .chars()
+            .map(char::len_utf16)
+            .sum();
+        (line, col)
+    }
+
+    /// inverse of `line_col_utf16`
+    pub fn offset_utf16(&self, line: usize, col: usize) -> usize {
+        let start = match self.line_starts.get(line) {
+            Some(s) => *s,
+            None => return self.text.len(),
+        };
+        let mut units = 0;
+        for (i, c) in self.text[start..].char_indices() {
+            if units >= col || c == '\n' {
+                return start + i;
+            }
+            units += c.len_utf16();
+        }
+        self.text.len()
+    }
+}
+
 pub fn run_spwn(
     code: String,
     included: Vec<PathBuf>,
     optimize: bool,
 ) -> Result<[String; 2], String> {
     let source = SpwnSource::String(LocalIntern::new(code.clone()));
-    let cache = SpwnCache::default();
-    let (statements, notes) = match parse_spwn(code, source.clone(), BUILTIN_NAMES) {
+    let mut cache = SpwnCache::default();
+    let (statements, notes) = match parse_spwn_all(code, source.clone(), BUILTIN_NAMES) {
         Ok(a) => a,
-        Err(e) => {
+        Err(errors) => {
             let mut out = Vec::<u8>::new();
-            create_report(ErrorReport::from(e))
-                .write(cache, &mut out)
-                .unwrap();
+            for e in errors {
+                create_report(ErrorReport::from(e))
+                    .write(&mut cache, &mut out)
+                    .unwrap();
+            }
             return Err(String::from_utf8_lossy(&out).to_string());
         }
     };
@@ -89,6 +216,8 @@ pub fn run_spwn(
         notes,
         Default::default(),
         "".to_string(),
+        None,
+        None,
         &mut std_out,
     ) {
         Ok(a) => a,
@@ -103,20 +232,28 @@ pub fn run_spwn(
 
     let has_stuff = compiled.func_ids.iter().any(|x| !x.obj_list.is_empty());
 
-    let reserved = optimizer::ReservedIds::from_objects(&compiled.objects, &compiled.func_ids);
+    let reserved = optimizer::ReservedIds::from_globals(&compiled);
 
     if has_stuff && optimize {
         compiled.func_ids =
             optimizer::optimize::optimize(compiled.func_ids, compiled.closed_groups, reserved);
     }
 
-    let mut objects = leveldata::apply_fn_ids(&compiled.func_ids);
+    let id_options = leveldata::IdOptions {
+        pinned: compiled.pinned_ids(),
+        ..Default::default()
+    };
+    let mut objects = leveldata::apply_fn_ids(&compiled.func_ids, &compiled.layout);
 
     objects.extend(compiled.objects);
 
-    let (new_ls, _) = leveldata::append_objects(objects, &String::new())?;
+    let (new_objects, _) = leveldata::append_objects(objects, &Default::default(), &id_options)
+        .map_err(|e| e.to_string())?;
 
-    Ok([String::from_utf8_lossy(&std_out).to_string(), new_ls])
+    Ok([
+        String::from_utf8_lossy(&std_out).to_string(),
+        new_objects.to_string(),
+    ])
 }
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,1082 @@
+//! Language server for SPWN, speaking the language server protocol over stdin/stdout
+//!
+//! Scripts are reparsed on every change to report syntax errors, and fully compiled
+//! (without building a level) when they are opened or saved, which gives runtime errors
+//! and the types and members defined by the standard library and imports.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::panic;
+use std::path::{Path, PathBuf};
+
+use internment::LocalIntern;
+use serde_json::{json, Value as Json};
+
+use crate::ast;
+use crate::builtins::{Builtin, BuiltinPermissions, BUILTIN_DESCRIPTIONS, BUILTIN_NAMES};
+use crate::compiler_info::{CodeArea, CompilerInfo};
+use crate::context::FullContext;
+use crate::globals::Globals;
+use crate::value::{find_key_for_value, Value};
+use crate::{compiler, parse_spwn_all, read_source, LineIndex};
+use errors::{strip_ansi, ErrorReport};
+use shared::{FileRange, ImportType, SpwnSource};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+
+// completion item kinds from the specification
+const KIND_METHOD: u8 = 2;
+const KIND_FUNCTION: u8 = 3;
+const KIND_VARIABLE: u8 = 6;
+const KIND_CLASS: u8 = 7;
+
+/// Something with a name that can be hovered, completed or jumped to
+#[derive(Debug, Clone)]
+struct Symbol {
+    name: String,
+    // the type this is a member of, for `impl` members
+    owner: Option<String>,
+    desc: Option<String>,
+    signature: Option<String>,
+    location: Option<(SpwnSource, FileRange)>,
+}
+
+/// Names collected by walking the AST of an open document
+#[derive(Default)]
+struct AstIndex {
+    variables: Vec<Symbol>,
+    members: Vec<Symbol>,
+    types: Vec<Symbol>,
+    imports: Vec<(ImportType, FileRange)>,
+}
+
+/// Names collected from the last successful compilation of a document
+#[derive(Default)]
+struct CompiledIndex {
+    globals: Vec<Symbol>,
+    members: Vec<Symbol>,
+    types: Vec<Symbol>,
+}
+
+struct Document {
+    path: PathBuf,
+    text: LineIndex,
+    ast: AstIndex,
+    compiled: CompiledIndex,
+}
+
+#[derive(Default)]
+pub struct Server {
+    includes: Vec<PathBuf>,
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+pub fn run_lsp(includes: Vec<PathBuf>) -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+
+    let mut server = Server::new(includes);
+
+    while let Some(message) = read_message(&mut input)? {
+        if message["method"] == "exit" {
+            break;
+        }
+        for reply in server.handle_message(&message) {
+            write_message(&mut output, &reply)?;
+        }
+    }
+
+    if server.shutdown {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "client exited without shutting down the server",
+        ))
+    }
+}
+
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = match length {
+        Some(l) => l,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "message is missing a Content-Length header",
+            ))
+        }
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+impl Server {
+    pub fn new(includes: Vec<PathBuf>) -> Self {
+        Server {
+            includes,
+            ..Default::default()
+        }
+    }
+
+    /// Handles one message from the client, returning the messages to send back
+    pub fn handle_message(&mut self, message: &Json) -> Vec<Json> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+
+        match message.get("id") {
+            // request
+            Some(id) if !method.is_empty() => {
+                vec![match self.handle_request(method, params) {
+                    Some(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    None => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": {
+                            "code": METHOD_NOT_FOUND,
+                            "message": format!("Unsupported method: {}", method),
+                        },
+                    }),
+                }]
+            }
+            // response to something we never send
+            Some(_) => Vec::new(),
+            // notification
+            None => self.handle_notification(method, params),
+        }
+    }
+
+    fn handle_request(&mut self, method: &str, params: &Json) -> Option<Json> {
+        Some(match method {
+            "initialize" => {
+                let root = params["rootUri"]
+                    .as_str()
+                    .and_then(uri_to_path)
+                    .or_else(|| params["rootPath"].as_str().map(PathBuf::from));
+                if let Some(root) = root {
+                    self.includes.insert(0, root);
+                }
+                json!({
+                    "capabilities": {
+                        "textDocumentSync": {
+                            "openClose": true,
+                            "change": 1,
+                            "save": { "includeText": false },
+                        },
+                        "hoverProvider": true,
+                        "definitionProvider": true,
+                        "completionProvider": { "triggerCharacters": [".", "@", ":"] },
+                    },
+                    "serverInfo": { "name": "spwn", "version": env!("CARGO_PKG_VERSION") },
+                })
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                Json::Null
+            }
+            "textDocument/hover" => self.hover(params).unwrap_or(Json::Null),
+            "textDocument/definition" => self.definition(params).unwrap_or(Json::Null),
+            "textDocument/completion" => json!(self.completion(params).unwrap_or_default()),
+            _ => return None,
+        })
+    }
+
+    fn handle_notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.update(&uri, text, true)
+            }
+            "textDocument/didChange" => {
+                // we only ask for full document syncing, so the last change has the whole text
+                match params["contentChanges"]
+                    .as_array()
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                {
+                    Some(text) => self.update(&uri, text, false),
+                    None => Vec::new(),
+                }
+            }
+            "textDocument/didSave" => match self.documents.get(&uri) {
+                Some(doc) => {
+                    let text = doc.text.text().to_string();
+                    self.update(&uri, &text, true)
+                }
+                None => Vec::new(),
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                vec![publish_diagnostics(&uri, Vec::new())]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // reparses a document (and compiles it if `compile` is true), returning the new diagnostics
+    fn update(&mut self, uri: &str, text: &str, compile: bool) -> Vec<Json> {
+        let path = match uri_to_path(uri) {
+            Some(p) => p,
+            None => return Vec::new(),
+        };
+        let text = LineIndex::new(text);
+        let source = SpwnSource::File(path.clone());
+
+        let mut doc = match self.documents.remove(uri) {
+            Some(doc) => Document { text, ..doc },
+            None => Document {
+                path,
+                text,
+                ast: AstIndex::default(),
+                compiled: CompiledIndex::default(),
+            },
+        };
+
+        let mut diagnostics = Vec::new();
+
+        let parsed = catch_panics(|| {
+            Ok(parse_spwn_all(
+                doc.text.text().to_string(),
+                source.clone(),
+                BUILTIN_NAMES,
+            ))
+        });
+
+        match parsed {
+            Ok(Ok((statements, notes))) => {
+                let mut index = AstIndex::default();
+                index.walk_statements(&statements, &source);
+                doc.ast = index;
+
+                if compile {
+                    let no_std = notes.tag.tags.iter().any(|x| x.0 == "no_std");
+                    let includes = self.includes.clone();
+                    match catch_panics(|| compile_document(&statements, &source, no_std, includes))
+                    {
+                        Ok(index) => doc.compiled = index,
+                        Err(report) => diagnostics.push(to_diagnostic(&report, &doc)),
+                    }
+                }
+            }
+            // keep the old indexes around, they are probably still mostly correct
+            Ok(Err(errors)) => diagnostics.extend(
+                errors
+                    .into_iter()
+                    .map(|e| to_diagnostic(&ErrorReport::from(e), &doc)),
+            ),
+            Err(report) => diagnostics.push(to_diagnostic(&report, &doc)),
+        }
+
+        self.documents.insert(uri.to_string(), doc);
+        vec![publish_diagnostics(uri, diagnostics)]
+    }
+
+    fn document_position(&self, params: &Json) -> Option<(&Document, usize)> {
+        let doc = self
+            .documents
+            .get(params["textDocument"]["uri"].as_str()?)?;
+        let line = params["position"]["line"].as_u64()? as usize;
+        let col = params["position"]["character"].as_u64()? as usize;
+        Some((doc, doc.text.offset_utf16(line, col)))
+    }
+
+    fn hover(&self, params: &Json) -> Option<Json> {
+        let (doc, offset) = self.document_position(params)?;
+        let (start, end) = word_at(doc.text.text(), offset)?;
+        let name = &doc.text.text()[start..end];
+
+        let contents = match Prefix::before(doc.text.text(), start) {
+            Prefix::Builtin => {
+                let (_, desc) = BUILTIN_DESCRIPTIONS.iter().find(|(n, _)| *n == name)?;
+                format!("```spwn\n$.{}\n```\n{}", name, desc)
+            }
+            Prefix::Member => {
+                let found = doc.lookup_members(name);
+                if found.is_empty() {
+                    return None;
+                }
+                found
+                    .iter()
+                    .map(|s| s.markdown())
+                    .collect::<Vec<_>>()
+                    .join("\n\n---\n\n")
+            }
+            Prefix::Type => doc.lookup_type(name)?.markdown(),
+            Prefix::None => doc.lookup_variable(name, start)?.markdown(),
+        };
+
+        let (start, end) = (range_json(&doc.text, start), range_json(&doc.text, end));
+        Some(json!({
+            "contents": { "kind": "markdown", "value": contents },
+            "range": { "start": start, "end": end },
+        }))
+    }
+
+    fn definition(&self, params: &Json) -> Option<Json> {
+        let (doc, offset) = self.document_position(params)?;
+
+        // jumping to an imported file
+        if let Some((import, _)) = doc
+            .ast
+            .imports
+            .iter()
+            .find(|(_, pos)| pos.0 <= offset && offset <= pos.1)
+        {
+            let path = self.resolve_import(import, &doc.path)?;
+            return Some(json!({
+                "uri": path_to_uri(&path),
+                "range": {
+                    "start": { "line": 0, "character": 0 },
+                    "end": { "line": 0, "character": 0 },
+                },
+            }));
+        }
+
+        let (start, end) = word_at(doc.text.text(), offset)?;
+        let name = &doc.text.text()[start..end];
+
+        let locations = match Prefix::before(doc.text.text(), start) {
+            Prefix::Builtin => return None,
+            Prefix::Member => doc
+                .lookup_members(name)
+                .into_iter()
+                .filter_map(|s| self.location_json(s.location.as_ref()?))
+                .collect(),
+            Prefix::Type => vec![self.location_json(doc.lookup_type(name)?.location.as_ref()?)?],
+            Prefix::None => {
+                vec![self.location_json(doc.lookup_variable(name, start)?.location.as_ref()?)?]
+            }
+        };
+
+        Some(Json::Array(locations))
+    }
+
+    fn completion(&self, params: &Json) -> Option<Vec<Json>> {
+        let (doc, offset) = self.document_position(params)?;
+        let text = doc.text.text();
+        let start = text[..offset]
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| is_word_char(*c))
+            .last()
+            .map(|(i, _)| i)
+            .unwrap_or(offset);
+
+        let mut items = Vec::new();
+        let mut seen = Vec::new();
+        let mut add = |symbol: &Symbol, kind: u8| {
+            if seen.contains(&symbol.name) {
+                return;
+            }
+            seen.push(symbol.name.clone());
+            items.push(json!({
+                "label": symbol.name,
+                "kind": kind,
+                "detail": symbol.detail(),
+                "documentation": symbol.desc,
+            }));
+        };
+
+        match Prefix::before(text, start) {
+            Prefix::Builtin => {
+                for (name, desc) in BUILTIN_DESCRIPTIONS {
+                    // operator implementations are not meant to be called directly
+                    if name.starts_with('_') {
+                        continue;
+                    }
+                    add(
+                        &Symbol {
+                            name: name.to_string(),
+                            owner: None,
+                            desc: Some(desc.to_string()),
+                            signature: None,
+                            location: None,
+                        },
+                        KIND_FUNCTION,
+                    );
+                }
+            }
+            Prefix::Member => {
+                for symbol in doc.ast.members.iter().chain(&doc.compiled.members) {
+                    add(symbol, KIND_METHOD);
+                }
+            }
+            Prefix::Type => {
+                for symbol in doc.ast.types.iter().chain(&doc.compiled.types) {
+                    add(symbol, KIND_CLASS);
+                }
+            }
+            Prefix::None => {
+                for symbol in doc.ast.variables.iter().chain(&doc.compiled.globals) {
+                    add(symbol, KIND_VARIABLE);
+                }
+            }
+        }
+
+        Some(items)
+    }
+
+    fn resolve_import(&self, import: &ImportType, current: &Path) -> Option<PathBuf> {
+        let path = match import {
+            ImportType::Script(p) => current.parent()?.join(p),
+            ImportType::Lib(name) => self
+                .includes
+                .iter()
+                .map(|i| i.join("libraries").join(name))
+                .find(|p| p.exists())?,
+        };
+        if path.is_dir() {
+            Some(path.join("lib.spwn"))
+        } else if path.extension().is_none() {
+            Some(path.with_extension("spwn"))
+        } else {
+            Some(path)
+        }
+    }
+
+    fn location_json(&self, (source, pos): &(SpwnSource, FileRange)) -> Option<Json> {
+        let path = match source {
+            SpwnSource::File(p) => p.clone(),
+            // built in libraries can only be shown if they also exist on disk
+            SpwnSource::BuiltIn(p) => self
+                .includes
+                .iter()
+                .map(|i| i.join("libraries").join(p))
+                .find(|p| p.exists())?,
+            SpwnSource::String(_) => return None,
+        };
+        let text = match self
+            .documents
+            .values()
+            .find(|d| d.path == path)
+            .map(|d| d.text.text().to_string())
+        {
+            Some(t) => t,
+            None => read_source(&SpwnSource::File(path.clone())).ok()?,
+        };
+        let index = LineIndex::new(&text);
+        Some(json!({
+            "uri": path_to_uri(&path),
+            "range": {
+                "start": range_json(&index, pos.0),
+                "end": range_json(&index, pos.1),
+            },
+        }))
+    }
+}
+
+impl Document {
+    fn lookup_members(&self, name: &str) -> Vec<&Symbol> {
+        let mut found: Vec<&Symbol> = Vec::new();
+        for symbol in self.ast.members.iter().chain(&self.compiled.members) {
+            if symbol.name == name && !found.iter().any(|s| s.owner == symbol.owner) {
+                found.push(symbol);
+            }
+        }
+        found
+    }
+
+    fn lookup_type(&self, name: &str) -> Option<&Symbol> {
+        self.ast
+            .types
+            .iter()
+            .chain(&self.compiled.types)
+            .find(|s| s.name == name)
+    }
+
+    // finds the closest definition before the usage, or the first one after it
+    fn lookup_variable(&self, name: &str, usage: usize) -> Option<&Symbol> {
+        let mut candidates = self.ast.variables.iter().filter(|s| s.name == name);
+        let mut best = None;
+        for symbol in candidates.by_ref() {
+            let def_start = symbol.location.as_ref().map(|(_, pos)| pos.0).unwrap_or(0);
+            if def_start > usage && best.is_some() {
+                break;
+            }
+            best = Some(symbol);
+        }
+        best.or_else(|| self.compiled.globals.iter().find(|s| s.name == name))
+    }
+}
+
+impl Symbol {
+    fn detail(&self) -> String {
+        let mut out = String::new();
+        if let Some(owner) = &self.owner {
+            out += &format!("@{}::", owner);
+        }
+        out += &self.name;
+        if let Some(signature) = &self.signature {
+            out += signature;
+        }
+        out
+    }
+
+    fn markdown(&self) -> String {
+        match &self.desc {
+            Some(desc) => format!("```spwn\n{}\n```\n{}", self.detail(), desc),
+            None => format!("```spwn\n{}\n```", self.detail()),
+        }
+    }
+}
+
+impl AstIndex {
+    fn walk_statements(&mut self, statements: &[ast::Statement], file: &SpwnSource) {
+        use ast::StatementBody::*;
+        for statement in statements {
+            match &statement.body {
+                Call(c) => self.walk_variable(&c.function, file),
+                Expr(e) | Extract(e) | Return(Some(e)) => self.walk_expression(e, file),
+                Return(None) | Break | Continue => (),
+                Definition(def) => {
+                    if let ast::ValueBody::Symbol(name) = &def.symbol.value.body {
+                        if def.symbol.path.is_empty() {
+                            self.variables.push(Symbol {
+                                name: name.to_string(),
+                                owner: None,
+                                desc: def
+                                    .symbol
+                                    .tag
+                                    .get_desc()
+                                    .or_else(|| def.value.as_ref().and_then(expression_desc)),
+                                signature: def.value.as_ref().and_then(expression_signature),
+                                location: Some((file.clone(), def.symbol.pos)),
+                            });
+                        }
+                    }
+                    self.walk_variable(&def.symbol, file);
+                    if let Some(value) = &def.value {
+                        self.walk_expression(value, file);
+                    }
+                }
+                TypeDef { name, attr } => self.types.push(Symbol {
+                    name: name.clone(),
+                    owner: None,
+                    desc: attr.get_desc(),
+                    signature: None,
+                    location: Some((file.clone(), statement.pos)),
+                }),
+                Impl(imp) => {
+                    let owner = match &imp.symbol.value.body {
+                        ast::ValueBody::TypeIndicator(t) => Some(t.clone()),
+                        _ => None,
+                    };
+                    for member in &imp.members {
+                        match member {
+                            ast::DictDef::Def((name, value)) => {
+                                self.members.push(Symbol {
+                                    name: name.to_string(),
+                                    owner: owner.clone(),
+                                    desc: expression_desc(value),
+                                    signature: expression_signature(value),
+                                    location: Some((file.clone(), value.get_pos())),
+                                });
+                                self.walk_expression(value, file);
+                            }
+                            ast::DictDef::Extract(value) => self.walk_expression(value, file),
+                        }
+                    }
+                }
+                If(i) => {
+                    self.walk_expression(&i.condition, file);
+                    self.walk_statements(&i.if_body, file);
+                    if let Some(body) = &i.else_body {
+                        self.walk_statements(body, file);
+                    }
+                }
+                For(f) => {
+                    for symbol in &f.symbol.values {
+                        if let ast::ValueBody::Symbol(name) = &symbol.value.body {
+                            self.variables.push(Symbol {
+                                name: name.to_string(),
+                                owner: None,
+                                desc: None,
+                                signature: None,
+                                location: Some((file.clone(), symbol.pos)),
+                            });
+                        }
+                    }
+                    self.walk_expression(&f.array, file);
+                    self.walk_statements(&f.body, file);
+                }
+                While(w) => {
+                    self.walk_expression(&w.condition, file);
+                    self.walk_statements(&w.body, file);
+                }
+                Error(e) => self.walk_expression(&e.message, file),
+            }
+        }
+    }
+
+    fn walk_expression(&mut self, expr: &ast::Expression, file: &SpwnSource) {
+        for value in &expr.values {
+            self.walk_variable(value, file);
+        }
+    }
+
+    fn walk_dict(&mut self, defs: &[ast::DictDef], file: &SpwnSource) {
+        for def in defs {
+            match def {
+                ast::DictDef::Def((_, e)) | ast::DictDef::Extract(e) => {
+                    self.walk_expression(e, file)
+                }
+            }
+        }
+    }
+
+    fn walk_variable(&mut self, var: &ast::Variable, file: &SpwnSource) {
+        use ast::ValueBody as V;
+        match &var.value.body {
+            V::CmpStmt(c) => self.walk_statements(&c.statements, file),
+            V::Dictionary(defs) => self.walk_dict(defs, file),
+            V::Expression(e) => self.walk_expression(e, file),
+            V::Import(import, _) => self.imports.push((import.clone(), var.pos)),
+            V::Match(value, cases) => {
+                self.walk_expression(value, file);
+                for case in cases {
+                    if let ast::CaseType::Pattern(p) = &case.typ {
+                        self.walk_expression(p, file);
+                    }
+                    self.walk_expression(&case.body, file);
+                }
+            }
+            V::Array(elements) => {
+                for element in elements {
+                    self.walk_expression(&element.value, file);
+                }
+            }
+            V::ListComp(comp) => {
+                self.walk_expression(&comp.iterator, file);
+                if let Some(condition) = &comp.condition {
+                    self.walk_expression(condition, file);
+                }
+                self.walk_expression(&comp.body, file);
+            }
+            V::Obj(obj) => {
+                for (key, value) in &obj.props {
+                    self.walk_expression(key, file);
+                    self.walk_expression(value, file);
+                }
+            }
+            V::Macro(m) => {
+                for (name, default, attr, pattern, pos, _) in &m.args {
+                    self.variables.push(Symbol {
+                        name: name.to_string(),
+                        owner: None,
+                        desc: attr.get_desc(),
+                        signature: None,
+                        location: Some((file.clone(), *pos)),
+                    });
+                    for e in default.iter().chain(pattern) {
+                        self.walk_expression(e, file);
+                    }
+                }
+                if let Some(ret) = &m.ret_type {
+                    self.walk_expression(ret, file);
+                }
+                self.walk_statements(&m.body.statements, file);
+            }
+            V::MacroPattern(p) => {
+                for arg in &p.args {
+                    self.walk_expression(arg, file);
+                }
+                self.walk_expression(&p.ret, file);
+            }
+            V::Ternary(t) => {
+                self.walk_expression(&t.condition, file);
+                self.walk_expression(&t.if_expr, file);
+                self.walk_expression(&t.else_expr, file);
+            }
+            V::Id(_)
+            | V::Number(_)
+            | V::Symbol(_)
+            | V::Bool(_)
+            | V::Str(_)
+            | V::Resolved(_)
+            | V::TypeIndicator(_)
+            | V::SelfVal
+            | V::Null => (),
+        }
+
+        for path in &var.path {
+            match path {
+                ast::Path::Index(e) => self.walk_expression(e, file),
+                ast::Path::NSlice(slices) => {
+                    for slice in slices {
+                        for e in slice.left.iter().chain(&slice.right).chain(&slice.step) {
+                            self.walk_expression(e, file);
+                        }
+                    }
+                }
+                ast::Path::Call(args) => {
+                    for arg in args {
+                        self.walk_expression(&arg.value, file);
+                    }
+                }
+                ast::Path::Constructor(defs) => self.walk_dict(defs, file),
+                ast::Path::Member(_)
+                | ast::Path::Associated(_)
+                | ast::Path::Increment
+                | ast::Path::Decrement => (),
+            }
+        }
+    }
+}
+
+// the description of a value defined with `#[desc(...)]`
+fn variable_desc(var: &ast::Variable) -> Option<String> {
+    var.tag.get_desc().or_else(|| match &var.value.body {
+        ast::ValueBody::Macro(m) => m.properties.get_desc(),
+        _ => None,
+    })
+}
+
+fn variable_signature(var: &ast::Variable) -> Option<String> {
+    match &var.value.body {
+        ast::ValueBody::Macro(m) => Some(format!(
+            "({})",
+            m.args
+                .iter()
+                .map(|a| a.0.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+        _ => None,
+    }
+}
+
+fn expression_desc(expr: &ast::Expression) -> Option<String> {
+    variable_desc(expr.values.first()?)
+}
+
+fn expression_signature(expr: &ast::Expression) -> Option<String> {
+    variable_signature(expr.values.first()?)
+}
+
+fn value_symbol(name: String, owner: Option<String>, value: &Value) -> Symbol {
+    match value {
+        Value::Macro(m) => Symbol {
+            name,
+            owner,
+            desc: m.tag.get_desc(),
+            signature: Some(format!(
+                "({})",
+                m.args
+                    .iter()
+                    .map(|a| a.name.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+            location: Some((m.def_file.as_ref().clone(), m.arg_pos)),
+        },
+        _ => Symbol {
+            name,
+            owner,
+            desc: None,
+            signature: None,
+            location: None,
+        },
+    }
+}
+
+// compiles a script without building a level, and collects the names it ended up defining
+fn compile_document(
+    statements: &[ast::Statement],
+    source: &SpwnSource,
+    no_std: bool,
+    includes: Vec<PathBuf>,
+) -> Result<CompiledIndex, Box<ErrorReport>> {
+    // anything printed by the script is thrown away, and reading input would steal from the client
+    let mut std_out = Vec::<u8>::new();
+    let mut permissions = BuiltinPermissions::new();
+    permissions.set(Builtin::GetInput, false);
+
+    let mut globals = Globals::new(source.clone(), permissions, String::new(), &mut std_out);
+    globals.includes = includes;
+
+    let mut contexts = FullContext::new(&globals);
+    contexts.inner().root_context_ptr = &mut contexts;
+
+    let info = CompilerInfo::from_area(CodeArea {
+        file: LocalIntern::new(source.clone()),
+        pos: (0, 0),
+    });
+
+    let mut index = CompiledIndex::default();
+
+    if !no_std {
+        compiler::import_std(&mut contexts, &mut globals, info.clone())
+            .map_err(|e| Box::new(e.into()))?;
+        if let Value::Dict(d) = &globals.stored_values[contexts.inner().return_value] {
+            for (name, val) in d {
+                index.globals.push(value_symbol(
+                    name.to_string(),
+                    None,
+                    &globals.stored_values[*val],
+                ));
+            }
+        }
+    }
+
+    compiler::compile_scope(statements, &mut contexts, &mut globals, info)
+        .map_err(|e| Box::new(e.into()))?;
+
+    for (name, (_, area)) in &globals.type_ids {
+        index.types.push(Symbol {
+            name: name.clone(),
+            owner: None,
+            desc: None,
+            signature: None,
+            location: Some((area.file.as_ref().clone(), area.pos)),
+        });
+    }
+
+    for (typ, members) in &globals.implementations {
+        let owner = find_key_for_value(&globals.type_ids, *typ).cloned();
+        for (name, (val, _)) in members {
+            index.members.push(value_symbol(
+                name.to_string(),
+                owner.clone(),
+                &globals.stored_values[*val],
+            ));
+        }
+    }
+
+    for (typ, desc) in &globals.type_descriptions {
+        if let Some(name) = find_key_for_value(&globals.type_ids, *typ) {
+            if let Some(symbol) = index.types.iter_mut().find(|s| &s.name == name) {
+                symbol.desc = Some(desc.clone());
+            }
+        }
+    }
+
+    Ok(index)
+}
+
+// the compiler and parser still have some panics in them, which should not bring down the server
+fn catch_panics<T>(f: impl FnOnce() -> Result<T, Box<ErrorReport>>) -> Result<T, Box<ErrorReport>> {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| ()));
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(f));
+    panic::set_hook(hook);
+
+    match result {
+        Ok(r) => r,
+        Err(e) => {
+            let message = e
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| e.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown error".to_string());
+            Err(Box::new(ErrorReport {
+                info: CompilerInfo::new(),
+                message: format!("The compiler crashed: {}", message),
+                labels: Vec::new(),
+                note: None,
+            }))
+        }
+    }
+}
+
+fn to_diagnostic(report: &ErrorReport, doc: &Document) -> Json {
+    let in_document = |area: &CodeArea| match area.file.as_ref() {
+        SpwnSource::File(p) => p == &doc.path,
+        _ => false,
+    };
+
+    // point at the error itself if it's in this file, otherwise at where this file led to it
+    let main_area = std::iter::once(&report.info.position)
+        .chain(report.labels.iter().map(|(a, _)| a))
+        .chain(report.info.call_stack.iter().rev())
+        .find(|a| in_document(a));
+
+    let (start, end) = match main_area {
+        Some(area) => (area.pos.0, area.pos.1),
+        None => (0, 0),
+    };
+
+    let mut message = strip_ansi(&report.message);
+    // syntax errors keep the actual explanation in the label
+    for (area, label) in &report.labels {
+        if Some(area) == main_area {
+            message += &format!("\n{}", strip_ansi(label));
+        }
+    }
+    if let Some(note) = &report.note {
+        message += &format!("\nNote: {}", strip_ansi(note));
+    }
+
+    let related = report
+        .labels
+        .iter()
+        .filter_map(|(area, label)| {
+            let uri = match area.file.as_ref() {
+                SpwnSource::File(p) => path_to_uri(p),
+                _ => return None,
+            };
+            let index = if in_document(area) {
+                None
+            } else {
+                Some(LineIndex::new(&read_source(area.file.as_ref()).ok()?))
+            };
+            let index = index.as_ref().unwrap_or(&doc.text);
+            Some(json!({
+                "location": {
+                    "uri": uri,
+                    "range": {
+                        "start": range_json(index, area.pos.0),
+                        "end": range_json(index, area.pos.1),
+                    },
+                },
+                "message": strip_ansi(label),
+            }))
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "range": {
+            "start": range_json(&doc.text, start),
+            "end": range_json(&doc.text, end),
+        },
+        "severity": 1,
+        "source": "spwn",
+        "message": message,
+        "relatedInformation": related,
+    })
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+fn range_json(index: &LineIndex, offset: usize) -> Json {
+    let (line, character) = index.line_col_utf16(offset);
+    json!({ "line": line, "character": character })
+}
+
+/// What comes right before an identifier, which decides what it refers to
+#[derive(Debug, PartialEq)]
+enum Prefix {
+    Builtin,
+    Member,
+    Type,
+    None,
+}
+
+impl Prefix {
+    fn before(text: &str, start: usize) -> Self {
+        let before = &text[..start];
+        if before.ends_with("$.") {
+            Prefix::Builtin
+        } else if before.ends_with('.') || before.ends_with("::") {
+            Prefix::Member
+        } else if before.ends_with('@') {
+            Prefix::Type
+        } else {
+            Prefix::None
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// the byte range of the identifier under the cursor
+fn word_at(text: &str, offset: usize) -> Option<(usize, usize)> {
+    if !text.is_char_boundary(offset) {
+        return None;
+    }
+    let start = text[..offset]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_word_char(*c))
+        .last()
+        .map(|(i, _)| i)
+        .unwrap_or(offset);
+    let end = text[offset..]
+        .char_indices()
+        .find(|(_, c)| !is_word_char(*c))
+        .map(|(i, _)| offset + i)
+        .unwrap_or(text.len());
+    if start == end {
+        None
+    } else {
+        Some((start, end))
+    }
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+            if let Ok(b) = u8::from_str_radix(hex, 16) {
+                decoded.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    let decoded = String::from_utf8(decoded).ok()?;
+    // windows paths look like file:///C:/...
+    let decoded = match decoded.strip_prefix('/') {
+        Some(rest) if rest.get(1..2) == Some(":") => rest.to_string(),
+        _ => decoded,
+    };
+    Some(PathBuf::from(decoded))
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::from("file://");
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+    for b in path.bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~:".contains(&b) {
+            uri.push(b as char);
+        } else {
+            uri += &format!("%{:02X}", b);
+        }
+    }
+    uri
+}
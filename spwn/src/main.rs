@@ -1,29 +1,35 @@
-use ariadne::Fmt;
 use clap::arg;
-use clap::AppSettings;
 use clap::ValueHint;
 //#![feature(arbitrary_enum_discriminant)]
 use ::compiler::builtins;
 use ::compiler::compiler;
-use std::io::Read;
 
 use ::docgen::documentation;
 
+use ::compiler::level_codec::{LevelHeader, LevelString};
 use ::compiler::leveldata;
+use ::compiler::leveldata::{IdLimits, IdMap, IdOptions};
 
+use optimizer::graph::{NodeKind, TriggerGraph};
 use optimizer::optimize;
+use optimizer::passes::{DroppedTrigger, OptReport, Pipeline};
+use optimizer::TriggerRole;
 
 use ariadne::Cache;
 
-use optimize::optimize;
+use optimize::optimize_with;
 
+use ::parser::fmt::{self, FmtConfig};
 use ::parser::parser::*;
 use builtins::BuiltinPermissions;
 
 use shared::SpwnSource;
-use spwn::SpwnCache;
+use spwn::json_errors::{print_error, print_error_message, MessageFormat};
+use spwn::{read_source, LineIndex, SpwnCache};
 
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use editorlive::editorlive::editor_paste;
 use std::fs;
@@ -37,6 +43,7 @@ use clap::App;
 use std::io::Write;
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
+use errors::compiler_info::CodeArea;
 use errors::{create_report, ErrorReport};
 
 fn print_with_color(text: &str, color: Color) {
@@ -48,27 +55,30 @@ fn print_with_color(text: &str, color: Color) {
     stdout.set_color(&ColorSpec::new()).unwrap();
 }
 
-fn eprint_with_color(text: &str, color: termcolor::Color) {
-    let mut stdout = StandardStream::stderr(ColorChoice::Always);
-    stdout
-        .set_color(ColorSpec::new().set_fg(Some(color)))
-        .unwrap();
-    writeln!(&mut stdout, "{}", text).unwrap();
-    stdout.set_color(&ColorSpec::new()).unwrap();
-}
-
+#[derive(Clone)]
 pub struct BuildOptions<'a> {
     permissions: BuiltinPermissions,
     include_paths: Vec<PathBuf>,
     gd_enabled: bool,
     opti_enabled: bool,
+    opt_passes: Option<&'a str>,
+    opt_report: Option<MessageFormat>,
     level_name: Option<String>,
     live_editor: bool,
     save_file: Option<&'a str>,
+    out_file: Option<&'a str>,
+    message_format: MessageFormat,
+    output_cache: Option<PathBuf>,
+    id_limits: IdLimits,
+    id_report: bool,
+    source_map: Option<&'a str>,
+    emit_graph: Option<&'a str>,
+    seed: Option<u64>,
+    incremental: bool,
 }
 
 impl<'a> BuildOptions<'a> {
-    fn from(build_cmd: &'a clap::ArgMatches) -> Result<Self, std::io::Error> {
+    fn from(build_cmd: &'a clap::ArgMatches) -> Result<Self, Box<dyn std::error::Error>> {
         let mut permissions = BuiltinPermissions::new();
         let mut include_paths = vec![
             std::env::current_dir().expect("Cannot access current directory"),
@@ -81,9 +91,31 @@ impl<'a> BuildOptions<'a> {
         let gd_enabled =
             !build_cmd.is_present("no-level") && !build_cmd.is_present("console-output");
         let opti_enabled = !build_cmd.is_present("no-optimize");
+        let opt_passes = build_cmd.value_of("opt-passes");
+        if let Some(names) = opt_passes {
+            Pipeline::from_names(names)?;
+        }
         let level_name = build_cmd.value_of("level-name").map(str::to_string);
         let live_editor = build_cmd.is_present("live-editor");
         let save_file = build_cmd.value_of("save-file");
+        let out_file = build_cmd.value_of("out");
+        let message_format = parse_message_format(build_cmd.value_of("message-format"));
+        let mut id_limits = IdLimits::default();
+        for limit in build_cmd.values_of("id-limit").unwrap_or_default() {
+            id_limits.set(limit)?;
+        }
+        let seed = match build_cmd.value_of("seed") {
+            Some(seed) => Some(
+                seed.parse::<u64>()
+                    .map_err(|_| format!("Invalid seed: `{}` (expected a whole number)", seed))?,
+            ),
+            None => None,
+        };
+        let output_cache = if build_cmd.is_present("no-cache") {
+            None
+        } else {
+            ::compiler::output_cache::default_dir()
+        };
 
         build_cmd
             .values_of("include-path")
@@ -116,33 +148,59 @@ impl<'a> BuildOptions<'a> {
             include_paths,
             gd_enabled,
             opti_enabled,
+            opt_passes,
+            opt_report: build_cmd
+                .is_present("opt-report")
+                .then(|| parse_message_format(build_cmd.value_of("opt-report"))),
             level_name,
             live_editor,
             save_file,
+            out_file,
+            message_format,
+            output_cache,
+            id_limits,
+            id_report: build_cmd.is_present("id-report"),
+            source_map: build_cmd.value_of("source-map"),
+            emit_graph: build_cmd.value_of("emit-graph"),
+            seed,
+            incremental: build_cmd.is_present("incremental"),
         })
     }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = App::new("SPWN")
-    .global_setting(AppSettings::ArgRequiredElseHelp)
+    .arg_required_else_help(true)
     .subcommands(
         [
             App::new("build")
                 .about("Runs/builds a given file"
             )
                 .visible_alias("b")
+                .arg_required_else_help(true)
                 .args(&[
                     arg!(<SCRIPT> "Path to spwn source file").value_hint(ValueHint::AnyPath),
                     arg!(-c --"console-output" "Makes the script print the created level into the console instead of writing it to your save file"),
                     arg!(-l --"no-level" "Only compiles the script, no level creation at all"),
                     arg!(-o --"no-optimize" "Removes post-optimization of triggers, making the output more readable, while also using a lot more objects and groups"),
+                    arg!(--"opt-passes" [PASSES] "Only runs these optimizer passes, in this order, like `dead_code,dedup` (the passes are dead_code, spawn, dedup and group_toggling)"),
+                    arg!(--"opt-report" [FORMAT] "Prints what every optimizer pass did to the triggers, as a table or (with `json`) as a JSON object").possible_values(["human", "json"]).min_values(0),
                     arg!(-n --"level-name" [NAME] "Targets a specific level"),
                     arg!(-e --"live-editor" "Instead of writing the level to the save file, the script will use a live editor library if it's installed (Currently works only for MacOS)"),
                     arg!(-s --"save-file" [FILE] "Chooses a specific save file to write to"),
+                    arg!(--out [FILE] "Writes the level to a standalone file instead of the save file: a .gmd level export, or the raw level string for any other extension (like .txt). Only builds on top of an existing level if --save-file is given").value_hint(ValueHint::FilePath),
                     arg!(-i --"include-path" "Adds a search path to look for libraries").takes_value(true).multiple_occurrences(true).min_values(0),
                     arg!(-a --allow "Allow the use of a builtin").takes_value(true).multiple_occurrences(true).min_values(0),
                     arg!(-d --deny "Deny the use of a builtin").takes_value(true).multiple_occurrences(true).min_values(0),
+                    arg!(--"message-format" [FORMAT] "How errors are printed, `json` prints one JSON object per error to stderr").possible_values(["human", "json"]),
+                    arg!(--"no-cache" "Evaluates every library again instead of using the outputs stored by earlier builds"),
+                    arg!(--"id-limit" "Sets the highest ID of a class the level may use, like `group=9999` (the defaults are 999, and 1099 for colors)").takes_value(true).multiple_occurrences(true),
+                    arg!(--"id-report" "Prints where in the scripts the level's arbitrary IDs (like `?g`) were made"),
+                    arg!(--"source-map" [FILE] "Writes a JSON file that maps every object and group in the level to the line of code that made it").value_hint(ValueHint::FilePath),
+                    arg!(--"emit-graph" [FILE] "Writes the triggers as a graph of groups and the triggers in them: Graphviz DOT, or JSON for a .json file. The graph from before optimizing is written next to it as FILE.before.dot").value_hint(ValueHint::FilePath),
+                    arg!(--seed [SEED] "Seeds `$.random`, so builds that use it give the same level every time"),
+                    arg!(--incremental "Keeps the IDs the last incremental build gave the arbitrary IDs (like `?g`) made by the same lines, and only adds and removes the objects that changed, instead of replacing all of them"),
+                    arg!(-w --watch "Keeps running, and builds the script again whenever it or a file it imports changes"),
                 ]),
 
             App::new("eval")
@@ -152,15 +210,27 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     arg!(-c --"console-output" "Makes the script print the created level into the console instead of writing it to your save file"),
                     arg!(-l --"no-level" "Only compiles the script, no level creation at all"),
                     arg!(-o --"no-optimize" "Removes post-optimization of triggers, making the output more readable, while also using a lot more objects and groups"),
+                    arg!(--"opt-passes" [PASSES] "Only runs these optimizer passes, in this order, like `dead_code,dedup` (the passes are dead_code, spawn, dedup and group_toggling)"),
+                    arg!(--"opt-report" [FORMAT] "Prints what every optimizer pass did to the triggers, as a table or (with `json`) as a JSON object").possible_values(["human", "json"]).min_values(0),
                     arg!(-n --"level-name" [NAME] "Targets a specific level"),
                     arg!(-e --"live-editor" "Instead of writing the level to the save file, the script will use a live editor library if it's installed (Currently works only for MacOS)"),
                     arg!(-s --"save-file" [FILE] "Chooses a specific save file to write to"),
+                    arg!(--out [FILE] "Writes the level to a standalone file instead of the save file: a .gmd level export, or the raw level string for any other extension (like .txt). Only builds on top of an existing level if --save-file is given").value_hint(ValueHint::FilePath),
                     arg!(-i --"include-path" "Adds a search path to look for libraries").takes_value(true).multiple_occurrences(true).min_values(0),
                     arg!(-a --allow "Allow the use of a builtin").takes_value(true).multiple_occurrences(true).min_values(0),
                     arg!(-d --deny "Deny the use of a builtin").takes_value(true).multiple_occurrences(true).min_values(0),
+                    arg!(--"message-format" [FORMAT] "How errors are printed, `json` prints one JSON object per error to stderr").possible_values(["human", "json"]),
+                    arg!(--"no-cache" "Evaluates every library again instead of using the outputs stored by earlier builds"),
+                    arg!(--"id-limit" "Sets the highest ID of a class the level may use, like `group=9999` (the defaults are 999, and 1099 for colors)").takes_value(true).multiple_occurrences(true),
+                    arg!(--"id-report" "Prints where in the scripts the level's arbitrary IDs (like `?g`) were made"),
+                    arg!(--"source-map" [FILE] "Writes a JSON file that maps every object and group in the level to the line of code that made it").value_hint(ValueHint::FilePath),
+                    arg!(--"emit-graph" [FILE] "Writes the triggers as a graph of groups and the triggers in them: Graphviz DOT, or JSON for a .json file. The graph from before optimizing is written next to it as FILE.before.dot").value_hint(ValueHint::FilePath),
+                    arg!(--seed [SEED] "Seeds `$.random`, so builds that use it give the same level every time"),
+                    arg!(--incremental "Keeps the IDs the last incremental build gave the arbitrary IDs (like `?g`) made by the same lines, and only adds and removes the objects that changed, instead of replacing all of them"),
                 ]),
 
             App::new("doc")
+            .arg_required_else_help(true)
             .arg(
                 arg!(<LIBRARY> "Library to document")
             )
@@ -169,19 +239,50 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             App::new("new")
                 .about("Creates a new SPWN project in the given directory"
             )
+                .arg_required_else_help(true)
                 .args(&[
                     arg!(-l --"lib" "Creates a PCKP-compatible SPWN library"),
                     arg!(<PATH> "Path to create project in").value_hint(ValueHint::AnyPath),
                 ]),
+
+            App::new("lsp")
+                .about("Starts a language server for editor integration, communicating over stdin/stdout")
+                .args(&[
+                    arg!(-i --"include-path" "Adds a search path to look for libraries").takes_value(true).multiple_occurrences(true).min_values(0),
+                ]),
+
+            App::new("fmt")
+                .about("Formats SPWN files, keeping their comments")
+                .arg_required_else_help(true)
+                .args(&[
+                    arg!(<FILES> ... "Files to format, directories are searched for .spwn files").value_hint(ValueHint::AnyPath),
+                    arg!(--check "Only lists the files that aren't formatted, without changing them, and fails if there are any"),
+                    arg!(--indent [INDENT] "How much to indent by: a number of spaces (the default is 4) or `tab`"),
+                ]),
+
+            App::new("restore")
+                .about("Puts back one of the save file backups SPWN makes before it writes to the save file")
+                .args(&[
+                    arg!([BACKUP] "Which backup to restore: its number in the list (1 is the newest, and the default) or its file name"),
+                    arg!(--list "Only lists the backups, newest first"),
+                    arg!(-s --"save-file" [FILE] "Chooses a specific save file to restore").value_hint(ValueHint::FilePath),
+                ]),
         ]
-    ).global_setting(clap::AppSettings::ArgRequiredElseHelp).get_matches();
+    ).get_matches();
 
     if let Some(build_cmd) = matches.subcommand_matches("build") {
         let script_path = build_cmd.value_of("SCRIPT").ok_or("unreachable")?;
 
-        let options = BuildOptions::from(build_cmd)?;
+        let format = parse_message_format(build_cmd.value_of("message-format"));
+        let options = exit_on_failure(BuildOptions::from(build_cmd), format);
         let source = SpwnSource::File(script_path.into());
-        let unparsed = fs::read_to_string(script_path)?;
+        let unparsed = match fs::read_to_string(script_path) {
+            Ok(unparsed) => unparsed,
+            Err(e) => {
+                print_error_message(&format!("Could not read {}: {}", script_path, e), format);
+                std::process::exit(ERROR_EXIT_CODE);
+            }
+        };
 
         let pckp_path = PathBuf::from(script_path).parent().unwrap().to_path_buf();
         let cfg_file = config_file::get_config(Some(pckp_path.clone()));
@@ -190,7 +291,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let pckp_package = match config_file::config_to_package(cfg_file) {
                 Ok(p) => p,
                 Err(e) => {
-                    eprint_with_color(&format!("Error reading pckp file:\n{e}"), Color::Red);
+                    print_error_message(&format!("Error reading pckp file:\n{e}"), format);
 
                     std::process::exit(ERROR_EXIT_CODE);
                 }
@@ -199,9 +300,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 match pack.install_dependencies(pckp_path) {
                     Ok(_) => (),
                     Err(e) => {
-                        eprint_with_color(
+                        print_error_message(
                             &format!("Error installing dependencies:\n{e}"),
-                            Color::Red,
+                            format,
                         );
 
                         std::process::exit(ERROR_EXIT_CODE);
@@ -210,28 +311,36 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
 
-        build_spwn_source(source, unparsed, options)
+        if build_cmd.is_present("watch") {
+            watch(source, options)
+        } else {
+            exit_on_failure(
+                build_spwn_source(source, unparsed, options, &mut Vec::new()),
+                format,
+            );
+            Ok(())
+        }
     } else if let Some(eval_cmd) = matches.subcommand_matches("eval") {
-        use ariadne::Color::{Blue, Red};
-        let end_command = ":build";
-        println!(
-            "{}{}{}",
-            "Write your code, and then type \"".fg(Blue),
-            end_command.fg(Red),
-            "\" to build it".fg(Blue)
-        );
+        let format = parse_message_format(eval_cmd.value_of("message-format"));
+        let options = exit_on_failure(BuildOptions::from(eval_cmd), format);
 
-        let mut input = String::new();
-        while !input.trim_end().ends_with(end_command) {
-            std::io::stdin().read_line(&mut input)?;
+        match spwn::repl::run_repl(
+            options.include_paths.clone(),
+            options.permissions.clone(),
+            options.output_cache.clone(),
+            options.seed,
+            options.message_format,
+        )? {
+            Some(unparsed) => {
+                let source = SpwnSource::String(internment::LocalIntern::from(unparsed.as_str()));
+                exit_on_failure(
+                    build_spwn_source(source, unparsed, options, &mut Vec::new()),
+                    format,
+                );
+                Ok(())
+            }
+            None => Ok(()),
         }
-        input = input.trim().to_string();
-
-        let unparsed = &input[..input.len() - end_command.len()];
-        let options = BuildOptions::from(eval_cmd)?;
-        let source = SpwnSource::String(internment::LocalIntern::from(unparsed));
-
-        build_spwn_source(source, unparsed.to_string(), options)
     } else if let Some(doc_cmd) = matches.subcommand_matches("doc") {
         let lib_path = doc_cmd.value_of("LIBRARY").unwrap();
         if "$" == lib_path {
@@ -313,16 +422,466 @@ folders:
             .unwrap();
         }
 
+        Ok(())
+    } else if let Some(lsp_cmd) = matches.subcommand_matches("lsp") {
+        let mut include_paths = vec![
+            std::env::current_dir().expect("Cannot access current directory"),
+            std::env::current_exe()?
+                .parent()
+                .expect("Executable must be in a directory")
+                .to_path_buf(),
+        ];
+        lsp_cmd
+            .values_of("include-path")
+            .unwrap_or_default()
+            .for_each(|val| include_paths.push(val.into()));
+
+        spwn::lsp::run_lsp(include_paths)?;
+        Ok(())
+    } else if let Some(fmt_cmd) = matches.subcommand_matches("fmt") {
+        let mut config = FmtConfig::default();
+        if let Some(indent) = fmt_cmd.value_of("indent") {
+            config.indent = indent.parse()?;
+        }
+        let check = fmt_cmd.is_present("check");
+
+        let mut files = Vec::new();
+        for path in fmt_cmd.values_of("FILES").unwrap_or_default() {
+            find_spwn_files(PathBuf::from(path), &mut files)?;
+        }
+
+        let mut cache = SpwnCache::default();
+        let mut failed = false;
+        let mut unformatted = 0;
+        for file in files {
+            let code = fs::read_to_string(&file)?;
+            let source = SpwnSource::File(file.clone());
+            match fmt::format(&code, source, builtins::BUILTIN_NAMES, &config) {
+                Ok(formatted) if formatted == code => (),
+                Ok(formatted) => {
+                    if check {
+                        println!("{}", file.display());
+                        unformatted += 1;
+                    } else {
+                        fs::write(&file, formatted)?;
+                        print_with_color(&format!("Formatted {}", file.display()), Color::Green);
+                    }
+                }
+                Err(errors) => {
+                    for err in errors {
+                        print_error(ErrorReport::from(err), &mut cache, MessageFormat::Human);
+                    }
+                    failed = true;
+                }
+            }
+        }
+
+        if unformatted > 0 {
+            print_error_message(
+                &format!("{} file(s) need formatting", unformatted),
+                MessageFormat::Human,
+            );
+        }
+        if failed || unformatted > 0 {
+            std::process::exit(ERROR_EXIT_CODE);
+        }
+        Ok(())
+    } else if let Some(restore_cmd) = matches.subcommand_matches("restore") {
+        let save = match restore_cmd.value_of("save-file") {
+            Some(file) => PathBuf::from(file),
+            None => default_save_file()?,
+        };
+        let backups = levelstring::list_backups(&save)?;
+        if backups.is_empty() {
+            return Err(format!("There are no backups of {}", save.display()).into());
+        }
+
+        if restore_cmd.is_present("list") {
+            for (i, backup) in backups.iter().enumerate() {
+                println!(
+                    "{}: {}",
+                    i + 1,
+                    backup.file_name().unwrap().to_string_lossy()
+                );
+            }
+            return Ok(());
+        }
+
+        let which = restore_cmd.value_of("BACKUP").unwrap_or("1");
+        let backup = match which.parse::<usize>() {
+            Ok(n) => backups.get(n.wrapping_sub(1)),
+            Err(_) => backups
+                .iter()
+                .find(|b| b.file_name() == Some(which.as_ref())),
+        }
+        .ok_or_else(|| {
+            format!(
+                "No backup \"{}\", see `spwn restore --list` for the backups",
+                which
+            )
+        })?;
+
+        levelstring::restore_backup(&save, backup)?;
+        print_with_color(
+            &format!(
+                "Restored {} from {}",
+                save.display(),
+                backup.file_name().unwrap().to_string_lossy()
+            ),
+            Color::Green,
+        );
         Ok(())
     } else {
         unreachable!()
     }
 }
 
+// adds `path` if it's a file, or all the .spwn files in it if it's a directory
+fn find_spwn_files(
+    path: PathBuf,
+    files: &mut Vec<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !path.is_dir() {
+        files.push(path);
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(&path)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() || entry.extension().is_some_and(|ext| ext == "spwn") {
+            find_spwn_files(entry, files)?;
+        }
+    }
+    Ok(())
+}
+
+// returned by a build that failed after printing its errors
+#[derive(Debug)]
+struct BuildFailed;
+
+impl std::fmt::Display for BuildFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Build failed")
+    }
+}
+
+impl std::error::Error for BuildFailed {}
+
+// errors that weren't printed yet (like a file that couldn't be written) are printed here, so they
+// follow `--message-format` too
+fn exit_on_failure<T>(result: Result<T, Box<dyn std::error::Error>>, format: MessageFormat) -> T {
+    result.unwrap_or_else(|e| {
+        if !e.is::<BuildFailed>() {
+            print_error_message(&e.to_string(), format);
+        }
+        std::process::exit(ERROR_EXIT_CODE)
+    })
+}
+
+// `--message-format` and `--opt-report` take `human` (the default) or `json`
+fn parse_message_format(value: Option<&str>) -> MessageFormat {
+    match value {
+        Some("json") => MessageFormat::Json,
+        _ => MessageFormat::Human,
+    }
+}
+
+// the files an error happened in, which a failed build has to be watched for
+fn error_files(report: &ErrorReport) -> Vec<PathBuf> {
+    std::iter::once(&report.info.position)
+        .chain(&report.info.call_stack)
+        .chain(report.labels.iter().map(|(area, _)| area))
+        .filter_map(|area| match &*area.file {
+            SpwnSource::File(path) => Some(path.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+// builds the script every time one of the files it used changes, until the process is stopped
+fn watch(source: SpwnSource, options: BuildOptions) -> Result<(), Box<dyn std::error::Error>> {
+    const POLL_INTERVAL: Duration = Duration::from_millis(200);
+    // editors often save a file in more than one step
+    const DEBOUNCE: Duration = Duration::from_millis(300);
+
+    let script_path = match &source {
+        SpwnSource::File(path) => path.clone(),
+        _ => unreachable!(),
+    };
+    let modified_times = |files: &[PathBuf]| {
+        files
+            .iter()
+            .map(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+            .collect::<Vec<_>>()
+    };
+
+    let mut watched = vec![script_path.clone()];
+    loop {
+        let mut files = vec![script_path.clone()];
+        let result = fs::read_to_string(&script_path)
+            .map_err(|e| e.into())
+            .and_then(|unparsed| {
+                build_spwn_source(source.clone(), unparsed, options.clone(), &mut files)
+            });
+        match result {
+            Ok(()) => watched = files,
+            Err(e) => {
+                if !e.is::<BuildFailed>() {
+                    print_error_message(&e.to_string(), options.message_format);
+                }
+                // the imports that weren't reached this time can still be what needs fixing
+                watched.extend(files);
+            }
+        }
+        watched.sort();
+        watched.dedup();
+
+        print_with_color(
+            &format!(
+                "\nWatching {} file(s) for changes (Ctrl+C to stop) ...",
+                watched.len()
+            ),
+            Color::Cyan,
+        );
+        let mut times = modified_times(&watched);
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            if modified_times(&watched) != times {
+                break;
+            }
+        }
+        // wait until the files stop changing
+        loop {
+            times = modified_times(&watched);
+            std::thread::sleep(DEBOUNCE);
+            if modified_times(&watched) == times {
+                break;
+            }
+        }
+    }
+}
+
+// the file, line and column (counting from 1) where an area starts
+fn source_place(
+    area: &CodeArea,
+    line_indexes: &mut HashMap<SpwnSource, Option<LineIndex>>,
+) -> (String, usize, usize) {
+    let file = match &*area.file {
+        SpwnSource::File(path) | SpwnSource::BuiltIn(path) => path.display().to_string(),
+        SpwnSource::String(_) => String::from("<input>"),
+    };
+    let index = line_indexes
+        .entry((*area.file).clone())
+        .or_insert_with(|| read_source(&area.file).ok().map(|s| LineIndex::new(&s)));
+    let (line, col) = index
+        .as_ref()
+        .map_or((0, 0), |index| index.line_col(area.pos.0));
+    (file, line + 1, col + 1)
+}
+
+// warns about output triggers the optimizer removed because nothing uses what they change
+fn print_dropped_triggers(dropped: &[DroppedTrigger]) {
+    let mut line_indexes = HashMap::new();
+    for trigger in dropped {
+        let place = match &trigger.object.origin {
+            Some(info) => {
+                let (file, line, col) = source_place(&info.position, &mut line_indexes);
+                format!(" ({}:{}:{})", file, line, col)
+            }
+            None => String::new(),
+        };
+        print_with_color(
+            &format!(
+                "Warning: removed a {} trigger, since nothing uses the {} it changes{}",
+                optimizer::trigger_name(&trigger.object),
+                trigger.class,
+                place
+            ),
+            Color::Yellow,
+        );
+    }
+}
+
+// prints the places in the source that made arbitrary IDs of a class, and how many each made
+fn print_id_origins(class: usize, origins: impl Iterator<Item = Option<CodeArea>>) {
+    let mut line_indexes = HashMap::<SpwnSource, Option<LineIndex>>::new();
+    // file, line and column
+    let mut counts = HashMap::<Option<(String, usize, usize)>, usize>::new();
+    for origin in origins {
+        let place = origin.map(|area| source_place(&area, &mut line_indexes));
+        *counts.entry(place).or_default() += 1;
+    }
+    if counts.is_empty() {
+        return;
+    }
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    print_with_color(
+        &format!("\nArbitrary {}s made by:", leveldata::ID_CLASS_NAMES[class]),
+        Color::Magenta,
+    );
+    for (place, count) in counts {
+        let place = match place {
+            Some((file, line, col)) => format!("{}:{}:{}", file, line, col),
+            None => String::from("the compiler, the optimizer or a cached library"),
+        };
+        print_with_color(&format!("{:>6}  {}", count, place), Color::White);
+    }
+}
+
+fn print_opt_report(report: &OptReport, format: MessageFormat) {
+    let role_name = |role: &TriggerRole| format!("{:?}", role).to_lowercase();
+    let roles = report
+        .before
+        .keys()
+        .chain(report.after.keys())
+        .collect::<BTreeSet<_>>();
+    let count =
+        |counts: &BTreeMap<TriggerRole, usize>, role| counts.get(role).copied().unwrap_or(0);
+
+    if format == MessageFormat::Json {
+        let json = serde_json::json!({
+            "passes": report
+                .passes
+                .iter()
+                .map(|p| serde_json::json!({
+                    "name": p.name,
+                    "runs": p.runs,
+                    "removed": p.removed,
+                    "merged": p.merged,
+                    "created": p.created,
+                    "groups_freed": p.groups_freed,
+                }))
+                .collect::<Vec<_>>(),
+            "triggers": roles
+                .iter()
+                .map(|role| (role_name(role), serde_json::json!({
+                    "before": count(&report.before, role),
+                    "after": count(&report.after, role),
+                })))
+                .collect::<serde_json::Map<_, _>>(),
+        });
+        println!("{}", json);
+        return;
+    }
+
+    print_with_color("\nOptimizer:", Color::Magenta);
+    print_with_color(
+        &format!(
+            "{:<16}{:>6}{:>9}{:>8}{:>9}{:>14}",
+            "pass", "runs", "removed", "merged", "created", "groups freed"
+        ),
+        Color::White,
+    );
+    for p in &report.passes {
+        print_with_color(
+            &format!(
+                "{:<16}{:>6}{:>9}{:>8}{:>9}{:>14}",
+                p.name, p.runs, p.removed, p.merged, p.created, p.groups_freed
+            ),
+            Color::White,
+        );
+    }
+    print_with_color(
+        &format!("\n{:<16}{:>8}{:>8}", "triggers", "before", "after"),
+        Color::White,
+    );
+    for role in &roles {
+        print_with_color(
+            &format!(
+                "{:<16}{:>8}{:>8}",
+                role_name(role),
+                count(&report.before, role),
+                count(&report.after, role)
+            ),
+            Color::White,
+        );
+    }
+    let total = |counts: &BTreeMap<TriggerRole, usize>| counts.values().sum::<usize>();
+    print_with_color(
+        &format!(
+            "{:<16}{:>8}{:>8}",
+            "total",
+            total(&report.before),
+            total(&report.after)
+        ),
+        Color::White,
+    );
+}
+
+// DOT, or JSON for .json files
+fn write_trigger_graph(path: &Path, graph: &TriggerGraph) -> std::io::Result<()> {
+    let contents = if path.extension().and_then(|e| e.to_str()) == Some("json") {
+        let json = serde_json::json!({
+            "nodes": graph
+                .nodes
+                .iter()
+                .map(|node| {
+                    let (kind, role) = match node.kind {
+                        NodeKind::Group => ("group", None),
+                        NodeKind::Trigger(role) => {
+                            ("trigger", Some(format!("{:?}", role).to_lowercase()))
+                        }
+                    };
+                    serde_json::json!({
+                        "id": node.id,
+                        "label": node.label,
+                        "kind": kind,
+                        "role": role,
+                    })
+                })
+                .collect::<Vec<_>>(),
+            "edges": graph
+                .edges
+                .iter()
+                .map(|edge| serde_json::json!({
+                    "from": edge.from,
+                    "to": edge.to,
+                    "kind": format!("{:?}", edge.kind).to_lowercase(),
+                    "delay": edge.delay,
+                    "order": edge.order,
+                }))
+                .collect::<Vec<_>>(),
+        });
+        serde_json::to_string_pretty(&json)?
+    } else {
+        graph.to_dot()
+    };
+    fs::write(path, contents)?;
+    print_with_color(
+        &format!("Trigger graph written to {}", path.display()),
+        Color::White,
+    );
+    Ok(())
+}
+
+// where Geometry Dash keeps its save file on this platform
+fn default_save_file() -> Result<PathBuf, String> {
+    let home = || std::env::var("HOME").map_err(|_| "No home directory".to_string());
+    Ok(if cfg!(target_os = "windows") {
+        PathBuf::from(std::env::var("localappdata").map_err(|_| "No local app data")?)
+            .join("GeometryDash/CCLocalLevels.dat")
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(home()?).join("Library/Application Support/GeometryDash/CCLocalLevels.dat")
+    } else if cfg!(target_os = "linux") {
+        PathBuf::from(home()?)
+            .join(".steam/steam/steamapps/compatdata/322170/pfx/drive_c/users/steamuser/Local Settings/Application Data/GeometryDash/CCLocalLevels.dat")
+    } else if cfg!(target_os = "android") {
+        PathBuf::from("/data/data/com.robtopx.geometryjump/CCLocalLevels.dat")
+    } else {
+        return Err("Unsupported operating system".to_string());
+    })
+}
+
+// `files` gets every file the build read
 fn build_spwn_source(
     source: SpwnSource,
     unparsed: String,
     mut options: BuildOptions,
+    files: &mut Vec<PathBuf>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut cache = SpwnCache::default();
     match cache.fetch(&source) {
@@ -332,14 +891,16 @@ fn build_spwn_source(
         }
     }
     print_with_color("Parsing ...", Color::Green);
-    let (statements, notes) = match parse_spwn(
+    let (statements, notes) = match parse_spwn_all(
         unparsed,
         source.clone(),
         ::compiler::builtins::BUILTIN_NAMES,
     ) {
-        Err(err) => {
-            create_report(ErrorReport::from(err)).eprint(cache).unwrap();
-            std::process::exit(ERROR_EXIT_CODE);
+        Err(errors) => {
+            for err in errors {
+                print_error(ErrorReport::from(err), &mut cache, options.message_format);
+            }
+            return Err(BuildFailed.into());
         }
         Ok(p) => p,
     };
@@ -353,22 +914,13 @@ fn build_spwn_source(
             _ => (),
         }
     }
-    let gd_path = if options.gd_enabled {
-        Some(if options.save_file != None {
-            PathBuf::from(options.save_file.expect("what"))
-        } else if cfg!(target_os = "windows") {
-            PathBuf::from(std::env::var("localappdata").expect("No local app data"))
-                .join("GeometryDash/CCLocalLevels.dat")
-        } else if cfg!(target_os = "macos") {
-            PathBuf::from(std::env::var("HOME").expect("No home directory"))
-                .join("Library/Application Support/GeometryDash/CCLocalLevels.dat")
-        } else if cfg!(target_os = "linux") {
-            PathBuf::from(std::env::var("HOME").expect("No home directory"))
-                .join(".steam/steam/steamapps/compatdata/322170/pfx/drive_c/users/steamuser/Local Settings/Application Data/GeometryDash/CCLocalLevels.dat")
-        } else if cfg!(target_os = "android") {
-            PathBuf::from("/data/data/com.robtopx.geometryjump/CCLocalLevels.dat")
-        } else {
-            panic!("Unsupported operating system");
+    let gd_path = if options.out_file.is_some() {
+        // standalone files only build on top of a level from a save file if it's asked for explicitly
+        options.save_file.map(PathBuf::from)
+    } else if options.gd_enabled {
+        Some(match options.save_file {
+            Some(file) => PathBuf::from(file),
+            None => default_save_file()?,
         })
     } else {
         None
@@ -376,93 +928,314 @@ fn build_spwn_source(
     let level_string = if options.gd_enabled {
         if let Some(gd_path) = &gd_path {
             print_with_color("Reading savefile...", Color::Cyan);
-            let mut file = fs::File::open(gd_path)?;
-            let mut file_content = Vec::new();
-            file.read_to_end(&mut file_content)
-                .expect("Problem reading savefile");
-            let mut level_string =
+            let file_content = fs::read(gd_path)
+                .map_err(|e| format!("Could not read {}: {}", gd_path.display(), e))?;
+            let level_string =
                 match levelstring::get_level_string(file_content, options.level_name.as_ref()) {
                     Ok(s) => s,
                     Err(e) => {
-                        eprint_with_color(&format!("Error reading level:\n{}", e), Color::Red);
+                        print_error_message(
+                            &format!("Error reading level:\n{}", e),
+                            options.message_format,
+                        );
 
-                        std::process::exit(ERROR_EXIT_CODE);
+                        return Err(BuildFailed.into());
                     }
                 };
-            if level_string.is_empty() {}
-            leveldata::remove_spwn_objects(&mut level_string);
             level_string
+        } else if let Some(out_file) = options.out_file.filter(|_| options.incremental) {
+            // without a save file, incremental builds update the level written last time
+            match fs::read_to_string(out_file) {
+                Ok(text) if Path::new(out_file).extension() == Some("gmd".as_ref()) => {
+                    match levelstring::import_gmd(&text) {
+                        Ok(level_string) => level_string,
+                        Err(e) => {
+                            print_error_message(
+                                &format!("Error reading level from {}:\n{}", out_file, e),
+                                options.message_format,
+                            );
+                            return Err(BuildFailed.into());
+                        }
+                    }
+                }
+                Ok(level_string) => level_string,
+                // nothing was written there yet
+                Err(_) => String::new(),
+            }
         } else {
             String::new()
         }
     } else {
         String::new()
     };
+    let mut level = match LevelString::parse(&level_string) {
+        Ok(level) => level,
+        Err(e) => {
+            print_error_message(
+                &format!("Error reading level:\n{}", e),
+                options.message_format,
+            );
+            return Err(BuildFailed.into());
+        }
+    };
+    // the level with the objects from the last build, which incremental builds compare against
+    let previous_level = options.incremental.then(|| level.clone());
+    leveldata::remove_spwn_objects(&mut level);
+    let level_ids = match leveldata::get_used_ids(&level) {
+        Ok(ids) => ids,
+        Err(e) => {
+            print_error_message(
+                &format!("Error reading level:\n{}", e),
+                options.message_format,
+            );
+            return Err(BuildFailed.into());
+        }
+    };
     let mut std_out = std::io::stdout();
     let mut compiled = match compiler::compile_spwn(
         statements,
-        source,
+        source.clone(),
         options.include_paths,
         notes,
         options.permissions,
-        level_string.clone(),
+        level.to_string(),
+        options.output_cache,
+        options.seed,
         &mut std_out,
     ) {
         Err(err) => {
-            create_report(ErrorReport::from(err)).eprint(cache).unwrap();
-            std::process::exit(ERROR_EXIT_CODE);
+            let report = ErrorReport::from(err);
+            files.extend(error_files(&report));
+            print_error(report, &mut cache, options.message_format);
+            return Err(BuildFailed.into());
         }
         Ok(p) => p,
     };
+    files.extend(
+        compiled
+            .import_files
+            .iter()
+            .filter_map(|(file, _)| match file {
+                SpwnSource::File(path) => Some(path.clone()),
+                _ => None,
+            }),
+    );
     if options.gd_enabled {
-        let reserved = optimizer::ReservedIds::from_objects(&compiled.objects, &compiled.func_ids);
+        let reserved = optimizer::ReservedIds::from_globals(&compiled);
 
         let has_stuff = compiled.func_ids.iter().any(|x| !x.obj_list.is_empty());
         if options.opti_enabled && has_stuff {
+            if let Some(path) = options.emit_graph {
+                let path = PathBuf::from(path);
+                let mut before = path.file_stem().unwrap_or_default().to_os_string();
+                before.push(".before");
+                if let Some(extension) = path.extension() {
+                    before.push(".");
+                    before.push(extension);
+                }
+                write_trigger_graph(
+                    &path.with_file_name(before),
+                    &TriggerGraph::from_triggers(&compiled.func_ids),
+                )?;
+            }
             print_with_color("Optimizing triggers...", Color::Cyan);
-            compiled.func_ids = optimize(compiled.func_ids, compiled.closed_groups, reserved);
+            let mut pipeline = match options.opt_passes {
+                Some(names) => Pipeline::from_names(names)?,
+                None => Pipeline::default(),
+            };
+            compiled.func_ids = optimize_with(
+                compiled.func_ids,
+                compiled.closed_groups,
+                reserved,
+                &mut pipeline,
+            );
+            print_dropped_triggers(&pipeline.report.dropped);
+            if let Some(format) = options.opt_report {
+                print_opt_report(&pipeline.report, format);
+            }
+        }
+
+        if let Some(path) = options.emit_graph {
+            write_trigger_graph(
+                &PathBuf::from(path),
+                &TriggerGraph::from_triggers(&compiled.func_ids),
+            )?;
         }
 
-        let mut objects = leveldata::apply_fn_ids(&compiled.func_ids);
+        let id_keys = spwn::id_keys(&compiled);
+        // the IDs of the named arbitrary IDs are kept in a file next to the script
+        let names_path = match &source {
+            SpwnSource::File(path) => Some(path.with_extension("ids.txt")),
+            _ => None,
+        };
+        let name_keys = compiled.id_name_keys();
+        let named_ids = match &names_path {
+            Some(path) if path.exists() => IdMap::from_text(&fs::read_to_string(path)?)
+                .map_err(|e| format!("{}: {}", path.display(), e))?,
+            _ => IdMap::default(),
+        };
+        let mut previous = match &previous_level {
+            Some(level) => IdMap::from_level(level).previous(&id_keys),
+            None => Default::default(),
+        };
+        for (class, ids) in named_ids.previous(&name_keys).into_iter().enumerate() {
+            previous[class].extend(ids);
+        }
+        let id_options = IdOptions {
+            limits: options.id_limits,
+            pinned: compiled.pinned_ids(),
+            previous,
+        };
+        let mut objects = leveldata::apply_fn_ids(&compiled.func_ids, &compiled.layout);
 
         objects.extend(compiled.objects);
 
         print_with_color(&format!("{} objects added", objects.len()), Color::White);
 
-        let (new_ls, used_ids) = leveldata::append_objects(objects, &level_string)?;
+        // the IDs are only known after `append_objects`, which takes the objects
+        let mut mapped_objects = options.source_map.map(|_| objects.clone());
+
+        let (mut new_objects, id_report) =
+            match leveldata::append_objects(objects, &level_ids, &id_options) {
+                Ok(r) => r,
+                Err(e) => {
+                    print_error_message(&e.to_string(), options.message_format);
+                    let origins = compiled.id_origins[e.class].values().cloned().map(Some);
+                    print_id_origins(e.class, origins);
+                    return Err(BuildFailed.into());
+                }
+            };
+
+        if let Some(previous_level) = &previous_level {
+            new_objects
+                .objects
+                .push(IdMap::new(&id_keys, &id_report).to_object());
+            let (kept, added, removed) =
+                leveldata::update_spwn_objects(previous_level, &new_objects);
+            // only the added objects are written, so the source map only has those
+            if let Some(objects) = &mut mapped_objects {
+                objects.push(IdMap::source_object());
+                *objects = added.iter().map(|i| objects[*i].clone()).collect();
+            }
+            print_with_color(
+                &format!(
+                    "Incremental update: {} objects removed, {} added",
+                    removed,
+                    added.len()
+                ),
+                Color::White,
+            );
+            level = kept;
+            new_objects.objects = added
+                .iter()
+                .map(|i| new_objects.objects[*i].clone())
+                .collect();
+        }
+        if !compiled.level_settings.is_empty() {
+            // settings need a header to go in, so levels without one get the default header
+            let header = level.header.get_or_insert_with(|| {
+                LevelHeader::parse(levelstring::DEFAULT_LEVEL_HEADER.trim_end_matches(';'), 0)
+                    .unwrap()
+            });
+            if let Err(e) = header.apply_settings(&compiled.level_settings) {
+                print_error_message(
+                    &format!("Error reading level:\n{}", e),
+                    options.message_format,
+                );
+                return Err(BuildFailed.into());
+            }
+        }
+        let new_ls = new_objects.to_string();
 
         print_with_color("\nLevel:", Color::Magenta);
-        for (i, len) in used_ids.iter().enumerate() {
-            if *len > 0 {
+        for class in 0..4 {
+            if id_report.used[class] > 0 {
                 print_with_color(
                     &format!(
-                        "{} {}",
-                        len,
-                        ["groups", "colors", "block IDs", "item IDs"][i]
+                        "{} {}s ({} free)",
+                        id_report.used[class],
+                        leveldata::ID_CLASS_NAMES[class],
+                        id_report.free[class]
                     ),
                     Color::White,
                 );
             }
         }
+        let named = IdMap::new(&name_keys, &id_report);
+        if named.0.iter().any(|ids| !ids.is_empty()) {
+            print_with_color("\nNamed IDs:", Color::Magenta);
+            print_with_color(named.to_text().trim_end(), Color::White);
+        }
+        if let Some(path) = names_path.filter(|p| p.exists() || named != IdMap::default()) {
+            let header = "# The IDs of the named arbitrary IDs (like `?g(\"door\")`) in the script with the same name.\n# Builds give the names these IDs again, so objects placed by hand can use them\n";
+            fs::write(&path, header.to_string() + &named.to_text())
+                .map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+        }
+        if options.id_report {
+            for class in 0..4 {
+                let origins = id_report.assigned[class]
+                    .iter()
+                    .map(|(arbitrary, _)| compiled.id_origins[class].get(arbitrary).cloned());
+                print_id_origins(class, origins);
+            }
+        }
+        if let (Some(path), Some(objects)) = (options.source_map, mapped_objects) {
+            let map = spwn::source_map::source_map(&objects, &id_report);
+            fs::write(path, serde_json::to_string_pretty(&map)?)
+                .map_err(|e| format!("Could not write {}: {}", path, e))?;
+            print_with_color(&format!("Source map written to {}", path), Color::White);
+        }
         //println!("level_string: {}", level_string);
         if options.live_editor {
+            if !compiled.level_settings.is_empty() {
+                print_with_color(
+                    "The live editor only gets the objects, so the level header isn't changed",
+                    Color::Yellow,
+                );
+            }
             match editor_paste(&new_ls) {
                 Err(e) => {
-                    eprint_with_color(&format!("Error pasting into editor:\n{}", e), Color::Red);
+                    print_error_message(
+                        &format!("Error pasting into editor:\n{}", e),
+                        options.message_format,
+                    );
 
-                    std::process::exit(ERROR_EXIT_CODE);
+                    return Err(BuildFailed.into());
                 }
                 Ok(_) => {
                     print_with_color("Pasted into the editor!", Color::Green);
                 }
             }
+        } else if let Some(out_file) = options.out_file {
+            let out_path = PathBuf::from(out_file);
+            let contents = if out_path.extension().and_then(|e| e.to_str()) == Some("gmd") {
+                let level_name = options.level_name.unwrap_or_else(|| match &source {
+                    SpwnSource::File(path) => path
+                        .file_stem()
+                        .map(|s| s.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    _ => String::from("SPWN level"),
+                });
+                // a level also needs its start object, which only a level from the save file has
+                let full_ls = if level.header.is_none() {
+                    levelstring::DEFAULT_LEVEL_HEADER.to_string() + &level.to_string() + &new_ls
+                } else {
+                    level.to_string() + &new_ls
+                };
+                levelstring::export_gmd(&full_ls, &level_name)
+            } else {
+                level.to_string() + &new_ls
+            };
+            fs::write(&out_path, contents)
+                .map_err(|e| format!("Could not write {}: {}", out_path.display(), e))?;
+            print_with_color(&format!("Written to {}", out_path.display()), Color::Green);
         } else {
             match gd_path {
                 Some(gd_path) => {
                     print_with_color("\nWriting back to savefile...", Color::Cyan);
                     levelstring::encrypt_level_string(
                         new_ls,
-                        level_string,
+                        level.to_string(),
                         gd_path,
                         options.level_name,
                     )?;
@@ -0,0 +1,418 @@
+//! The interactive console behind `spwn eval`
+//!
+//! Every input is compiled right away in one long-lived context, so variables, types and
+//! objects from earlier inputs can be used by later ones. Expressions print their value.
+//! Everything that compiled without errors is kept, and can be built into a level with `:build`.
+
+use std::fs;
+use std::io::{self, Write};
+use std::panic;
+use std::path::PathBuf;
+
+use ariadne::Fmt;
+use internment::LocalIntern;
+
+use crate::ast;
+use crate::builtins::{BuiltinPermissions, Id, BUILTIN_NAMES};
+use crate::compiler_info::{CodeArea, CompilerInfo};
+use crate::compiler_types::EvalExpression;
+use crate::context::FullContext;
+use crate::globals::Globals;
+use crate::json_errors::{print_error, print_error_message, MessageFormat};
+use crate::leveldata::{GdObj, ObjParam};
+use crate::value::Value;
+use crate::{compiler, parse_spwn_all, SpwnCache};
+use errors::{ErrorReport, RuntimeError, SyntaxError};
+use shared::SpwnSource;
+
+const HELP: &str = "\
+:objects      show the objects and triggers that have been added so far
+:type <expr>  show the type of an expression (the expression is evaluated)
+:load <file>  run a file in this session
+:reset        start over with a fresh session
+:build        build everything entered so far into a level, and exit
+:exit         exit without building
+:help         show this message
+Inputs with unclosed brackets continue on the next line, an empty line ends them.";
+
+/// One set of globals and contexts that all inputs are compiled in
+pub(crate) struct Session<'a> {
+    globals: Globals<'a>,
+    // boxed so the root context pointer stays valid
+    contexts: Box<FullContext>,
+    // code that has compiled without errors, for `:build`
+    history: String,
+}
+
+impl<'a> Session<'a> {
+    pub(crate) fn new(
+        std_out: &'a mut impl Write,
+        includes: Vec<PathBuf>,
+        permissions: BuiltinPermissions,
+        output_cache: Option<PathBuf>,
+        seed: Option<u64>,
+    ) -> Result<Self, RuntimeError> {
+        let source = SpwnSource::String(LocalIntern::new(String::new()));
+        let mut globals = Globals::new(source.clone(), permissions, String::new(), std_out);
+        globals.includes = includes;
+        globals.output_cache = output_cache;
+        if let Some(seed) = seed {
+            globals.seed_rng(seed);
+        }
+
+        let mut contexts = Box::new(FullContext::new(&globals));
+        let ptr: *mut FullContext = &mut *contexts;
+        contexts.inner().root_context_ptr = ptr;
+
+        compiler::import_std(&mut contexts, &mut globals, info_at(&source))?;
+
+        Ok(Session {
+            globals,
+            contexts,
+            history: String::new(),
+        })
+    }
+
+    // compiles the code and returns the values of the last statement, if it's an expression
+    fn eval(&mut self, code: &str, source: SpwnSource) -> Result<Vec<Value>, Vec<ErrorReport>> {
+        let (statements, _) = parse_spwn_all(code.to_string(), source.clone(), BUILTIN_NAMES)
+            .map_err(|errors| {
+                errors
+                    .into_iter()
+                    .map(ErrorReport::from)
+                    .collect::<Vec<_>>()
+            })?;
+
+        // the value of an expression is only kept until the end of its statement,
+        // so a trailing expression is evaluated separately
+        let (last_expr, rest) = match statements.split_last() {
+            Some((
+                ast::Statement {
+                    body: ast::StatementBody::Expr(expr),
+                    arrow: false,
+                    pos,
+                },
+                rest,
+            )) => (Some((expr, *pos)), rest),
+            _ => (None, &statements[..]),
+        };
+
+        let result = compiler::compile_statements(
+            rest,
+            &mut self.contexts,
+            &mut self.globals,
+            info_at(&source),
+        );
+        let broken = self.unbreak(&statements, &source);
+        result.map_err(|e| vec![e.into()])?;
+        broken.map_err(|e| vec![e.into()])?;
+
+        let mut values = Vec::new();
+        if let Some((expr, pos)) = last_expr {
+            let mut info = info_at(&source);
+            info.position.pos = pos;
+            expr.eval(&mut self.contexts, &mut self.globals, info, true)
+                .map_err(|e| vec![e.into()])?;
+
+            for c in self.contexts.iter() {
+                let val = self.globals.stored_values[c.inner().return_value].clone();
+                if val != Value::Null && !values.contains(&val) {
+                    values.push(val);
+                }
+            }
+            self.contexts.reset_return_vals(&self.globals);
+        }
+        Ok(values)
+    }
+
+    // a `break` or `return` outside of a loop or macro would silently skip every later input
+    fn unbreak(
+        &mut self,
+        statements: &[ast::Statement],
+        source: &SpwnSource,
+    ) -> Result<(), RuntimeError> {
+        let end = statements.last().map(|s| s.pos.1).unwrap_or(0);
+        let mut err = None;
+        for c in self.contexts.with_breaks() {
+            if let Some((breaktype, area)) = c.inner().broken.take() {
+                err.get_or_insert(RuntimeError::BreakNeverUsedError {
+                    breaktype,
+                    info: CompilerInfo::from_area(area),
+                    broke: area,
+                    dropped: CodeArea {
+                        pos: (end, end),
+                        file: LocalIntern::new(source.clone()),
+                    },
+                    reason: "the input ended".to_string(),
+                });
+            }
+        }
+        err.map_or(Ok(()), Err)
+    }
+
+    fn value_str(&mut self, val: &Value) -> String {
+        val.to_str(&mut self.globals)
+    }
+
+    fn objects(&self) -> String {
+        let mut out = format!("{} objects:\n", self.globals.objects.len());
+        for obj in &self.globals.objects {
+            out += &format!("    {}\n", obj_str(obj));
+        }
+
+        let used = self
+            .globals
+            .func_ids
+            .iter()
+            .filter(|f| !f.obj_list.is_empty())
+            .count();
+        out += &format!("{} trigger functions with triggers:\n", used);
+        for (i, f) in self.globals.func_ids.iter().enumerate() {
+            if f.obj_list.is_empty() {
+                continue;
+            }
+            match f.parent {
+                Some(p) => out += &format!("  function {} (in function {}):\n", i, p),
+                None => out += &format!("  function {}:\n", i),
+            }
+            for (obj, _) in &f.obj_list {
+                out += &format!("    {}\n", obj_str(obj));
+            }
+        }
+        out
+    }
+}
+
+/// What the console does after a line of input
+pub(crate) enum Step {
+    /// shows this, and waits for the next line
+    Output(String),
+    /// shows these errors, and waits for the next line
+    Errors(Vec<ErrorReport>),
+    /// shows this in red, and waits for the next line
+    Failed(String),
+    /// starts over with a fresh session
+    Reset,
+    /// leaves the console, with the code to build if it was left with `:build`
+    Exit(Option<String>),
+}
+
+// handles one line typed into the console, `input` has the lines of an unfinished input
+pub(crate) fn input_line(session: &mut Session, input: &mut String, line: &str) -> Step {
+    if input.is_empty() {
+        let trimmed = line.trim();
+        let (command, arg) = trimmed.split_once(' ').unwrap_or((trimmed, ""));
+        let arg = arg.trim();
+        match command {
+            "" => return Step::Output(String::new()),
+            ":help" => return Step::Output(format!("{}\n", HELP)),
+            ":exit" | ":quit" => return Step::Exit(None),
+            ":build" => return Step::Exit(Some(std::mem::take(&mut session.history))),
+            ":reset" => return Step::Reset,
+            ":objects" => return Step::Output(session.objects()),
+            ":type" => {
+                let source = SpwnSource::String(LocalIntern::new(arg.to_string()));
+                return match catch_crash(|| session.eval(arg, source)) {
+                    Some(Ok(values)) if values.is_empty() => Step::Output("@NULL\n".to_string()),
+                    Some(Ok(values)) => Step::Output(
+                        values
+                            .iter()
+                            .map(|v| format!("@{}\n", v.get_type_str(&session.globals)))
+                            .collect(),
+                    ),
+                    Some(Err(errors)) => Step::Errors(errors),
+                    None => Step::Output(String::new()),
+                };
+            }
+            ":load" => {
+                let path = PathBuf::from(arg);
+                let code = match fs::read_to_string(&path) {
+                    Ok(c) => c,
+                    Err(e) => return Step::Failed(format!("Couldn't read {}: {}", arg, e)),
+                };
+                return match catch_crash(|| session.eval(&code, SpwnSource::File(path))) {
+                    Some(Ok(_)) => {
+                        session.history += &code;
+                        session.history.push('\n');
+                        Step::Output(String::new())
+                    }
+                    Some(Err(errors)) => Step::Errors(errors),
+                    None => Step::Output(String::new()),
+                };
+            }
+            c if c.starts_with(':') => {
+                return Step::Failed(format!("Unknown command {}, see :help", c))
+            }
+            _ => (),
+        }
+    } else if line.trim().is_empty() {
+        // an empty line evaluates unfinished input anyway, to show what's wrong with it
+        let code = std::mem::take(input);
+        let source = SpwnSource::String(LocalIntern::new(code.clone()));
+        return match catch_crash(|| session.eval(&code, source)) {
+            Some(Err(errors)) => Step::Errors(errors),
+            _ => Step::Output(String::new()),
+        };
+    }
+
+    *input += line;
+    if is_incomplete(input) {
+        return Step::Output(String::new());
+    }
+    let code = std::mem::take(input);
+    let source = SpwnSource::String(LocalIntern::new(code.clone()));
+    match catch_crash(|| session.eval(&code, source)) {
+        Some(Ok(values)) => {
+            let out = values
+                .iter()
+                .map(|v| format!("{}\n", session.value_str(v)))
+                .collect();
+            session.history += &code;
+            Step::Output(out)
+        }
+        Some(Err(errors)) => Step::Errors(errors),
+        None => Step::Output(String::new()),
+    }
+}
+
+fn info_at(source: &SpwnSource) -> CompilerInfo {
+    CompilerInfo::from_area(CodeArea {
+        file: LocalIntern::new(source.clone()),
+        pos: (0, 0),
+    })
+}
+
+fn obj_str(obj: &GdObj) -> String {
+    let mut params = obj.params.iter().collect::<Vec<_>>();
+    params.sort_by_key(|(k, _)| **k);
+    params
+        .iter()
+        .map(|(k, v)| format!("{}: {}", k, param_str(v)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// like the `Display` impl, but without replacing arbitrary ids with 0
+fn param_str(param: &ObjParam) -> String {
+    let id_str = |id: &Id, suffix: &str| match id {
+        Id::Specific(n) => format!("{}{}", n, suffix),
+        Id::Arbitrary(n) => format!("?{}{}", suffix, n),
+    };
+    match param {
+        ObjParam::Group(g) => id_str(&g.id, "g"),
+        ObjParam::Color(c) => id_str(&c.id, "c"),
+        ObjParam::Block(b) => id_str(&b.id, "b"),
+        ObjParam::Item(i) => id_str(&i.id, "i"),
+        ObjParam::GroupList(list) => format!(
+            "[{}]",
+            list.iter()
+                .map(|g| id_str(&g.id, "g"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        ObjParam::Text(t) => format!("{:?}", t),
+        p => p.to_string(),
+    }
+}
+
+// whether the code stops in the middle of a statement, so the user is probably still typing it
+fn is_incomplete(code: &str) -> bool {
+    let source = SpwnSource::String(LocalIntern::new(code.to_string()));
+    match parse_spwn_all(code.to_string(), source, BUILTIN_NAMES) {
+        Ok(_) => false,
+        Err(errors) => errors.iter().any(|e| match e {
+            SyntaxError::ExpectedErr { found, .. } => found.starts_with("EOF"),
+            SyntaxError::SyntaxError { message, .. } => {
+                message.starts_with("Couldn't find matching")
+            }
+            _ => false,
+        }),
+    }
+}
+
+fn print_errors(errors: Vec<ErrorReport>, cache: &mut SpwnCache, format: MessageFormat) {
+    for e in errors {
+        print_error(e, cache, format);
+    }
+}
+
+fn read_line(prompt: &str) -> io::Result<Option<String>> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line)? == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(line))
+    }
+}
+
+// runs a panicking compiler without taking the whole console down
+fn catch_crash<T>(f: impl FnOnce() -> T) -> Option<T> {
+    match panic::catch_unwind(panic::AssertUnwindSafe(f)) {
+        Ok(r) => Some(r),
+        Err(_) => {
+            eprintln!(
+                "{}",
+                "The compiler crashed, use :reset if the session stops working"
+                    .fg(ariadne::Color::Red)
+            );
+            None
+        }
+    }
+}
+
+/// Runs the console until it's exited. Returns the code to build into a level if it was left with `:build`
+pub fn run_repl(
+    includes: Vec<PathBuf>,
+    permissions: BuiltinPermissions,
+    output_cache: Option<PathBuf>,
+    seed: Option<u64>,
+    format: MessageFormat,
+) -> io::Result<Option<String>> {
+    use ariadne::Color::{Blue, Red};
+    println!(
+        "{}{}{}{}{}",
+        "Write your code, type \"".fg(Blue),
+        ":build".fg(Red),
+        "\" to build it into a level, or \"".fg(Blue),
+        ":help".fg(Red),
+        "\" for more commands".fg(Blue)
+    );
+
+    let mut cache = SpwnCache::default();
+    'session: loop {
+        let mut std_out = io::stdout();
+        let mut session = match catch_crash(|| {
+            Session::new(
+                &mut std_out,
+                includes.clone(),
+                permissions.clone(),
+                output_cache.clone(),
+                seed,
+            )
+        }) {
+            Some(Ok(s)) => s,
+            Some(Err(e)) => {
+                print_errors(vec![e.into()], &mut cache, format);
+                return Ok(None);
+            }
+            None => return Ok(None),
+        };
+
+        let mut input = String::new();
+        loop {
+            let line = match read_line(if input.is_empty() { "> " } else { "... " })? {
+                Some(l) => l,
+                None => return Ok(None),
+            };
+            match input_line(&mut session, &mut input, &line) {
+                Step::Output(out) => print!("{}", out),
+                Step::Errors(errors) => print_errors(errors, &mut cache, format),
+                Step::Failed(message) => print_error_message(&message, format),
+                Step::Reset => continue 'session,
+                Step::Exit(code) => return Ok(code),
+            }
+        }
+    }
+}
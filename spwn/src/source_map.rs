@@ -0,0 +1,102 @@
+//! Source maps, which point every object a build added to the level back at the code that made it
+//!
+//! The source map is one JSON object:
+//! ```json
+//! {
+//!     "objects": [
+//!         {
+//!             "index": 0,
+//!             "object_id": 1268,
+//!             "trigger": true,
+//!             "groups": [5],
+//!             "origin": <area>,
+//!             "call_stack": [<area>, ...]
+//!         }
+//!     ],
+//!     "groups": { "5": [0, 3] }
+//! }
+//! ```
+//! where `index` is the position of the object among the objects added by the build, in the order
+//! they are written to the level string (after the objects that were already in the level), and
+//! `groups` maps every group ID to the indexes of the objects in that group. Incremental builds
+//! only write the objects that weren't in the level yet (which can include the text object the
+//! IDs are kept in), so their source maps only have those. Areas are formatted
+//! like in the JSON error messages (see `json_errors`), and `origin` is null for objects that
+//! don't come from any code. The call stack starts at the outermost call.
+
+use std::collections::BTreeMap;
+
+use serde_json::{json, Value as Json};
+
+use crate::ast::ObjectMode;
+use crate::builtins::{Id, SpecificId};
+use crate::json_errors::JsonReporter;
+use crate::leveldata::{GdObj, IdReport, ObjParam};
+
+/// Makes the source map for the objects given to `append_objects`, using the report it returned
+pub fn source_map(objects: &[GdObj], report: &IdReport) -> Json {
+    let mut reporter = JsonReporter::default();
+    let mut groups = BTreeMap::<SpecificId, Vec<usize>>::new();
+
+    let group_id = |id: Id| match id {
+        Id::Specific(id) => Some(id),
+        Id::Arbitrary(id) => report.assigned[0]
+            .binary_search_by_key(&id, |(arbitrary, _)| *arbitrary)
+            .ok()
+            .map(|i| report.assigned[0][i].1),
+    };
+
+    let objects = objects
+        .iter()
+        .enumerate()
+        .map(|(index, obj)| {
+            let obj_groups = match obj.params.get(&57) {
+                Some(ObjParam::Group(g)) => vec![g.id],
+                Some(ObjParam::GroupList(list)) => list.iter().map(|g| g.id).collect(),
+                _ => Vec::new(),
+            }
+            .into_iter()
+            .filter_map(group_id)
+            // group 0 is the same as no group
+            .filter(|g| *g != 0)
+            .collect::<Vec<_>>();
+
+            for group in &obj_groups {
+                groups.entry(*group).or_default().push(index);
+            }
+
+            let object_id = match obj.params.get(&1) {
+                Some(ObjParam::Number(n)) => Some(*n as u32),
+                _ => None,
+            };
+
+            let (origin, call_stack) = match &obj.origin {
+                Some(info) => (
+                    reporter.area(&info.position),
+                    info.call_stack
+                        .iter()
+                        .map(|area| reporter.area(area))
+                        .collect(),
+                ),
+                None => (Json::Null, Vec::new()),
+            };
+
+            json!({
+                "index": index,
+                "object_id": object_id,
+                "trigger": obj.mode == ObjectMode::Trigger,
+                "groups": obj_groups,
+                "origin": origin,
+                "call_stack": call_stack,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "objects": objects,
+        "groups": groups
+            .into_iter()
+            .map(|(group, indexes)| (group.to_string(), json!(indexes)))
+            .collect::<serde_json::Map<_, _>>(),
+    })
+}
@@ -1,7 +1,10 @@
 #![allow(unused_variables)]
 
+use std::fs;
 use std::path::PathBuf;
 
+use serde_json::{json, Value};
+
 use crate::run_spwn;
 
 macro_rules! run_test {
@@ -21,6 +24,41 @@ macro_rules! run_test {
     };
 }
 
+// compiles code that doesn't use the standard library
+fn compile<'a>(code: &str, std_out: &'a mut Vec<u8>) -> crate::globals::Globals<'a> {
+    compile_seeded(code, None, std_out)
+}
+
+// with `$.random` seeded like with `--seed`
+fn compile_seeded<'a>(
+    code: &str,
+    seed: Option<u64>,
+    std_out: &'a mut Vec<u8>,
+) -> crate::globals::Globals<'a> {
+    use internment::LocalIntern;
+    use shared::SpwnSource;
+
+    let source = SpwnSource::String(LocalIntern::new(code.to_string()));
+    let (statements, notes) = crate::parse_spwn(
+        code.to_string(),
+        source.clone(),
+        crate::builtins::BUILTIN_NAMES,
+    )
+    .unwrap();
+    crate::compiler::compile_spwn(
+        statements,
+        source,
+        Vec::new(),
+        notes,
+        Default::default(),
+        String::new(),
+        None,
+        seed,
+        std_out,
+    )
+    .unwrap_or_else(|_| panic!("build failed"))
+}
+
 // just basic parsing
 run_test! {
     NAME: basic_parsing
@@ -118,3 +156,1375 @@ true
 true
     "
 }
+
+#[test]
+fn line_index_positions() {
+    let index = crate::LineIndex::new("a = 1\r\nbé = \"𝄞\"\nc");
+    // windows line endings are removed like in the parser
+    assert_eq!(index.line_col(6), (1, 0));
+    assert_eq!(index.line_col(index.text().len()), (2, 1));
+
+    let string_end = index.text().find("\"\n").unwrap();
+    assert_eq!(index.line_col(string_end), (1, 7));
+    assert_eq!(index.line_col_utf16(string_end), (1, 8));
+    assert_eq!(index.offset_utf16(1, 8), string_end);
+}
+
+#[test]
+fn json_error_positions() {
+    use crate::json_errors::JsonReporter;
+    use internment::LocalIntern;
+    use shared::SpwnSource;
+
+    let code = "a = 1\nb = (".to_string();
+    let source = SpwnSource::String(LocalIntern::new(code.clone()));
+    let err = match crate::parse_spwn(code, source, crate::builtins::BUILTIN_NAMES) {
+        Err(e) => e,
+        Ok(_) => panic!("expected a syntax error"),
+    };
+    let json = JsonReporter::default().report(&errors::ErrorReport::from(err));
+
+    assert_eq!(json["message"], "Syntax error");
+    assert_eq!(json["position"]["source"], "string");
+    assert!(json["position"]["file"].is_null());
+    assert_eq!(json["position"]["start"]["line"], 2);
+    // labels have the terminal colors removed
+    assert!(!json["labels"][0]["message"]
+        .as_str()
+        .unwrap()
+        .contains('\u{1b}'));
+}
+
+#[test]
+fn parser_error_recovery() {
+    use internment::LocalIntern;
+    use shared::SpwnSource;
+
+    let code = "a = 1 2\nm = () {\n    b = [1, 2\n    c = 3 +\n}\nd = (4 5)\ne = 6".to_string();
+    let source = SpwnSource::String(LocalIntern::new(code.clone()));
+    let errors = match crate::parse_spwn_all(code, source, crate::builtins::BUILTIN_NAMES) {
+        Err(e) => e,
+        Ok(_) => panic!("expected syntax errors"),
+    };
+
+    let starts = errors
+        .iter()
+        .map(|e| match e {
+            errors::SyntaxError::ExpectedErr { pos, .. }
+            | errors::SyntaxError::UnexpectedErr { pos, .. }
+            | errors::SyntaxError::SyntaxError { pos, .. } => pos.0,
+            errors::SyntaxError::CustomError(_) => panic!("unexpected custom error"),
+        })
+        .collect::<Vec<_>>();
+    // one error for the first line, the block and the call each, in order
+    assert_eq!(starts.len(), 3, "{:?}", errors);
+    assert!(starts.windows(2).all(|w| w[0] < w[1]));
+
+    let error_count = |code: &str| {
+        let source = SpwnSource::String(LocalIntern::new(code.to_string()));
+        match crate::parse_spwn_all(code.to_string(), source, crate::builtins::BUILTIN_NAMES) {
+            Err(errors) => errors.len(),
+            Ok(_) => 0,
+        }
+    };
+    // brackets that are never closed only skip until the next line at the statement's indentation
+    // (`b = 2 +` goes on to the next line, so `b` and `c` are one statement)
+    assert_eq!(error_count("a = (1 +\nb = 2 +\nc = ]\nd = 3 +"), 3);
+    // but lines indented further and lines that close a bracket don't end the statement
+    assert_eq!(error_count("e = [\n    4 5,\n    f = 6\n]\ng = 7"), 1);
+}
+
+#[test]
+fn console_session() {
+    use crate::builtins::BuiltinPermissions;
+    use crate::repl::{input_line as line, Session, Step};
+
+    let output = |step: Step| match step {
+        Step::Output(out) => out,
+        Step::Errors(errors) => panic!("{}", errors[0].message),
+        Step::Failed(message) => panic!("{}", message),
+        _ => panic!("expected output"),
+    };
+
+    fn new_session(std_out: &mut Vec<u8>) -> Session<'_> {
+        let permissions = BuiltinPermissions::new();
+        Session::new(
+            std_out,
+            vec![PathBuf::from("./")],
+            permissions,
+            None,
+            Some(3),
+        )
+        .unwrap()
+    }
+
+    let mut std_out = Vec::<u8>::new();
+    let mut session = new_session(&mut std_out);
+    let mut input = String::new();
+
+    // variables and macros are kept between inputs, also ones written over several lines
+    assert_eq!(output(line(&mut session, &mut input, "a = 5\n")), "");
+    assert_eq!(
+        output(line(&mut session, &mut input, "double = (x) {\n")),
+        ""
+    );
+    assert_eq!(
+        output(line(&mut session, &mut input, "    return x * 2\n")),
+        ""
+    );
+    assert_eq!(output(line(&mut session, &mut input, "}\n")), "");
+    assert_eq!(
+        output(line(&mut session, &mut input, "double(a) + 1\n")),
+        "11\n"
+    );
+    assert_eq!(output(line(&mut session, &mut input, "$.print(a)\n")), "");
+
+    assert_eq!(
+        output(line(&mut session, &mut input, ":type a\n")),
+        "@number\n"
+    );
+    assert_eq!(
+        output(line(&mut session, &mut input, ":type double\n")),
+        "@macro\n"
+    );
+    assert_eq!(
+        output(line(&mut session, &mut input, ":type $.print(1)\n")),
+        "@NULL\n"
+    );
+    assert!(matches!(
+        line(&mut session, &mut input, ":type b\n"),
+        Step::Errors(_)
+    ));
+    assert!(matches!(
+        line(&mut session, &mut input, ":nope\n"),
+        Step::Failed(_)
+    ));
+
+    // the console starts a new session after a reset, which has forgotten everything
+    assert!(matches!(
+        line(&mut session, &mut input, ":reset\n"),
+        Step::Reset
+    ));
+    drop(session);
+    assert_eq!(String::from_utf8_lossy(&std_out), "5\n1\n");
+    let mut std_out = Vec::<u8>::new();
+    let mut session = new_session(&mut std_out);
+    assert!(matches!(
+        line(&mut session, &mut input, "a\n"),
+        Step::Errors(_)
+    ));
+    assert_eq!(output(line(&mut session, &mut input, "b = 2\n")), "");
+    match line(&mut session, &mut input, ":build\n") {
+        Step::Exit(Some(code)) => assert_eq!(code, "b = 2\n"),
+        _ => panic!("expected the code to build"),
+    }
+
+    // `--seed` works in the console too
+    let random = output(line(&mut session, &mut input, "$.random()\n"));
+    drop(session);
+    let mut std_out = Vec::<u8>::new();
+    let mut session = new_session(&mut std_out);
+    assert_eq!(
+        output(line(&mut session, &mut input, "$.random()\n")),
+        random
+    );
+}
+
+fn lsp_request(server: &mut crate::lsp::Server, method: &str, params: Value) -> Value {
+    let mut replies = server.handle_message(&json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    }));
+    assert_eq!(replies.len(), 1);
+    replies.remove(0)
+}
+
+fn lsp_open(server: &mut crate::lsp::Server, uri: &str, text: &str) -> Value {
+    let mut replies = server.handle_message(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": { "textDocument": { "uri": uri, "languageId": "spwn", "version": 1, "text": text } },
+    }));
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0]["method"], "textDocument/publishDiagnostics");
+    replies.remove(0)
+}
+
+#[test]
+fn language_server_requests() {
+    let mut server = crate::lsp::Server::new(Vec::new());
+    let uri = "file:///spwn-lsp-test/main.spwn";
+
+    let init = lsp_request(&mut server, "initialize", json!({ "rootUri": null }));
+    assert_eq!(init["id"], 1);
+    assert_eq!(init["result"]["capabilities"]["hoverProvider"], true);
+
+    let code =
+        "#[no_std]\ncounter = 5\nadd = (x: @number) { return x + 1 }\ny = add(counter)\n$.print(y)";
+    let opened = lsp_open(&mut server, uri, code);
+    assert_eq!(opened["params"]["uri"], uri);
+    assert_eq!(opened["params"]["diagnostics"], json!([]));
+
+    let at = |line: usize, character: usize| {
+        let position = json!({ "line": line, "character": character });
+        json!({ "textDocument": { "uri": uri }, "position": position })
+    };
+
+    let hover = lsp_request(&mut server, "textDocument/hover", at(3, 10));
+    let contents = hover["result"]["contents"]["value"].as_str().unwrap();
+    assert!(contents.contains("counter"), "{}", contents);
+    assert_eq!(
+        hover["result"]["range"]["start"],
+        json!({ "line": 3, "character": 8 })
+    );
+    let hover = lsp_request(&mut server, "textDocument/hover", at(3, 5));
+    let contents = hover["result"]["contents"]["value"].as_str().unwrap();
+    assert!(contents.contains("add(x)"), "{}", contents);
+
+    let definition = lsp_request(&mut server, "textDocument/definition", at(3, 10));
+    let location = &definition["result"][0];
+    assert_eq!(location["uri"], uri);
+    assert_eq!(
+        location["range"]["start"],
+        json!({ "line": 1, "character": 0 })
+    );
+
+    let completion = lsp_request(&mut server, "textDocument/completion", at(4, 2));
+    let labels = completion["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert!(labels.contains(&"print"));
+    assert!(!labels.contains(&"counter"));
+    let completion = lsp_request(&mut server, "textDocument/completion", at(3, 0));
+    assert!(completion["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|item| item["label"] == "counter"));
+
+    let unknown = lsp_request(&mut server, "textDocument/rename", at(3, 0));
+    assert!(unknown["error"]["message"].is_string());
+    assert_eq!(
+        lsp_request(&mut server, "shutdown", Value::Null)["result"],
+        Value::Null
+    );
+}
+
+#[test]
+fn language_server_diagnostics() {
+    let mut server = crate::lsp::Server::new(Vec::new());
+    let uri = "file:///spwn-lsp-test/broken.spwn";
+
+    // a syntax error
+    let opened = lsp_open(&mut server, uri, "#[no_std]\na = 1\nb = (");
+    let diagnostics = opened["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["severity"], 1);
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 2);
+
+    // a runtime error, found by compiling the document when it's saved
+    server.handle_message(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {
+            "textDocument": { "uri": uri, "version": 2 },
+            "contentChanges": [{ "text": "#[no_std]\na = 1\n$.print(missing)" }],
+        },
+    }));
+    let saved = server.handle_message(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didSave",
+        "params": { "textDocument": { "uri": uri } },
+    }));
+    let diagnostics = saved[0]["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert!(
+        diagnostics[0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("missing"),
+        "{}",
+        diagnostics[0]
+    );
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 2);
+
+    // requests on what's left of a broken document still get answers
+    let hover = lsp_request(
+        &mut server,
+        "textDocument/hover",
+        json!({ "textDocument": { "uri": uri }, "position": { "line": 40, "character": 0 } }),
+    );
+    assert_eq!(hover["result"], Value::Null);
+}
+
+#[test]
+fn library_output_cache() {
+    use internment::LocalIntern;
+    use shared::SpwnSource;
+
+    let cache_dir = std::env::temp_dir().join(format!("spwn-output-cache-{}", std::process::id()));
+    let code = "c = counter(3)\n$.print([1, 2, 3].map(x => x * 2), @counter, 5g..7g, ?i)";
+
+    let build = || {
+        let source = SpwnSource::String(LocalIntern::new(code.to_string()));
+        let (statements, notes) = crate::parse_spwn(
+            code.to_string(),
+            source.clone(),
+            crate::builtins::BUILTIN_NAMES,
+        )
+        .unwrap();
+        let mut std_out = Vec::<u8>::new();
+        let globals = crate::compiler::compile_spwn(
+            statements,
+            source,
+            // the std in the repository, since debug builds don't include it
+            vec![PathBuf::from("../")],
+            notes,
+            Default::default(),
+            String::new(),
+            Some(cache_dir.clone()),
+            None,
+            &mut std_out,
+        )
+        .unwrap_or_else(|_| panic!("build failed"));
+        let imports = globals.prev_imports.len();
+        let items = globals.closed_items;
+        (String::from_utf8(std_out).unwrap(), imports, items)
+    };
+
+    let (cold, cold_imports, cold_items) = build();
+    assert!(fs::read_dir(&cache_dir).unwrap().count() > 0);
+    let (warm, warm_imports, warm_items) = build();
+    fs::remove_dir_all(&cache_dir).unwrap();
+
+    assert_eq!(cold, warm);
+    assert_eq!(cold_items, warm_items);
+    // the files the std imports were never evaluated
+    assert!(warm_imports < cold_imports);
+}
+
+#[test]
+fn output_cache_skips_outside_reads() {
+    use crate::builtins::{Builtin, BuiltinPermissions};
+    use internment::LocalIntern;
+    use shared::SpwnSource;
+
+    let dir = std::env::temp_dir().join(format!("spwn-outside-reads-{}", std::process::id()));
+    let cache_dir = dir.join("cache");
+    let libraries = [
+        ("pure", "return { n: 5 }"),
+        ("random", "return { n: $.random() }"),
+        // only reads `random` from the imports of this build
+        ("wrapper", "r = import random\nreturn { r: r.n }"),
+        ("printer", "$.print(\"imported\")\nreturn 1"),
+        ("level", "return $.level_string()"),
+        ("named", "return ?g(\"door\")"),
+    ];
+    for (name, code) in libraries {
+        let lib_dir = dir.join("libraries").join(name);
+        fs::create_dir_all(&lib_dir).unwrap();
+        fs::write(
+            lib_dir.join("lib.spwn"),
+            format!("#[cache_output, no_std]\n{}", code),
+        )
+        .unwrap();
+    }
+    let code = "#[no_std]\na = import random\nb = import wrapper\nc = import pure\nd = import printer\n$.print(a.n == b.r)\n$.print(import level)\ne = import named";
+
+    let build = |permissions: BuiltinPermissions, level: &str| {
+        let source = SpwnSource::String(LocalIntern::new(code.to_string()));
+        let (statements, notes) = crate::parse_spwn(
+            code.to_string(),
+            source.clone(),
+            crate::builtins::BUILTIN_NAMES,
+        )
+        .unwrap();
+        let mut std_out = Vec::<u8>::new();
+        crate::compiler::compile_spwn(
+            statements,
+            source,
+            vec![dir.clone()],
+            notes,
+            permissions,
+            level.to_string(),
+            Some(cache_dir.clone()),
+            None,
+            &mut std_out,
+        )
+        .unwrap_or_else(|_| panic!("build failed"));
+        String::from_utf8(std_out).unwrap()
+    };
+
+    let level = "1,1,2,15,3,15;";
+    let output = "imported\ntrue\n1,1,2,15,3,15;\n";
+    assert_eq!(build(BuiltinPermissions::new(), level), output);
+    // only the libraries that don't depend on anything but their files and the level were stored
+    assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 2);
+    assert_eq!(build(BuiltinPermissions::new(), level), output);
+    assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 2);
+    // a library that read the level is evaluated again for another level
+    assert_eq!(
+        build(BuiltinPermissions::new(), "1,1,2,45,3,15;"),
+        "imported\ntrue\n1,1,2,45,3,15;\n"
+    );
+
+    // entries are kept apart for different permissions
+    let mut permissions = BuiltinPermissions::new();
+    permissions.set(Builtin::ReadFile, true);
+    build(permissions, level);
+    assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 4);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn formatter_keeps_comments() {
+    use crate::fmt::{format, FmtConfig, IndentStyle};
+    use internment::LocalIntern;
+    use shared::SpwnSource;
+
+    let code = "// header\n\n#[no_std]   // file tag\nlet a=[1,2,3] // trailing\n\n\n/* block\n   comment */\nm = (x, y) {\n  // inside\n  return x+y*2 // sum\n}\nd = {\n    a,\n    // the b\n    b: 2,\n}\n";
+    let expected = "// header\n\n#[no_std] // file tag\nlet a = [1, 2, 3] // trailing\n\n/* block\n   comment */\nm = (x, y) {\n\t// inside\n\treturn x + y * 2 // sum\n}\nd = {\n\ta,\n\t// the b\n\tb: 2,\n}\n";
+
+    let config = FmtConfig {
+        indent: IndentStyle::Tabs,
+    };
+    let source = SpwnSource::String(LocalIntern::new(code.to_string()));
+    let formatted = format(
+        code,
+        source.clone(),
+        crate::builtins::BUILTIN_NAMES,
+        &config,
+    )
+    .unwrap();
+    assert_eq!(formatted, expected);
+    let again = format(&formatted, source, crate::builtins::BUILTIN_NAMES, &config).unwrap();
+    assert_eq!(again, formatted);
+}
+
+#[test]
+fn formatter_std_library() {
+    use crate::fmt::{format, FmtConfig};
+    use shared::SpwnSource;
+
+    let mut dirs = vec![PathBuf::from("../libraries/std")];
+    let mut files = Vec::new();
+    while let Some(dir) = dirs.pop() {
+        for path in fs::read_dir(dir).unwrap().map(|f| f.unwrap().path()) {
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|e| e == "spwn") {
+                files.push(path);
+            }
+        }
+    }
+    assert!(!files.is_empty());
+    for path in files {
+        let code = fs::read_to_string(&path).unwrap();
+        let source = SpwnSource::File(path.clone());
+        let format = |code: &str| {
+            format(
+                code,
+                source.clone(),
+                crate::builtins::BUILTIN_NAMES,
+                &FmtConfig::default(),
+            )
+            .unwrap_or_else(|e| panic!("could not format {}: {:?}", path.display(), e))
+        };
+        let formatted = format(&code);
+        assert_eq!(format(&formatted), formatted, "{}", path.display());
+    }
+}
+
+#[test]
+fn id_limits() {
+    use crate::leveldata::{append_objects, IdOptions};
+
+    let code = "#[no_std]\nfor i in 0..5 { $.add(obj {1: 1, 57: ?g}) }\n$.add(obj {1: 1, 57: 3g})";
+    let mut std_out = Vec::new();
+    let globals = compile(code, &mut std_out);
+    // every arbitrary group was made by the `?g` in the loop
+    let origins = globals.id_origins[0].values().collect::<Vec<_>>();
+    assert_eq!(origins.len(), 5);
+    assert!(origins.iter().all(|area| area.pos == origins[0].pos));
+
+    let (_, report) = append_objects(
+        globals.objects.clone(),
+        &Default::default(),
+        &IdOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(report.used[0], 6);
+    assert_eq!(report.free[0], 999 - 6);
+    let assigned = report.assigned[0]
+        .iter()
+        .map(|(_, id)| *id)
+        .collect::<Vec<_>>();
+    assert_eq!(assigned, vec![1, 2, 4, 5, 6]);
+
+    // the specific group takes one of the six IDs
+    let mut options = IdOptions::default();
+    options.limits.set("group=5").unwrap();
+    let error = append_objects(globals.objects.clone(), &Default::default(), &options).unwrap_err();
+    assert_eq!(error.class, 0);
+    options.limits.set("group=2").unwrap();
+    assert!(append_objects(globals.objects, &Default::default(), &options).is_err());
+}
+
+#[test]
+fn pinned_id_ranges() {
+    use crate::leveldata::{append_objects, IdOptions};
+    use internment::LocalIntern;
+    use shared::SpwnSource;
+
+    let dir = std::env::temp_dir().join(format!("spwn-id-ranges-{}", std::process::id()));
+    let lib_dir = dir.join("libraries").join("pinned");
+    fs::create_dir_all(&lib_dir).unwrap();
+    let code = "#[no_std]\nl = import pinned\n$.add(obj {1: 1, 57: l})\n$.add(obj {1: 1, 57: ?g})";
+
+    let build = |lib: &str| {
+        fs::write(lib_dir.join("lib.spwn"), lib).unwrap();
+        let source = SpwnSource::String(LocalIntern::new(code.to_string()));
+        let (statements, notes) = crate::parse_spwn(
+            code.to_string(),
+            source.clone(),
+            crate::builtins::BUILTIN_NAMES,
+        )
+        .unwrap();
+        let mut std_out = Vec::<u8>::new();
+        let globals = crate::compiler::compile_spwn(
+            statements,
+            source,
+            vec![dir.clone()],
+            notes,
+            Default::default(),
+            String::new(),
+            None,
+            None,
+            &mut std_out,
+        )
+        .unwrap_or_else(|_| panic!("build failed"));
+        let options = IdOptions {
+            pinned: globals.pinned_ids(),
+            ..Default::default()
+        };
+        append_objects(globals.objects.clone(), &Default::default(), &options)
+    };
+
+    let lib = "#[no_std, id_range(group = 500..503)]\n$.add(obj {1: 1, 57: ?g})\nreturn ?g";
+    let (_, report) = build(lib).unwrap();
+    let mut assigned = report.assigned[0]
+        .iter()
+        .map(|(_, id)| *id)
+        .collect::<Vec<_>>();
+    assigned.sort_unstable();
+    // the group made by the main file stays out of the library's range
+    assert_eq!(assigned, vec![1, 500, 501]);
+
+    let error = build(&lib.replace("500..503", "500..=500")).unwrap_err();
+    assert_eq!(error.class, 0);
+    assert!(error.message.contains("500..=500"), "{}", error.message);
+    // a range up to the highest ID can be shown too
+    let error = build(&lib.replace("500..503", "2000..=65535")).unwrap_err();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(error.message.contains("2000..=65535"), "{}", error.message);
+}
+
+#[test]
+fn incremental_ids() {
+    use crate::level_codec::LevelString;
+    use crate::leveldata::{
+        append_objects, get_used_ids, remove_spwn_objects, update_spwn_objects, IdMap, IdOptions,
+    };
+
+    let build = |code: &str, level: &str| {
+        let mut std_out = Vec::new();
+        let globals = compile(code, &mut std_out);
+        let keys = crate::id_keys(&globals);
+        let mut old = LevelString::parse(level).unwrap();
+        let options = IdOptions {
+            previous: IdMap::from_level(&old).previous(&keys),
+            ..Default::default()
+        };
+        remove_spwn_objects(&mut old);
+        let level_ids = get_used_ids(&old).unwrap();
+        let (mut new_objects, report) =
+            append_objects(globals.objects, &level_ids, &options).unwrap();
+        new_objects
+            .objects
+            .push(IdMap::new(&keys, &report).to_object());
+        new_objects.to_string()
+    };
+
+    let code = "#[no_std]\na = ?g\nb = ?g\n$.add(obj {1: 1, 2: 0, 57: a})\n$.add(obj {1: 1, 2: 30, 57: b})";
+    let first = build(code, "");
+    assert!(
+        first.starts_with("1,1,2,0,57,1.1001;1,1,2,30,57,2.1001;"),
+        "{}",
+        first
+    );
+
+    // a new group made before the others, and an object placed by hand in the level that uses `a`
+    let level = first.clone() + "1,1,2,60,57,1;";
+    let code = code.replace("b = ?g", "b = ?g\nc = ?g\n$.add(obj {1: 1, 2: 90, 57: c})");
+    let code = code.replace("a = ?g", "x = ?g\n$.add(obj {1: 1, 2: 120, 57: x})\na = ?g");
+    let second = build(&code, &level);
+    assert!(second.contains("1,1,2,0,57,1.1001;"), "{}", second);
+    assert!(second.contains("1,1,2,30,57,2.1001;"), "{}", second);
+
+    let (kept, added, removed) = update_spwn_objects(
+        &LevelString::parse(&level).unwrap(),
+        &LevelString::parse(&second).unwrap(),
+    );
+    // only the ID map changed, and the objects of `a`, `b` and the hand placed object stay
+    assert_eq!(removed, 1);
+    assert_eq!(kept.objects.len(), 3);
+    assert!(kept.to_string().ends_with("1,1,2,60,57,1;"));
+    // the objects of `x` and `c`, and the ID map, which is last
+    let second = LevelString::parse(&second).unwrap();
+    assert_eq!(added, [0, 1, 4]);
+    let added = added
+        .iter()
+        .map(|i| second.objects[*i].to_string())
+        .collect::<Vec<_>>();
+    assert!(added[0].starts_with("1,1,2,120,"), "{:?}", added);
+    assert!(added[1].starts_with("1,1,2,90,"), "{:?}", added);
+    assert!(added[2].starts_with("1,914,"), "{:?}", added);
+}
+
+#[test]
+fn named_ids() {
+    use crate::leveldata::{append_objects, IdMap, IdOptions};
+
+    let code = "#[no_std]\n$.add(obj {1: 1, 57: ?g})\n$.add(obj {1: 1, 57: ?g(\"door\")})\n$.add(obj {1: 1, 57: ?g(\"door\"), 80: ?i(\"score\")})";
+    let mut std_out = Vec::new();
+    let globals = compile(code, &mut std_out);
+    let keys = globals.id_name_keys();
+    assert_eq!(keys[0].len(), 1);
+    assert_eq!(keys[3].len(), 1);
+
+    // the names keep the IDs from the table, and the other IDs are given out around them
+    let table = IdMap::from_text("# comment\ngroup   40  door\nitem 7 score\n").unwrap();
+    let options = IdOptions {
+        previous: table.previous(&keys),
+        ..Default::default()
+    };
+    let (new_objects, report) =
+        append_objects(globals.objects, &Default::default(), &options).unwrap();
+    let new_ls = new_objects.to_string();
+    assert_eq!(new_ls.matches("57,40.1001").count(), 2, "{}", new_ls);
+    assert!(new_ls.contains("80,7;"), "{}", new_ls);
+    assert_eq!(IdMap::new(&keys, &report), table);
+    assert_eq!(IdMap::from_text(&table.to_text()).unwrap(), table);
+    assert!(IdMap::from_text("group door").is_err());
+
+    let config = crate::fmt::FmtConfig::default();
+    let source = shared::SpwnSource::String(internment::LocalIntern::new(code.to_string()));
+    let formatted =
+        crate::fmt::format(code, source, crate::builtins::BUILTIN_NAMES, &config).unwrap();
+    assert!(formatted.contains("?g(\"door\")"), "{}", formatted);
+}
+
+#[test]
+fn source_map_origins() {
+    use crate::leveldata::{append_objects, apply_fn_ids, IdOptions};
+
+    let code = "#[no_std]\ng = ?g\n$.add(obj {1: 1, 57: g})\nm = () {\n    $.add(trigger {1: 901, 51: g})\n}\nm()";
+    let mut std_out = Vec::new();
+    let globals = compile(code, &mut std_out);
+
+    let mut objects = apply_fn_ids(&globals.func_ids, &globals.layout);
+    objects.extend(globals.objects);
+    let (_, report) =
+        append_objects(objects.clone(), &Default::default(), &IdOptions::default()).unwrap();
+    let map = crate::source_map::source_map(&objects, &report);
+
+    let lines = map["objects"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|o| {
+            let call_lines = o["call_stack"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|a| a["start"]["line"].as_u64().unwrap())
+                .collect::<Vec<_>>();
+            (o["origin"]["start"]["line"].as_u64().unwrap(), call_lines)
+        })
+        .collect::<Vec<_>>();
+    // the trigger was added inside the macro called on line 7
+    assert!(lines.contains(&(3, vec![])));
+    assert!(lines.contains(&(5, vec![7])));
+    // only the object is in the group, the trigger just targets it
+    let in_group = map["groups"]["1"].as_array().unwrap();
+    assert_eq!(in_group.len(), 1);
+    assert_eq!(
+        map["objects"][in_group[0].as_u64().unwrap() as usize]["object_id"],
+        1
+    );
+}
+
+#[test]
+fn optimizer_passes() {
+    use optimizer::optimize::optimize_with;
+    use optimizer::passes::{Pipeline, PASS_NAMES};
+    use optimizer::ReservedIds;
+
+    assert!(Pipeline::from_names("dead_code,inline").is_err());
+    // spawn optimisation needs the dead code to be gone
+    assert!(Pipeline::from_names("spawn,dead_code").is_err());
+    assert_eq!(Pipeline::default().names(), PASS_NAMES);
+    assert_eq!(
+        Pipeline::from_names(" dedup, dead_code").unwrap().names(),
+        ["dedup", "dead_code"]
+    );
+
+    let code = "#[no_std]\nf = !{ $.add(trigger {1: 901, 51: 10g, 28: 10}) }\ng = !{ f! }\nh = !{ g! }\nh!\nh!";
+    let mut std_out = Vec::new();
+    let globals = compile(code, &mut std_out);
+
+    let triggers = |names: &str| {
+        let reserved = ReservedIds::from_objects(&globals.objects, &globals.func_ids);
+        let mut pipeline = Pipeline::from_names(names).unwrap();
+        let count = optimize_with(
+            globals.func_ids.clone(),
+            globals.closed_groups,
+            reserved,
+            &mut pipeline,
+        )
+        .iter()
+        .map(|f| f.obj_list.len())
+        .sum::<usize>();
+        (count, pipeline.report)
+    };
+    let (all, report) = triggers(&PASS_NAMES.join(","));
+    let (spawn_only, spawn_report) = triggers("dead_code,spawn");
+    // the spawn triggers in between can all be removed
+    assert!(spawn_only < triggers("").0);
+    assert!(all <= spawn_only);
+
+    assert_eq!(report.after.values().sum::<usize>(), all);
+    let spawn = &spawn_report.passes[1];
+    assert_eq!(spawn.name, "spawn");
+    assert!(spawn.removed > spawn.created);
+    // the repeated passes ran until nothing changed anymore
+    assert!(spawn.runs > 1 && spawn.runs < 10);
+    let before = spawn_report.before.values().sum::<usize>();
+    let passes = &spawn_report.passes;
+    // group 0 is left with a spawn trigger for each call of `h`, so after the passes
+    // they're moved into a new group with one spawn trigger that starts it
+    assert_eq!(
+        before + passes.iter().map(|p| p.created).sum::<usize>()
+            - passes.iter().map(|p| p.removed).sum::<usize>()
+            + 1,
+        spawn_only
+    );
+}
+
+#[test]
+fn trigger_graph() {
+    use optimizer::graph::{EdgeKind, NodeKind, TriggerGraph};
+    use optimizer::TriggerRole;
+
+    let code = "#[no_std]\nf = !{ $.add(trigger {1: 901, 51: 10g, 28: 10}) }\n$.add(trigger {1: 1268, 51: f.start_group, 63: 0.5})";
+    let mut std_out = Vec::new();
+    let globals = compile(code, &mut std_out);
+    let graph = TriggerGraph::from_triggers(&globals.func_ids);
+
+    let node = |id: &str| graph.nodes.iter().find(|n| n.id == id).unwrap();
+    let spawn = graph
+        .edges
+        .iter()
+        .find(|e| e.kind == EdgeKind::Activates)
+        .unwrap();
+    assert_eq!(spawn.delay, Some(0.5));
+    assert_eq!(
+        node(&spawn.from).kind,
+        NodeKind::Trigger(TriggerRole::Spawn)
+    );
+    assert_eq!(node(&spawn.to).kind, NodeKind::Group);
+    // the group the spawn trigger activates contains the move trigger
+    let moved = graph
+        .edges
+        .iter()
+        .find(|e| e.kind == EdgeKind::Contains && e.from == spawn.to)
+        .unwrap();
+    assert_eq!(node(&moved.to).label, "move");
+
+    let dot = graph.to_dot();
+    assert!(dot.starts_with("digraph"));
+    assert!(dot.contains(&format!("{} -> {} [label=\"0.5s", spawn.from, spawn.to)));
+}
+
+#[test]
+fn unused_output_targets() {
+    use optimizer::optimize::optimize_with;
+    use optimizer::passes::Pipeline;
+    use optimizer::ReservedIds;
+
+    // the pickup trigger is kept, since the instant count trigger reads its item ID,
+    // and the color trigger is kept, since an object has its color
+    let code = "#[no_std]
+i = ?i
+c = ?c
+$.add(trigger {1: 901, 51: ?g, 28: 10})
+$.add(trigger {1: 899, 23: ?c, 7: 255})
+$.add(trigger {1: 1817, 80: ?i, 77: 1})
+$.add(trigger {1: 1817, 80: i, 77: 1})
+$.add(trigger {1: 1611, 80: i, 51: 5g, 77: 2})
+$.add(trigger {1: 899, 23: c, 7: 255})
+$.add(obj {1: 1, 21: c})
+$.add(trigger {1: 1007, 51: 5g, 35: 0.5})";
+    let mut std_out = Vec::new();
+    let globals = compile(code, &mut std_out);
+
+    let reserved = ReservedIds::from_objects(&globals.objects, &globals.func_ids);
+    let mut pipeline = Pipeline::default();
+    let triggers = optimize_with(
+        globals.func_ids.clone(),
+        globals.closed_groups,
+        reserved,
+        &mut pipeline,
+    )
+    .iter()
+    .map(|f| f.obj_list.len())
+    .sum::<usize>();
+    assert_eq!(triggers, 5);
+
+    let dropped = &pipeline.report.dropped;
+    assert_eq!(
+        dropped.iter().map(|d| d.class).collect::<Vec<_>>(),
+        ["group", "color", "item ID"]
+    );
+    // the warnings point at the code that added the triggers
+    assert!(dropped.iter().all(|d| d.object.origin.is_some()));
+}
+
+#[test]
+fn named_id_targets() {
+    use optimizer::optimize::optimize_with;
+    use optimizer::passes::Pipeline;
+    use optimizer::ReservedIds;
+
+    // the named group may be on objects placed in the editor, so the move trigger is kept
+    let code =
+        "#[no_std]\ng = ?g(\"door\")\nf = !{\n    $.add(trigger {1: 901, 51: g, 28: 10})\n}\nf!";
+    let mut std_out = Vec::new();
+    let globals = compile(code, &mut std_out);
+
+    let mut pipeline = Pipeline::default();
+    let triggers = optimize_with(
+        globals.func_ids.clone(),
+        globals.closed_groups,
+        ReservedIds::from_globals(&globals),
+        &mut pipeline,
+    )
+    .iter()
+    .map(|f| f.obj_list.len())
+    .sum::<usize>();
+    assert_eq!(triggers, 1);
+    assert!(pipeline.report.dropped.is_empty());
+
+    // without the names the trigger has no target
+    let mut pipeline = Pipeline::default();
+    optimize_with(
+        globals.func_ids.clone(),
+        globals.closed_groups,
+        ReservedIds::from_objects(&globals.objects, &globals.func_ids),
+        &mut pipeline,
+    );
+    assert_eq!(pipeline.report.dropped.len(), 1);
+}
+
+// a random program of trigger functions that call, wait for, toggle, stop and count each other
+fn random_triggers(seed: u64) -> String {
+    let mut state = seed * 2 + 1;
+    let mut next = move |n: u64| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state % n
+    };
+    let functions = 6;
+    let mut code = String::from("#[no_std]\ni = ?i\n");
+    for f in 0..functions {
+        code += &format!("f{} = !{{\n", f);
+        for _ in 0..=next(4) {
+            // functions only use the ones before them, so there's no recursion
+            let other = if f > 0 { next(f) } else { 0 };
+            code += &match next(if f > 0 { 8 } else { 3 }) {
+                0 => format!("$.add(trigger {{1: 901, 51: 10g, 28: {}}})", next(100)),
+                1 => String::from("$.add(trigger {1: 1817, 80: i, 77: 1})"),
+                2 => format!("$.add(trigger {{1: 1007, 51: 11g, 35: {}}})", next(10)),
+                3 => format!("f{}!", other),
+                4 => format!(
+                    "$.add(trigger {{1: 1268, 51: f{}.start_group, 63: {}}})",
+                    other,
+                    [0.25, 0.5, 1.0][next(3) as usize]
+                ),
+                5 => format!(
+                    "$.add(trigger {{1: 1049, 51: f{}.start_group, 56: {}}})",
+                    other,
+                    next(2) == 0
+                ),
+                6 => format!(
+                    "$.add(trigger {{1: 1811, 80: i, 77: {}, 88: {}, 51: f{}.start_group, 56: true}})",
+                    next(3),
+                    next(3),
+                    other
+                ),
+                _ => format!("$.add(trigger {{1: 1616, 51: f{}.start_group}})", other),
+            };
+            code += "\n";
+        }
+        code += "}\n";
+    }
+    for _ in 0..3 {
+        code += &format!("f{}!\n", next(functions));
+    }
+    code
+}
+
+#[test]
+fn optimizer_keeps_behavior() {
+    use optimizer::optimize::optimize_with;
+    use optimizer::passes::{Pipeline, PASS_NAMES};
+    use optimizer::simulate::{check_equivalent, compare, simulate};
+    use optimizer::{ReservedIds, NO_GROUP};
+
+    let code = "#[no_std]\nf = !{ $.add(trigger {1: 901, 51: 10g, 28: 10}) }\n$.add(trigger {1: 1268, 51: f.start_group, 63: 0.5})\n$.add(trigger {1: 901, 51: 10g, 28: 20})";
+    let mut std_out = Vec::new();
+    let globals = compile(code, &mut std_out);
+    let trace = simulate(&globals.objects, &globals.func_ids, NO_GROUP);
+    assert_eq!(
+        trace
+            .events
+            .iter()
+            .map(|e| (e.time, e.trigger.as_str()))
+            .collect::<Vec<_>>(),
+        [(0.0, "move 28:20 51:10"), (0.5, "move 28:10 51:10")]
+    );
+    let mut changed = trace.clone();
+    changed.events[1].time = 0.25;
+    assert!(compare(&trace, &changed).is_err());
+    // parameters that are written differently but are the same in the level string
+    let params_trace = |params: &str| {
+        let mut std_out = Vec::new();
+        let code = code.replace("51: 10g, 28: 20", &format!("51: 10g, 28: 20, {}", params));
+        let globals = compile(&code, &mut std_out);
+        simulate(&globals.objects, &globals.func_ids, NO_GROUP)
+    };
+    let groups = params_trace("300: [11g, 10g, 10g]");
+    assert!(compare(&params_trace("300: [10g, 11g]"), &groups).is_ok());
+    assert!(compare(&params_trace("300: 10g"), &params_trace("300: [10g]")).is_ok());
+    assert!(compare(&params_trace("300: [10g, 12g]"), &groups).is_err());
+
+    // every pass on its own (with the passes it needs), and all of them
+    let mut pipelines = vec!["dead_code", "dead_code,spawn", "dedup", "group_toggling"];
+    let all = PASS_NAMES.join(",");
+    pipelines.push(&all);
+
+    for seed in 0..100 {
+        let code = random_triggers(seed);
+        let mut std_out = Vec::new();
+        let globals = compile(&code, &mut std_out);
+        for names in &pipelines {
+            let reserved = ReservedIds::from_objects(&globals.objects, &globals.func_ids);
+            let optimized = optimize_with(
+                globals.func_ids.clone(),
+                globals.closed_groups,
+                reserved,
+                &mut Pipeline::from_names(names).unwrap(),
+            );
+            if let Err(e) = check_equivalent(&globals.objects, &globals.func_ids, &optimized) {
+                panic!("`{}` changed what this does: {}\n{}", names, e, code);
+            }
+        }
+    }
+}
+
+#[test]
+fn dedup_canonical_params() {
+    use optimizer::optimize::optimize_with;
+    use optimizer::passes::Pipeline;
+    use optimizer::simulate::check_equivalent;
+    use optimizer::ReservedIds;
+
+    let triggers = |code: &str| {
+        let mut std_out = Vec::new();
+        let globals = compile(code, &mut std_out);
+        let count = |names: &str| {
+            let optimized = optimize_with(
+                globals.func_ids.clone(),
+                globals.closed_groups,
+                ReservedIds::from_objects(&globals.objects, &globals.func_ids),
+                &mut Pipeline::from_names(names).unwrap(),
+            );
+            check_equivalent(&globals.objects, &globals.func_ids, &optimized).unwrap();
+            optimized.iter().map(|f| f.obj_list.len()).sum::<usize>()
+        };
+        (count(""), count("dedup"))
+    };
+
+    // the same triggers, with the parameters written differently
+    let code = "#[no_std]
+a = !{ $.add(trigger {1: 1007, 51: 11g, 35: 0.5}) }
+f = !{
+$.add(trigger {1: 1268, 51: a.start_group, 63: 0.05})
+$.add(trigger {1: 1049, 51: 12g, 56: true, 10: 1})
+}
+g = !{
+$.add(trigger {1: 1268, 51: a.start_group, 63: {type: @epsilon}})
+$.add(trigger {1: 1049, 51: [12g], 56: 1, 10: 0.9999})
+}
+f!
+g!";
+    let (before, after) = triggers(code);
+    assert_eq!(after, before - 2);
+
+    let (before, after) = triggers(&code.replace("{type: @epsilon}", "0.1"));
+    assert_eq!(after, before);
+}
+
+#[test]
+fn same_build_same_level() {
+    use optimizer::optimize::optimize_with;
+    use optimizer::passes::Pipeline;
+    use optimizer::ReservedIds;
+
+    // dictionaries are hash maps, and the IDs are given out while going through one
+    let code = "#[no_std]
+d = {c: 3, a: 1, e: 5, b: 2, d: 4}
+for [k, v] in d {
+    g = ?g
+    $.add(obj {1: 1, 2: v * 30, 3: 300, 57: g})
+    f = !{
+        $.add(trigger {1: 901, 51: g, 28: v})
+        $.add(trigger {1: 1817, 80: ?i, 77: v})
+    }
+    f!
+}
+$.add(obj {1: 1, 2: $.random() * 300, 3: 600, 57: ?g})";
+
+    let level = |seed| {
+        let mut std_out = Vec::new();
+        let globals = compile_seeded(code, Some(seed), &mut std_out);
+        let func_ids = optimize_with(
+            globals.func_ids.clone(),
+            globals.closed_groups,
+            ReservedIds::from_objects(&globals.objects, &globals.func_ids),
+            &mut Pipeline::default(),
+        );
+        let mut objects = crate::leveldata::apply_fn_ids(&func_ids, &globals.layout);
+        objects.extend(globals.objects);
+        crate::leveldata::append_objects(objects, &Default::default(), &Default::default())
+            .unwrap()
+            .0
+            .to_string()
+    };
+    let first = level(1);
+    for _ in 0..5 {
+        assert_eq!(level(1), first);
+    }
+    assert_ne!(level(2), first);
+}
+
+#[test]
+fn trigger_layouts() {
+    use crate::leveldata::{apply_fn_ids, ObjParam, TriggerLayout};
+
+    let code = "
+$.add(trigger {1: 901, 51: 1g})
+f = !{
+    $.add(trigger {1: 901, 51: 2g})
+    g = !{
+        for i in 0..40 {
+            $.add(trigger {1: 1817, 80: 3i, 77: i})
+        }
+    }
+    g!
+    $.add(trigger {1: 901, 51: 4g})
+}
+f!";
+    let number = |obj: &crate::leveldata::GdObj, key| match obj.params.get(&key) {
+        Some(ObjParam::Number(n)) => *n,
+        _ => 0.0,
+    };
+
+    for (attribute, layout) in [
+        ("", TriggerLayout::Column),
+        (", layout(grouped)", TriggerLayout::Grouped),
+        (", layout(tree)", TriggerLayout::Tree),
+        (
+            ", layout(x = 3000, y = 300, w = 4)",
+            TriggerLayout::Grid {
+                x: 3000.0,
+                y: 300.0,
+                width: 4,
+            },
+        ),
+    ] {
+        let mut std_out = Vec::new();
+        let globals = compile(&format!("#[no_std{}]{}", attribute, code), &mut std_out);
+        assert_eq!(globals.layout, layout);
+
+        let objects = apply_fn_ids(&globals.func_ids, &globals.layout);
+        let (spawned, start) = objects
+            .iter()
+            .filter(|o| o.mode == crate::ast::ObjectMode::Trigger)
+            .partition::<Vec<_>, _>(|o| o.params.get(&62) == Some(&ObjParam::Bool(true)));
+        // with the spawn triggers for `f!` and `g!`
+        assert_eq!((start.len(), spawned.len()), (2, 43));
+
+        // the triggers that aren't spawned stay at the start of the level
+        assert!(start.iter().all(|t| number(t, 2) == 0.0));
+
+        // the spawned triggers in the same group still run in the same order
+        let mut last_x = std::collections::HashMap::new();
+        for trigger in &spawned {
+            let group = format!("{:?}", trigger.params.get(&57));
+            let x = number(trigger, 2);
+            if let Some(last) = last_x.insert(group, x) {
+                assert!(x > last, "{:?}: {} is left of {}", layout, x, last);
+            }
+        }
+
+        let labels = objects.len() - start.len() - spawned.len();
+        match layout {
+            TriggerLayout::Grouped | TriggerLayout::Tree => assert_eq!(labels, 2),
+            TriggerLayout::Grid { .. } => {
+                assert_eq!(labels, 0);
+                for trigger in &spawned {
+                    let (x, y) = (number(trigger, 2), number(trigger, 3));
+                    assert!((3000.0..3000.0 + 4.0 * 30.0).contains(&x), "{}", x);
+                    assert!((300.0..300.0 + 11.0 * 30.0).contains(&y), "{}", y);
+                }
+            }
+            TriggerLayout::Column => assert_eq!(labels, 0),
+        }
+    }
+}
+
+#[test]
+fn level_string_codec() {
+    use crate::level_codec::{LevelErrorKind, LevelString};
+    use crate::leveldata::get_used_ids;
+
+    let text = "kS38,1_40_2_125_3_255_6_1000_7_1|1_0_2_102_6_1001|,kA13,0,kA14,;1,1,2,15,3,15,57,2.5,999,x;1,914,2,45,3,15,31,SGk=;";
+    let level = LevelString::parse(text).unwrap();
+    // keys SPWN doesn't know about are written back the way they were
+    assert_eq!(level.to_string(), text);
+    assert_eq!(level.objects.len(), 2);
+
+    let header = level.header.clone().unwrap();
+    assert_eq!(header.get("kA14").unwrap().value, "");
+    let mut colors = header.colors().unwrap();
+    assert_eq!(colors.len(), 2);
+    assert_eq!(colors[1].get(6).unwrap().id().unwrap(), 1001);
+    colors[0].set(7, 0.5);
+    let mut header = header;
+    header.set_colors(&colors);
+    assert!(header
+        .to_string()
+        .starts_with("kS38,1_40_2_125_3_255_6_1000_7_0.5|1_0"));
+
+    let ids = get_used_ids(&level).unwrap();
+    assert_eq!(ids[0].len(), 2);
+
+    // a trailing comma is fine, and levels SPWN wrote without a level have no header
+    let level = LevelString::parse("1,1,2,30,;;1,1,2,60;").unwrap();
+    assert!(level.header.is_none());
+    assert_eq!(level.to_string(), "1,1,2,30;1,1,2,60;");
+
+    // values that can't be read are errors pointing at them, instead of panics
+    let level = LevelString::parse("1,1,2,30,57,1.x;").unwrap();
+    let error = get_used_ids(&level).unwrap_err();
+    assert_eq!(error.offset, 12);
+    assert!(matches!(error.kind, LevelErrorKind::InvalidValue { .. }));
+
+    let error = LevelString::parse("1,1;1,1,2,30,3;").unwrap_err();
+    assert_eq!(error.kind, LevelErrorKind::MissingValue("3".to_string()));
+    assert_eq!(error.offset, 13);
+    let error = LevelString::parse("1,1;1,1,x,30;").unwrap_err();
+    assert_eq!(error.kind, LevelErrorKind::InvalidKey("x".to_string()));
+    assert_eq!(error.offset, 8);
+}
+
+#[test]
+fn gmd_export_round_trip() {
+    use crate::level_codec::LevelString;
+
+    let [_, level] = run_spwn(
+        "#[no_std]\n$.add(obj {1: 1, 2: 45, 3: 15, 57: 5g})\n$.add(obj {1: 914, 2: 75, 3: 15, 31: $.b64encode(\"a & b\")})".to_string(),
+        vec![PathBuf::from("./")],
+        false,
+    )
+    .unwrap();
+    // built the same way `--out level.gmd` does
+    let full_ls = levelstring::DEFAULT_LEVEL_HEADER.to_string() + &level;
+    let gmd = levelstring::export_gmd(&full_ls, "<spwn & gmd>");
+    assert!(gmd.contains("<s>&lt;spwn &amp; gmd&gt;</s>"));
+
+    let read = levelstring::import_gmd(&gmd).unwrap();
+    assert_eq!(read, full_ls);
+    let objects = LevelString::parse(&read).unwrap().objects;
+    assert_eq!(objects, LevelString::parse(&level).unwrap().objects);
+    assert_eq!(objects.len(), 2);
+    // a level named like the key of the level string
+    let gmd = levelstring::export_gmd(&full_ls, "k4");
+    assert_eq!(levelstring::import_gmd(&gmd).unwrap(), full_ls);
+
+    assert!(levelstring::import_gmd("<plist><dict><k>k2</k><s>x</s></dict></plist>").is_err());
+}
+
+#[test]
+fn level_header_settings() {
+    use crate::level_codec::LevelString;
+    use internment::LocalIntern;
+    use shared::SpwnSource;
+
+    let level = "kS38,1_40_2_125_3_255_6_1000_7_1|,kA2,0,kA4,0,kA13,0;1,1,2,15,3,15;";
+    let code = r#"
+#[no_std]
+header = $.level_header()
+$.assert(header.gamemode == "cube" && header.speed == "normal")
+$.assert(header.colors[0].channel == 1000c && header.colors[0].g == 125)
+
+$.set_level_header({
+    speed: "fast",
+    song_offset: 2.5,
+    colors: [{ channel: 1000c, r: 0, g: 0, b: 0 }, { channel: 1c, opacity: 0.5, copy: 1000c }],
+})
+$.assert($.level_header().speed == "fast")
+"#;
+    let source = SpwnSource::String(LocalIntern::new(code.to_string()));
+    let (statements, notes) = crate::parse_spwn(
+        code.to_string(),
+        source.clone(),
+        crate::builtins::BUILTIN_NAMES,
+    )
+    .unwrap();
+    let mut std_out = Vec::<u8>::new();
+    let globals = crate::compiler::compile_spwn(
+        statements,
+        source,
+        Vec::new(),
+        notes,
+        Default::default(),
+        level.to_string(),
+        None,
+        None,
+        &mut std_out,
+    )
+    .unwrap_or_else(|_| panic!("build failed"));
+
+    let mut header = LevelString::parse_header(level).unwrap().unwrap();
+    header.apply_settings(&globals.level_settings).unwrap();
+    assert_eq!(
+        header.to_string(),
+        "kS38,1_0_2_0_3_0_6_1000_7_1_4_-1_5_0|1_255_2_255_3_255_4_-1_5_0_6_1_7_0.5_9_1000_8_1_11_255_12_255_13_255_15_1_18_0|,kA2,0,kA4,2,kA13,2.5"
+    );
+}
+
+#[test]
+fn object_prop_schema() {
+    use crate::builtins::{Block, Id, Item};
+    use crate::level_codec::{LevelObject, LevelString};
+    use crate::leveldata::{get_used_ids, ObjParam};
+    use crate::obj_schema::{check_object, decode_object};
+
+    // what key 80 is depends on the object
+    let block = ObjParam::Block(Block {
+        id: Id::Specific(3),
+    });
+    let item = ObjParam::Item(Item {
+        id: Id::Specific(3),
+    });
+    assert!(check_object(&[(1, ObjParam::Number(1815.0)), (80, block.clone())]).is_ok());
+    assert!(check_object(&[(1, ObjParam::Number(1817.0)), (80, item)]).is_ok());
+    let error = check_object(&[(1, ObjParam::Number(1817.0)), (80, block)]).unwrap_err();
+    assert!(error.contains("takes an item ID"), "{}", error);
+    assert!(check_object(&[(2, ObjParam::Text("a".to_string()))]).is_err());
+    assert!(check_object(&[(62, ObjParam::Number(2.0))]).is_err());
+    // keys SPWN doesn't know about take anything
+    assert!(check_object(&[(200, ObjParam::Text("a".to_string()))]).is_ok());
+
+    let decode = |text| decode_object(&LevelObject::parse(text, 0).unwrap()).unwrap();
+    assert!(matches!(
+        decode("1,1006,51,5,52,1")[1].1,
+        ObjParam::Group(_)
+    ));
+    assert!(matches!(decode("1,1006,51,5")[1].1, ObjParam::Color(_)));
+    assert!(matches!(decode("1,1816,80,3")[1].1, ObjParam::Block(_)));
+    assert!(matches!(
+        decode("1,1,31,SGk=,200,x")[1].1,
+        ObjParam::Text(_)
+    ));
+
+    // the item counter only shows an ID, and copied colors are used colors
+    let level = LevelString::parse("1,1816,80,3;1,1615,80,9;1,1,21,7,50,8;").unwrap();
+    let ids = get_used_ids(&level).unwrap();
+    assert_eq!(ids[2].iter().collect::<Vec<_>>(), vec![&3]);
+    assert!(ids[3].is_empty());
+    assert_eq!(ids[1].len(), 2);
+
+    let mut std_out = Vec::new();
+    compile(
+        "#[no_std]
+props = $.obj_props()
+$.assert(props.BLOCK_A.id == 80 && props.ITEM.id == 80)
+$.assert(props.SCALING.default == 1 && props.BLENDING.default == false)
+let o = obj { 1: 1006, 51: 2c }
+$.edit_obj(o, props.TARGET, 5g)",
+        &mut std_out,
+    );
+}
+
+#[test]
+fn save_file_backups() {
+    let dir = std::env::temp_dir().join(format!("spwn-save-backups-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let save = dir.join("CCLocalLevels.dat");
+
+    // nothing to back up yet
+    levelstring::write_save_file(&save, b"0").unwrap();
+    assert!(levelstring::list_backups(&save).unwrap().is_empty());
+
+    for i in 1..=levelstring::BACKUPS_KEPT + 2 {
+        levelstring::write_save_file(&save, i.to_string().as_bytes()).unwrap();
+    }
+    assert_eq!(fs::read(&save).unwrap(), b"12");
+    // the temporary file was renamed over the save
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+    // the newest backups, and the oldest one from before the first write
+    let backups = levelstring::list_backups(&save).unwrap();
+    assert_eq!(backups.len(), levelstring::BACKUPS_KEPT + 1);
+    assert_eq!(fs::read(&backups[0]).unwrap(), b"11");
+    let last_kept = &backups[levelstring::BACKUPS_KEPT - 1];
+    assert_eq!(fs::read(last_kept).unwrap(), b"2");
+    assert_eq!(fs::read(backups.last().unwrap()).unwrap(), b"0");
+
+    levelstring::restore_backup(&save, last_kept).unwrap();
+    assert_eq!(fs::read(&save).unwrap(), b"2");
+    // the save that was replaced is the newest backup now, and the oldest one is still there
+    let backups = levelstring::list_backups(&save).unwrap();
+    assert_eq!(backups.len(), levelstring::BACKUPS_KEPT + 1);
+    assert_eq!(fs::read(&backups[0]).unwrap(), b"12");
+    assert_eq!(fs::read(backups.last().unwrap()).unwrap(), b"0");
+
+    // a broken save file is an error instead of a panic, and is left alone
+    fs::write(&save, b"not a save file").unwrap();
+    assert!(levelstring::get_level_string(b"not a save file".to_vec(), None).is_err());
+    assert!(
+        levelstring::encrypt_level_string(String::new(), String::new(), save.clone(), None)
+            .is_err()
+    );
+    assert_eq!(fs::read(&save).unwrap(), b"not a save file");
+    fs::remove_dir_all(&dir).unwrap();
+}